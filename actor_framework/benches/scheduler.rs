@@ -0,0 +1,574 @@
+//! Synthetic workloads, run against every scheduler backend
+//!
+//! Reports how many messages each backend dispatches per second of host time:
+//!
+//!     cargo bench -p actor_framework --bench scheduler
+//!
+//! The cycle count of every workload can be scaled with an argument, `-- 0.1` runs a tenth as long.
+
+use std::time::Instant;
+
+use actor_framework::{Instance, MakeNamed, SchedulerBackend, Time};
+
+fn main() {
+    let scale = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<f64>().ok())
+        .unwrap_or(1.0);
+    let cycles = |cycles: u64| (cycles as f64 * scale) as u64;
+
+    println!("{:<16} {:<14} {:>12} {:>12} {:>10} {:>12}", "workload", "backend", "messages", "zero limits", "time", "messages/s");
+    run::<many_actors::Names>("many_actors", cycles(2_000_000), || many_actors::Config);
+    run::<wide::Names>("wide", cycles(2_000_000), || wide::Config);
+    run::<bursty::Names>("bursty", cycles(2_000_000), || bursty::Config);
+    run::<bus_contention::Names>("bus_contention", cycles(2_000_000), || bus_contention::Config);
+    run_parallel::<cores::Names>("cores", cycles(200_000), || cores::Config);
+}
+
+fn run<ActorNames>(workload: &str, cycles: u64, config: impl Fn() -> ActorNames::Config)
+where
+    ActorNames: MakeNamed,
+{
+    let mut reference = None;
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<ActorNames>::with_backend(config(), backend).unwrap();
+
+        let start = Instant::now();
+        instance.run_until(Time::from(cycles)).unwrap();
+        let elapsed = start.elapsed();
+
+        let stats = instance.scheduler_stats();
+        let throughput = stats.runs as f64 / elapsed.as_secs_f64();
+        println!("{:<16} {:<14} {:>12} {:>12} {:>10.2?} {:>10.2} M",
+            workload, format!("{:?}", backend), stats.runs, stats.zero_limits, elapsed, throughput / 1e6);
+
+        // Same-cycle messages are only delivered in FIFO order by the queue based backends, so
+        // workloads that depend on that order can do different amounts of work under Branchless
+        let total: u64 = stats.actors.iter().map(|counts| counts.received).sum();
+        match reference {
+            None => reference = Some((backend, total)),
+            Some((reference, expected)) if expected != total => {
+                println!("    delivered {} messages, {:?} delivered {}", total, reference, expected);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Same workload on the default backend, with different numbers of threads
+fn run_parallel<ActorNames>(workload: &str, cycles: u64, config: impl Fn() -> ActorNames::Config)
+where
+    ActorNames: MakeNamed,
+{
+    let mut reference = None;
+    for threads in [1, 2, 4, 8] {
+        let mut instance = Instance::<ActorNames>::new(config()).unwrap();
+        instance.set_threads(threads).unwrap();
+
+        let start = Instant::now();
+        instance.run_until(Time::from(cycles)).unwrap();
+        let elapsed = start.elapsed();
+
+        let stats = instance.scheduler_stats();
+        let throughput = stats.runs as f64 / elapsed.as_secs_f64();
+        let backend = format!("{:?} x{}", SchedulerBackend::default(), threads);
+        println!("{:<16} {:<14} {:>12} {:>12} {:>10.2?} {:>10.2} M",
+            workload, backend, stats.runs, stats.zero_limits, elapsed, throughput / 1e6);
+
+        match reference {
+            None => reference = Some(stats.runs),
+            Some(expected) if expected != stats.runs => {
+                println!("    ran {} times, single threaded ran {}", stats.runs, expected);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+pub struct Tick;
+common::impl_save_state!(Tick {});
+
+/// Lots of independent actors, all with different periods
+mod many_actors {
+    use actor_framework::*;
+    use common::impl_save_state;
+
+    use super::Tick;
+
+    pub struct Config;
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    #[derive(Named)]
+    #[named(base(actor_framework::ActorBox), config(Config))]
+    pub enum Names {
+        #[named(class(T0))] T0,
+        #[named(class(T1))] T1,
+        #[named(class(T2))] T2,
+        #[named(class(T3))] T3,
+        #[named(class(T4))] T4,
+        #[named(class(T5))] T5,
+        #[named(class(T6))] T6,
+        #[named(class(T7))] T7,
+        #[named(class(T8))] T8,
+        #[named(class(T9))] T9,
+        #[named(class(T10))] T10,
+        #[named(class(T11))] T11,
+        #[named(class(T12))] T12,
+        #[named(class(T13))] T13,
+        #[named(class(T14))] T14,
+        #[named(class(T15))] T15,
+        #[named(terminal)] Terminal,
+    }
+
+    macro_rules! ticker {
+        ($name:ident, $outbox:ident, $period:expr) => {
+            pub struct $name;
+            impl_save_state!($name {});
+
+            make_outbox!($outbox<Names, $name> { tick: Tick });
+
+            impl Actor<Names> for $name {
+                type OutboxType = $outbox;
+            }
+
+            impl ActorInit<Names> for $name {
+                fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                    outbox.send::<$name>(Tick, time.add($period));
+                    Ok($name)
+                }
+            }
+
+            impl Handler<Names, Tick> for $name {
+                fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                    outbox.send::<$name>(Tick, time.add($period))
+                }
+            }
+        };
+    }
+
+    // Like a CPU, something is always running
+    ticker!(T0, T0Outbox, 1);
+    ticker!(T1, T1Outbox, 2);
+    ticker!(T2, T2Outbox, 3);
+    ticker!(T3, T3Outbox, 5);
+    ticker!(T4, T4Outbox, 7);
+    ticker!(T5, T5Outbox, 11);
+    ticker!(T6, T6Outbox, 13);
+    ticker!(T7, T7Outbox, 17);
+    ticker!(T8, T8Outbox, 19);
+    ticker!(T9, T9Outbox, 23);
+    ticker!(T10, T10Outbox, 29);
+    ticker!(T11, T11Outbox, 31);
+    ticker!(T12, T12Outbox, 37);
+    ticker!(T13, T13Outbox, 41);
+    ticker!(T14, T14Outbox, 43);
+    ticker!(T15, T15Outbox, 47);
+}
+
+/// A CPU running every cycle, plus lots of actors with long periods spread across the queue
+mod wide {
+    use actor_framework::*;
+    use common::impl_save_state;
+
+    use super::Tick;
+
+    pub struct Config;
+
+    fn period(id: Names) -> u64 {
+        match usize::from(id) {
+            0 => 1,
+            n => 64 + (n as u64 * 37) % 97,
+        }
+    }
+
+    macro_rules! wide {
+        ($($name:ident $outbox:ident),* $(,)?) => {
+            #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+            #[derive(Named)]
+            #[named(base(actor_framework::ActorBox), config(Config))]
+            pub enum Names {
+                $(#[named(class($name))] $name,)*
+                #[named(terminal)] Terminal,
+            }
+
+            $(
+                pub struct $name;
+                impl_save_state!($name {});
+
+                make_outbox!($outbox<Names, $name> { tick: Tick });
+
+                impl Actor<Names> for $name {
+                    type OutboxType = $outbox;
+                }
+
+                impl ActorInit<Names> for $name {
+                    fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                        outbox.send::<$name>(Tick, time.add(period(Self::name())));
+                        Ok($name)
+                    }
+                }
+
+                impl Handler<Names, Tick> for $name {
+                    fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                        outbox.send::<$name>(Tick, time.add(period(Self::name())))
+                    }
+                }
+            )*
+        };
+    }
+
+    wide!(
+        W0 W0Outbox, W1 W1Outbox, W2 W2Outbox, W3 W3Outbox, W4 W4Outbox, W5 W5Outbox, W6 W6Outbox, W7 W7Outbox,
+        W8 W8Outbox, W9 W9Outbox, W10 W10Outbox, W11 W11Outbox, W12 W12Outbox, W13 W13Outbox, W14 W14Outbox, W15 W15Outbox,
+        W16 W16Outbox, W17 W17Outbox, W18 W18Outbox, W19 W19Outbox, W20 W20Outbox, W21 W21Outbox, W22 W22Outbox, W23 W23Outbox,
+        W24 W24Outbox, W25 W25Outbox, W26 W26Outbox, W27 W27Outbox, W28 W28Outbox, W29 W29Outbox, W30 W30Outbox, W31 W31Outbox,
+        W32 W32Outbox, W33 W33Outbox, W34 W34Outbox, W35 W35Outbox, W36 W36Outbox, W37 W37Outbox, W38 W38Outbox, W39 W39Outbox,
+        W40 W40Outbox, W41 W41Outbox, W42 W42Outbox, W43 W43Outbox, W44 W44Outbox, W45 W45Outbox, W46 W46Outbox, W47 W47Outbox,
+        W48 W48Outbox, W49 W49Outbox, W50 W50Outbox, W51 W51Outbox, W52 W52Outbox, W53 W53Outbox, W54 W54Outbox, W55 W55Outbox,
+        W56 W56Outbox, W57 W57Outbox, W58 W58Outbox, W59 W59Outbox, W60 W60Outbox, W61 W61Outbox, W62 W62Outbox, W63 W63Outbox,
+    );
+}
+
+/// A CPU running every cycle, while a group of devices all send to the same sink on the same cycle
+mod bursty {
+    use actor_framework::*;
+    use common::impl_save_state;
+
+    use super::Tick;
+
+    pub struct Config;
+
+    const BURST_PERIOD: u64 = 64;
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    #[derive(Named)]
+    #[named(base(actor_framework::ActorBox), config(Config))]
+    pub enum Names {
+        #[named(class(Cpu))] Cpu,
+        #[named(class(Sink))] Sink,
+        #[named(class(D0))] D0,
+        #[named(class(D1))] D1,
+        #[named(class(D2))] D2,
+        #[named(class(D3))] D3,
+        #[named(class(D4))] D4,
+        #[named(class(D5))] D5,
+        #[named(class(D6))] D6,
+        #[named(class(D7))] D7,
+        #[named(terminal)] Terminal,
+    }
+
+    pub struct Work;
+    impl_save_state!(Work {});
+
+    pub struct Cpu;
+    impl_save_state!(Cpu {});
+
+    make_outbox!(CpuOutbox<Names, Cpu> { tick: Tick });
+
+    impl Actor<Names> for Cpu {
+        type OutboxType = CpuOutbox;
+    }
+
+    impl ActorInit<Names> for Cpu {
+        fn init(_: &Config, outbox: &mut CpuOutbox, time: Time) -> Result<Self, anyhow::Error> {
+            outbox.send::<Cpu>(Tick, time.add(1));
+            Ok(Cpu)
+        }
+    }
+
+    impl Handler<Names, Tick> for Cpu {
+        fn recv(&mut self, outbox: &mut CpuOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+            outbox.send::<Cpu>(Tick, time.add(1))
+        }
+    }
+
+    #[derive(Default)]
+    pub struct Sink {
+        received: u64,
+    }
+    impl_save_state!(Sink { received });
+
+    make_outbox!(SinkOutbox<Names, Sink> {});
+
+    impl Actor<Names> for Sink {
+        type OutboxType = SinkOutbox;
+    }
+
+    impl Handler<Names, Work> for Sink {
+        fn recv(&mut self, _: &mut SinkOutbox, _: Work, _: Time, _: Time) -> SchedulerResult {
+            self.received += 1;
+            SchedulerResult::Ok
+        }
+    }
+
+    macro_rules! device {
+        ($name:ident, $outbox:ident) => {
+            pub struct $name;
+            impl_save_state!($name {});
+
+            make_outbox!($outbox<Names, $name> { tick: Tick, work: Work });
+
+            impl Actor<Names> for $name {
+                type OutboxType = $outbox;
+
+                fn delivering<Message>(&mut self, outbox: &mut $outbox, _: &Message, time: Time)
+                where
+                    Message: 'static,
+                {
+                    // Wait for the next burst once the sink has our work
+                    if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<Work>() {
+                        outbox.send::<$name>(Tick, time.add(BURST_PERIOD));
+                    }
+                }
+            }
+
+            impl ActorInit<Names> for $name {
+                fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                    outbox.send::<$name>(Tick, time.add(BURST_PERIOD));
+                    Ok($name)
+                }
+            }
+
+            impl Handler<Names, Tick> for $name {
+                fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                    outbox.send::<Sink>(Work, time)
+                }
+            }
+        };
+    }
+
+    device!(D0, D0Outbox);
+    device!(D1, D1Outbox);
+    device!(D2, D2Outbox);
+    device!(D3, D3Outbox);
+    device!(D4, D4Outbox);
+    device!(D5, D5Outbox);
+    device!(D6, D6Outbox);
+    device!(D7, D7Outbox);
+}
+
+/// A CPU running every cycle, while devices with different priorities fight over a shared bus
+mod bus_contention {
+    use actor_framework::*;
+    use common::impl_save_state;
+
+    use super::Tick;
+
+    pub struct Config;
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    #[derive(Named)]
+    #[named(base(actor_framework::ActorBox), config(Config))]
+    pub enum Names {
+        #[named(class(Cpu))] Cpu,
+        #[named(class(Bus))] Bus,
+        #[named(class(D0))] D0,
+        #[named(class(D1))] D1,
+        #[named(class(D2))] D2,
+        #[named(class(D3))] D3,
+        #[named(class(D4))] D4,
+        #[named(class(D5))] D5,
+        #[named(terminal)] Terminal,
+    }
+
+    type BusRequest = Acquire<Names, Bus, u32>;
+
+    pub struct Cpu;
+    impl_save_state!(Cpu {});
+
+    make_outbox!(CpuOutbox<Names, Cpu> { tick: Tick });
+
+    impl Actor<Names> for Cpu {
+        type OutboxType = CpuOutbox;
+    }
+
+    impl ActorInit<Names> for Cpu {
+        fn init(_: &Config, outbox: &mut CpuOutbox, time: Time) -> Result<Self, anyhow::Error> {
+            outbox.send::<Cpu>(Tick, time.add(1));
+            Ok(Cpu)
+        }
+    }
+
+    impl Handler<Names, Tick> for Cpu {
+        fn recv(&mut self, outbox: &mut CpuOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+            outbox.send::<Cpu>(Tick, time.add(1))
+        }
+    }
+
+    pub struct Bus {
+        bus: Resource<Names, Bus, u32>,
+    }
+    impl_save_state!(Bus { bus });
+
+    make_outbox!(BusOutbox<Names, Bus> { grant: Grant<u32>, preempt: Preempt<u32> });
+
+    impl Actor<Names> for Bus {
+        type OutboxType = BusOutbox;
+
+        fn delivering<Message>(&mut self, outbox: &mut BusOutbox, _: &Message, time: Time)
+        where
+            Message: 'static,
+        {
+            self.bus.delivering::<Message>(outbox, time)
+        }
+    }
+
+    impl ActorInit<Names> for Bus {
+        fn init(_: &Config, _: &mut BusOutbox, _: Time) -> Result<Self, anyhow::Error> {
+            Ok(Bus { bus: Resource::new(Box::new(0)) })
+        }
+    }
+
+    impl Handler<Names, BusRequest> for Bus {
+        fn recv(&mut self, outbox: &mut BusOutbox, request: BusRequest, time: Time, _: Time) -> SchedulerResult {
+            self.bus.acquire(outbox, request, time)
+        }
+    }
+
+    impl Handler<Names, Release<u32>> for Bus {
+        fn recv(&mut self, outbox: &mut BusOutbox, release: Release<u32>, time: Time, _: Time) -> SchedulerResult {
+            self.bus.release(outbox, release, time)
+        }
+    }
+
+    macro_rules! device {
+        ($name:ident, $outbox:ident, priority: $priority:expr, hold: $hold:expr, gap: $gap:expr) => {
+            pub struct $name;
+            impl_save_state!($name {});
+
+            make_outbox!($outbox<Names, $name> { request: BusRequest, release: Release<u32> });
+
+            impl Actor<Names> for $name {
+                type OutboxType = $outbox;
+
+                fn delivering<Message>(&mut self, outbox: &mut $outbox, _: &Message, time: Time)
+                where
+                    Message: 'static,
+                {
+                    // Ask again once the bus is back with its owner
+                    if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<Release<u32>>() {
+                        outbox.send::<Bus>(BusRequest::new::<$name>($priority), time.add($gap));
+                    }
+                }
+            }
+
+            impl ActorInit<Names> for $name {
+                fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                    outbox.send::<Bus>(BusRequest::new::<$name>($priority), time.add($gap));
+                    Ok($name)
+                }
+            }
+
+            impl Handler<Names, Grant<u32>> for $name {
+                fn recv(&mut self, outbox: &mut $outbox, mut grant: Grant<u32>, time: Time, _: Time) -> SchedulerResult {
+                    *grant.0 += 1;
+                    outbox.send::<Bus>(Release(grant.0), time.add($hold))
+                }
+            }
+
+            impl Handler<Names, Preempt<u32>> for $name {
+                fn recv(&mut self, outbox: &mut $outbox, _: Preempt<u32>, time: Time, _: Time) -> SchedulerResult {
+                    // Give it back early
+                    let (_, release) = outbox.try_cancel::<Release<u32>>().unwrap();
+                    outbox.send::<Bus>(release, time)
+                }
+            }
+        };
+    }
+
+    device!(D0, D0Outbox, priority: 1, hold: 20, gap: 3);
+    device!(D1, D1Outbox, priority: 2, hold: 12, gap: 7);
+    device!(D2, D2Outbox, priority: 3, hold: 8, gap: 11);
+    device!(D3, D3Outbox, priority: 4, hold: 6, gap: 16);
+    device!(D4, D4Outbox, priority: 5, hold: 4, gap: 24);
+    device!(D5, D5Outbox, priority: 6, hold: 2, gap: 40);
+}
+
+/// CPU-like actors that do some real work every cycle, and only occasionally sync with their
+/// neighbour in a ring. Declares a lookahead, so it can run on multiple threads
+mod cores {
+    use std::any::TypeId;
+
+    use actor_framework::*;
+    use common::impl_save_state;
+
+    use super::Tick;
+
+    pub struct Config;
+
+    const SYNC_PERIOD: u64 = 256;
+    const WORK: u32 = 200;
+
+    pub struct Sync {
+        value: u64,
+    }
+    impl_save_state!(Sync { value });
+
+    macro_rules! cores {
+        ($($name:ident $outbox:ident -> $next:ident),* $(,)?) => {
+            #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+            #[derive(Named)]
+            #[named(base(actor_framework::ActorBox), config(Config))]
+            pub enum Names {
+                $(#[named(class($name))] $name,)*
+                #[named(terminal)] Terminal,
+            }
+
+            $(
+                pub struct $name {
+                    state: u64,
+                }
+                impl_save_state!($name { state });
+
+                make_outbox!($outbox<Names, $name> { tick: Tick, sync: Sync });
+
+                impl Actor<Names> for $name {
+                    type OutboxType = $outbox;
+                    const LOOKAHEAD: u64 = 32;
+
+                    fn delivering<Message>(&mut self, outbox: &mut $outbox, _: &Message, time: Time)
+                    where
+                        Message: 'static,
+                    {
+                        if TypeId::of::<Message>() == TypeId::of::<Sync>() {
+                            outbox.send::<$name>(Tick, time.add(1));
+                        }
+                    }
+                }
+
+                impl ActorInit<Names> for $name {
+                    fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                        outbox.send::<$name>(Tick, time.add(1));
+                        Ok($name { state: usize::from(Self::name()) as u64 + 1 })
+                    }
+                }
+
+                impl Handler<Names, Tick> for $name {
+                    fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                        for _ in 0..WORK {
+                            self.state ^= self.state << 13;
+                            self.state ^= self.state >> 7;
+                            self.state ^= self.state << 17;
+                        }
+                        match u64::from(time) % SYNC_PERIOD {
+                            0 => outbox.send::<$next>(Sync { value: self.state }, time.add(Self::LOOKAHEAD)),
+                            _ => outbox.send::<$name>(Tick, time.add(1)),
+                        }
+                    }
+                }
+
+                impl Handler<Names, Sync> for $name {
+                    fn recv(&mut self, _: &mut $outbox, sync: Sync, _: Time, _: Time) -> SchedulerResult {
+                        self.state = self.state.wrapping_add(sync.value);
+                        SchedulerResult::Ok
+                    }
+                }
+            )*
+        };
+    }
+
+    cores!(
+        C0 C0Outbox -> C1, C1 C1Outbox -> C2, C2 C2Outbox -> C3, C3 C3Outbox -> C4,
+        C4 C4Outbox -> C5, C5 C5Outbox -> C6, C6 C6Outbox -> C7, C7 C7Outbox -> C0,
+    );
+}
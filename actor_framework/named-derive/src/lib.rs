@@ -1,7 +1,19 @@
-use darling::{FromDeriveInput, FromVariant, ast::Data, util::{Ignored, PathList}};
+use darling::{FromDeriveInput, FromMeta, FromVariant, ast::Data, util::{Ignored, PathList}};
 use proc_macro::{self, TokenStream};
 use quote::{quote, format_ident};
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, punctuated::Punctuated, DeriveInput, Token};
+
+/// `handles(...)` takes types rather than paths, so messages like `Grant<RspMem>` can be listed
+#[derive(Debug)]
+struct TypeList(Vec<syn::Type>);
+
+impl FromMeta for TypeList {
+    fn from_meta(item: &syn::Meta) -> darling::Result<Self> {
+        let list = item.require_list()?;
+        let types = list.parse_args_with(Punctuated::<syn::Type, Token![,]>::parse_terminated)?;
+        Ok(TypeList(types.into_iter().collect()))
+    }
+}
 
 #[derive(Debug)]
 #[derive(FromVariant)]
@@ -10,6 +22,8 @@ struct Variant {
     ident: syn::Ident,
     discriminant: Option<syn::Expr>,
     class: Option<PathList>,
+    /// Messages the class has a `Handler` for, so pending messages can be rebuilt from a save state
+    handles: Option<TypeList>,
     terminal: Option<()>,
 }
 
@@ -27,10 +41,12 @@ impl Variant {
                 _ => Err(darling::Error::custom("expected exactly one class"))
             }
         } else {
-            if self.terminal.is_some() {
-                Ok(self)
-            } else {
+            if self.terminal.is_none() {
                 Err(darling::Error::custom("expected either class or terminal"))
+            } else if self.handles.is_some() {
+                Err(darling::Error::custom("the terminal doesn't handle any messages"))
+            } else {
+                Ok(self)
             }
         }
     }
@@ -62,8 +78,11 @@ pub fn derive_named(input: TokenStream) -> TokenStream {
 
     let mut from_patterns = Vec::new();
     let mut size_patterns = Vec::new();
+    let mut lookahead_patterns = Vec::new();
     let mut storage_patterns = Vec::new();
     let mut storage_new = Vec::new();
+    let mut classes = Vec::new();
+    let mut endpoint_patterns = Vec::new();
 
     let mut impls = Vec::new();
 
@@ -85,12 +104,19 @@ pub fn derive_named(input: TokenStream) -> TokenStream {
             size_patterns.push(quote! {
                 #ident::#name => core::mem::size_of::<()>(),
             });
+            lookahead_patterns.push(quote! {
+                #ident::#name => u64::MAX,
+            });
             storage_patterns.push(quote! {
                 #lower_name: #base<#ident, ()>,
             });
             storage_new.push(quote! {
                 #lower_name: <#base<#ident, ()>>::with(#config_obj)?,
             });
+            classes.push(quote! { () });
+            endpoint_patterns.push(quote! {
+                #ident::#name => {}
+            });
             impls.push(quote! {
                 impl actor_framework::Named<#ident> for () {
                     #[inline(always)] fn name() -> #ident { #ident::#name }
@@ -113,12 +139,27 @@ pub fn derive_named(input: TokenStream) -> TokenStream {
         size_patterns.push(quote! {
             #ident::#name => core::mem::size_of::<#class_path>(),
         });
+        lookahead_patterns.push(quote! {
+            #ident::#name => <#class_path as actor_framework::Actor<#ident>>::LOOKAHEAD,
+        });
         storage_patterns.push(quote! {
             #lower_name: #base<#ident, #class_path>,
         });
         storage_new.push(quote! {
             #lower_name: <#base<#ident, #class_path>>::with(#config_obj)?,
         });
+        classes.push(quote! { #class_path });
+        let handles = variant.handles.map(|handles| handles.0).unwrap_or_default();
+        endpoint_patterns.push(quote! {
+            #ident::#name => {
+                #(
+                    let endpoint = actor_framework::Endpoint::<#ident, #handles>::new::<#class_path>();
+                    if let Some(endpoint) = (&endpoint as &dyn core::any::Any).downcast_ref::<actor_framework::Endpoint<#ident, Message>>() {
+                        return Some(endpoint.clone());
+                    }
+                )*
+            }
+        });
 
         impls.push(quote! {
             impl actor_framework::Named<#ident> for #class_path {
@@ -176,6 +217,75 @@ pub fn derive_named(input: TokenStream) -> TokenStream {
                 }
             }
 
+            fn lookahead(id: Self) -> u64 {
+                match id {
+                    #(#lookahead_patterns)*
+                }
+            }
+
+            fn save_storage(storage: &#storage_type, state: &mut actor_framework::StateWriter) {
+                #(state.section(stringify!(#varients), |state| {
+                    actor_framework::SaveState::save_state(&storage.inner.#varients_lower, state)
+                });)*
+            }
+
+            fn load_storage(storage: &mut #storage_type, state: &mut actor_framework::StateReader) -> Result<(), anyhow::Error> {
+                #(state.section(stringify!(#varients), |state| {
+                    actor_framework::SaveState::load_state(&mut storage.inner.#varients_lower, state)
+                })?;)*
+                Ok(())
+            }
+
+            fn stats_storage(storage: &#storage_type, stats: &mut actor_framework::Stats) {
+                #({
+                    let mut group = actor_framework::StatsGroup::new(stringify!(#varients));
+                    actor_framework::Actor::<#ident>::stats(&storage.inner.#varients_lower.obj, &mut group);
+                    if !group.entries.is_empty() {
+                        stats.groups.push(group);
+                    }
+                })*
+            }
+
+            fn reset_stats_storage(storage: &mut #storage_type) {
+                #(actor_framework::Actor::<#ident>::reset_stats(&mut storage.inner.#varients_lower.obj);)*
+            }
+
+            fn soft_reset_storage(storage: &mut #storage_type) -> Result<(), anyhow::Error> {
+                let results = [#(actor_framework::Actor::<#ident>::soft_reset(&mut storage.inner.#varients_lower.obj),)*];
+                results.into_iter().collect()
+            }
+
+            fn msg_type_name(storage: &#storage_type, id: Self) -> &'static str {
+                match id {
+                    #(#ident::#varients => actor_framework::Outbox::<#ident>::msg_type_name(&storage.inner.#varients_lower.outbox),)*
+                }
+            }
+
+            fn endpoint<Message>(receiver: Self) -> Option<actor_framework::Endpoint<Self, Message>>
+            where
+                Message: 'static,
+            {
+                match receiver {
+                    #(#endpoint_patterns)*
+                }
+                None
+            }
+
+            fn actor_ptr(storage: &mut #storage_type, id: Self) -> core::ptr::NonNull<actor_framework::ActorBoxBase<#ident>> {
+                match id {
+                    #(#ident::#varients => core::ptr::NonNull::from(&mut storage.inner.#varients_lower).cast(),)*
+                }
+            }
+
+            unsafe fn msg_type_name_at(actor: core::ptr::NonNull<actor_framework::ActorBoxBase<#ident>>, id: Self) -> &'static str {
+                match id {
+                    #(#ident::#varients => {
+                        let actor = actor.cast::<#base<#ident, #classes>>().as_ref();
+                        actor_framework::Outbox::<#ident>::msg_type_name(&actor.outbox)
+                    })*
+                }
+            }
+
             fn index_array<T>(array: &Self::ArrayType<T>, id: Self) -> &T
             where T: Send
             {
@@ -1,5 +1,5 @@
 
-use crate::{MakeNamed, MessagePacketProxy, Actor, Outbox, ActorInit};
+use crate::{timers, MakeNamed, MessagePacketProxy, Actor, Outbox, ActorInit, SaveState, StateReader, StateWriter};
 
 #[repr(C)]
 pub struct ActorBoxBase<ActorName>
@@ -29,7 +29,8 @@ where
     pub fn with(config: &ActorNames::Config) -> Result<Self, anyhow::Error> {
         let mut outbox = Default::default();
         let time = 0.into();
-        let actor = <A as ActorInit<ActorNames>>::init(config, &mut outbox, time)?;
+        let mut actor = <A as ActorInit<ActorNames>>::init(config, &mut outbox, time)?;
+        timers::settle(&mut actor, &mut outbox);
         Ok(ActorBox {
             outbox,
             obj: actor
@@ -37,6 +38,23 @@ where
     }
 }
 
+impl<ActorNames, A> SaveState for ActorBox<ActorNames, A>
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+    A::OutboxType: Outbox<ActorNames>,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.outbox.save_state(state);
+        self.obj.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.outbox.load_state(state)?;
+        self.obj.load_state(state)
+    }
+}
+
 pub trait AsBase<ActorNames>
 where
     ActorNames: MakeNamed,
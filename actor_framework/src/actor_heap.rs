@@ -0,0 +1,205 @@
+use crate::{EnumMap, MakeNamed, Time};
+
+/// Binary min-heap of scheduled actors, with an index so any actor can be removed in O(log n)
+///
+/// Entries are ordered by time, then by when they were added. That gives the same FIFO order
+/// for messages on the same cycle as the linked list.
+///
+/// Most of the time, the actor that was just taken is added straight back. So `take` leaves it
+/// at the root, where `push` can update it in place, and it's only removed properly by the next
+/// `take`.
+pub(crate) struct ActorHeap<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    heap: Vec<HeapEntry<ActorNames>>,
+    position: EnumMap<Option<u32>, ActorNames>,
+    /// The root was taken, but hasn't been removed yet
+    taken: Option<ActorNames>,
+    next_seq: u64,
+}
+
+#[derive(Clone, Copy)]
+struct HeapEntry<ActorNames> {
+    time: Time,
+    seq: u64,
+    id: ActorNames,
+}
+
+impl<ActorNames> HeapEntry<ActorNames> {
+    #[inline(always)]
+    fn before(&self, other: &Self) -> bool {
+        (self.time, self.seq) < (other.time, other.seq)
+    }
+}
+
+impl<ActorNames> ActorHeap<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub fn new() -> Self {
+        ActorHeap {
+            heap: Vec::with_capacity(ActorNames::COUNT),
+            position: EnumMap::from_fn(|_| None),
+            taken: None,
+            next_seq: 0,
+        }
+    }
+
+    pub fn contains(&self, id: ActorNames) -> bool {
+        self.position[id].is_some() && self.taken != Some(id)
+    }
+
+    /// The entry `take` would return next
+    pub fn peek(&self) -> Option<(ActorNames, Time)> {
+        self.peek_entry().map(|entry| (entry.id, entry.time))
+    }
+
+    /// Like `peek`, but also returns the order the entry was added in
+    pub fn peek_seq(&self) -> Option<(ActorNames, Time, u64)> {
+        self.peek_entry().map(|entry| (entry.id, entry.time, entry.seq))
+    }
+
+    /// The order the next pushed entry will get
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    fn peek_entry(&self) -> Option<&HeapEntry<ActorNames>> {
+        match self.taken {
+            None => self.heap.first(),
+            // The root is on its way out, so the next entry is one of its children
+            Some(_) => match (self.heap.get(1), self.heap.get(2)) {
+                (Some(left), Some(right)) if right.before(left) => Some(right),
+                (left, _) => left,
+            },
+        }
+    }
+
+    /// Returns the number of levels the entry moved
+    pub fn push(&mut self, id: ActorNames, time: Time) -> u64 {
+        debug_assert!(!self.contains(id), "{:?} is already in the heap", id);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if self.taken == Some(id) {
+            self.taken = None;
+            self.heap[0].time = time;
+            self.heap[0].seq = seq;
+            return self.sift_down(0);
+        }
+
+        // Time never goes backwards, so a taken root is still before anything new and stays put
+        let pos = self.heap.len();
+        self.heap.push(HeapEntry { time, seq, id });
+        debug_assert!(self.taken.is_none() || self.heap[0].before(&self.heap[pos]), "{:?} pushed before the current time", id);
+        self.position[id] = Some(pos as u32);
+        self.sift_up(pos)
+    }
+
+    pub fn take(&mut self) -> Option<(ActorNames, Time)> {
+        self.remove_taken();
+        let top = *self.heap.first()?;
+        self.taken = Some(top.id);
+        Some((top.id, top.time))
+    }
+
+    /// Returns false if the actor wasn't in the heap
+    pub fn remove(&mut self, id: ActorNames) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        match self.position[id] {
+            Some(pos) => {
+                self.remove_at(pos as usize);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Actors in the order they would be taken
+    pub fn ordered(&self) -> Vec<ActorNames> {
+        let mut entries: Vec<_> = self.heap.iter()
+            .filter(|entry| Some(entry.id) != self.taken)
+            .copied()
+            .collect();
+        entries.sort_unstable_by_key(|entry| (entry.time, entry.seq));
+        entries.into_iter().map(|entry| entry.id).collect()
+    }
+
+    /// Every entry (except a taken root), with the order it was added in. Not sorted
+    pub fn entries(&self) -> Vec<(ActorNames, Time, u64)> {
+        self.heap.iter()
+            .filter(|entry| Some(entry.id) != self.taken)
+            .map(|entry| (entry.id, entry.time, entry.seq))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+        self.position = EnumMap::from_fn(|_| None);
+        self.taken = None;
+        self.next_seq = 0;
+    }
+
+    fn remove_taken(&mut self) {
+        if self.taken.take().is_some() {
+            self.remove_at(0);
+        }
+    }
+
+    fn remove_at(&mut self, pos: usize) {
+        let removed = self.heap.swap_remove(pos);
+        self.position[removed.id] = None;
+
+        if pos < self.heap.len() {
+            // The last entry was moved into the hole, it might need to go either way
+            self.position[self.heap[pos].id] = Some(pos as u32);
+            if self.sift_up(pos) == 0 {
+                self.sift_down(pos);
+            }
+        }
+    }
+
+    fn sift_up(&mut self, mut pos: usize) -> u64 {
+        let mut steps = 0;
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if !self.heap[pos].before(&self.heap[parent]) {
+                break;
+            }
+            self.swap(pos, parent);
+            pos = parent;
+            steps += 1;
+        }
+        steps
+    }
+
+    fn sift_down(&mut self, mut pos: usize) -> u64 {
+        let mut steps = 0;
+        loop {
+            let left = pos * 2 + 1;
+            let right = left + 1;
+            let mut min = pos;
+            if left < self.heap.len() && self.heap[left].before(&self.heap[min]) {
+                min = left;
+            }
+            if right < self.heap.len() && self.heap[right].before(&self.heap[min]) {
+                min = right;
+            }
+            if min == pos {
+                return steps;
+            }
+            self.swap(pos, min);
+            pos = min;
+            steps += 1;
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.position[self.heap[a].id] = Some(a as u32);
+        self.position[self.heap[b].id] = Some(b as u32);
+    }
+}
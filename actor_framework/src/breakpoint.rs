@@ -0,0 +1,206 @@
+use std::{any::{Any, TypeId}, fmt::Display};
+
+use crate::{MakeNamed, Time};
+
+/// Stops the scheduler before a matching message is delivered
+///
+/// An empty breakpoint matches every message, each call narrows it down:
+///
+/// ```ignore
+/// Breakpoint::new()
+///     .receiver(N64Actors::PiActor)
+///     .when(|write: &CBusWrite| write.address & 0x3c == 0x0c) // PI_WR_LEN
+/// ```
+pub struct Breakpoint<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    sender: Option<ActorNames>,
+    receiver: Option<ActorNames>,
+    msg_type: Option<TypeId>,
+    start: Time,
+    end: Time,
+    condition: Option<Box<Condition>>,
+}
+
+type Condition = dyn Fn(&dyn Any) -> bool + Send;
+
+impl<ActorNames> Breakpoint<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub fn new() -> Self {
+        Breakpoint {
+            sender: None,
+            receiver: None,
+            msg_type: None,
+            start: Time::default(),
+            end: Time::MAX,
+            condition: None,
+        }
+    }
+
+    pub fn sender(mut self, sender: ActorNames) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    pub fn receiver(mut self, receiver: ActorNames) -> Self {
+        self.receiver = Some(receiver);
+        self
+    }
+
+    pub fn message<Message>(mut self) -> Self
+    where
+        Message: 'static,
+    {
+        self.msg_type = Some(TypeId::of::<Message>());
+        self
+    }
+
+    /// Only match messages of this type where `condition` returns true
+    pub fn when<Message>(mut self, condition: impl Fn(&Message) -> bool + Send + 'static) -> Self
+    where
+        Message: 'static,
+    {
+        self.condition = Some(Box::new(move |msg: &dyn Any| {
+            msg.downcast_ref::<Message>().is_some_and(&condition)
+        }));
+        self.message::<Message>()
+    }
+
+    /// Only match messages delivered in `start..end`
+    pub fn between(mut self, start: Time, end: Time) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    pub(crate) fn matches(&self, sender: ActorNames, receiver: ActorNames, time: Time, msg: &dyn Any) -> bool {
+        self.sender.is_none_or(|s| s == sender)
+            && self.receiver.is_none_or(|r| r == receiver)
+            && self.msg_type.is_none_or(|id| id == msg.type_id())
+            && time >= self.start && time < self.end
+            && self.condition.as_ref().is_none_or(|condition| condition(msg))
+    }
+}
+
+impl<ActorNames> Default for Breakpoint<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BreakpointId(pub(crate) u32);
+
+/// The message a breakpoint stopped in front of
+///
+/// The message is still in the sender's outbox, use `Scheduler::pending_message` to inspect it.
+/// Resuming the scheduler delivers it without triggering the breakpoint again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BreakpointHit<ActorNames> {
+    pub id: BreakpointId,
+    pub sender: ActorNames,
+    pub receiver: ActorNames,
+    pub msg_type: &'static str,
+    pub time: Time,
+}
+
+impl<ActorNames> Display for BreakpointHit<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Breakpoint {} hit: {:?} -> {:?} {} at {}",
+            self.id.0, self.sender, self.receiver, self.msg_type, self.time)
+    }
+}
+
+impl<ActorNames> std::error::Error for BreakpointHit<ActorNames>
+where
+    ActorNames: MakeNamed,
+{}
+
+pub(crate) struct Breakpoints<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    breakpoints: Vec<(BreakpointId, Breakpoint<ActorNames>)>,
+    next_id: u32,
+    /// The message we last stopped in front of, which shouldn't trigger again when resumed, and
+    /// the breakpoint that stopped it
+    resume: Option<(ActorNames, Time, BreakpointId)>,
+}
+
+impl<ActorNames> Breakpoints<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub(crate) fn new() -> Self {
+        Breakpoints {
+            breakpoints: Vec::new(),
+            next_id: 0,
+            resume: None,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub(crate) fn add(&mut self, breakpoint: Breakpoint<ActorNames>) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: BreakpointId) -> bool {
+        // Other breakpoints should still get to stop in front of the message
+        if self.resume.is_some_and(|(_, _, hit)| hit == id) {
+            self.resume = None;
+        }
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|(bp_id, _)| *bp_id != id);
+        len != self.breakpoints.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.resume = None;
+    }
+
+    #[cold]
+    pub(crate) fn check<Message>(&mut self, sender: ActorNames, receiver: ActorNames, time: Time, msg: &Message) -> Option<BreakpointHit<ActorNames>>
+    where
+        Message: 'static,
+    {
+        // The sender is queued again after stopping, so other messages for the same time can be
+        // checked before we get back to it
+        match self.resume {
+            Some((s, t, _)) if (s, t) == (sender, time) => {
+                self.resume = None;
+                return None;
+            }
+            Some((_, t, _)) if time > t => self.resume = None,
+            _ => {}
+        }
+
+        let (id, _) = self.breakpoints.iter()
+            .find(|(_, bp)| bp.matches(sender, receiver, time, msg))?;
+
+        self.resume = Some((sender, time, *id));
+        Some(BreakpointHit {
+            id: *id,
+            sender,
+            receiver,
+            msg_type: std::any::type_name::<Message>(),
+            time,
+        })
+    }
+}
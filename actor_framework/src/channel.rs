@@ -1,6 +1,8 @@
 use std::marker::PhantomData;
 
-use crate::{MakeNamed, Actor, Handler};
+use anyhow::bail;
+
+use crate::{MakeNamed, Actor, Handler, OutboxSend, RestoreState, SaveState, StateReader, StateWriter};
 
 /// Allows registering a channel between a Sender and Receiver for a given Message type.
 ///
@@ -13,6 +15,8 @@ pub struct Channel<ActorNames, Sender, Message>
         Sender: Actor<ActorNames>,
 {
     pub(super) execute_fn: crate::scheduler::ExecuteFn<ActorNames>,
+    /// Only set for channels restored from a save state, which deliver through an endpoint
+    pub(super) endpoint_fn: Option<crate::scheduler::EndpointFn<ActorNames, Message>>,
     receiver: ActorNames,
     message_type: PhantomData<Message>,
     sender: PhantomData<Sender>,
@@ -33,6 +37,7 @@ where
             // Safety: It is essential that we instantiate the correct execute_fn
             //         template here. It relies on this function for type checking
             execute_fn: crate::scheduler::direct_execute::<ActorNames, Sender, Receiver, Message>,
+            endpoint_fn: None,
             receiver: Receiver::name(),
             message_type: PhantomData,
             sender: PhantomData,
@@ -51,9 +56,56 @@ impl<ActorNames, Sender, Message> Clone for Channel<ActorNames, Sender, Message>
     fn clone(&self) -> Self {
         Channel {
             execute_fn: self.execute_fn,
+            endpoint_fn: self.endpoint_fn,
             receiver: self.receiver,
             message_type: PhantomData,
             sender: PhantomData,
         }
     }
-}
\ No newline at end of file
+}
+// Like `MessagePacket`, only the receiver is saved
+impl<ActorNames, Sender, Message> SaveState for Channel<ActorNames, Sender, Message>
+    where ActorNames: MakeNamed,
+        Sender: Actor<ActorNames>,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        debug_assert!(ActorNames::endpoint::<Message>(self.receiver).is_some(),
+            "{:?} needs to list {} in handles(...) to be saved", self.receiver, std::any::type_name::<Message>());
+        let receiver: usize = self.receiver.into();
+        receiver.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<ActorNames, Sender, Message> RestoreState for Channel<ActorNames, Sender, Message>
+    where ActorNames: MakeNamed,
+        Sender: Actor<ActorNames>,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let receiver = usize::restore_state(state)?;
+        if receiver >= ActorNames::COUNT {
+            bail!("Invalid channel receiver {} in save state", receiver);
+        }
+        let receiver: ActorNames = receiver.into();
+        let Some(endpoint) = ActorNames::endpoint::<Message>(receiver) else {
+            bail!("{:?} doesn't handle {}", receiver, std::any::type_name::<Message>());
+        };
+
+        Ok(Channel {
+            // MessagePacket::from_channel copies endpoint_fn into the packet, for endpoint_execute
+            execute_fn: crate::scheduler::endpoint_execute::<ActorNames, Sender, Message>,
+            endpoint_fn: Some(endpoint.endpoint_fn),
+            receiver,
+            message_type: PhantomData,
+            sender: PhantomData,
+        })
+    }
+}
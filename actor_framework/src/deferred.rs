@@ -0,0 +1,162 @@
+use std::marker::PhantomData;
+
+use crate::{MakeNamed, Outbox, OutboxSend, RestoreState, SaveState, SchedulerResult, StateReader, StateWriter, Time};
+
+/// Extra outbox slots for an actor that needs more than one message in flight
+///
+/// The outbox always holds the earliest message; everything else waits here, sorted by time.
+/// When the outbox empties, the scheduler moves the next deferred message into it. Messages sent
+/// for the same time are delivered in the order they were sent.
+///
+/// To opt in, an actor keeps a `Deferred` and returns it from `Actor::deferred`. Once it has,
+/// all sends should go through `Deferred::send`; sending directly will still panic if the outbox
+/// is full.
+pub struct Deferred<ActorNames, O> {
+    /// Each entry is an outbox holding a single message. Kept in reverse order, so the next
+    /// message is at the end
+    queue: Vec<O>,
+    names: PhantomData<ActorNames>,
+}
+
+impl<ActorNames, O> Deferred<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    pub fn new() -> Self {
+        Deferred {
+            queue: Vec::new(),
+            names: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Send a message, whether or not the outbox is already full
+    ///
+    /// `send_fn` is given an empty outbox to send into, so existing helpers that take an outbox
+    /// can be used: `deferred.send(outbox, |o| o.send::<CpuActor>(msg, time))`
+    pub fn send<F>(&mut self, outbox: &mut O, send_fn: F) -> SchedulerResult
+    where
+        F: FnOnce(&mut O) -> SchedulerResult,
+    {
+        self.settle(outbox);
+
+        let mut slot = O::default();
+        let result = send_fn(&mut slot);
+
+        if slot.time() < outbox.time() {
+            std::mem::swap(outbox, &mut slot);
+            // The displaced message was sent before anything queued for the same time
+            if slot.time() != Time::MAX {
+                self.push_first(slot);
+            }
+        } else if slot.time() != Time::MAX {
+            self.push(slot);
+        }
+
+        result
+    }
+
+    /// Cancel the earliest message of this type, wherever it is
+    pub fn try_cancel<Message>(&mut self, outbox: &mut O) -> Option<(Time, Message)>
+    where
+        O: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        if let Some(cancelled) = outbox.try_cancel::<Message>() {
+            self.settle(outbox);
+            return Some(cancelled);
+        }
+
+        let (idx, cancelled) = self.queue.iter_mut()
+            .enumerate()
+            .rev()
+            .find_map(|(idx, slot)| slot.try_cancel::<Message>().map(|c| (idx, c)))?;
+        self.queue.remove(idx);
+        Some(cancelled)
+    }
+
+    fn push(&mut self, slot: O) {
+        // Insert before any existing messages for the same time, so they are delivered first
+        let time = slot.time();
+        let idx = self.queue.partition_point(|queued| queued.time() > time);
+        self.queue.insert(idx, slot);
+    }
+
+    fn push_first(&mut self, slot: O) {
+        // Insert after any existing messages for the same time, so it's delivered before them
+        let time = slot.time();
+        let idx = self.queue.partition_point(|queued| queued.time() >= time);
+        self.queue.insert(idx, slot);
+    }
+}
+
+impl<ActorNames, O> Deferred<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames>,
+{
+    /// Move the next message into the outbox if it's empty
+    #[inline(always)]
+    pub(crate) fn settle(&mut self, outbox: &mut O) {
+        if outbox.time() == Time::MAX {
+            if let Some(next) = self.queue.pop() {
+                *outbox = next;
+            }
+        }
+    }
+}
+
+impl<ActorNames, O> Default for Deferred<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ActorNames, O> SaveState for Deferred<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.queue.len().save_state(state);
+        for slot in &self.queue {
+            slot.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        let len = usize::restore_state(state)?;
+        self.queue = (0..len)
+            .map(|_| {
+                let mut slot = O::default();
+                slot.load_state(state)?;
+                Ok(slot)
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        Ok(())
+    }
+}
+
+impl<ActorNames, O> RestoreState for Deferred<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let mut deferred = Self::new();
+        deferred.load_state(state)?;
+        Ok(deferred)
+    }
+}
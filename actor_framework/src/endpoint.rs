@@ -1,4 +1,6 @@
-use crate::{MakeNamed, Handler, Actor};
+use anyhow::bail;
+
+use crate::{MakeNamed, Handler, Actor, RestoreState, SaveState, StateReader, StateWriter};
 
 /// An Endpoint is half of a `Channel`.
 /// The Receiver and Message type is known at compile time but the Sender is dynamically dispatched.
@@ -15,6 +17,7 @@ pub struct Endpoint<ActorNames, Message>
         Message: 'static
 {
     pub(super) endpoint_fn: crate::scheduler::EndpointFn<ActorNames, Message>,
+    receiver: ActorNames,
 }
 
 impl<ActorNames, Message> Endpoint<ActorNames, Message>
@@ -27,8 +30,13 @@ where
     {
         Endpoint {
             endpoint_fn: crate::scheduler::receive_for_endpoint::<ActorNames, Receiver, Message>,
+            receiver: Receiver::name(),
         }
     }
+
+    pub fn receiver(&self) -> ActorNames {
+        self.receiver
+    }
 }
 
 impl<ActorNames, Message> Clone for Endpoint<ActorNames, Message>
@@ -37,6 +45,40 @@ impl<ActorNames, Message> Clone for Endpoint<ActorNames, Message>
     fn clone(&self) -> Self {
         Endpoint {
             endpoint_fn: self.endpoint_fn,
+            receiver: self.receiver,
+        }
+    }
+}
+
+// Only the receiver is saved, `MakeNamed::endpoint` rebuilds the rest
+impl<ActorNames, Message> SaveState for Endpoint<ActorNames, Message>
+    where ActorNames: MakeNamed,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        debug_assert!(ActorNames::endpoint::<Message>(self.receiver).is_some(),
+            "{:?} needs to list {} in handles(...) to be saved", self.receiver, std::any::type_name::<Message>());
+        let receiver: usize = self.receiver.into();
+        receiver.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<ActorNames, Message> RestoreState for Endpoint<ActorNames, Message>
+    where ActorNames: MakeNamed,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let receiver = usize::restore_state(state)?;
+        if receiver >= ActorNames::COUNT {
+            bail!("Invalid endpoint receiver {} in save state", receiver);
+        }
+        let receiver: ActorNames = receiver.into();
+        match ActorNames::endpoint::<Message>(receiver) {
+            Some(endpoint) => Ok(endpoint),
+            None => bail!("{:?} doesn't handle {}", receiver, std::any::type_name::<Message>()),
         }
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::Debug;
+
+use crate::{timers, Actor, ActorInit, Handler, MakeNamed, Outbox, OutboxSend, SchedulerResult, Time};
+
+/// Runs a single actor without a scheduler, for unit tests
+///
+/// Messages are delivered straight to the actor's handlers, and whatever it sends stays in its
+/// outbox until the test takes it:
+///
+/// ```ignore
+/// let mut si = ActorHarness::<N64Actors, SiActor>::new(SiActor::default());
+/// si.deliver(CBusRead { address: 0x1fc0_07fc }, Time::from(100), Time::MAX);
+/// let request = si.expect::<BusRequest>(N64Actors::BusActor, Time::from(100));
+/// ```
+pub struct ActorHarness<ActorNames, A>
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+{
+    pub actor: A,
+    pub outbox: A::OutboxType,
+}
+
+/// A message the actor sent
+#[derive(Debug)]
+pub struct Sent<ActorNames, Message> {
+    pub receiver: ActorNames,
+    pub time: Time,
+    pub message: Message,
+}
+
+impl<ActorNames, A> ActorHarness<ActorNames, A>
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+    A::OutboxType: Default,
+{
+    pub fn new(actor: A) -> Self {
+        let mut harness = ActorHarness {
+            actor,
+            outbox: Default::default(),
+        };
+        harness.settle();
+        harness
+    }
+
+    /// Create the actor with `ActorInit`, any message sent during init stays in the outbox
+    pub fn init(config: &ActorNames::Config, time: Time) -> Result<Self, anyhow::Error>
+    where
+        A: ActorInit<ActorNames>,
+    {
+        let mut outbox = Default::default();
+        let actor = A::init(config, &mut outbox, time)?;
+        let mut harness = ActorHarness { actor, outbox };
+        harness.settle();
+        Ok(harness)
+    }
+
+    /// Call the actor's handler for `message`, as if it was delivered at `time`
+    pub fn deliver<Message>(&mut self, message: Message, time: Time, limit: Time) -> SchedulerResult
+    where
+        A: Handler<ActorNames, Message>,
+    {
+        timers::unsettle(&mut self.actor, &mut self.outbox);
+        let result = self.actor.recv(&mut self.outbox, message, time, limit);
+        self.settle();
+        result
+    }
+
+    /// Time of the pending message, `Time::MAX` if there isn't one
+    pub fn pending_time(&self) -> Time {
+        self.outbox.time()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outbox.time() == Time::MAX
+    }
+
+    /// Take the pending message if it's a `Message`
+    ///
+    /// Like the scheduler, this calls the actor's `delivering` hook afterwards, which might send
+    /// another message.
+    pub fn take<Message>(&mut self) -> Option<Sent<ActorNames, Message>>
+    where
+        A::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        let packet = self.outbox.as_packet()?;
+        let receiver = packet.receiver();
+        let (time, message) = packet.take()?;
+
+        if let Some(timers) = self.actor.timers() {
+            timers.delivered(&mut self.outbox, &message, time);
+        }
+        self.actor.delivering(&mut self.outbox, &message, time);
+        self.settle();
+
+        Some(Sent { receiver, time, message })
+    }
+
+    /// Take the pending message, panicking unless it's a `Message` sent to `receiver` at `time`
+    #[track_caller]
+    pub fn expect<Message>(&mut self, receiver: ActorNames, time: Time) -> Message
+    where
+        A::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        let pending = self.pending_time();
+        let Some(sent) = self.take::<Message>() else {
+            panic!("Expected {} to {:?} at {}, but outbox {}", std::any::type_name::<Message>(), receiver, time,
+                if pending == Time::MAX { "is empty".to_string() } else { format!("has a different message at {}", pending) });
+        };
+        assert_eq!((sent.receiver, sent.time), (receiver, time), "{} sent to the wrong actor or time", std::any::type_name::<Message>());
+        sent.message
+    }
+
+    fn settle(&mut self) {
+        timers::settle(&mut self.actor, &mut self.outbox);
+    }
+}
+
+impl<ActorNames, A> Debug for ActorHarness<ActorNames, A>
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ActorHarness<{:?}> pending at {}", A::name(), self.outbox.time())
+    }
+}
@@ -1,39 +1,75 @@
 mod actor_box;
+mod actor_heap;
 mod addr;
+mod breakpoint;
 mod channel;
+mod deferred;
 mod endpoint;
 mod enum_map;
+mod harness;
 mod message_packet;
 mod named;
 mod object_map;
 mod outbox;
+mod parallel;
+mod profiler;
+mod resource;
+mod save_state;
 mod scheduler;
+mod stats;
 mod time;
-mod time_queue;
+mod timers;
+mod trace;
+mod zero_limit;
 
-use std::sync::mpsc;
+use std::{path::Path, sync::mpsc};
 
 pub use actor_box::{ActorBox, ActorBoxBase, AsBase};
 pub use addr::Addr;
+pub use breakpoint::{Breakpoint, BreakpointHit, BreakpointId};
 pub use channel::Channel;
-use common::{ControlMessage, UpdateMessage};
+pub use deferred::Deferred;
+use common::{input::PadState, pacing::{Pacer, Speed}, ControlMessage, Progress, ResetKind, StopConditions, UpdateMessage};
 pub use endpoint::Endpoint;
 pub use enum_map::EnumMap;
+pub use harness::{ActorHarness, Sent};
 pub use message_packet::{MessagePacket, MessagePacketProxy};
 pub use named::{MakeNamed, Named};
 pub use named_derive::Named;
 pub use outbox::{Outbox, OutboxSend};
-pub use scheduler::{Scheduler, SchedulerResult};
-pub use time::Time;
-pub use time_queue::TimeQueue;
+pub use parallel::LookaheadViolation;
+pub use profiler::ProfiledCall;
+#[cfg(feature = "profiling")]
+pub use profiler::{ProfileEntry, ProfileReport};
+pub use resource::{Acquire, Grant, Preempt, Release, Resource};
+pub use save_state::SAVE_STATE_VERSION;
+pub use common::save_state::{RestoreState, SaveState, StateReader, StateWriter};
+pub use scheduler::{Scheduler, SchedulerBackend, SchedulerResult, StopReason};
+pub use stats::{ActorMessageCounts, SchedulerStats};
+pub use common::stats::{Stats, StatsGroup};
+pub use time::{ClockDomain, Time};
+pub use timers::Timers;
+#[doc(hidden)]
+pub use timers::{RearmClone, RearmFn, RearmOther, RearmProbe};
+pub use trace::{Trace, TraceDivergence, TraceEntry};
+pub use zero_limit::{ZeroLimitDelivery, ZeroLimitMessage, ZeroLimitProblem, ZeroLimitReport};
 
-pub trait Actor<ActorNames>: Named<ActorNames>
+/// Actors must implement `SaveState` so the whole machine can be snapshotted
+pub trait Actor<ActorNames>: Named<ActorNames> + SaveState
 where
     ActorNames: MakeNamed,
     Self::OutboxType: Outbox<ActorNames>,
 {
     type OutboxType;
 
+    /// The minimum number of cycles between the time this actor runs at and any message it sends
+    /// to another actor. Messages to itself don't count.
+    ///
+    /// Only used by the parallel scheduler (see `Scheduler::set_threads`), which can only run
+    /// actors concurrently within a window of the smallest lookahead. Zero means any message
+    /// could affect another actor on the same cycle, so nothing can run in parallel.
+    const LOOKAHEAD: u64 = 0;
+
     /// `delivering` is called just before the scheduler delivers a message
     ///
     /// The message has already been removed from the outbox, allowing the actor to send
@@ -46,6 +82,59 @@ where
         // Default implementation: do nothing
         let _ = (outbox, message, time);
     }
+
+    /// `resolve` is called instead of delivering when this actor's pending message has a lazy time
+    /// and its lower bound is the earliest time in the system.
+    ///
+    /// The actor must make progress, by moving the lower bound later, resolving the time with
+    /// `Outbox::resolve_to` or replacing the message. If it can't because `limit` equals the lower
+    /// bound, it should return `SchedulerResult::ZeroLimit`.
+    #[inline(always)]
+    fn resolve(&mut self, outbox: &mut Self::OutboxType, limit: Time) -> SchedulerResult {
+        // Default implementation: deliver the message at its lower bound
+        let _ = limit;
+        let time = outbox.time().lower_bound();
+        outbox.resolve_to(time);
+        SchedulerResult::Ok
+    }
+
+    /// Extra outbox slots, for actors that need more than one message in flight
+    ///
+    /// After this actor runs, the scheduler moves the next deferred message into the outbox if
+    /// it's empty.
+    #[inline(always)]
+    fn deferred(&mut self) -> Option<&mut Deferred<ActorNames, Self::OutboxType>> {
+        // Default implementation: single slot outbox
+        None
+    }
+
+    /// Timers that deliver messages to this actor without using up its outbox, see `Timers`
+    #[inline(always)]
+    fn timers(&mut self) -> Option<&mut Timers<ActorNames, Self::OutboxType>> {
+        // Default implementation: no timers
+        None
+    }
+
+    /// Add any actor specific counters to `stats`
+    fn stats(&self, stats: &mut StatsGroup) {
+        // Default implementation: no counters
+        let _ = stats;
+    }
+
+    /// Reset the counters reported by `stats`
+    fn reset_stats(&mut self) {
+        // Default implementation: do nothing
+    }
+
+    /// Called by `Instance::soft_reset`, when the machine's reset button is pressed
+    ///
+    /// Nothing can be sent from here, so actors should latch the press and act on it the next time
+    /// they run. Actors that aren't wired to the reset button can ignore it. Return an error if
+    /// the press is ignored, so the frontend can tell it didn't do anything.
+    fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        // Default implementation: do nothing
+        Ok(())
+    }
 }
 
 pub trait ActorInit<ActorNames>: Actor<ActorNames>
@@ -99,12 +188,160 @@ where
         })
     }
 
+    /// Like `new`, but with a specific scheduler backend instead of the one picked by cargo features
+    pub fn with_backend(config: ActorNames::Config, backend: SchedulerBackend) -> Result<Instance<ActorNames>, anyhow::Error> {
+        Ok(Instance {
+            scheduler: Scheduler::<ActorNames>::with_backend(config, backend)?,
+        })
+    }
+
+    /// See `Scheduler::set_threads`
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), anyhow::Error> {
+        self.scheduler.set_threads(threads)
+    }
+
     pub fn actor<ActorType>(&mut self) -> &mut ActorType
     where
         ActorType: Actor<ActorNames>,
     {
         self.scheduler.get::<ActorType>()
     }
+
+    /// Write a snapshot of the entire instance to `path`
+    ///
+    /// Save states are only compatible with the exact build that created them.
+    pub fn save_state(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, self.save_state_bytes())?;
+        Ok(())
+    }
+
+    /// Restore a snapshot created by `save_state`
+    ///
+    /// If an error is returned, the instance is left unchanged.
+    pub fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.load_state_bytes(&std::fs::read(path)?)
+    }
+
+    /// `save_state`, without the file
+    pub fn save_state_bytes(&self) -> Vec<u8> {
+        self.scheduler.save_state()
+    }
+
+    /// `load_state`, without the file
+    pub fn load_state_bytes(&mut self, data: &[u8]) -> Result<(), anyhow::Error> {
+        self.scheduler.load_state(data)
+    }
+
+    /// Start recording every message delivered by the scheduler
+    pub fn record_trace(&mut self) {
+        self.scheduler.record_trace()
+    }
+
+    /// Re-run against a recorded trace. `run` will return a `TraceDivergence` error at the first
+    /// message that differs.
+    pub fn verify_trace(&mut self, trace: Trace<ActorNames>) {
+        self.scheduler.verify_trace(trace)
+    }
+
+    pub fn stop_trace(&mut self) -> Option<Trace<ActorNames>> {
+        self.scheduler.stop_trace()
+    }
+
+    /// Run until every message before `target` has been delivered
+    pub fn run_until(&mut self, target: Time) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        self.scheduler.run_until(target)
+    }
+
+    pub fn run_for(&mut self, cycles: u64) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        self.scheduler.run_for(cycles)
+    }
+
+    pub fn step_message(&mut self) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        self.scheduler.step_message()
+    }
+
+    pub fn now(&self) -> Time {
+        self.scheduler.now()
+    }
+
+    /// Like `common::Instance::run`, but a `ControlMessage::Reset` or `ControlMessage::Shutdown`
+    /// is returned instead of being handled, for cores that need to do more than the framework.
+    /// Pass it to `ControlMessage::reply` to carry it out.
+    pub fn run_until_request(
+        &mut self,
+        control_rx: &mpsc::Receiver<ControlMessage>,
+        update: mpsc::SyncSender<UpdateMessage>,
+    ) -> Result<Option<ControlMessage>, anyhow::Error> {
+        self.scheduler.run(control_rx, update)?;
+        Ok(self.scheduler.take_request())
+    }
+
+    /// See `Scheduler::soft_reset`
+    pub fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        self.scheduler.soft_reset()
+    }
+
+    /// See `Scheduler::power_cycle`
+    pub fn power_cycle(&mut self) -> Result<(), anyhow::Error> {
+        self.scheduler.power_cycle()
+    }
+
+    /// See `Scheduler::set_stop_time`
+    pub fn set_stop_time(&mut self, time: Time) {
+        self.scheduler.set_stop_time(time)
+    }
+
+    /// See `Scheduler::set_frame_receiver`
+    pub fn set_frame_receiver(&mut self, frames: Option<common::video::FrameReceiver>) {
+        self.scheduler.set_frame_receiver(frames)
+    }
+
+    /// See `Scheduler::set_input_latch`
+    pub fn set_input_latch(&mut self, input: Option<common::input::InputLatch>) {
+        self.scheduler.set_input_latch(input)
+    }
+
+    /// See `Scheduler::set_pacer`
+    pub fn set_pacer(&mut self, pacer: Option<Pacer>) {
+        self.scheduler.set_pacer(pacer)
+    }
+
+    /// Stop `run_until`, `run_for` and `step_message` before delivering any matching message
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint<ActorNames>) -> BreakpointId {
+        self.scheduler.add_breakpoint(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.scheduler.remove_breakpoint(id)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.scheduler.clear_breakpoints()
+    }
+
+    /// The breakpoint that stopped the last `run`, if any
+    pub fn last_breakpoint(&self) -> Option<&BreakpointHit<ActorNames>> {
+        self.scheduler.last_breakpoint()
+    }
+
+    /// The message waiting in Sender's outbox, if it's of type Message
+    pub fn pending_message<'a, Sender, Message>(&'a mut self) -> Option<(Time, &'a Message)>
+    where
+        Sender: Actor<ActorNames> + 'a,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message> + 'a,
+        Message: 'static,
+    {
+        self.scheduler.pending_message::<Sender, Message>()
+    }
+
+    pub fn scheduler_stats(&self) -> SchedulerStats<ActorNames> {
+        self.scheduler.stats()
+    }
+
+    #[cfg(feature = "profiling")]
+    pub fn profile_report(&self) -> ProfileReport<ActorNames> {
+        self.scheduler.profile_report()
+    }
 }
 
 impl<ActorNames> common::Instance for Instance<ActorNames>
@@ -116,10 +353,73 @@ where
         control_rx: &mpsc::Receiver<ControlMessage>,
         update: mpsc::SyncSender<UpdateMessage>,
     ) -> Result<(), anyhow::Error> {
-        self.scheduler.run(control_rx, update)
+        while let Some(request) = self.run_until_request(control_rx, update.clone())? {
+            if !request.reply(self, &update)? {
+                break;
+            }
+        }
+        Ok(())
     }
 
     fn as_any(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_speed(&mut self, speed: Speed) {
+        self.scheduler.set_speed(speed)
+    }
+
+    fn speed_ratio(&self) -> Option<f64> {
+        self.scheduler.speed_ratio()
+    }
+
+    /// Only cycles are supported here, cores that know more about their actors can wrap the
+    /// instance to support the rest
+    fn set_stop_conditions(&mut self, stop: StopConditions) -> Result<(), anyhow::Error> {
+        if stop.vsyncs.is_some() || stop.pc.is_some() {
+            anyhow::bail!("Only cycle stop conditions are supported");
+        }
+        self.scheduler.set_stop_time(stop.cycles.map_or(Time::MAX, Time::from));
+        Ok(())
+    }
+
+    fn progress(&mut self) -> Progress {
+        Progress {
+            cycles: self.scheduler.now().lower_bound().into(),
+            ..Progress::default()
+        }
+    }
+
+    fn reset(&mut self, kind: ResetKind) -> Result<(), anyhow::Error> {
+        match kind {
+            ResetKind::Soft => self.soft_reset()?,
+            ResetKind::PowerCycle => self.power_cycle()?,
+        }
+        Ok(())
+    }
+
+    fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        self.scheduler.set_input(port, pad)
+    }
+
+    fn save_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        Instance::save_state(self, path)
+    }
+
+    fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        Instance::load_state(self, path)
+    }
+
+    fn stats(&mut self) -> Stats {
+        self.scheduler.collect_stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.scheduler.reset_stats()
+    }
+
+    #[cfg(feature = "profiling")]
+    fn profile_folded(&mut self) -> Option<String> {
+        Some(self.scheduler.profile_report().folded())
+    }
 }
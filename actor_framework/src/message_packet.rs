@@ -2,7 +2,7 @@
 
 use std::{mem::{MaybeUninit, ManuallyDrop}, any::TypeId};
 
-use crate::{channel::Channel, scheduler, Actor, Endpoint, Handler, MakeNamed, OutboxSend, Time};
+use crate::{channel::Channel, scheduler, Actor, Endpoint, Handler, MakeNamed, OutboxSend, RestoreState, SaveState, StateReader, StateWriter, Time};
 
 #[derive(Debug)]
 #[repr(C)]
@@ -14,6 +14,7 @@ where
     pub time: Time,
     pub(crate) execute_fn: scheduler::ExecuteFn<ActorNames>,
     msg_type: TypeId,
+    receiver: ActorNames,
 }
 
 #[repr(C)]
@@ -25,8 +26,9 @@ where
     pub time: Time,
     pub(crate) execute_fn: scheduler::ExecuteFn<ActorNames>,
     msg_type: std::any::TypeId,
+    receiver: ActorNames,
 
-    pub(crate) endpoint_fn: MaybeUninit<scheduler::EndpointFn<ActorNames, Message>>,
+    pub(crate) endpoint_fn: Option<scheduler::EndpointFn<ActorNames, Message>>,
     data: MaybeUninit<ManuallyDrop<Message>>,
 }
 
@@ -40,6 +42,11 @@ where
     pub fn msg_type(&self) -> TypeId {
         self.msg_type
     }
+
+    /// ActorNames::TERMINAL if the outbox is empty
+    pub fn receiver(&self) -> ActorNames {
+        self.receiver
+    }
 }
 
 impl<ActorNames, Message> MessagePacket<ActorNames, Message>
@@ -55,6 +62,10 @@ where
         self.msg_type
     }
 
+    pub fn receiver(&self) -> ActorNames {
+        self.receiver
+    }
+
     pub fn new<'a, 'b, Sender, Receiver>(time: Time, data: Message) -> Self
     where
         Receiver: Actor<ActorNames> + Handler<ActorNames, Message>,
@@ -67,7 +78,8 @@ where
             //         template here. It relies on this function for type checking
             execute_fn: scheduler::direct_execute::<ActorNames, Sender, Receiver, Message>,
             msg_type: TypeId::of::<Message>(),
-            endpoint_fn: MaybeUninit::uninit(),
+            receiver: Receiver::name(),
+            endpoint_fn: None,
             data: MaybeUninit::new(ManuallyDrop::new(data)),
         }
     }
@@ -83,7 +95,8 @@ where
             //         template here. It relies on this function for type checking
             execute_fn: crate::scheduler::endpoint_execute::<ActorNames, Sender, Message>,
             msg_type: TypeId::of::<Message>(),
-            endpoint_fn: MaybeUninit::new(endpoint.endpoint_fn),
+            receiver: endpoint.receiver(),
+            endpoint_fn: Some(endpoint.endpoint_fn),
             data: MaybeUninit::new(ManuallyDrop::new(data)),
         }
     }
@@ -94,14 +107,25 @@ where
     {
         Self {
             time,
-            // Safety: Channel::new ensures that the execute_fn is correct
+            // Safety: Channel::new and Channel::restore_state ensure execute_fn and endpoint_fn match
             execute_fn: channel.execute_fn,
             msg_type: TypeId::of::<Message>(),
-            endpoint_fn: MaybeUninit::uninit(),
+            receiver: channel.receiver(),
+            endpoint_fn: channel.endpoint_fn,
             data: MaybeUninit::new(ManuallyDrop::new(data)),
         }
     }
 
+    /// Look at the message without taking it
+    pub fn peek(&self) -> Option<&Message> {
+        if self.msg_type != TypeId::of::<Message>() {
+            return None
+        }
+
+        // Safety: Depends on the msg_type being correct
+        Some(unsafe { self.data.assume_init_ref() })
+    }
+
     pub fn take<'b>(&'b mut self) -> Option<(Time, Message)> {
         if self.msg_type != TypeId::of::<Message>() {
             return None
@@ -142,8 +166,49 @@ where
             time: Time::MAX,
             execute_fn: scheduler::null_execute_fn::<ActorNames>(),
             msg_type: TypeId::of::<()>(),
-            endpoint_fn: MaybeUninit::uninit(),
+            receiver: ActorNames::TERMINAL,
+            endpoint_fn: None,
             data: MaybeUninit::new(ManuallyDrop::new(())),
         }
     }
 }
+
+// Only the receiver is saved, not the functions that deliver the message. They are rebuilt from
+// `MakeNamed::endpoint` on load, so a save state can't point them anywhere else.
+impl<ActorNames, Message> MessagePacket<ActorNames, Message>
+where
+    ActorNames: MakeNamed,
+    Message: SaveState + RestoreState + 'static,
+{
+    pub fn save_state(&self, state: &mut StateWriter) {
+        assert!(self.is_some() && self.msg_type == TypeId::of::<Message>());
+        debug_assert!(ActorNames::endpoint::<Message>(self.receiver).is_some(),
+            "{:?} needs to list {} in handles(...) to be saved", self.receiver, std::any::type_name::<Message>());
+
+        self.time.save_state(state);
+        let receiver: usize = self.receiver.into();
+        receiver.save_state(state);
+        // Safety: msg_type is correct, so data is initialized
+        unsafe { self.data.assume_init_ref() }.save_state(state);
+    }
+
+    /// Restores a packet saved by `save_state`, which is always delivered through an endpoint
+    pub fn restore_state<Sender>(state: &mut StateReader) -> Result<Self, anyhow::Error>
+    where
+        Sender: Actor<ActorNames>,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
+    {
+        let time = Time::restore_state(state)?;
+        let receiver = usize::restore_state(state)?;
+        if receiver >= ActorNames::COUNT {
+            anyhow::bail!("Invalid message receiver {} in save state", receiver);
+        }
+        let receiver: ActorNames = receiver.into();
+        let Some(endpoint) = ActorNames::endpoint::<Message>(receiver) else {
+            anyhow::bail!("{:?} doesn't handle {}", receiver, std::any::type_name::<Message>());
+        };
+        let data = Message::restore_state(state)?;
+
+        Ok(Self::from_endpoint::<Sender>(endpoint, time, data))
+    }
+}
@@ -1,8 +1,8 @@
 // TODO: Think of better name for named
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, ptr::NonNull};
 
-use crate::{ActorBox, Actor, AsBase};
+use crate::{ActorBox, ActorBoxBase, Actor, AsBase, Endpoint, StateReader, StateWriter, Stats};
 
 pub trait Named<E> {
     fn name() -> E;
@@ -14,7 +14,7 @@ pub trait Named<E> {
         Self: Actor<E> + Sized;
 }
 
-pub trait MakeNamed : From<usize> + Into<usize> + PartialEq + Copy + 'static + std::fmt::Debug + Send
+pub trait MakeNamed : From<usize> + Into<usize> + PartialEq + Copy + 'static + std::fmt::Debug + Send + Sync
 
 {
     const COUNT: usize;
@@ -38,6 +38,37 @@ pub trait MakeNamed : From<usize> + Into<usize> + PartialEq + Copy + 'static + s
     fn array_from_fn<T>(f: impl FnMut(Self) -> T) -> Self::ArrayType<T> where T: Send;
 
     fn size_of(id: Self) -> usize;
+
+    /// `Actor::LOOKAHEAD` of the actor. u64::MAX for the terminal, which never sends anything
+    fn lookahead(id: Self) -> u64;
+
+    fn save_storage(storage: &Self::StorageType, state: &mut StateWriter);
+    fn load_storage(storage: &mut Self::StorageType, state: &mut StateReader) -> Result<(), anyhow::Error>;
+
+    fn stats_storage(storage: &Self::StorageType, stats: &mut Stats);
+    fn reset_stats_storage(storage: &mut Self::StorageType);
+    /// Every actor sees the press, the first error is returned
+    fn soft_reset_storage(storage: &mut Self::StorageType) -> Result<(), anyhow::Error>;
+
+    /// Type name of the message waiting in the actor's outbox, see `Outbox::msg_type_name`
+    fn msg_type_name(storage: &Self::StorageType, id: Self) -> &'static str;
+
+    /// An `Endpoint` to `receiver` for `Message`, if its class lists the message in `handles(...)`.
+    ///
+    /// Save states only store the receiver of pending messages, this rebuilds the function that
+    /// delivers them.
+    fn endpoint<Message>(receiver: Self) -> Option<Endpoint<Self, Message>>
+    where
+        Message: 'static;
+
+    /// Pointer to the actor's `ActorBox`, for handing actors to parallel workers
+    fn actor_ptr(storage: &mut Self::StorageType, id: Self) -> NonNull<ActorBoxBase<Self>>;
+
+    /// `msg_type_name` for an actor pointer from `actor_ptr`
+    ///
+    /// # Safety
+    /// `actor` must be `id`'s pointer, and nothing else can be using the actor
+    unsafe fn msg_type_name_at(actor: NonNull<ActorBoxBase<Self>>, id: Self) -> &'static str;
 }
 
 pub struct NamedIterator<E> {
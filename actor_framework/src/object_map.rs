@@ -1,10 +1,21 @@
-use crate::{MakeNamed, Named, Actor, ActorBox, Outbox, ActorBoxBase, actor_box::AsBase};
+use std::ptr::NonNull;
+
+use crate::{MakeNamed, Named, Actor, ActorBox, Outbox, ActorBoxBase, actor_box::AsBase, StateReader, StateWriter, Stats};
 
 pub struct ObjectStore<E>
     where
         E: MakeNamed,
 {
-    storage: E::StorageType,
+    storage: Storage<E>,
+}
+
+enum Storage<E>
+    where
+        E: MakeNamed,
+{
+    Owned(E::StorageType),
+    /// A parallel worker's store, which can only reach the actors in its group
+    Group(ActorGroup<E>),
 }
 
 impl<E> ObjectStore<E> where
@@ -13,10 +24,44 @@ impl<E> ObjectStore<E> where
     pub fn with(config: &E::Config) -> Result<ObjectStore<E>, anyhow::Error>
     {
         Ok(Self {
-            storage: E::storage_with(config)?
+            storage: Storage::Owned(E::storage_with(config)?),
         })
     }
 
+    /// A store for a parallel worker, which only has the actors in `group`
+    pub(crate) fn from_group(group: ActorGroup<E>) -> ObjectStore<E> {
+        Self {
+            storage: Storage::Group(group),
+        }
+    }
+
+    /// Hands out the actors in each group, so they can be used from other threads
+    ///
+    /// Panics if an actor is in more than one group. The pointers are only valid until this store
+    /// is next used, so the caller must wait for every group to be dropped before then.
+    pub(crate) fn split(&mut self, groups: &[Vec<E>]) -> Vec<ActorGroup<E>> {
+        let storage = self.owned_mut();
+        let mut taken = vec![false; E::COUNT];
+        groups.iter().map(|group| {
+            let mut actors = vec![None; E::COUNT];
+            for &id in group {
+                let index: usize = id.into();
+                assert!(!taken[index], "{:?} is in more than one group", id);
+                taken[index] = true;
+                actors[index] = Some(E::actor_ptr(storage, id));
+            }
+            ActorGroup { actors }
+        }).collect()
+    }
+
+    /// False for actors in another worker's group
+    pub(crate) fn contains(&self, id: E) -> bool {
+        match &self.storage {
+            Storage::Owned(_) => true,
+            Storage::Group(group) => group.actors[id.into()].is_some(),
+        }
+    }
+
     #[inline(always)]
     pub fn get<'a, 'b, U>(&'a mut self) -> &'b mut ActorBox<E, U>
     where
@@ -24,7 +69,12 @@ impl<E> ObjectStore<E> where
         <U as Actor<E>>::OutboxType: Outbox<E>,
         'a: 'b,
     {
-        U::from_storage(&mut self.storage)
+        match &mut self.storage {
+            Storage::Owned(storage) => U::from_storage(storage),
+            // Safety: The pointer came from `U::name()`'s ActorBox, and nothing else can use it
+            //         while this store has it
+            Storage::Group(group) => unsafe { group.get(U::name()).cast().as_mut() },
+        }
     }
 
     #[inline(always)]
@@ -45,7 +95,84 @@ impl<E> ObjectStore<E> where
     where
         E::StorageType: AsBase<E>,
     {
-        self.storage.as_base(id)
+        match &self.storage {
+            Storage::Owned(storage) => storage.as_base(id),
+            // Safety: See `get`
+            Storage::Group(group) => unsafe { group.get(id).as_ref() },
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        E::save_storage(self.owned(), state)
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        E::load_storage(self.owned_mut(), state)
+    }
+
+    pub fn stats(&self, stats: &mut Stats) {
+        E::stats_storage(self.owned(), stats)
+    }
+
+    pub fn reset_stats(&mut self) {
+        E::reset_stats_storage(self.owned_mut())
+    }
+
+    pub fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        E::soft_reset_storage(self.owned_mut())
+    }
+
+    pub fn msg_type_name(&self, id: E) -> &'static str {
+        match &self.storage {
+            Storage::Owned(storage) => E::msg_type_name(storage, id),
+            // Safety: See `get`
+            Storage::Group(group) => unsafe { E::msg_type_name_at(group.get(id), id) },
+        }
+    }
+
+    /// Everything that works on all actors at once is only done by the main scheduler
+    fn owned(&self) -> &E::StorageType {
+        match &self.storage {
+            Storage::Owned(storage) => storage,
+            Storage::Group(_) => panic!("A parallel worker only has its own group of actors"),
+        }
+    }
+
+    fn owned_mut(&mut self) -> &mut E::StorageType {
+        match &mut self.storage {
+            Storage::Owned(storage) => storage,
+            Storage::Group(_) => panic!("A parallel worker only has its own group of actors"),
+        }
+    }
+}
+
+/// The actors a parallel worker runs for one window, from `ObjectStore::split`
+pub(crate) struct ActorGroup<E>
+    where
+        E: MakeNamed,
+{
+    /// Indexed by actor. None for actors in other groups. Not an EnumMap, NonNull isn't Send
+    actors: Vec<Option<NonNull<ActorBoxBase<E>>>>,
+}
+
+// Safety: Groups are only made by `ObjectStore::split`, which never puts an actor in more than one
+//         group, so no two threads can reach the same actor
+unsafe impl<E> Send for ActorGroup<E> where E: MakeNamed {}
+
+impl<E> ActorGroup<E> where
+    E: MakeNamed,
+{
+    /// A group with no actors, for workers between windows
+    pub(crate) fn empty() -> Self {
+        ActorGroup { actors: vec![None; E::COUNT] }
+    }
+
+    #[inline(always)]
+    fn get(&self, id: E) -> NonNull<ActorBoxBase<E>> {
+        match self.actors[id.into()] {
+            Some(actor) => actor,
+            None => panic!("{:?} is in another worker's group", id),
+        }
     }
 }
 
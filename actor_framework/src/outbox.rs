@@ -1,7 +1,7 @@
-use crate::{MakeNamed, Handler, Actor, Time, Endpoint, MessagePacket, channel::Channel, SchedulerResult};
+use crate::{MakeNamed, Handler, Actor, Time, Endpoint, MessagePacket, channel::Channel, SchedulerResult, SaveState, timers::RearmFn};
 
 
-pub trait Outbox<ActorNames>
+pub trait Outbox<ActorNames>: SaveState
 where
     ActorNames: MakeNamed,
 {
@@ -13,6 +13,19 @@ where
         Message: 'static;
     fn stash(&mut self, other: &mut Self);
     fn restore(&mut self, other: &mut Self);
+
+    /// Move the pending message, which must have a lazy time, to a new time.
+    /// The new time can be lazy again, but it can't be earlier than the current lower bound.
+    fn resolve_to(&mut self, time: Time);
+
+    /// Type name of the pending message, or "Empty"
+    fn msg_type_name(&self) -> &'static str;
+
+    /// How `Timers::every` re-arms the message with this type name, for loading save states
+    #[doc(hidden)]
+    fn rearm_fn(msg_type_name: &str) -> Option<RearmFn<Self>>
+    where
+        Self: Sized;
 }
 
 pub trait OutboxSend<ActorNames, Message>
@@ -28,7 +41,7 @@ where
         Sender: Actor<ActorNames>,
         Self: Outbox<ActorNames, Sender=Sender>,
     ;
-    fn send_endpoint(&mut self, endpoint: Endpoint<ActorNames, Message>, message: Message, time: Time);
+    fn send_endpoint(&mut self, endpoint: Endpoint<ActorNames, Message>, message: Message, time: Time) -> SchedulerResult;
     fn cancel(&mut self) -> (Time, Message);
     fn as_packet<'a>(&'a mut self) -> Option<&'a mut MessagePacket<ActorNames, Message>>;
 }
@@ -124,6 +137,53 @@ macro_rules! make_outbox {
                 assert!(self.is_empty());
                 *self = core::mem::take(other);
             }
+
+            #[inline(always)]
+            fn resolve_to(&mut self, time: actor_framework::Time) {
+                debug_assert!(self.time().is_lazy(), "{} isn't lazy", self.msg_type_name());
+                debug_assert!(time.lower_bound() >= self.time().lower_bound(), "Lazy time can't move backwards");
+                unsafe { (*self.none).time = time };
+            }
+
+            fn msg_type_name(&self) -> &'static str {
+                $name::msg_type_name(self)
+            }
+
+            fn rearm_fn(msg_type_name: &str) -> Option<actor_framework::RearmFn<Self>> {
+                #[allow(unused_imports)]
+                use actor_framework::{RearmClone, RearmOther};
+
+                $(if msg_type_name == std::any::type_name::<$field_type>() {
+                    return (&actor_framework::RearmProbe::<$name_type, Self, $field_type>::new()).rearm_fn();
+                })*
+                None
+            }
+        }
+
+        impl actor_framework::SaveState for $name {
+            fn save_state(&self, state: &mut actor_framework::StateWriter) {
+                // Messages are tagged by type name, rather than field order
+                state.write_str(self.msg_type_name());
+                let msg_type = self.msg_type();
+                $(if msg_type == std::any::TypeId::of::<$field_type>() {
+                    unsafe { &*self.$field_ident }.save_state(state);
+                })*
+            }
+
+            fn load_state(&mut self, state: &mut actor_framework::StateReader) -> Result<(), anyhow::Error> {
+                use actor_framework::RestoreState;
+
+                *self = Default::default();
+                let msg_type_name = String::restore_state(state)?;
+                if msg_type_name == "Empty" {
+                    return Ok(());
+                }
+                $(else if msg_type_name == std::any::type_name::<$field_type>() {
+                    self.$field_ident = core::mem::ManuallyDrop::new(actor_framework::MessagePacket::restore_state::<$sender>(state)?);
+                    return Ok(());
+                })*
+                anyhow::bail!("{} can't contain {}", std::any::type_name::<Self>(), msg_type_name)
+            }
         }
 
         impl core::default::Default for $name {
@@ -167,7 +227,7 @@ macro_rules! make_outbox {
                 }
 
                 #[inline(always)]
-                fn send_endpoint(&mut self, endpoint: actor_framework::Endpoint<$name_type, $field_type>, message: $field_type, time: actor_framework::Time)
+                fn send_endpoint(&mut self, endpoint: actor_framework::Endpoint<$name_type, $field_type>, message: $field_type, time: actor_framework::Time) -> SchedulerResult
                 {
                     assert!(self.is_empty(), "Sending {}, but {} already contains {}", std::any::type_name::<$field_type>(), std::any::type_name::<Self>(), self.msg_type_name());
 
@@ -175,6 +235,7 @@ macro_rules! make_outbox {
                             actor_framework::MessagePacket::from_endpoint::<
                                 <Self as actor_framework::Outbox<$name_type>>::Sender>
                         (endpoint, time, message));
+                    SchedulerResult::Ok
                 }
 
                 #[inline(always)]
@@ -0,0 +1,294 @@
+use std::{fmt::Display, sync::mpsc, thread::JoinHandle};
+
+use crate::{object_map::ActorGroup, profiler::Profiler, stats::MessageCounters, EnumMap, MakeNamed, Time};
+
+// Conservative parallel execution, used when `Scheduler::set_threads` is more than one.
+//
+// The scheduler runs in windows. If the earliest pending message is at `start`, nothing an actor
+// sends while running can reach another actor before `start + lookahead`, where lookahead is the
+// smallest `Actor::LOOKAHEAD`. So within that window, actors can only affect each other through
+// messages that are already pending. Actors linked by those messages are grouped together, and
+// each group runs on its own worker thread with a private queue.
+//
+// To be identical to a single threaded run, the queue order of messages sent for the same cycle
+// has to be the same too. Each worker logs the queue entries it takes, and afterwards `merge`
+// replays those logs in the order a single queue would have taken them, which gives every new
+// queue entry the same order it would have had.
+//
+// An actor that sends sooner than its LOOKAHEAD breaks all of this. Workers can only reach their
+// own group's actors, so they check every receiver and stop with a `LookaheadViolation`.
+
+/// Everything a worker needs to run one window
+pub(crate) struct WindowJob<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    /// The group's queued actors, in the order they would be taken
+    pub queued: Vec<(ActorNames, Time)>,
+    /// Deliver every message before this time
+    pub end: Time,
+    pub actors: ActorGroup<ActorNames>,
+}
+
+/// A message sent to another worker's group before the end of the window
+///
+/// The sender's `Actor::LOOKAHEAD` is larger than how far ahead it actually sends. The message is
+/// still in the sender's outbox.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookaheadViolation<ActorNames> {
+    pub sender: ActorNames,
+    pub receiver: ActorNames,
+    pub msg_type: &'static str,
+    pub time: Time,
+    pub window_end: Time,
+}
+
+impl<ActorNames> Display for LookaheadViolation<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} -> {:?} {} at {} is before the parallel window ends at {}, {:?}'s LOOKAHEAD is too large",
+            self.sender, self.receiver, self.msg_type, self.time, self.window_end, self.sender)
+    }
+}
+
+impl<ActorNames> std::error::Error for LookaheadViolation<ActorNames>
+where
+    ActorNames: MakeNamed,
+{}
+
+/// A single queue entry taken by a worker
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WindowStep {
+    pub time: Time,
+    /// The worker's order for the entry
+    pub seq: u64,
+    /// Entries added while running this step have an order before this
+    pub seq_end: u64,
+}
+
+pub(crate) struct WindowResult<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub steps: Vec<WindowStep>,
+    /// The worker's queue at the end of the window, with the worker's order for each entry
+    pub queued: Vec<(ActorNames, Time, u64)>,
+    /// Returned by the last step, which stopped the worker early
+    pub error: Option<anyhow::Error>,
+    pub runs: u64,
+    pub zero_limits: u64,
+    pub messages: MessageCounters<ActorNames>,
+    pub profiler: Profiler<ActorNames>,
+}
+
+/// The queue after a window, put back into a single order
+pub(crate) struct Merged<ActorNames> {
+    /// Every entry from the workers' queues, with a global order. Not sorted
+    pub queued: Vec<(ActorNames, Time, u64)>,
+    /// Time of the last entry taken
+    pub last_time: Option<Time>,
+    /// The first error, in the order entries were taken
+    pub error: Option<anyhow::Error>,
+}
+
+/// Splits the actors linked by `links` (sender, receiver) into at most `max_groups` groups,
+/// so no link crosses between groups. Actors that aren't linked to anything are left out.
+pub(crate) fn group<ActorNames>(links: &[(ActorNames, ActorNames)], max_groups: usize) -> Vec<Vec<ActorNames>>
+where
+    ActorNames: MakeNamed,
+{
+    let mut parent: EnumMap<ActorNames, ActorNames> = EnumMap::from_fn(|id| id);
+    let mut linked: EnumMap<bool, ActorNames> = EnumMap::new();
+
+    fn find<ActorNames: MakeNamed>(parent: &mut EnumMap<ActorNames, ActorNames>, mut id: ActorNames) -> ActorNames {
+        while parent[id] != id {
+            parent[id] = parent[parent[id]];
+            id = parent[id];
+        }
+        id
+    }
+
+    for &(sender, receiver) in links {
+        linked[sender] = true;
+        linked[receiver] = true;
+        let (a, b) = (find(&mut parent, sender), find(&mut parent, receiver));
+        if a != b {
+            parent[a] = b;
+        }
+    }
+
+    // Collect each cluster, weighted by how many messages it has to deliver
+    let mut clusters: Vec<(usize, Vec<ActorNames>)> = Vec::new();
+    let mut cluster_of: EnumMap<Option<usize>, ActorNames> = EnumMap::new();
+    for id in ActorNames::iter().filter(|&id| linked[id]) {
+        let root = find(&mut parent, id);
+        let index = *cluster_of[root].get_or_insert_with(|| {
+            clusters.push((0, Vec::new()));
+            clusters.len() - 1
+        });
+        clusters[index].1.push(id);
+    }
+    for &(sender, _) in links {
+        let root = find(&mut parent, sender);
+        clusters[cluster_of[root].unwrap()].0 += 1;
+    }
+
+    // Biggest first, each into the group with the least work so far
+    clusters.sort_by_key(|&(weight, _)| std::cmp::Reverse(weight));
+    let mut groups: Vec<(usize, Vec<ActorNames>)> = Vec::new();
+    for (weight, actors) in clusters {
+        if groups.len() < max_groups {
+            groups.push((weight, actors));
+        } else {
+            let group = groups.iter_mut().min_by_key(|(total, _)| *total).unwrap();
+            group.0 += weight;
+            group.1.extend(actors);
+        }
+    }
+    groups.into_iter().map(|(_, actors)| actors).collect()
+}
+
+/// Replays every worker's steps in the order a single queue would have taken them
+///
+/// `globals` maps each worker's order for its initial queue entries to their global order, and
+/// `next_seq` is the first unused global order.
+pub(crate) fn merge<ActorNames>(results: &mut [WindowResult<ActorNames>], mut globals: Vec<Vec<u64>>, mut next_seq: u64) -> Merged<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    let mut positions = vec![0; results.len()];
+    let mut last_time = None;
+    let mut error = None;
+
+    loop {
+        // The next entry a single queue would take is the earliest next entry of any worker.
+        // Every entry a worker takes is either from its initial queue, or was added by an earlier
+        // step in the same worker, so its global order is always known by now
+        let next = results.iter()
+            .enumerate()
+            .filter_map(|(worker, result)| {
+                let step = result.steps.get(positions[worker])?;
+                Some((step.time, globals[worker][step.seq as usize], worker))
+            })
+            .min();
+        let Some((time, _, worker)) = next else {
+            break;
+        };
+
+        let step = results[worker].steps[positions[worker]];
+        positions[worker] += 1;
+        last_time = Some(time);
+
+        // Entries added by this step come after everything added so far, by any worker
+        while (globals[worker].len() as u64) < step.seq_end {
+            globals[worker].push(next_seq);
+            next_seq += 1;
+        }
+
+        if positions[worker] == results[worker].steps.len() && error.is_none() {
+            error = results[worker].error.take();
+        }
+    }
+
+    if error.is_none() {
+        // A worker that failed before taking any entries
+        error = results.iter_mut().find_map(|result| result.error.take());
+    }
+
+    let queued = results.iter()
+        .zip(globals.iter())
+        .flat_map(|(result, globals)| {
+            result.queued.iter().map(|&(id, time, seq)| (id, time, globals[seq as usize]))
+        })
+        .collect();
+
+    Merged { queued, last_time, error }
+}
+
+/// Threads that each run one group of actors per window
+pub(crate) struct WorkerPool<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    workers: Vec<Worker<ActorNames>>,
+}
+
+struct Worker<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    /// Dropped to stop the thread
+    jobs: Option<mpsc::Sender<WindowJob<ActorNames>>>,
+    results: mpsc::Receiver<WindowResult<ActorNames>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<ActorNames> WorkerPool<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    /// `make_worker` is called once for each thread, to create the function that runs a window
+    pub fn new<F>(threads: usize, mut make_worker: impl FnMut() -> F) -> Self
+    where
+        F: FnMut(WindowJob<ActorNames>) -> WindowResult<ActorNames> + Send + 'static,
+    {
+        let workers = (0..threads).map(|n| {
+            let (jobs_tx, jobs_rx) = mpsc::channel::<WindowJob<ActorNames>>();
+            let (results_tx, results_rx) = mpsc::channel();
+            let mut run_window = make_worker();
+
+            let thread = std::thread::Builder::new()
+                .name(format!("scheduler worker {}", n))
+                .spawn(move || {
+                    while let Ok(job) = jobs_rx.recv() {
+                        if results_tx.send(run_window(job)).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .expect("Failed to start scheduler worker thread");
+
+            Worker { jobs: Some(jobs_tx), results: results_rx, thread: Some(thread) }
+        }).collect();
+
+        WorkerPool { workers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs each job on a different worker, and waits for all of them to finish
+    pub fn run(&mut self, jobs: Vec<WindowJob<ActorNames>>) -> Vec<WindowResult<ActorNames>> {
+        assert!(jobs.len() <= self.workers.len());
+        let count = jobs.len();
+        for (worker, job) in self.workers.iter().zip(jobs) {
+            worker.jobs.as_ref().unwrap().send(job).expect("Scheduler worker thread died");
+        }
+        // Must wait for every worker, even if one died, because they're all using the actors
+        let results: Vec<_> = self.workers[..count].iter()
+            .map(|worker| worker.results.recv())
+            .collect();
+        results.into_iter()
+            .map(|result| result.expect("Scheduler worker thread died"))
+            .collect()
+    }
+}
+
+impl<ActorNames> Drop for WorkerPool<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            worker.jobs = None;
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
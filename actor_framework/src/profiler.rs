@@ -0,0 +1,209 @@
+use std::marker::PhantomData;
+
+use crate::MakeNamed;
+
+#[cfg(feature = "profiling")]
+use std::{any::TypeId, collections::HashMap, fmt::Display, time::{Duration, Instant}};
+
+#[cfg(feature = "profiling")]
+use crate::Stats;
+
+// Host-time profiler, enabled by the "profiling" cargo feature.
+//
+// When the feature is disabled, Profiler is a zero sized type and `start`/`record` compile to
+// nothing, so the scheduler can call them unconditionally.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProfiledCall {
+    Recv,
+    Delivering,
+    Resolve,
+}
+
+#[cfg(feature = "profiling")]
+pub(crate) type ProfileStart = Instant;
+/// Nothing to measure from, but still a value so callers don't bind `()`
+#[cfg(not(feature = "profiling"))]
+#[derive(Clone, Copy)]
+pub(crate) struct ProfileStart;
+
+pub(crate) struct Profiler<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    #[cfg(feature = "profiling")]
+    entries: HashMap<(usize, ProfiledCall, TypeId), ProfileEntry<ActorNames>>,
+    names: PhantomData<ActorNames>,
+}
+
+impl<ActorNames> Profiler<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub(crate) fn new() -> Self {
+        Profiler {
+            #[cfg(feature = "profiling")]
+            entries: HashMap::new(),
+            names: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    #[inline(always)]
+    pub(crate) fn start(&self) -> ProfileStart {
+        Instant::now()
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    #[inline(always)]
+    pub(crate) fn start(&self) -> ProfileStart {
+        ProfileStart
+    }
+
+    #[cfg(feature = "profiling")]
+    #[inline(always)]
+    pub(crate) fn record<Message>(&mut self, actor: ActorNames, call: ProfiledCall, start: ProfileStart)
+    where
+        Message: 'static,
+    {
+        let elapsed = start.elapsed();
+        let entry = self.entries
+            .entry((actor.into(), call, TypeId::of::<Message>()))
+            .or_insert_with(|| ProfileEntry {
+                actor,
+                call,
+                msg_type: std::any::type_name::<Message>(),
+                calls: 0,
+                total: Duration::ZERO,
+            });
+        entry.calls += 1;
+        entry.total += elapsed;
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    #[inline(always)]
+    #[allow(clippy::extra_unused_type_parameters)] // Keep the same signature as the profiling version
+    pub(crate) fn record<Message>(&mut self, _: ActorNames, _: ProfiledCall, _: ProfileStart)
+    where
+        Message: 'static,
+    {}
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Add entries from another profiler, such as a parallel worker's
+    #[cfg(feature = "profiling")]
+    pub(crate) fn merge(&mut self, other: Self) {
+        for (key, entry) in other.entries {
+            match self.entries.get_mut(&key) {
+                Some(existing) => {
+                    existing.calls += entry.calls;
+                    existing.total += entry.total;
+                }
+                None => {
+                    self.entries.insert(key, entry);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    pub(crate) fn merge(&mut self, _: Self) {}
+
+    #[cfg(feature = "profiling")]
+    pub(crate) fn report(&self) -> ProfileReport<ActorNames> {
+        let mut entries: Vec<_> = self.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.total));
+
+        ProfileReport { entries }
+    }
+}
+
+/// Host time spent in one actor function, for one message type
+#[cfg(feature = "profiling")]
+#[derive(Clone, Debug)]
+pub struct ProfileEntry<ActorNames> {
+    pub actor: ActorNames,
+    pub call: ProfiledCall,
+    pub msg_type: &'static str,
+    pub calls: u64,
+    pub total: Duration,
+}
+
+/// Displays as a table, sorted by total time
+#[cfg(feature = "profiling")]
+pub struct ProfileReport<ActorNames> {
+    pub entries: Vec<ProfileEntry<ActorNames>>,
+}
+
+#[cfg(feature = "profiling")]
+impl<ActorNames> ProfileReport<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub fn total(&self) -> Duration {
+        self.entries.iter().map(|e| e.total).sum()
+    }
+
+    /// Folded stacks (nanoseconds), as consumed by flamegraph.pl and inferno
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for entry in &self.entries {
+            folded += &format!("{:?};{:?};{} {}\n",
+                entry.actor, entry.call, folded_frame(entry.msg_type), entry.total.as_nanos());
+        }
+        folded
+    }
+
+    pub fn add_to(&self, stats: &mut Stats) {
+        let group = stats.group("Host time (ms)");
+        for entry in &self.entries {
+            let name = format!("{:?} {:?} {}", entry.actor, entry.call, entry.msg_type);
+            group.ratio(name, entry.total.as_secs_f64() * 1000.0);
+        }
+    }
+}
+
+/// Type names can contain `;` and spaces (`Grant<[u32; 2048]>`), which separate frames and the
+/// count in folded stacks
+#[cfg(feature = "profiling")]
+fn folded_frame(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| if c == ';' { ',' } else { c })
+        .collect()
+}
+
+#[cfg(feature = "profiling")]
+impl<ActorNames> Display for ProfileReport<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total().as_secs_f64();
+        writeln!(f, "{:>10} {:>6} {:>12} {:>9}  actor/call/message", "total ms", "%", "calls", "avg ns")?;
+        for entry in &self.entries {
+            let secs = entry.total.as_secs_f64();
+            writeln!(f, "{:>10.3} {:>6.2} {:>12} {:>9}  {:?} {:?} {}",
+                secs * 1000.0,
+                if total > 0.0 { secs / total * 100.0 } else { 0.0 },
+                entry.calls,
+                entry.total.as_nanos() / entry.calls.max(1) as u128,
+                entry.actor, entry.call, entry.msg_type,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "profiling"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded_frames_stay_whole() {
+        assert_eq!(folded_frame("Grant<[u32; 2048]>"), "Grant<[u32,2048]>");
+        assert_eq!(folded_frame("n64::pi::PiRead"), "n64::pi::PiRead");
+    }
+}
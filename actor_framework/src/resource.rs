@@ -0,0 +1,488 @@
+use std::{any::TypeId, collections::BinaryHeap, marker::PhantomData};
+
+use anyhow::bail;
+
+use crate::{Actor, Channel, EnumMap, Handler, MakeNamed, Outbox, OutboxSend, RestoreState, SaveState, SchedulerResult, StateReader, StateWriter, StatsGroup, Time};
+
+/// A resource that can only be used by one actor at a time, such as a bus or a shared memory
+///
+/// The `Owner` actor keeps the `Resource` and forwards it the arbitration messages:
+///
+///  * `Acquire` from actors that want the resource, calls `Resource::acquire`
+///  * `Release` when the borrower hands it back, calls `Resource::release`
+///  * the `delivering` hook, calls `Resource::delivering`
+///
+/// Borrowers receive `Grant` with the resource inside, and `Preempt` when a another actor is
+/// waiting. Their `Preempt` handler is the preemption hook: they can finish (or stash) what they
+/// were doing, but must eventually send the resource back with `Release`.
+///
+/// The highest priority request is granted next. Because everything goes through normal messages,
+/// arbitration is deterministic, traceable, and saved with the rest of the machine.
+pub struct Resource<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    queue: BinaryHeap<Acquire<ActorNames, Owner, R>>,
+    /// The request we sent a `Grant` to, which hasn't been delivered yet
+    granting: Option<Acquire<ActorNames, Owner, R>>,
+    /// When we decided to send that grant
+    granted_time: Time,
+    borrower: Option<Borrow<ActorNames, Owner, R>>,
+    /// The resource, while nobody has borrowed it
+    home: Option<Box<R>>,
+
+    grants: EnumMap<u64, ActorNames>,
+    cycles_held: EnumMap<u64, ActorNames>,
+    preemptions: u64,
+}
+
+struct Borrow<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    actor: ActorNames,
+    preempt: Channel<ActorNames, Owner, Preempt<R>>,
+    since: Time,
+    preempted: bool,
+}
+
+/// Request a resource, delivered to the owner
+pub struct Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    requestor: ActorNames,
+    priority: u16,
+    grant: Channel<ActorNames, Owner, Grant<R>>,
+    preempt: Channel<ActorNames, Owner, Preempt<R>>,
+}
+
+/// Hands the resource to the requestor
+pub struct Grant<R>(pub Box<R>);
+
+/// Asks the borrower to `Release` the resource as soon as it can
+pub struct Preempt<R> {
+    resource: PhantomData<R>,
+}
+
+/// Returns the resource to its owner
+pub struct Release<R>(pub Box<R>);
+
+impl<ActorNames, Owner, R> Resource<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: Outbox<ActorNames, Sender = Owner>
+        + OutboxSend<ActorNames, Grant<R>>
+        + OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    /// A resource that starts out with its owner
+    pub fn new(resource: Box<R>) -> Self {
+        Self::with_home(Some(resource), None)
+    }
+
+    /// A resource that starts out borrowed, for when the borrower creates it
+    pub fn lent_to<Borrower>() -> Self
+    where
+        Borrower: Actor<ActorNames> + Handler<ActorNames, Preempt<R>>,
+    {
+        Self::with_home(None, Some(Borrow {
+            actor: Borrower::name(),
+            preempt: Channel::new::<Borrower>(),
+            since: Time::default(),
+            preempted: false,
+        }))
+    }
+
+    fn with_home(home: Option<Box<R>>, borrower: Option<Borrow<ActorNames, Owner, R>>) -> Self {
+        Resource {
+            queue: BinaryHeap::new(),
+            granting: None,
+            granted_time: Time::default(),
+            borrower,
+            home,
+            grants: EnumMap::new(),
+            cycles_held: EnumMap::new(),
+            preemptions: 0,
+        }
+    }
+
+    /// The actor currently borrowing the resource
+    pub fn borrower(&self) -> Option<ActorNames> {
+        self.borrower.as_ref().map(|b| b.actor)
+    }
+
+    /// The resource, if the owner has it right now
+    pub fn get_mut(&mut self) -> Option<&mut R> {
+        self.home.as_deref_mut()
+    }
+
+    pub fn is_home(&self) -> bool {
+        self.home.is_some()
+    }
+
+    /// Number of requests waiting for the resource
+    pub fn waiting(&self) -> usize {
+        self.queue.len() + self.granting.is_some() as usize
+    }
+
+    pub fn acquire(&mut self, outbox: &mut Owner::OutboxType, request: Acquire<ActorNames, Owner, R>, time: Time) -> SchedulerResult {
+        let priority = request.priority;
+        self.queue.push(request);
+
+        if let Some(resource) = self.home.take() {
+            // Nobody is using it, hand it straight over
+            self.grant(outbox, resource, time, time)
+        } else if self.borrower.is_some() {
+            self.preempt(outbox, time)
+        } else if self.granted_time == time && self.granting.as_ref().is_some_and(|g| g.priority < priority) {
+            // We already granted the resource this cycle, but this request has a higher priority.
+            // Change our mind.
+            match outbox.try_cancel::<Grant<R>>() {
+                Some((send_time, Grant(resource))) => {
+                    self.queue.extend(self.granting.take());
+                    self.grant(outbox, resource, time, send_time)
+                }
+                None => SchedulerResult::Ok,
+            }
+        } else {
+            SchedulerResult::Ok
+        }
+    }
+
+    pub fn release(&mut self, outbox: &mut Owner::OutboxType, release: Release<R>, time: Time) -> SchedulerResult {
+        let borrow = self.borrower.take().expect("Resource released, but it wasn't borrowed");
+        self.cycles_held[borrow.actor] += u64::from(time).saturating_sub(borrow.since.into());
+
+        if self.queue.is_empty() {
+            self.home = Some(release.0);
+            SchedulerResult::Ok
+        } else {
+            // Takes a cycle to hand it over
+            self.grant(outbox, release.0, time, time.add(1))
+        }
+    }
+
+    /// Ask the borrower to give the resource back to the owner.
+    /// If other actors are waiting, they get it first.
+    pub fn recall(&mut self, outbox: &mut Owner::OutboxType, time: Time) -> SchedulerResult {
+        self.preempt(outbox, time)
+    }
+
+    /// Call from the owner's `Actor::delivering`
+    #[inline(always)]
+    pub fn delivering<Message>(&mut self, outbox: &mut Owner::OutboxType, time: Time)
+    where
+        Message: 'static,
+    {
+        if TypeId::of::<Message>() != TypeId::of::<Grant<R>>() {
+            return;
+        }
+
+        let request = self.granting.take().expect("Granted a resource without a request");
+        self.grants[request.requestor] += 1;
+        self.borrower = Some(Borrow {
+            actor: request.requestor,
+            preempt: request.preempt,
+            since: time,
+            preempted: false,
+        });
+
+        if !self.queue.is_empty() {
+            // There is another request waiting, immediately ask for the resource back
+            self.preempt(outbox, time.add(1));
+        }
+    }
+
+    fn grant(&mut self, outbox: &mut Owner::OutboxType, resource: Box<R>, time: Time, send_time: Time) -> SchedulerResult {
+        let request = self.queue.pop().unwrap();
+        let grant = request.grant.clone();
+        self.granting = Some(request);
+        self.granted_time = time;
+
+        outbox.send_channel(grant, Grant(resource), send_time)
+    }
+
+    fn preempt(&mut self, outbox: &mut Owner::OutboxType, time: Time) -> SchedulerResult {
+        match &mut self.borrower {
+            Some(borrow) if !borrow.preempted => {
+                borrow.preempted = true;
+                self.preemptions += 1;
+                outbox.send_channel(borrow.preempt.clone(), Preempt::default(), time)
+            }
+            _ => SchedulerResult::Ok,
+        }
+    }
+
+    pub fn stats(&self, stats: &mut StatsGroup) {
+        for (actor, &grants) in self.grants.iter().filter(|(_, &grants)| grants != 0) {
+            stats.count(format!("{:?} grants", actor), grants);
+            stats.count(format!("{:?} cycles held", actor), self.cycles_held[actor]);
+        }
+        stats.count("Preemptions", self.preemptions);
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.grants = EnumMap::new();
+        self.cycles_held = EnumMap::new();
+        self.preemptions = 0;
+    }
+}
+
+impl<ActorNames, Owner, R> Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Grant<R>> + OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    /// Higher priorities are granted first, each requestor should use a unique priority
+    pub fn new<Requestor>(priority: u16) -> Self
+    where
+        Requestor: Actor<ActorNames> + Handler<ActorNames, Grant<R>> + Handler<ActorNames, Preempt<R>>,
+    {
+        Acquire {
+            requestor: Requestor::name(),
+            priority,
+            grant: Channel::new::<Requestor>(),
+            preempt: Channel::new::<Requestor>(),
+        }
+    }
+}
+
+impl<R> Default for Preempt<R> {
+    fn default() -> Self {
+        Preempt { resource: PhantomData }
+    }
+}
+
+impl<ActorNames, Owner, R> PartialEq for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl<ActorNames, Owner, R> Eq for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{}
+
+impl<ActorNames, Owner, R> PartialOrd for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<ActorNames, Owner, R> Ord for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+fn save_name<ActorNames: MakeNamed>(name: ActorNames, state: &mut StateWriter) {
+    let name: usize = name.into();
+    name.save_state(state);
+}
+
+fn restore_name<ActorNames: MakeNamed>(state: &mut StateReader) -> Result<ActorNames, anyhow::Error> {
+    let name = usize::restore_state(state)?;
+    if name >= ActorNames::COUNT {
+        bail!("Invalid actor {} in save state", name);
+    }
+    Ok(name.into())
+}
+
+impl<ActorNames, Owner, R> SaveState for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Grant<R>> + OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        save_name(self.requestor, state);
+        self.priority.save_state(state);
+        self.grant.save_state(state);
+        self.preempt.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<ActorNames, Owner, R> RestoreState for Acquire<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Grant<R>> + OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Acquire {
+            requestor: restore_name(state)?,
+            priority: u16::restore_state(state)?,
+            grant: Channel::restore_state(state)?,
+            preempt: Channel::restore_state(state)?,
+        })
+    }
+}
+
+impl<ActorNames, Owner, R> SaveState for Borrow<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        save_name(self.actor, state);
+        self.preempt.save_state(state);
+        self.since.save_state(state);
+        self.preempted.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<ActorNames, Owner, R> RestoreState for Borrow<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Preempt<R>>,
+    R: 'static,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Borrow {
+            actor: restore_name(state)?,
+            preempt: Channel::restore_state(state)?,
+            since: Time::restore_state(state)?,
+            preempted: bool::restore_state(state)?,
+        })
+    }
+}
+
+// Stats aren't part of the machine state, so they aren't saved
+impl<ActorNames, Owner, R> SaveState for Resource<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Grant<R>> + OutboxSend<ActorNames, Preempt<R>>,
+    R: SaveState + RestoreState + 'static,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.queue.save_state(state);
+        self.granting.save_state(state);
+        self.granted_time.save_state(state);
+        self.borrower.save_state(state);
+        self.home.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.queue.load_state(state)?;
+        self.granting.load_state(state)?;
+        self.granted_time.load_state(state)?;
+        self.borrower.load_state(state)?;
+        self.home.load_state(state)
+    }
+}
+
+impl<ActorNames, Owner, R> RestoreState for Resource<ActorNames, Owner, R>
+where
+    ActorNames: MakeNamed,
+    Owner: Actor<ActorNames>,
+    Owner::OutboxType: OutboxSend<ActorNames, Grant<R>> + OutboxSend<ActorNames, Preempt<R>>,
+    R: SaveState + RestoreState + 'static,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Resource {
+            queue: BinaryHeap::restore_state(state)?,
+            granting: Option::restore_state(state)?,
+            granted_time: Time::restore_state(state)?,
+            borrower: Option::restore_state(state)?,
+            home: Option::restore_state(state)?,
+            grants: EnumMap::new(),
+            cycles_held: EnumMap::new(),
+            preemptions: 0,
+        })
+    }
+}
+
+impl<R> SaveState for Grant<R>
+where
+    R: SaveState + RestoreState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.0.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.0.load_state(state)
+    }
+}
+
+impl<R> RestoreState for Grant<R>
+where
+    R: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Grant(Box::restore_state(state)?))
+    }
+}
+
+impl<R> SaveState for Release<R>
+where
+    R: SaveState + RestoreState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.0.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.0.load_state(state)
+    }
+}
+
+impl<R> RestoreState for Release<R>
+where
+    R: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Release(Box::restore_state(state)?))
+    }
+}
+
+impl<R> SaveState for Preempt<R> {
+    fn save_state(&self, _: &mut StateWriter) {}
+
+    fn load_state(&mut self, _: &mut StateReader) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+impl<R> RestoreState for Preempt<R> {
+    fn restore_state(_: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Preempt::default())
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::bail;
+
+use crate::{MakeNamed, StateReader, StateWriter, RestoreState, SaveState};
+
+// Save state format:
+//
+//   magic, version, type_name of ActorNames
+//   scheduler queue order
+//   one section per actor, containing its outbox followed by the actor itself
+//
+// Pending messages contain function pointers to monomorphized execute functions, which can't be
+// saved. Only their receiver and message type are, and `MakeNamed::endpoint` rebuilds a function
+// that delivers them, from the messages each actor lists in `handles(...)`.
+
+const MAGIC: [u8; 8] = *b"BUSMUSAV";
+
+/// Bump this whenever the framework's layout changes
+pub const SAVE_STATE_VERSION: u32 = 6;
+
+pub(crate) fn write_header<ActorNames>(state: &mut StateWriter)
+where
+    ActorNames: MakeNamed,
+{
+    state.write_bytes(&MAGIC);
+    SAVE_STATE_VERSION.save_state(state);
+    state.write_str(std::any::type_name::<ActorNames>());
+}
+
+pub(crate) fn read_header<ActorNames>(state: &mut StateReader) -> Result<(), anyhow::Error>
+where
+    ActorNames: MakeNamed,
+{
+    if state.read_bytes(MAGIC.len())? != MAGIC {
+        bail!("Not a save state");
+    }
+    let version = u32::restore_state(state)?;
+    if version != SAVE_STATE_VERSION {
+        bail!("Unsupported save state version {} (expected {})", version, SAVE_STATE_VERSION);
+    }
+    let actor_names = String::restore_state(state)?;
+    if actor_names != std::any::type_name::<ActorNames>() {
+        bail!("Save state is for {}, not {}", actor_names, std::any::type_name::<ActorNames>());
+    }
+    Ok(())
+}
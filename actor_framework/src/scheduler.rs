@@ -1,12 +1,14 @@
 
-use std::{usize, sync::mpsc::{self, TryRecvError}};
+use std::{ops::ControlFlow, usize, sync::mpsc::{self, TryRecvError}};
 
-use common::{ControlMessage, UpdateMessage};
+use anyhow::{anyhow, bail};
+use common::{input::{InputLatch, PadState}, pacing::{Pacer, Speed}, video::FrameReceiver, Command, CommandError, ControlMessage, UpdateMessage};
 
-use crate::{object_map::{ObjectStore, ObjectStoreView}, Time, MakeNamed, Actor, MessagePacket, OutboxSend, Handler, Outbox, EnumMap};
+use crate::{actor_heap::ActorHeap, breakpoint::Breakpoints, Breakpoint, BreakpointHit, BreakpointId, object_map::{ActorGroup, ObjectStore}, parallel::{self, LookaheadViolation, WindowJob, WindowResult, WindowStep, WorkerPool}, profiler::{ProfiledCall, Profiler}, save_state, stats::MessageCounters, timers, trace::{Trace, TraceEntry, Tracer}, zero_limit::{ZeroLimitDelivery, ZeroLimitMessage, ZeroLimitProblem, ZeroLimitReport}, SchedulerStats, Stats, Time, MakeNamed, Actor, OutboxSend, Handler, Outbox, EnumMap, RestoreState, SaveState, StateReader, StateWriter};
 
 // PERF: TODO:
-// This is currently a bit of a mess. It implements four different scheduling algorithms.
+// This is currently a bit of a mess. It implements five different scheduling algorithms, selected
+// at runtime with `SchedulerBackend`. The cargo features only choose the default.
 //
 //  - Branchless uses a chain of conditional moves to select the next actor without any branches
 //    Problem is that it's complexity grows with total actors, even if the actors do nothing
@@ -15,6 +17,9 @@ use crate::{object_map::{ObjectStore, ObjectStoreView}, Time, MakeNamed, Actor,
 //    active actors and falling back to the linked list
 //  - updatding_cache is cached, except it continually updates the cache as actors are executed
 //    so the cache always returns a valid result
+//  - heap uses an indexed binary heap, ordered by time then insertion order so messages sent on
+//    the same cycle are still delivered in FIFO order. O(log n) with the number of scheduled actors,
+//    so it should hold up better than the others as the number of actors grows
 //
 //  Unfortunately, I had to abandon optimization efforts because my n64 implementation didn't
 //  generate complex enough workloads. I'll need to come back once I have multiple cores all
@@ -27,6 +32,10 @@ use crate::{object_map::{ObjectStore, ObjectStoreView}, Time, MakeNamed, Actor,
 //   - branchless is the slowest, especially as the number of actors gets larger
 //
 //  I'm expecting that cached might be faster on more complex workloads... though need to test
+//  `cargo bench -p actor_framework --bench scheduler` runs some synthetic workloads against every backend
+//
+//  `set_threads` can also split each window of `Actor::LOOKAHEAD` cycles across worker threads,
+//  see parallel.rs. Each worker runs its own Heap scheduler.
 //
 //  Other optimization ideas:
 //     Don't access time (and execute_fn?) via indirect loads to the outbox.
@@ -37,14 +46,98 @@ use crate::{object_map::{ObjectStore, ObjectStoreView}, Time, MakeNamed, Actor,
 //         FIFO order. Which would limits queue options.
 
 const CACHE_SIZE: usize = 2;
+/// How many of the last iterations of `run_zero_limit` are included in its report
+const ZERO_LIMIT_REPORTED: usize = 2;
 const UNCACHED: u8 = CACHE_SIZE as u8;
 
+/// Which algorithm the scheduler uses to find the next message to deliver
+///
+/// See the comment at the top of scheduler.rs. The default is picked by cargo features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerBackend {
+    /// Scans every actor's outbox with conditional moves, no queue
+    Branchless,
+    /// Double-linked list, sorted by time
+    LinkedList,
+    /// Branchless over a small cache of active actors, falling back to the linked list
+    Cached,
+    /// Cached, but actors scheduled after the queue head are moved out of the cache straight away
+    UpdatingCache,
+    /// Indexed binary heap, which scales with the number of scheduled actors instead of
+    /// `ActorNames::COUNT`
+    Heap,
+}
+
+impl SchedulerBackend {
+    pub const ALL: [SchedulerBackend; 5] = [
+        SchedulerBackend::Branchless,
+        SchedulerBackend::LinkedList,
+        SchedulerBackend::Cached,
+        SchedulerBackend::UpdatingCache,
+        SchedulerBackend::Heap,
+    ];
+
+    #[inline(always)]
+    fn is_cached(self) -> bool {
+        matches!(self, SchedulerBackend::Cached | SchedulerBackend::UpdatingCache)
+    }
+
+    /// Backends that always deliver messages for the same cycle in the order they were sent
+    #[inline(always)]
+    fn is_fifo(self) -> bool {
+        matches!(self, SchedulerBackend::LinkedList | SchedulerBackend::Heap)
+    }
+}
+
+impl SaveState for SchedulerBackend {
+    fn save_state(&self, state: &mut StateWriter) {
+        (*self as u8).save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for SchedulerBackend {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let index = u8::restore_state(state)?;
+        match SchedulerBackend::ALL.get(index as usize) {
+            Some(&backend) => Ok(backend),
+            None => bail!("Invalid scheduler backend {} in save state", index),
+        }
+    }
+}
+
+impl Default for SchedulerBackend {
+    fn default() -> Self {
+        if cfg!(feature = "branchless") {
+            SchedulerBackend::Branchless
+        } else if cfg!(feature = "heap") {
+            SchedulerBackend::Heap
+        } else if cfg!(feature = "updating_cache") {
+            SchedulerBackend::UpdatingCache
+        } else if cfg!(feature = "cached") {
+            SchedulerBackend::Cached
+        } else {
+            SchedulerBackend::LinkedList
+        }
+    }
+}
+
 pub struct Scheduler<ActorNames> where
     ActorNames: MakeNamed,
 {
+    backend: SchedulerBackend,
+    /// Declared before `actors`, so the threads are stopped before the actors are dropped
+    workers: Option<WorkerPool<ActorNames>>,
+    /// Smallest `Actor::LOOKAHEAD` of any actor
+    lookahead: u64,
     actors: ObjectStore<ActorNames>,
     queue: EnumMap<QueueEntry<ActorNames>, ActorNames>,
     queue_head: Option<ActorNames>,
+    heap: ActorHeap<ActorNames>,
     is_cached: EnumMap<u8, ActorNames>,
     cached: [ActorNames; CACHE_SIZE],
     cache_limit: Time,
@@ -55,20 +148,29 @@ pub struct Scheduler<ActorNames> where
     count_queue_removes: u64,
     count_queue_add_complexity: u64,
     zero_limit_count: u64,
-}
-
-impl<ActorNames> Drop for Scheduler<ActorNames>
-where
-    ActorNames: MakeNamed,
-{
-    fn drop(&mut self) {
-        eprintln!("Scheduler ran {} times", self.count);
-        eprintln!(" with {} zero limits", self.zero_limit_count);
-        eprintln!(" {} cache inserts", self.count_cache_inserts);
-        let complexity = self.count_queue_add_complexity as f64 / self.count_queue_adds as f64;
-        eprintln!(" {} queue adds, complexity {}", self.count_queue_adds, complexity);
-        eprintln!(" {} queue removes", self.count_queue_removes);
-    }
+    messages: MessageCounters<ActorNames>,
+    profiler: Profiler<ActorNames>,
+    tracer: Option<Tracer<ActorNames>>,
+    breakpoints: Breakpoints<ActorNames>,
+    last_breakpoint: Option<BreakpointHit<ActorNames>>,
+    /// Only `run` is paced, and not at all without a pacer
+    pacer: Option<Pacer>,
+    /// `run` returns once it reaches this time
+    stop_time: Time,
+    /// End of the window a parallel worker is running
+    window_end: Time,
+    /// Kept for `power_cycle`. Parallel workers don't have one
+    config: Option<ActorNames::Config>,
+    /// Set when `run` returns for the instance to handle a `ControlMessage::Reset` or
+    /// `ControlMessage::Shutdown`
+    request: Option<ControlMessage>,
+    /// End of the current `ControlMessage::FrameAdvance` or `ControlMessage::RunFor`
+    run_for: Option<(Time, Command)>,
+    /// Frames from the actors, passed on when the UI syncs
+    frames: Option<FrameReceiver>,
+    /// Input from the frontend waits here until `run` reaches its latch point
+    input: Option<InputLatch>,
+    now: Time,
 }
 
 impl<ActorNames> Scheduler<ActorNames> where
@@ -77,48 +179,77 @@ impl<ActorNames> Scheduler<ActorNames> where
     const EMPTY_CACHE: ActorNames = ActorNames::TERMINAL;
 
     pub fn new(config: ActorNames::Config) -> Result<Scheduler<ActorNames>, anyhow::Error> {
-        let mut scheduler = Scheduler {
-            actors: ObjectStore::with(&config)?,
-            queue: EnumMap::from_fn(|_| QueueEntry { next: None, prev: None }),
-            queue_head: None,
-            is_cached: EnumMap::from_fn(|_| UNCACHED),
-            cached: [Self::EMPTY_CACHE; CACHE_SIZE],
-            cache_limit: Time::MAX,
-            num_cache_entries: 0,
-            count: 0,
-            count_cache_inserts: 0,
-            count_queue_adds: 0,
-            count_queue_removes: 0,
-            count_queue_add_complexity: 0,
-            zero_limit_count: 0,
-        };
+        Self::with_backend(config, SchedulerBackend::default())
+    }
 
+    pub fn with_backend(config: ActorNames::Config, backend: SchedulerBackend) -> Result<Scheduler<ActorNames>, anyhow::Error> {
+        let mut scheduler = Self::with_actors(ObjectStore::with(&config)?, backend);
+        scheduler.config = Some(config);
+        scheduler.init_queue();
+        Ok(scheduler)
+    }
+
+    /// Calculate the initial priority queue from freshly initialized actors
+    fn init_queue(&mut self) {
         assert!(CACHE_SIZE < core::cmp::max(ActorNames::COUNT, 254));
 
-        // Calculate initial priority queue
         for id in ActorNames::iter() {
-            let time = scheduler.get_time(id);
+            let time = self.get_time(id);
             if time != Time::MAX {
-                scheduler.queue_add(id, time);
+                self.enqueue(id, time);
             }
         }
-        assert!(scheduler.queue_head.is_some(), "No schedulable actors found");
+        assert!(self.next_time() != Time::MAX, "No schedulable actors found");
 
-        if cfg!(feature = "cached") {
-            scheduler.cached = std::array::from_fn(|idx| {
-                let (id, _, limit) = scheduler.queue_pop();
+        if self.backend.is_cached() {
+            self.cached = std::array::from_fn(|idx| {
+                let (id, _, limit) = self.queue_pop();
                 if let Some(id) = id {
-                    scheduler.cache_limit = limit;
-                    scheduler.is_cached[id] = idx as u8;
-                    scheduler.num_cache_entries += 1;
+                    self.cache_limit = limit.lower_bound();
+                    self.is_cached[id] = idx as u8;
+                    self.num_cache_entries += 1;
                     id
                 } else {
                     Self::EMPTY_CACHE
                 }
             });
         }
+    }
 
-        Ok(scheduler)
+    fn with_actors(actors: ObjectStore<ActorNames>, backend: SchedulerBackend) -> Scheduler<ActorNames> {
+        Scheduler {
+            backend,
+            workers: None,
+            lookahead: ActorNames::iter().map(ActorNames::lookahead).min().unwrap_or(0),
+            actors,
+            queue: EnumMap::from_fn(|_| QueueEntry { next: None, prev: None }),
+            queue_head: None,
+            heap: ActorHeap::new(),
+            is_cached: EnumMap::from_fn(|_| UNCACHED),
+            cached: [Self::EMPTY_CACHE; CACHE_SIZE],
+            cache_limit: Time::MAX,
+            num_cache_entries: 0,
+            count: 0,
+            count_cache_inserts: 0,
+            count_queue_adds: 0,
+            count_queue_removes: 0,
+            count_queue_add_complexity: 0,
+            zero_limit_count: 0,
+            messages: MessageCounters::new(),
+            profiler: Profiler::new(),
+            tracer: None,
+            breakpoints: Breakpoints::new(),
+            last_breakpoint: None,
+            pacer: None,
+            stop_time: Time::MAX,
+            window_end: Time::MAX,
+            config: None,
+            request: None,
+            run_for: None,
+            frames: None,
+            input: None,
+            now: Time::default(),
+        }
     }
 
     pub fn get<ActorType>(&mut self) -> &mut ActorType
@@ -128,15 +259,61 @@ impl<ActorNames> Scheduler<ActorNames> where
         &mut self.actors.get::<ActorType>().obj
     }
 
-    #[cfg(feature = "branchless")]
+    pub fn backend(&self) -> SchedulerBackend {
+        self.backend
+    }
+
+    /// Run independent actors on up to `threads` threads. 1 (the default) runs everything on the
+    /// calling thread.
+    ///
+    /// Actors run concurrently within windows as long as the smallest `Actor::LOOKAHEAD`, and the
+    /// results are identical to a single threaded run as long as actors only use `limit` to split
+    /// up their work, not to change what they do. Only `run`, `run_until` and `run_for` use the
+    /// threads, and not while tracing or when there are breakpoints.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), anyhow::Error> {
+        self.workers = None;
+        if threads <= 1 {
+            return Ok(());
+        }
+        if !self.backend.is_fifo() {
+            // Merging the workers' queues recreates FIFO order, other backends have their own
+            bail!("The {:?} scheduler backend can't run on multiple threads", self.backend);
+        }
+        if let Some(id) = ActorNames::iter().find(|&id| ActorNames::lookahead(id) == 0) {
+            bail!("{:?} has no LOOKAHEAD, so nothing can run in parallel", id);
+        }
+
+        self.workers = Some(WorkerPool::new(threads, || {
+            // Each window's job brings the worker's actors
+            let mut worker = Scheduler::with_actors(ObjectStore::from_group(ActorGroup::empty()), SchedulerBackend::Heap);
+            move |job| worker.run_window(job)
+        }));
+        Ok(())
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.as_ref().map_or(1, WorkerPool::threads)
+    }
+
+    #[inline(always)]
     fn take_next(&mut self) -> (ActorNames, Time, Time) {
+        match self.backend {
+            SchedulerBackend::Branchless => self.take_next_branchless(),
+            SchedulerBackend::LinkedList => self.take_next_linked_list(),
+            SchedulerBackend::Cached | SchedulerBackend::UpdatingCache => self.take_next_cached(),
+            SchedulerBackend::Heap => self.take_next_heap(),
+        }
+    }
+
+    fn take_next_branchless(&mut self) -> (ActorNames, Time, Time) {
         let mut min = Time::MAX;
         let mut min_actor = 0.into();
         let mut limit = Time::MAX;
 
         // This compiles down to a chain of conditional-moves
+        // Full times are compared, so resolved times win over lazy times on the same cycle
         for actor_id in ActorNames::iter() {
-            let time = self.get_time(actor_id).lower_bound();
+            let time = self.get_time(actor_id);
             if time < min {
                 (limit, min, min_actor) = (min, time, actor_id);
             } else {
@@ -144,10 +321,9 @@ impl<ActorNames> Scheduler<ActorNames> where
             }
         }
         debug_assert!(min != Time::MAX);
-        return (min_actor, min, limit)
+        (min_actor, min.lower_bound(), limit.lower_bound())
     }
 
-    #[allow(dead_code)]
     fn find_next_cached(&mut self) -> (Option<ActorNames>, Time, Time) {
         let mut min = self.cache_limit;
         let mut min_actor: Option<ActorNames> = None;
@@ -155,14 +331,14 @@ impl<ActorNames> Scheduler<ActorNames> where
 
         // This compiles down to a chain of conditional-moves
         for actor_id in self.cached {
-            let time = self.get_time(actor_id).lower_bound();
+            let time = self.get_time(actor_id);
             if time < min {
                 (limit, min, min_actor) = (min, time, Some(actor_id));
             } else {
                 limit = std::cmp::min(limit, time);
             }
         }
-        return (min_actor, min, limit)
+        return (min_actor, min.lower_bound(), limit.lower_bound())
     }
 
     fn cache_remove(&mut self, id: ActorNames, time: Time) -> usize {
@@ -235,87 +411,586 @@ impl<ActorNames> Scheduler<ActorNames> where
         (execute_fn)(sender_id, self, limit)
     }
 
-    #[cfg(feature = "cached")]
     //#[inline(never)]
-    fn take_next(&mut self) -> (ActorNames, Time, Time) {
+    fn take_next_cached(&mut self) -> (ActorNames, Time, Time) {
+        // Evictions and receivers that don't fit in the cache can move the queue head at any time,
+        // so the limit can't be trusted across deliveries
+        self.cache_limit = match self.queue_head {
+            Some(head_id) => self.get_time(head_id),
+            None => Time::MAX,
+        };
+
         let (next, time, limit) = self.find_next_cached();
-        if cfg!(feature = "updating_cache") {
-            // The "updating_cache" fully updates the cache in `fn execute_message`
-            // to make it always return a valid result
-            (next.unwrap(), time, limit)
-        } else if next.is_some() && time != Time::MAX {
-            (next.unwrap(), time, limit)
-        } else {
-            let (next, time, limit) = self.queue_pop();
-            self.cache_insert(next.unwrap());
-            (next.unwrap(), time, limit)
+        if let Some(next) = next {
+            return (next, time, limit);
         }
+
+        // Nothing in the cache is due before the queue head
+        let (next, time, limit) = self.queue_pop();
+        let next = next.expect("No schedulable actors");
+        self.cache_insert(next);
+
+        // Whatever is still cached might be due before the new queue head
+        let limit = self.cached.iter()
+            .filter(|&&id| id != next)
+            .map(|&id| self.get_time(id).lower_bound())
+            .fold(limit, std::cmp::min);
+        (next, time, limit)
     }
 
-    #[cfg(all(feature = "linked_list", not(feature = "cached")))]
-    fn take_next(&mut self) -> (ActorNames, Time, Time) {
+    fn take_next_heap(&mut self) -> (ActorNames, Time, Time) {
+        let (next, time) = self.heap.take().expect("No schedulable actors");
+        let limit = match self.heap.peek() {
+            Some((_, limit)) => limit.lower_bound(),
+            None => Time::MAX,
+        };
+        (next, time.lower_bound(), limit)
+    }
+
+    fn take_next_linked_list(&mut self) -> (ActorNames, Time, Time) {
         let (next, time, limit) = self.queue_pop();
         assert!(next.is_some() && time != Time::MAX, "next: {:?}, time: {:?}", next, time);
         (next.unwrap(), time, limit)
     }
 
-    #[inline(never)]
     pub fn run(
         &mut self,
         control_rx: &mpsc::Receiver<ControlMessage>,
         updates_tx: mpsc::SyncSender<UpdateMessage>,
     ) -> Result<(), anyhow::Error> {
+        let result = self.run_loop(control_rx, &updates_tx);
+        let cancelled = self.cancel_run_for(&updates_tx, "Stopped before it finished");
+        result.and(cancelled)
+    }
+
+    #[inline(never)]
+    fn run_loop(
+        &mut self,
+        control_rx: &mpsc::Receiver<ControlMessage>,
+        updates_tx: &mpsc::SyncSender<UpdateMessage>,
+    ) -> Result<(), anyhow::Error> {
+        self.last_breakpoint = None;
+        let now = self.now.lower_bound().into();
+        if let Some(pacer) = &mut self.pacer {
+            pacer.resume(now);
+        }
+        // Frame advance or run for has finished, and nothing happens until the next message
+        let mut waiting = false;
         loop {
-            match control_rx.try_recv() {
-                Err(TryRecvError::Empty) => {},
-                Ok(ControlMessage::Pause) => { return Ok(()); },
-                Ok(ControlMessage::UiSync) => {
+            let message = match waiting {
+                true => Some(control_rx.recv()?),
+                false => match control_rx.try_recv() {
+                    Err(TryRecvError::Empty) => None,
+                    Ok(message) => Some(message),
+                    Err(err) => { return Err(err.into()); },
+                },
+            };
+            match message {
+                None => {},
+                Some(ControlMessage::Pause) => { return Ok(()); },
+                Some(ControlMessage::UiSync) => {
+                    if let Some(frame) = self.frames.as_ref().and_then(FrameReceiver::latest) {
+                        updates_tx.send(UpdateMessage::Vsync(frame))?;
+                    }
+                    updates_tx.send(UpdateMessage::Stats(self.collect_stats()))?;
                     updates_tx.send(UpdateMessage::UiSynced)?;
                 },
-                Err(err) => { return Err(err.into()); },
-            }
-            let (sender_id, time, limit) = self.take_next();
-            //println!("Running actor {:?}. Next @ {}", sender_id, limit);
-
-            match self.run_inner(sender_id, limit) {
-                SchedulerResult::Ok => {
-                    // Hot path
-                    continue;
+                Some(ControlMessage::SetSpeed(speed)) => {
+                    self.set_speed(speed);
+                    waiting = false;
+                    updates_tx.send(UpdateMessage::Ack(Command::SetSpeed))?;
+                },
+                Some(ControlMessage::Input { port, pad }) => {
+                    // Only failures are replied to, see `ControlMessage::command`
+                    if let Err(err) = self.set_input(port, pad) {
+                        updates_tx.send(UpdateMessage::reply(Command::Input, Err(err)))?;
+                    }
+                },
+                Some(ControlMessage::FrameAdvance) => match self.pacer.as_ref().map(Pacer::cycles_per_frame) {
+                    Some(cycles) => {
+                        self.begin_run_for(updates_tx, Command::FrameAdvance, cycles)?;
+                        waiting = false;
+                    }
+                    None => {
+                        let err = anyhow!("Frame advance needs a pacer, to know how long a frame is");
+                        updates_tx.send(UpdateMessage::reply(Command::FrameAdvance, Err(err)))?;
+                    }
+                },
+                Some(ControlMessage::RunFor(cycles)) => {
+                    self.begin_run_for(updates_tx, Command::RunFor, cycles)?;
+                    waiting = false;
                 },
-                SchedulerResult::ZeroLimit if cfg!(any(feature = "branchless", feature = "cached")) => {
-                    // There are multiple messages scheduled to be delivered on the same cycle.
-                    // And one of the receivers couldn't deal with the zero limit message, so we switch
-                    // to a more complex scheduler until the current cycle finishes.
-                    self.zero_limit_count += 1;
-                    self.run_zero_limit(time, limit)?;
+                Some(ControlMessage::SaveState(path)) => {
+                    let result = std::fs::write(&path, self.save_state()).map_err(anyhow::Error::from);
+                    updates_tx.send(UpdateMessage::reply(Command::SaveState, result))?;
                 },
-                SchedulerResult::ZeroLimit => {
-                    self.zero_limit_count += 1;
+                Some(ControlMessage::LoadState(path)) => {
+                    let result = std::fs::read(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|data| self.load_state(&data));
+                    if result.is_ok() {
+                        // Time has moved, so anything measured from the old time is meaningless
+                        self.cancel_run_for(updates_tx, "A save state was loaded")?;
+                        let now = self.now.lower_bound().into();
+                        if let Some(pacer) = &mut self.pacer {
+                            pacer.resume(now);
+                        }
+                    }
+                    updates_tx.send(UpdateMessage::reply(Command::LoadState, result))?;
+                },
+                Some(message @ (ControlMessage::Reset(_) | ControlMessage::Shutdown)) => {
+                    // Left to the instance, which might need to do more than the scheduler can
+                    self.request = Some(message);
+                    return Ok(());
                 },
-                SchedulerResult::Err(reason) => {
-                    return Err(reason);
+            }
+            if waiting {
+                continue;
+            }
+
+            let slice_end = match &self.pacer {
+                Some(pacer) => Time::from(pacer.slice_end()),
+                None => Time::MAX,
+            };
+            let latch = self.input.as_ref().and_then(InputLatch::latch_at).map_or(Time::MAX, Time::from);
+            let run_for = self.run_for.map_or(Time::MAX, |(end, _)| end);
+            let target = [slice_end, self.stop_time, latch, run_for].into_iter().min().unwrap();
+            if latch != Time::MAX && target == latch && self.next_time() >= target {
+                // Everything before the latch point has seen the old input, and nothing after it will
+                self.now = std::cmp::max(self.now, target);
+                self.input.as_mut().unwrap().latch();
+                continue;
+            }
+            if target != Time::MAX && self.next_time() >= target {
+                self.now = std::cmp::max(self.now, target);
+                if target == run_for {
+                    let (_, command) = self.run_for.take().unwrap();
+                    updates_tx.send(UpdateMessage::Ack(command))?;
+                    waiting = true;
+                }
+                if target == self.stop_time {
+                    return Ok(());
                 }
+                if target == slice_end && !waiting {
+                    waiting = !self.pacer.as_mut().unwrap().pace(target.into());
+                }
+                continue;
+            }
+            if let Err(err) = self.advance(target) {
+                // Breakpoints pause, just like ControlMessage::Pause
+                self.last_breakpoint = Some(err.downcast()?);
+                return Ok(());
             }
         }
     }
 
+    /// Starts a `FrameAdvance` or `RunFor`, replacing any that hasn't finished
+    fn begin_run_for(&mut self, updates_tx: &mpsc::SyncSender<UpdateMessage>, command: Command, cycles: u64) -> Result<(), anyhow::Error> {
+        self.cancel_run_for(updates_tx, "Replaced before it finished")?;
+        let now = self.now.lower_bound();
+        self.run_for = Some((now.add(cycles), command));
+        // The pacer might have been waiting at the end of a frame advance
+        if let Some(pacer) = &mut self.pacer {
+            pacer.resume(now.into());
+        }
+        Ok(())
+    }
+
+    /// Replies with an error to any unfinished `FrameAdvance` or `RunFor`
+    fn cancel_run_for(&mut self, updates_tx: &mpsc::SyncSender<UpdateMessage>, reason: &str) -> Result<(), anyhow::Error> {
+        if let Some((_, command)) = self.run_for.take() {
+            let err = CommandError { command, message: reason.to_string() };
+            updates_tx.send(UpdateMessage::Error(err))?;
+        }
+        Ok(())
+    }
+
+    /// Delivers every message before `target`, without delivering anything at or after it.
+    ///
+    /// Actors are limited to `target`, so anything running ahead (like a CPU) stops exactly on it.
+    /// The scheduler can be resumed with any of the run functions afterwards.
+    pub fn run_until(&mut self, target: Time) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        while self.next_time() < target {
+            if let Err(err) = self.advance(target) {
+                return Ok(StopReason::Breakpoint(err.downcast()?));
+            }
+        }
+        self.now = std::cmp::max(self.now, target);
+
+        Ok(StopReason::ReachedTime(target))
+    }
+
+    /// Runs `cycles` past `now()`
+    pub fn run_for(&mut self, cycles: u64) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        self.run_until(self.now.lower_bound().add(cycles))
+    }
+
+    /// Delivers a single message (or resolves a single lazy time)
+    pub fn step_message(&mut self) -> Result<StopReason<ActorNames>, anyhow::Error> {
+        match self.step(Time::MAX) {
+            Ok((sender, time)) => Ok(StopReason::Stepped { sender, time }),
+            Err(err) => Ok(StopReason::Breakpoint(err.downcast()?)),
+        }
+    }
+
+    /// Stop before delivering any message matching `breakpoint`
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint<ActorNames>) -> BreakpointId {
+        self.breakpoints.add(breakpoint)
+    }
+
+    /// Returns false if there was no such breakpoint
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.breakpoints.remove(id)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear()
+    }
+
+    /// The breakpoint that stopped the last `run`, if any
+    ///
+    /// `run_until`, `run_for` and `step_message` return the hit as a `StopReason` instead.
+    pub fn last_breakpoint(&self) -> Option<&BreakpointHit<ActorNames>> {
+        self.last_breakpoint.as_ref()
+    }
+
+    /// The message waiting in Sender's outbox, if it's of type Message
+    ///
+    /// After a breakpoint, this is the message that's about to be delivered.
+    pub fn pending_message<'a, Sender, Message>(&'a mut self) -> Option<(Time, &'a Message)>
+    where
+        Sender: Actor<ActorNames> + 'a,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message> + 'a,
+        Message: 'static,
+    {
+        let packet = self.actors.get::<Sender>().outbox.as_packet()?;
+        let time = packet.time;
+        packet.peek().map(|msg| (time, msg))
+    }
+
+    /// Time of the most recently delivered message, or the target of the last `run_until`
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    /// Pace `run` against the host's clock. Without a pacer, `run` goes as fast as it can
+    pub fn set_pacer(&mut self, pacer: Option<Pacer>) {
+        self.pacer = pacer;
+    }
+
+    /// Where the actors send their video, see `common::video::frame_channel`
+    pub fn set_frame_receiver(&mut self, frames: Option<FrameReceiver>) {
+        self.frames = frames;
+    }
+
+    /// Where input from `ControlMessage::Input` goes. The latch is only updated by `run`
+    pub fn set_input_latch(&mut self, input: Option<InputLatch>) {
+        self.input = input;
+    }
+
+    /// Queues input for the next latch point, see `common::Instance::set_input`
+    pub fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        let now = self.now.lower_bound().into();
+        match &mut self.input {
+            Some(input) => input.set(now, port, pad),
+            None => bail!("No input latch, so input on port {} can't be used", port),
+        }
+    }
+
+    /// Make `run` return once every message before `time` has been delivered, like `run_until`.
+    /// `Time::MAX` removes the limit
+    pub fn set_stop_time(&mut self, time: Time) {
+        self.stop_time = time;
+    }
+
+    /// The `ControlMessage::Reset` or `ControlMessage::Shutdown` that made the last `run`
+    /// return, if any
+    pub fn take_request(&mut self) -> Option<ControlMessage> {
+        self.request.take()
+    }
+
+    /// Press the reset button, see `Actor::soft_reset`
+    pub fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        self.actors.soft_reset()
+    }
+
+    /// Throw away every actor and start again from the config, back at time zero
+    ///
+    /// Debugging and host side settings (backend, threads, pacer, stop time, breakpoints, the
+    /// frame receiver and the input latch) carry over, statistics and traces don't. If the actors
+    /// fail to initialize, the old ones are kept.
+    pub fn power_cycle(&mut self) -> Result<(), anyhow::Error> {
+        let Some(config) = &self.config else {
+            bail!("Only the main scheduler can be power cycled");
+        };
+        let mut scheduler = Self::with_actors(ObjectStore::with(config)?, self.backend);
+        scheduler.init_queue();
+
+        scheduler.config = self.config.take();
+        scheduler.pacer = self.pacer.take();
+        scheduler.stop_time = self.stop_time;
+        scheduler.frames = self.frames.take();
+        scheduler.input = self.input.take();
+        scheduler.breakpoints = std::mem::replace(&mut self.breakpoints, Breakpoints::new());
+        let threads = self.threads();
+
+        // Workers are dropped first, they still point at the old actors
+        *self = scheduler;
+        self.set_threads(threads)
+    }
+
+    /// Does nothing without a pacer
+    pub fn set_speed(&mut self, speed: Speed) {
+        let now = self.now.lower_bound().into();
+        if let Some(pacer) = &mut self.pacer {
+            pacer.set_speed(speed, now);
+        }
+    }
+
+    /// See `Pacer::ratio`
+    pub fn speed_ratio(&self) -> Option<f64> {
+        self.pacer.as_ref().and_then(Pacer::ratio)
+    }
+
+    #[inline(always)]
+    fn step(&mut self, max_limit: Time) -> Result<(ActorNames, Time), anyhow::Error> {
+        let (sender_id, time, limit) = self.take_next();
+        let limit = std::cmp::min(limit, max_limit);
+        self.now = time;
+        //println!("Running actor {:?}. Next @ {}", sender_id, limit);
+
+        match self.run_inner(sender_id, limit) {
+            SchedulerResult::Ok => {
+                // Hot path
+            },
+            SchedulerResult::ZeroLimit if !self.backend.is_fifo() => {
+                // There are multiple messages scheduled to be delivered on the same cycle.
+                // And one of the receivers couldn't deal with the zero limit message, so we switch
+                // to a more complex scheduler until the current cycle finishes.
+                self.zero_limit_count += 1;
+                self.run_zero_limit(sender_id, time, limit)?;
+            },
+            SchedulerResult::ZeroLimit => {
+                self.zero_limit_count += 1;
+            },
+            SchedulerResult::Err(reason) => {
+                self.requeue_after_error(sender_id);
+                return Err(reason);
+            }
+        }
+        Ok((sender_id, time))
+    }
+
+    /// Delivers the next message, or with multiple threads, every message in the next window
+    #[inline(always)]
+    fn advance(&mut self, target: Time) -> Result<(), anyhow::Error> {
+        if self.workers.is_some() && self.tracer.is_none() && self.breakpoints.is_empty() {
+            self.run_parallel_window(target)
+        } else {
+            self.step(target).map(|_| ())
+        }
+    }
+
+    /// Delivers every message before the next window ends, splitting the actors across the
+    /// worker threads. See parallel.rs
     #[inline(never)]
-    pub fn run_zero_limit(&mut self, time: Time, limit: Time) -> Result<(), anyhow::Error> {
-        assert!(time == limit, "Actor incorrectly reported a zero limit");
+    fn run_parallel_window(&mut self, target: Time) -> Result<(), anyhow::Error> {
+        let start = u64::from(self.next_time());
+        let end = std::cmp::min(Time::from(start.saturating_add(self.lookahead)), target);
+
+        let order = self.queue_order();
+        let links: Vec<_> = order.iter()
+            .map(|&id| &self.actors.get_base(id).outbox)
+            .zip(order.iter())
+            .filter(|(outbox, _)| outbox.time.lower_bound() < end)
+            .map(|(outbox, &id)| (id, outbox.receiver()))
+            .collect();
+        let threads = self.threads();
+        let groups = parallel::group(&links, threads);
+
+        if groups.len() < 2 {
+            // Not worth handing over to the workers
+            while self.next_time() < end {
+                self.step(end)?;
+            }
+            return Ok(());
+        }
+
+        let mut group_of: EnumMap<Option<usize>, ActorNames> = EnumMap::new();
+        for (index, group) in groups.iter().enumerate() {
+            for &id in group {
+                group_of[id] = Some(index);
+            }
+        }
+
+        // Each worker gets its group's part of the queue, in the same order. Everything else stays
+        let mut group_queues = vec![Vec::new(); groups.len()];
+        let mut globals = vec![Vec::new(); groups.len()];
+        let mut queued = Vec::new();
+        for (seq, &id) in order.iter().enumerate() {
+            let time = self.get_time(id);
+            match group_of[id] {
+                Some(index) => {
+                    group_queues[index].push((id, time));
+                    globals[index].push(seq as u64);
+                }
+                None => queued.push((id, time, seq as u64)),
+            }
+        }
+
+        // The actors can't be touched from here until every worker is done with them
+        let jobs = group_queues.into_iter()
+            .zip(self.actors.split(&groups))
+            .map(|(queued, actors)| WindowJob { queued, end, actors })
+            .collect();
+        let mut results = self.workers.as_mut().unwrap().run(jobs);
+        let merged = parallel::merge(&mut results, globals, order.len() as u64);
+
+        for result in results {
+            self.count += result.runs;
+            self.zero_limit_count += result.zero_limits;
+            self.messages.merge(&result.messages);
+            self.profiler.merge(result.profiler);
+        }
+
+        queued.extend(merged.queued);
+        queued.sort_unstable_by_key(|&(_, time, seq)| (time, seq));
+        self.reset_queue();
+        for (id, time, _) in queued {
+            self.enqueue(id, time);
+        }
+        if let Some(time) = merged.last_time {
+            self.now = time.lower_bound();
+        }
+
+        match merged.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Worker side of `run_parallel_window`. Only ever called on a worker's own scheduler
+    fn run_window(&mut self, job: WindowJob<ActorNames>) -> WindowResult<ActorNames> {
+        debug_assert!(self.backend == SchedulerBackend::Heap);
+        self.actors = ObjectStore::from_group(job.actors);
+        self.window_end = job.end;
+        self.reset_queue();
+        for (id, time) in job.queued {
+            self.enqueue(id, time);
+        }
+
+        let mut steps = Vec::new();
+        let mut error = None;
+        while let Some((_, time, seq)) = self.heap.peek_seq() {
+            if time.lower_bound() >= job.end {
+                break;
+            }
+            let result = self.step(job.end);
+            steps.push(WindowStep { time, seq, seq_end: self.heap.next_seq() });
+            if let Err(err) = result {
+                error = Some(err);
+                break;
+            }
+        }
+
+        // Don't keep pointers to the actors past the window
+        self.actors = ObjectStore::from_group(ActorGroup::empty());
+
+        WindowResult {
+            steps,
+            queued: self.heap.entries(),
+            error,
+            runs: std::mem::take(&mut self.count),
+            zero_limits: std::mem::take(&mut self.zero_limit_count),
+            messages: std::mem::replace(&mut self.messages, MessageCounters::new()),
+            profiler: std::mem::replace(&mut self.profiler, Profiler::new()),
+        }
+    }
+
+    /// Queued actors, in the order they would be taken. The cached backends' cache isn't included
+    fn queue_order(&self) -> Vec<ActorNames> {
+        let mut order = Vec::new();
+        if self.backend == SchedulerBackend::Heap {
+            order.extend(self.heap.ordered());
+        }
+        let mut next = self.queue_head;
+        while let Some(id) = next {
+            order.push(id);
+            next = self.queue[id].next;
+        }
+        order
+    }
+
+    /// Lower bound of the next message, without taking anything from the queue
+    fn next_time(&self) -> Time {
+        if self.backend == SchedulerBackend::Branchless {
+            return ActorNames::iter()
+                .map(|id| self.get_time(id).lower_bound())
+                .min()
+                .unwrap_or(Time::MAX);
+        }
+
+        let head = match self.backend {
+            SchedulerBackend::Heap => self.heap.peek().map(|(id, _)| id),
+            _ => self.queue_head,
+        };
+        let queued = match head {
+            Some(id) => self.get_time(id).lower_bound(),
+            None => Time::MAX,
+        };
+        if self.backend.is_cached() {
+            self.cached.iter()
+                .map(|&id| self.get_time(id).lower_bound())
+                .fold(queued, std::cmp::min)
+        } else {
+            queued
+        }
+    }
+
+    /// An error can be returned before the sender was re-queued.
+    /// Put it back, so the scheduler can be resumed.
+    fn requeue_after_error(&mut self, id: ActorNames) {
+        let queued = match self.backend {
+            SchedulerBackend::LinkedList => self.queue_head == Some(id) || self.queue[id].prev.is_some(),
+            SchedulerBackend::Heap => self.heap.contains(id),
+            _ => return,
+        };
+        let time = self.get_time(id);
+        if time != Time::MAX && !queued {
+            self.enqueue(id, time);
+        }
+    }
+
+    #[inline(never)]
+    pub fn run_zero_limit(&mut self, sender: ActorNames, time: Time, limit: Time) -> Result<(), anyhow::Error> {
+        if time != limit {
+            return Err(self.zero_limit_report(time, ZeroLimitProblem::IncorrectLimit { sender, limit }, Vec::new()).into());
+        }
 
         // We might need to go though multiple iterations before this settles
-        for _ in 0..(ActorNames::COUNT * 3) {
-            for actor in ActorNames::iter() {
-                let message = self.actors.get_base(actor);
+        let iterations = ActorNames::COUNT * 3;
+        let mut deliveries = Vec::new();
+        for iteration in 0..iterations {
+            // Only the last few iterations are kept for the report
+            deliveries.retain(|delivery: &ZeroLimitDelivery<ActorNames>| delivery.iteration + ZERO_LIMIT_REPORTED > iteration);
 
-                if message.outbox.time.lower_bound() == time {
+            for actor in ActorNames::iter() {
+                if self.get_time(actor).lower_bound() == time {
+                    let message = self.zero_limit_message(actor);
+                    if self.backend.is_cached() && self.is_cached[actor] == UNCACHED {
+                        // Receivers expect the sender to be cached
+                        self.queue_remove(actor);
+                        self.cache_insert(actor);
+                    }
                     let result = self.run_inner(actor, time);
-                    match result {
-                        SchedulerResult::Ok | SchedulerResult::ZeroLimit => {},
+                    let zero_limit = match result {
+                        SchedulerResult::Ok => false,
+                        SchedulerResult::ZeroLimit => true,
                         SchedulerResult::Err(reason) => {
                             return Err(reason);
                         }
-                    }
+                    };
+                    deliveries.push(ZeroLimitDelivery { iteration, message, zero_limit });
                 }
             }
 
@@ -324,7 +999,206 @@ impl<ActorNames> Scheduler<ActorNames> where
                 return Ok(());
             }
         }
-        panic!("Zero limit cycle detected");
+        Err(self.zero_limit_report(time, ZeroLimitProblem::Unsettled { iterations }, deliveries).into())
+    }
+
+    fn zero_limit_message(&self, actor: ActorNames) -> ZeroLimitMessage<ActorNames> {
+        let outbox = &self.actors.get_base(actor).outbox;
+        ZeroLimitMessage {
+            sender: actor,
+            receiver: outbox.receiver(),
+            msg_type: self.actors.msg_type_name(actor),
+            lazy: outbox.time.is_lazy(),
+        }
+    }
+
+    #[cold]
+    fn zero_limit_report(&self, time: Time, problem: ZeroLimitProblem<ActorNames>, deliveries: Vec<ZeroLimitDelivery<ActorNames>>) -> ZeroLimitReport<ActorNames> {
+        let pending = ActorNames::iter()
+            .filter(|&actor| self.actors.contains(actor) && self.get_time(actor).lower_bound() == time)
+            .map(|actor| self.zero_limit_message(actor))
+            .collect();
+        ZeroLimitReport { time, problem, pending, deliveries }
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        save_state::write_header::<ActorNames>(&mut state);
+
+        state.section("Scheduler", |state| {
+            self.backend.save_state(state);
+            // Messages for the same cycle are delivered in queue order, so we can't just rebuild
+            // the queue from outbox times.
+            let order: Vec<usize> = self.queue_order().into_iter().map(|id| id.into()).collect();
+            order.save_state(state);
+            self.cached.map(|id| -> usize { id.into() }).save_state(state);
+            self.cache_limit.save_state(state);
+            self.now.save_state(state);
+        });
+
+        self.actors.save_state(&mut state);
+        state.into_inner()
+    }
+
+    /// If this returns an error, the scheduler and actors are left as they were
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), anyhow::Error> {
+        // Actors are restored in place, so a file that fails part way through would leave some
+        // of them loaded. Snapshot everything first and put it back on error.
+        let backup = self.save_state();
+        self.restore(data).inspect_err(|_| {
+            self.restore(&backup).expect("Failed to restore the state from before a failed load");
+        })
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), anyhow::Error> {
+        let mut state = StateReader::new(data);
+        save_state::read_header::<ActorNames>(&mut state)?;
+
+        let mut backend = self.backend;
+        let mut order = Vec::new();
+        let mut cached = [0; CACHE_SIZE];
+        let mut cache_limit = Time::MAX;
+        let mut now = Time::default();
+        state.section("Scheduler", |state| {
+            backend = SchedulerBackend::restore_state(state)?;
+            order = Vec::<usize>::restore_state(state)?;
+            cached = RestoreState::restore_state(state)?;
+            cache_limit = Time::restore_state(state)?;
+            now = Time::restore_state(state)?;
+            Ok(())
+        })?;
+        if backend != self.backend {
+            // The queue and cache can't be converted between backends
+            bail!("Save state was made with the {:?} scheduler backend, not {:?}", backend, self.backend);
+        }
+        if order.iter().chain(cached.iter()).any(|&id| id >= ActorNames::COUNT) {
+            bail!("Invalid actor id in save state");
+        }
+
+        self.actors.load_state(&mut state)?;
+        if !state.is_empty() {
+            bail!("Unexpected data at end of save state");
+        }
+
+        // The queue reads times from the outboxes, so can only be rebuilt after actors are loaded
+        self.reset_queue();
+        for id in order {
+            let id = ActorNames::from(id);
+            self.enqueue(id, self.get_time(id));
+        }
+        for (slot, id) in cached.into_iter().enumerate() {
+            let id = ActorNames::from(id);
+            self.cached[slot] = id;
+            if id != Self::EMPTY_CACHE {
+                self.is_cached[id] = slot as u8;
+                self.num_cache_entries += 1;
+            }
+        }
+        self.cache_limit = cache_limit;
+        self.now = now;
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> SchedulerStats<ActorNames> {
+        let queue_add_complexity = match self.count_queue_adds {
+            0 => 0.0,
+            adds => self.count_queue_add_complexity as f64 / adds as f64,
+        };
+
+        SchedulerStats {
+            runs: self.count,
+            zero_limits: self.zero_limit_count,
+            cache_inserts: self.count_cache_inserts,
+            queue_adds: self.count_queue_adds,
+            queue_add_complexity,
+            queue_removes: self.count_queue_removes,
+            actors: self.messages.actors(),
+            message_types: self.messages.message_types(),
+        }
+    }
+
+    /// Scheduler stats, plus any stats reported by actors
+    pub fn collect_stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        self.stats().add_to(&mut stats);
+        if let Some(pacer) = &self.pacer {
+            pacer.add_to(&mut stats);
+        }
+        self.actors.stats(&mut stats);
+        #[cfg(feature = "profiling")]
+        self.profiler.report().add_to(&mut stats);
+        stats
+    }
+
+    /// Host time spent in each actor since the last reset
+    #[cfg(feature = "profiling")]
+    pub fn profile_report(&self) -> crate::ProfileReport<ActorNames> {
+        self.profiler.report()
+    }
+
+    /// Resets all counters, including the actors' counters
+    pub fn reset_stats(&mut self) {
+        self.count = 0;
+        self.count_cache_inserts = 0;
+        self.count_queue_adds = 0;
+        self.count_queue_removes = 0;
+        self.count_queue_add_complexity = 0;
+        self.zero_limit_count = 0;
+        self.messages = MessageCounters::new();
+        self.profiler.reset();
+        self.actors.reset_stats();
+    }
+
+    /// Start recording every delivered message and resolved lazy time, discarding any previous
+    /// trace
+    pub fn record_trace(&mut self) {
+        self.tracer = Some(Tracer::Record(Vec::new()));
+    }
+
+    /// Check every delivered message against a previously recorded trace
+    ///
+    /// At the first difference, `run` returns a `TraceDivergence` error without delivering the
+    /// offending message. Verification stops once the whole trace has been matched.
+    pub fn verify_trace(&mut self, trace: Trace<ActorNames>) {
+        self.tracer = match trace.entries.is_empty() {
+            true => None,
+            false => Some(Tracer::Verify(trace, 0)),
+        };
+    }
+
+    /// Stop tracing. Returns the trace if one was being recorded
+    pub fn stop_trace(&mut self) -> Option<Trace<ActorNames>> {
+        match self.tracer.take() {
+            Some(Tracer::Record(entries)) => Some(Trace { entries }),
+            _ => None,
+        }
+    }
+
+    /// False once verification of a trace has finished
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn trace(&mut self, entry: TraceEntry<ActorNames>) -> Result<(), anyhow::Error> {
+        if let Some(tracer) = &mut self.tracer {
+            if !tracer.log(entry)? {
+                self.tracer = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_queue(&mut self) {
+        self.queue = EnumMap::from_fn(|_| QueueEntry { next: None, prev: None });
+        self.queue_head = None;
+        self.heap.clear();
+        self.is_cached = EnumMap::from_fn(|_| UNCACHED);
+        self.cached = [Self::EMPTY_CACHE; CACHE_SIZE];
+        self.cache_limit = Time::MAX;
+        self.num_cache_entries = 0;
     }
 }
 
@@ -338,6 +1212,28 @@ struct QueueEntry<ActorNames> where
 impl<ActorNames> Scheduler<ActorNames> where
     ActorNames: MakeNamed,
 {
+    /// Add to the backend's queue. Only the heap has its own, everything else uses the linked list
+    #[inline(always)]
+    fn enqueue(&mut self, id: ActorNames, time: Time) {
+        if self.backend == SchedulerBackend::Heap {
+            debug_assert!(time == self.get_time(id), "Time mismatch");
+            self.count_queue_adds += 1;
+            self.count_queue_add_complexity += 1 + self.heap.push(id, time);
+        } else {
+            self.queue_add(id, time);
+        }
+    }
+
+    #[inline(always)]
+    fn dequeue(&mut self, id: ActorNames) {
+        if self.backend == SchedulerBackend::Heap {
+            self.count_queue_removes += 1;
+            self.heap.remove(id);
+        } else {
+            self.queue_remove(id);
+        }
+    }
+
     fn queue_pop(&mut self) -> (Option<ActorNames>, Time, Time) {
         let sender_id = match self.queue_head.take() {
             Some(id) => id,
@@ -362,9 +1258,11 @@ impl<ActorNames> Scheduler<ActorNames> where
             },
             None => Time::MAX,
         };
-        return (Some(sender_id), self.get_time(sender_id), limit);
+        return (Some(sender_id), self.get_time(sender_id).lower_bound(), limit);
     }
 
+    /// Lazy times are sorted after resolved times on the same cycle, so any resolved messages get
+    /// delivered before the lazy time needs resolving
     #[inline(never)]
     fn queue_add(&mut self, id: ActorNames, time: Time) {
         debug_assert!(time == self.get_time(id), "Time mismatch");
@@ -382,7 +1280,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                 return;
             },
             Some(next_id) => {
-                if self.get_time(next_id).lower_bound() > time {
+                if self.get_time(next_id) > time {
                     self.queue_head = Some(id);
                     self.queue[id].prev = None;
                     self.queue[id].next = Some(next_id);
@@ -404,7 +1302,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                     return;
                 }
                 Some(next_id) => {
-                    let next_time = self.get_time(next_id).lower_bound();
+                    let next_time = self.get_time(next_id);
                     let next_actor = &mut self.queue[next_id];
                     if next_time <= time {
                         next = next_actor.next;
@@ -438,7 +1336,7 @@ impl<ActorNames> Scheduler<ActorNames> where
         debug_assert!(self.queue_head.is_some());
         if self.queue_head == Some(id) {
             match self.queue[id].next {
-                Some(next_id) if self.get_time(next_id).lower_bound() > time => { return; },
+                Some(next_id) if self.get_time(next_id) > time => { return; },
                 None => { return; },
                 Some(next_id) => {
                     self.queue_head = Some(next_id);
@@ -461,7 +1359,7 @@ impl<ActorNames> Scheduler<ActorNames> where
             // Safety: in a valid linked list, head will always be some here
             let next_id = unsafe { self.queue_head.unwrap_unchecked() };
 
-            if self.get_time(next_id).lower_bound() > time {
+            if self.get_time(next_id) > time {
                 // We can insert at the front
                 self.queue[id].next = Some(next_id);
                 self.queue[id].prev = None;
@@ -481,7 +1379,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                     return;
                 }
                 Some(next_id) => {
-                    let next_time = self.get_time(next_id).lower_bound();
+                    let next_time = self.get_time(next_id);
                     let next_actor = &mut self.queue[next_id];
                     if next_time <= time {
                         next = next_actor.next;
@@ -540,7 +1438,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                 if self.queue[next_id].prev != Some(id) {
                     self.queue_print();
                     panic!("actor {:?}'s next actor {:?} prev points to {:?} instead of {:?}", id, next_id, self.queue[next_id].prev, Some(id));
-                } else if self.get_time(next_id) < time {
+                } else if self.get_time(next_id).lower_bound() < time {
                     self.queue_print();
                     panic!("actor {:?}'s next actor {:?} has a lower time bound ({}) than {:?} ({})", id, next_id, self.get_time(next_id), id, time);
                 }
@@ -549,7 +1447,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                 if self.queue[prev_id].next != Some(id) {
                     self.queue_print();
                     panic!("actor {:?}'s prev actor {:?} next points to {:?} instead of {:?}", id, prev_id, self.queue[prev_id].next, Some(id));
-                } else if self.get_time(prev_id) > time {
+                } else if self.get_time(prev_id).lower_bound() > time {
                     self.queue_print();
                     panic!("actor {:?}'s prev actor {:?} has a higher time bound ({}) than {:?} ({})", id, prev_id, self.get_time(prev_id), id, time);
                 }
@@ -571,27 +1469,108 @@ impl<ActorNames> Scheduler<ActorNames> where
         actor.outbox.as_packet().and_then(|p| { p.take() })
     }
 
-    fn execute_message<Sender, Receiver, Message>(&mut self, msg: Message, time: Time, mut limit: Time) -> SchedulerResult
+    /// Everything before a message is handed to the receiver: resolving lazy times, breakpoints,
+    /// tracing and taking the message from the outbox
+    #[inline(always)]
+    fn take_for_delivery<Sender, Message>(&mut self, receiver: ActorNames, limit: Time) -> ControlFlow<SchedulerResult, (Time, Message)>
+    where
+        Sender: Actor<ActorNames>,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        if self.get_time(Sender::name()).is_lazy() {
+            if let Err(err) = self.trace_step::<Sender, Message>(receiver, limit, true) {
+                return ControlFlow::Break(SchedulerResult::Err(err));
+            }
+            return ControlFlow::Break(self.resolve_lazy::<Sender, Message>(limit));
+        }
+
+        if !self.actors.contains(receiver) {
+            return ControlFlow::Break(SchedulerResult::Err(self.lookahead_violation::<Sender>(receiver).into()));
+        }
+
+        if !self.breakpoints.is_empty() {
+            if let Some(hit) = self.check_breakpoints::<Sender, Message>(receiver) {
+                return ControlFlow::Break(SchedulerResult::Err(hit.into()));
+            }
+        }
+
+        if let Err(err) = self.trace_step::<Sender, Message>(receiver, limit, false) {
+            return ControlFlow::Break(SchedulerResult::Err(err));
+        }
+
+        self.messages.count::<Message>(Sender::name(), receiver);
+
+        // Safety: Type checked in MessagePacket::new/from_channel/from_endpoint
+        ControlFlow::Continue(unsafe {
+            self.take_message::<Sender, Message>().unwrap_unchecked()
+        })
+    }
+
+    /// Checked before anything runs, so a diverging message is left in the outbox
+    #[inline(always)]
+    fn trace_step<Sender, Message>(&mut self, receiver: ActorNames, limit: Time, resolve: bool) -> Result<(), anyhow::Error>
+    where
+        Sender: Actor<ActorNames>,
+        Message: 'static,
+    {
+        if self.tracer.is_none() {
+            return Ok(());
+        }
+        let time = self.get_time(Sender::name());
+        self.trace(TraceEntry::new::<Sender, Message>(receiver, time, limit, resolve))
+    }
+
+    /// The sender's half of delivering a message, which calls its `delivering` hook
+    #[inline(always)]
+    fn sender_delivering<Sender, Message>(&mut self, msg: &Message, time: Time) -> Delivered<ActorNames>
     where
         Sender: Actor<ActorNames>,
-        Receiver: Actor<ActorNames> + Handler<ActorNames, Message>,
         Message: 'static,
     {
         let sender = self.actors.get::<Sender>();
-        let before_delivered = sender.outbox.time();
-        sender.obj.delivering(&mut sender.outbox, &msg, time);
-        let after_delivered = sender.outbox.time();
+        let before = sender.outbox.time();
+        if let Some(timers) = sender.obj.timers() {
+            timers.delivered(&mut sender.outbox, msg, time);
+        }
+        let start = self.profiler.start();
+        sender.obj.delivering(&mut sender.outbox, msg, time);
+        self.profiler.record::<Message>(Sender::name(), ProfiledCall::Delivering, start);
+        timers::settle(&mut sender.obj, &mut sender.outbox);
+        let after = sender.outbox.time();
+
+        Delivered { sender: Sender::name(), before, after }
+    }
+
+    /// The receiver's half of delivering a message. Calls the handler then reschedules both actors.
+    ///
+    /// Only the receiver is known at compile time, so endpoints can use this too.
+    #[inline(always)]
+    fn receive<Receiver, Message>(&mut self, delivered: Delivered<ActorNames>, msg: Message, time: Time, mut limit: Time) -> SchedulerResult
+    where
+        Receiver: Actor<ActorNames> + Handler<ActorNames, Message>,
+        Message: 'static,
+    {
+        if delivered.sender == Receiver::name() {
+            return self.receive_self::<Receiver, Message>(delivered, msg, time, limit);
+        }
+
+        let Delivered { sender: sender_id, before: before_delivered, after: after_delivered } = delivered;
 
         // Update limit to take into account any new messages from sender
-        limit = std::cmp::min(limit, after_delivered);
+        limit = std::cmp::min(limit, after_delivered.lower_bound());
 
         let receiver = self.actors.get::<Receiver>();
 
         let before = receiver.outbox.time();
+        timers::unsettle(&mut receiver.obj, &mut receiver.outbox);
+        let start = self.profiler.start();
         let result = receiver.obj.recv(&mut receiver.outbox, msg, time, limit);
+        self.profiler.record::<Message>(Receiver::name(), ProfiledCall::Recv, start);
+        timers::settle(&mut receiver.obj, &mut receiver.outbox);
         let after = receiver.outbox.time();
 
-        if cfg!(feature = "cached") {
+        if self.backend.is_cached() {
             if self.is_cached[Receiver::name()] != UNCACHED {
                 return result;
             }
@@ -600,20 +1579,20 @@ impl<ActorNames> Scheduler<ActorNames> where
                 self.queue_remove(Receiver::name());
 
                 // receiver might have been the cache limit, so update it
-                if cfg!(feature = "updating_cache")  {
+                if self.backend == SchedulerBackend::UpdatingCache {
                     self.cache_limit = match self.queue_head {
-                        Some(head_id) => self.get_time(head_id),
+                        Some(head_id) => self.get_time(head_id).lower_bound(),
                         None => Time::MAX,
                     }
                 }
             }
 
             let empty_slot = if before_delivered == after_delivered {
-                Some(self.is_cached[Sender::name()] as usize)
+                Some(self.is_cached[sender_id] as usize)
             } else {
-                if cfg!(feature = "updating_cache") && after_delivered > self.cache_limit {
+                if self.backend == SchedulerBackend::UpdatingCache && after_delivered.lower_bound() > self.cache_limit {
                     // Sender needs to leave the cache
-                    Some(self.cache_remove(Sender::name(), after_delivered))
+                    Some(self.cache_remove(sender_id, after_delivered))
                 } else {
                     None
                 }
@@ -622,7 +1601,7 @@ impl<ActorNames> Scheduler<ActorNames> where
             if before != after {
                 match self.is_cached[Receiver::name()] {
                     UNCACHED => {
-                        if after < self.cache_limit {
+                        if after.lower_bound() < self.cache_limit {
                             match empty_slot {
                                 Some(slot) => {
                                     // We can take sender's position in the cache
@@ -632,7 +1611,7 @@ impl<ActorNames> Scheduler<ActorNames> where
                                     self.cache_insert(Receiver::name());
                                 }
                             }
-                        } else {
+                        } else if after != Time::MAX {
                             // Put receiver back into the queue
                             self.queue_add(Receiver::name(), after);
                         }
@@ -641,13 +1620,13 @@ impl<ActorNames> Scheduler<ActorNames> where
                 }
             }
         }
-        else if cfg!(feature = "linked_list") {
+        else if self.backend.is_fifo() {
             if before != after {
                 if before != Time::MAX {
-                    self.queue_remove(Receiver::name());
+                    self.dequeue(Receiver::name());
                 }
                 if after != Time::MAX {
-                    self.queue_add(Receiver::name(), after);
+                    self.enqueue(Receiver::name(), after);
                 }
             }
 
@@ -656,41 +1635,117 @@ impl<ActorNames> Scheduler<ActorNames> where
             //       code, so this generally produces better code
             if before_delivered != after_delivered {
                 debug_assert!(after_delivered != Time::MAX);
-                self.queue_add(Sender::name(), after_delivered);
+                self.enqueue(sender_id, after_delivered);
             }
         }
 
         result
     }
 
-    fn execute_message_self<Receiver, Message>(&mut self, msg: Message, time: Time, limit: Time) -> SchedulerResult
+    #[inline(always)]
+    fn receive_self<Receiver, Message>(&mut self, delivered: Delivered<ActorNames>, msg: Message, time: Time, limit: Time) -> SchedulerResult
     where
         Receiver: Actor<ActorNames> + Handler<ActorNames, Message>,
         Message: 'static,
     {
         let actor = self.actors.get::<Receiver>();
 
-        let before = actor.outbox.time();
-        actor.obj.delivering(&mut actor.outbox, &msg, time);
+        let before = delivered.before;
+        timers::unsettle(&mut actor.obj, &mut actor.outbox);
+        let start = self.profiler.start();
         let result = actor.obj.recv(&mut actor.outbox, msg, time, limit);
+        self.profiler.record::<Message>(Receiver::name(), ProfiledCall::Recv, start);
+        timers::settle(&mut actor.obj, &mut actor.outbox);
         let after = actor.outbox.time();
 
-        if cfg!(feature = "cached") {
+        if self.backend.is_cached() {
             debug_assert!(self.is_cached[Receiver::name()] != UNCACHED);
-            if cfg!(feature = "updating_cache") && before != after && after >= self.cache_limit {
+            if self.backend == SchedulerBackend::UpdatingCache && before != after && after.lower_bound() >= self.cache_limit {
                  // remove from cache
                  self.cache_remove(Receiver::name(), after);
             }
         }
-        else if cfg!(feature = "linked_list") && before != after {
+        else if self.backend.is_fifo() && before != after {
+            // The main scheduler loop already popped us from the queue
+            self.enqueue(Receiver::name(), after);
+        }
+
+        result
+    }
+
+    #[cold]
+    fn check_breakpoints<Sender, Message>(&mut self, receiver: ActorNames) -> Option<BreakpointHit<ActorNames>>
+    where
+        Sender: Actor<ActorNames>,
+        <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        let packet = self.actors.get::<Sender>().outbox.as_packet()?;
+        let time = packet.time;
+        let msg = packet.peek()?;
+        self.breakpoints.check(Sender::name(), receiver, time, msg)
+    }
+
+    #[cold]
+    fn lookahead_violation<Sender>(&self, receiver: ActorNames) -> LookaheadViolation<ActorNames>
+    where
+        Sender: Actor<ActorNames>,
+    {
+        LookaheadViolation {
+            sender: Sender::name(),
+            receiver,
+            msg_type: self.actors.msg_type_name(Sender::name()),
+            time: self.get_time(Sender::name()),
+            window_end: self.window_end,
+        }
+    }
+
+    /// Called instead of delivering when Sender's pending message has a lazy time
+    fn resolve_lazy<Sender, Message>(&mut self, limit: Time) -> SchedulerResult
+    where
+        Sender: Actor<ActorNames>,
+        Message: 'static,
+    {
+        let actor = self.actors.get::<Sender>();
+
+        let before = actor.outbox.time();
+        timers::unsettle(&mut actor.obj, &mut actor.outbox);
+        let start = self.profiler.start();
+        let result = actor.obj.resolve(&mut actor.outbox, limit);
+        self.profiler.record::<Message>(Sender::name(), ProfiledCall::Resolve, start);
+        timers::settle(&mut actor.obj, &mut actor.outbox);
+        let after = actor.outbox.time();
+        debug_assert!(before != after || !matches!(result, SchedulerResult::Ok),
+            "{:?} didn't make progress resolving {}", Sender::name(), before);
+
+        // Unlike a delivery, the message might still be in the outbox, so the sender always needs
+        // to be re-queued.
+        if self.backend.is_cached() {
+            debug_assert!(self.is_cached[Sender::name()] != UNCACHED);
+            if self.backend == SchedulerBackend::UpdatingCache && before != after && after.lower_bound() >= self.cache_limit {
+                 self.cache_remove(Sender::name(), after);
+            }
+        }
+        else if self.backend.is_fifo() && after != Time::MAX {
             // The main scheduler loop already popped us from the queue
-            self.queue_add(Receiver::name(), after);
+            self.enqueue(Sender::name(), after);
         }
 
         result
     }
 }
 
+/// Why `run_until`, `run_for` or `step_message` returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason<ActorNames> {
+    /// Every message before this time has been delivered
+    ReachedTime(Time),
+    /// A single message sent by `sender` was delivered at `time`
+    Stepped { sender: ActorNames, time: Time },
+    /// Stopped before delivering a message. It's still waiting in the sender's outbox
+    Breakpoint(BreakpointHit<ActorNames>),
+}
+
 #[derive(Debug)]
 pub enum SchedulerResult
 {
@@ -710,45 +1765,41 @@ where
     Sender: Actor<ActorNames>,
     <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
 {
-    // Safety: Type checked in MessagePacket::new
-    let (time, message) = unsafe {
-        scheduler.take_message::<Sender, Message>().unwrap_unchecked()
+    let (time, message) = match scheduler.take_for_delivery::<Sender, Message>(Receiver::name(), limit) {
+        ControlFlow::Continue(taken) => taken,
+        ControlFlow::Break(result) => return result,
     };
 
     //println!("{:?} -> {:?} @ ({})", Sender::name(), Receiver::name(), time);
 
-    if Receiver::name() == Sender::name() {
-        scheduler.execute_message_self::<Receiver, Message>(message, time, limit)
-    } else {
-        scheduler.execute_message::<Sender, Receiver, _>(message, time, limit)
-    }
+    let delivered = scheduler.sender_delivering::<Sender, Message>(&message, time);
+    scheduler.receive::<Receiver, Message>(delivered, message, time, limit)
 }
 
-pub(super) fn endpoint_execute<'a, ActorNames, Sender, Message>(_: ActorNames, _scheduler: &'a mut Scheduler<ActorNames>, _limit: Time) -> SchedulerResult
+/// Like `direct_execute`, except the Receiver is only known at runtime.
+///
+/// Dispatches to the `receive_for_endpoint` instance stored in the packet by `Endpoint::new`
+pub(super) fn endpoint_execute<'a, ActorNames, Sender, Message>(_: ActorNames, scheduler: &'a mut Scheduler<ActorNames>, limit: Time) -> SchedulerResult
 where
     ActorNames: MakeNamed,
     Message: 'static,
     Sender: Actor<ActorNames>,
     <Sender as Actor<ActorNames>>::OutboxType: OutboxSend<ActorNames, Message>,
 {
-    todo!();
-    // let mut packet_view = scheduler.actors.get_view::<Sender>().map(
-    //     |actor_box| {
-    //         let packet = actor_box.outbox.as_packet();
-    //         // Safety: Type checked in MessagePacket::from_endpoint
-    //         unsafe { packet.unwrap_unchecked() }
-    //     }
-    // );
-    // let (endpoint_fn, time) =
-    //     packet_view.run(|p| (p.endpoint_fn.clone(), p.time.clone()));
-
-    // // Safety: Type checked in MessagePacket::from_endpoint
-    // let endpoint_fn = unsafe { endpoint_fn.assume_init() };
-
-    // let result = (endpoint_fn)(packet_view, limit);
+    // Safety: Type checked in MessagePacket::from_endpoint, which always sets endpoint_fn, and
+    //         Channel::restore_state, which sets the endpoint_fn that from_channel copies
+    let (receiver, endpoint_fn) = unsafe {
+        let packet = scheduler.actors.get::<Sender>().outbox.as_packet().unwrap_unchecked();
+        (packet.receiver(), packet.endpoint_fn.unwrap_unchecked())
+    };
 
+    let (time, message) = match scheduler.take_for_delivery::<Sender, Message>(receiver, limit) {
+        ControlFlow::Continue(taken) => taken,
+        ControlFlow::Break(result) => return result,
+    };
 
-    // result
+    let delivered = scheduler.sender_delivering::<Sender, Message>(&message, time);
+    (endpoint_fn)(scheduler, delivered, message, time, limit)
 }
 
 fn null_execute<ActorNames>(_: ActorNames, _: &mut Scheduler<ActorNames>, _: Time) -> SchedulerResult
@@ -766,24 +1817,33 @@ where
 }
 
 pub(super) type EndpointFn<ActorNames, Message> = for<'a> fn(
-    packet_view: ObjectStoreView<'a, ActorNames, MessagePacket<ActorNames, Message>>,
-    limit: Time
+    scheduler: &'a mut Scheduler<ActorNames>,
+    delivered: Delivered<ActorNames>,
+    message: Message,
+    time: Time,
+    limit: Time,
 ) -> SchedulerResult;
 
 pub(super) fn receive_for_endpoint<ActorNames, Receiver, Message>(
-    mut packet_view: ObjectStoreView<'_, ActorNames, MessagePacket<ActorNames, Message>>,
-    limit: Time
+    scheduler: &mut Scheduler<ActorNames>,
+    delivered: Delivered<ActorNames>,
+    message: Message,
+    time: Time,
+    limit: Time,
 ) -> SchedulerResult
 where
     ActorNames: MakeNamed,
     Receiver: Handler<ActorNames, Message> + Actor<ActorNames>,
+    Message: 'static,
 {
-    let option = packet_view.run(|p| p.take() );
-    // Safety: Type checked in Endpoint::new + MessagePacket::from_endpoint
-    let (time, message) = unsafe { option.unwrap_unchecked() };
-
-    let receiver = packet_view.close().get::<Receiver>();
-    let _result = receiver.obj.recv(&mut receiver.outbox, message, time, limit);
+    scheduler.receive::<Receiver, Message>(delivered, message, time, limit)
+}
 
-    todo!("Reschedule receiver if wrote to outbox")
+/// The sender's side of a delivery, passed from `direct_execute`/`endpoint_execute` to `receive`
+#[derive(Clone, Copy)]
+pub(super) struct Delivered<ActorNames> {
+    sender: ActorNames,
+    /// The sender's outbox time, before and after its `delivering` hook
+    before: Time,
+    after: Time,
 }
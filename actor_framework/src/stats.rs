@@ -0,0 +1,156 @@
+#[cfg(feature = "profiling")]
+use std::{any::TypeId, collections::HashMap, hash::{BuildHasherDefault, Hasher}};
+
+use common::stats::Stats;
+
+use crate::{EnumMap, MakeNamed};
+
+/// A snapshot of the scheduler's counters since the last reset
+#[derive(Debug, Clone)]
+pub struct SchedulerStats<ActorNames> {
+    /// Number of times an actor was run, either to deliver a message or resolve a lazy time
+    pub runs: u64,
+    pub zero_limits: u64,
+    pub cache_inserts: u64,
+    pub queue_adds: u64,
+    /// Average number of queue entries visited by each queue add
+    pub queue_add_complexity: f64,
+    pub queue_removes: u64,
+    /// Messages sent and received by each actor
+    pub actors: Vec<ActorMessageCounts<ActorNames>>,
+    /// Messages delivered, by type name. Sorted from most to least common
+    ///
+    /// Counting by type costs a lookup per message, so it's only done with the "profiling" feature
+    pub message_types: Vec<(&'static str, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActorMessageCounts<ActorNames> {
+    pub actor: ActorNames,
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl<ActorNames> SchedulerStats<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub fn add_to(&self, stats: &mut Stats) {
+        let scheduler = stats.group("Scheduler");
+        scheduler.count("Runs", self.runs);
+        scheduler.count("Zero limits", self.zero_limits);
+        scheduler.count("Cache inserts", self.cache_inserts);
+        scheduler.count("Queue adds", self.queue_adds);
+        scheduler.ratio("Queue add complexity", self.queue_add_complexity);
+        scheduler.count("Queue removes", self.queue_removes);
+
+        let actors = stats.group("Messages by actor (sent/received)");
+        for counts in &self.actors {
+            actors.count(format!("{:?} sent", counts.actor), counts.sent);
+            actors.count(format!("{:?} received", counts.actor), counts.received);
+        }
+
+        let message_types = stats.group("Messages by type");
+        for &(name, count) in &self.message_types {
+            message_types.count(name, count);
+        }
+    }
+}
+
+pub(crate) struct MessageCounters<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    sent: EnumMap<u64, ActorNames>,
+    received: EnumMap<u64, ActorNames>,
+    #[cfg(feature = "profiling")]
+    types: HashMap<TypeId, (&'static str, u64), BuildHasherDefault<TypeIdHasher>>,
+}
+
+impl<ActorNames> MessageCounters<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub(crate) fn new() -> Self {
+        MessageCounters {
+            sent: EnumMap::new(),
+            received: EnumMap::new(),
+            #[cfg(feature = "profiling")]
+            types: HashMap::default(),
+        }
+    }
+
+    #[inline(always)]
+    #[cfg_attr(not(feature = "profiling"), allow(clippy::extra_unused_type_parameters))]
+    pub(crate) fn count<Message>(&mut self, sender: ActorNames, receiver: ActorNames)
+    where
+        Message: 'static,
+    {
+        self.sent[sender] += 1;
+        self.received[receiver] += 1;
+        #[cfg(feature = "profiling")]
+        {
+            self.types
+                .entry(TypeId::of::<Message>())
+                .or_insert((std::any::type_name::<Message>(), 0))
+                .1 += 1;
+        }
+    }
+
+    /// Add counts from another scheduler, such as a parallel worker
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for actor in ActorNames::iter() {
+            self.sent[actor] += other.sent[actor];
+            self.received[actor] += other.received[actor];
+        }
+        #[cfg(feature = "profiling")]
+        for (&type_id, &(name, count)) in &other.types {
+            self.types.entry(type_id).or_insert((name, 0)).1 += count;
+        }
+    }
+
+    pub(crate) fn actors(&self) -> Vec<ActorMessageCounts<ActorNames>> {
+        ActorNames::iter()
+            .filter(|&actor| actor != ActorNames::TERMINAL)
+            .map(|actor| ActorMessageCounts {
+                actor,
+                sent: self.sent[actor],
+                received: self.received[actor],
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "profiling")]
+    pub(crate) fn message_types(&self) -> Vec<(&'static str, u64)> {
+        let mut types: Vec<_> = self.types.values().copied().collect();
+        types.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        types
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    pub(crate) fn message_types(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+}
+
+/// TypeIds are already hashes, no point hashing them again in the hot path
+#[cfg(feature = "profiling")]
+#[derive(Default)]
+struct TypeIdHasher(u64);
+
+#[cfg(feature = "profiling")]
+impl Hasher for TypeIdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ byte as u64;
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 ^= value;
+    }
+}
@@ -1,35 +1,61 @@
 use std::fmt::Display;
 
+use common::impl_save_state;
 
 
+
+/// A point in time, measured in cycles
+///
+/// A time can be lazy, in which case `cycles` is only a lower bound. The actor that published it
+/// might move it later, and the scheduler will ask that actor to resolve it (see `Actor::resolve`)
+/// once nothing else can happen before the lower bound.
+///
+/// Lazy and resolved times with the same lower bound are not equal, a resolved time orders first.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Time {
-    // TODO: allow lazy times
-    pub(crate) cycles: u64
+    pub(crate) cycles: u64,
+    pub(crate) lazy: bool,
 }
 
 impl Time {
     pub const MAX: Self = Time {
-        cycles: u64::MAX
+        cycles: u64::MAX,
+        lazy: false,
     };
 
     pub fn is_resolved(&self) -> bool {
-        // TODO: allow lazy times
-        self.cycles != u64::MAX && self.cycles != 0
+        !self.lazy && self.cycles != u64::MAX && self.cycles != 0
+    }
+
+    #[inline(always)]
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    /// Turns this time into a lazy time, with this time as the lower bound
+    #[inline(always)]
+    pub fn into_lazy(self) -> Self {
+        debug_assert!(self != Time::MAX);
+        Time {
+            cycles: self.cycles,
+            lazy: true,
+        }
     }
 
     #[inline(always)]
     pub fn add(self, other: u64) -> Self {
         Time {
-            cycles: self.cycles + other
+            cycles: self.cycles + other,
+            lazy: self.lazy,
         }
     }
 
+    /// The earliest this time could be. Always resolved
     #[inline(always)]
     pub fn lower_bound(&self) -> Self {
-        // TODO: once we have lazy times, this will be the minimum of the lazy time
         Time {
-            cycles: self.cycles
+            cycles: self.cycles,
+            lazy: false,
         }
     }
 
@@ -39,17 +65,20 @@ impl Time {
         time
     }
 
-    // Once we have lazy times, hash should return a value that will change if the time changes
+    /// Returns a value that changes whenever the time changes, including when a lazy time resolves
     pub fn hash(&self) -> u64 {
-        self.cycles
+        (self.cycles << 1) | self.lazy as u64
     }
 }
 
+impl_save_state!(Time { cycles, lazy });
+
 impl Default for Time {
     #[inline(always)]
     fn default() -> Self {
         Time {
             cycles: 0,
+            lazy: false,
         }
     }
 }
@@ -65,7 +94,8 @@ impl From<u64> for Time {
     #[inline(always)]
     fn from(cycles: u64) -> Self {
         Time {
-            cycles
+            cycles,
+            lazy: false,
         }
     }
 }
@@ -74,8 +104,185 @@ impl Display for Time {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.cycles == u64::MAX {
             write!(f, "Time::MAX")
+        } else if self.lazy {
+            write!(f, "cycle {} (lazy)", self.cycles)
         } else {
             write!(f, "cycle {}", self.cycles)
         }
     }
-}
\ No newline at end of file
+}
+/// A clock that runs at a fixed rational ratio to scheduler time
+///
+/// `ticks` local cycles happen every `cycles` scheduler cycles, with local cycle 0 starting on
+/// scheduler cycle `phase`. Conversions round the same way hardware would see them:
+///
+///  - `to_local` is the local cycle in progress at a scheduler time (rounds down)
+///  - `to_scheduler` is the first scheduler cycle a local cycle is visible on (rounds up)
+///
+/// So `to_scheduler(to_local(time)) <= time` (after the phase), and `to_local(to_scheduler(n)) >= n`. For clocks
+/// that are no faster than the scheduler, the second is always equal, see `is_reversible`.
+///
+/// ```ignore
+/// const VI_CLOCK: ClockDomain = ClockDomain::new("VI", 484, 625); // 48.4 MHz vs 62.5 MHz
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ClockDomain {
+    pub name: &'static str,
+    ticks: u64,
+    cycles: u64,
+    phase: u64,
+}
+
+impl ClockDomain {
+    /// `ticks` local cycles for every `cycles` scheduler cycles
+    pub const fn new(name: &'static str, ticks: u64, cycles: u64) -> Self {
+        assert!(ticks != 0 && cycles != 0, "Clock ratio can't be zero");
+
+        let (mut a, mut b) = (ticks, cycles);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        ClockDomain { name, ticks: ticks / a, cycles: cycles / a, phase: 0 }
+    }
+
+    /// Start local cycle 0 on scheduler cycle `phase`, instead of 0
+    pub const fn with_phase(self, phase: u64) -> Self {
+        ClockDomain { phase, ..self }
+    }
+
+    /// The ratio, reduced to its lowest terms: (ticks, cycles)
+    pub const fn ratio(&self) -> (u64, u64) {
+        (self.ticks, self.cycles)
+    }
+
+    /// True if `to_local(to_scheduler(n)) == n` for every local cycle n
+    ///
+    /// Only when the local clock is no faster than the scheduler. Otherwise some local cycles
+    /// start part way through a scheduler cycle, and round up to the same scheduler cycle as the
+    /// next local cycle.
+    pub const fn is_reversible(&self) -> bool {
+        self.ticks <= self.cycles
+    }
+
+    /// The local cycle in progress at `time`. Lazy times use their lower bound, and times before
+    /// the phase count as cycle 0
+    pub fn to_local(&self, time: Time) -> u64 {
+        if time == Time::MAX {
+            return u64::MAX;
+        }
+        let elapsed = time.cycles.saturating_sub(self.phase) as u128;
+        Self::saturate(elapsed * self.ticks as u128 / self.cycles as u128)
+    }
+
+    /// The first local cycle that starts at or after `time`
+    pub fn to_local_ceil(&self, time: Time) -> u64 {
+        if time == Time::MAX {
+            return u64::MAX;
+        }
+        let elapsed = time.cycles.saturating_sub(self.phase) as u128;
+        Self::saturate((elapsed * self.ticks as u128).div_ceil(self.cycles as u128))
+    }
+
+    /// The first scheduler cycle where local cycle `local` has started
+    pub fn to_scheduler(&self, local: u64) -> Time {
+        if local == u64::MAX {
+            return Time::MAX;
+        }
+        let elapsed = (local as u128 * self.cycles as u128).div_ceil(self.ticks as u128);
+        let time = Time::from(Self::saturate(elapsed + self.phase as u128));
+
+        debug_assert!(time == Time::MAX || self.to_local(time) >= local,
+            "{} cycle {} converted to {}, which is too early", self.name, local, time);
+        debug_assert!(time == Time::MAX || time.cycles <= self.phase || self.to_local(Time::from(time.cycles - 1)) < local,
+            "{} cycle {} converted to {}, which isn't the earliest", self.name, local, time);
+        debug_assert!(!self.is_reversible() || time == Time::MAX || self.to_local(time) == local,
+            "{} cycle {} doesn't convert back from {}", self.name, local, time);
+
+        time
+    }
+
+    /// Rounds `time` up to the start of the next local cycle
+    pub fn next_edge(&self, time: Time) -> Time {
+        self.to_scheduler(self.to_local_ceil(time))
+    }
+
+    /// The scheduler time `local_cycles` after the next edge
+    pub fn add(&self, time: Time, local_cycles: u64) -> Time {
+        self.to_scheduler(self.to_local_ceil(time).saturating_add(local_cycles))
+    }
+
+    fn saturate(value: u128) -> u64 {
+        value.try_into().unwrap_or(u64::MAX)
+    }
+}
+
+impl Display for ClockDomain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}/{}", self.name, self.ticks, self.cycles)?;
+        if self.phase != 0 {
+            write!(f, ", phase {}", self.phase)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAINS: [ClockDomain; 5] = [
+        ClockDomain::new("VR4300", 3, 2),
+        ClockDomain::new("VI", 484, 625),
+        ClockDomain::new("SI", 1, 4),
+        ClockDomain::new("Same", 7, 7),
+        ClockDomain::new("Phased", 2, 5).with_phase(3),
+    ];
+
+    #[test]
+    fn reduced_ratio() {
+        assert_eq!(ClockDomain::new("Same", 7, 7).ratio(), (1, 1));
+        assert_eq!(ClockDomain::new("Double", 10, 5).ratio(), (2, 1));
+        assert_eq!(ClockDomain::new("VI", 484, 625).ratio(), (484, 625));
+    }
+
+    #[test]
+    fn round_trips() {
+        for domain in DOMAINS {
+            for cycles in 0..5000 {
+                let time = Time::from(cycles);
+                let local = domain.to_local(time);
+                assert!(cycles < domain.phase || domain.to_scheduler(local) <= time, "{} {}", domain, time);
+                assert!(domain.next_edge(time) >= time, "{} {}", domain, time);
+                assert_eq!(domain.to_scheduler(domain.to_local_ceil(time)), domain.next_edge(time));
+            }
+            for local in 0..5000 {
+                let round_trip = domain.to_local(domain.to_scheduler(local));
+                assert!(round_trip >= local, "{} {}", domain, local);
+                assert!(!domain.is_reversible() || round_trip == local, "{} {}", domain, local);
+            }
+        }
+    }
+
+    #[test]
+    fn rounding() {
+        let vr4300 = DOMAINS[0];
+        // Extra CPU cycles happen on odd scheduler cycles
+        let per_cycle: Vec<u64> = (0..6)
+            .map(|t| vr4300.to_local(Time::from(t + 1)) - vr4300.to_local(Time::from(t)))
+            .collect();
+        assert_eq!(per_cycle, [1, 2, 1, 2, 1, 2]);
+
+        let si = DOMAINS[2];
+        assert_eq!(si.next_edge(Time::from(5)), Time::from(8));
+        assert_eq!(si.next_edge(Time::from(8)), Time::from(8));
+        assert_eq!(si.add(Time::from(5), 12), Time::from(8 + 4 * 12));
+
+        let phased = DOMAINS[4];
+        assert_eq!(phased.to_local(Time::from(2)), 0);
+        assert_eq!(phased.to_scheduler(0), Time::from(3));
+        assert_eq!(phased.to_scheduler(1), Time::from(6));
+
+        assert_eq!(vr4300.to_local(Time::MAX), u64::MAX);
+        assert_eq!(vr4300.to_scheduler(u64::MAX), Time::MAX);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{any::Any, borrow::Cow, marker::PhantomData};
+
+use crate::{Actor, Handler, MakeNamed, Outbox, OutboxSend, RestoreState, SaveState, StateReader, StateWriter, Time};
+
+/// Periodic and one-shot timers, which deliver messages to the actor that armed them without
+/// using up its outbox
+///
+/// Each timer is identified by its message type, which must be in the actor's outbox, and is
+/// delivered to the actor's own `Handler` like any other message. Arming a timer replaces any
+/// timer with the same message type.
+///
+/// While a timer is due before the message in the outbox, the scheduler swaps the timer's
+/// message into the outbox so it gets scheduled, and swaps it back out before calling any of the
+/// actor's hooks. So actors never see timers in their outbox, and can send as usual. A timer due
+/// on the same cycle as the outbox's message is delivered after it.
+///
+/// To opt in, an actor keeps a `Timers` and returns it from `Actor::timers`.
+pub struct Timers<ActorNames, O> {
+    /// Each timer's message waits in an outbox of its own. In the order they were armed
+    timers: Vec<Timer<O>>,
+    /// The actor's own message, while a timer is in the outbox
+    stashed: O,
+    /// The timer in the outbox. Its slot is empty until it's swapped back
+    firing: Option<usize>,
+    names: PhantomData<ActorNames>,
+}
+
+/// Makes the next message for a periodic timer, from a copy of the one just delivered
+pub type RearmFn<O> = fn(&dyn Any, Time) -> O;
+
+struct Timer<O> {
+    slot: O,
+    /// Zero for one-shot timers
+    period: u64,
+    rearm: Option<RearmFn<O>>,
+    /// The slot is empty while the timer fires, so this is kept to save `rearm` by
+    msg_type: Cow<'static, str>,
+}
+
+impl<ActorNames, O> Timers<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    pub fn new() -> Self {
+        Timers {
+            timers: Vec::new(),
+            stashed: O::default(),
+            firing: None,
+            names: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Deliver `message` once, at `time`
+    pub fn once<Message>(&mut self, message: Message, time: Time)
+    where
+        O: OutboxSend<ActorNames, Message>,
+        O::Sender: Handler<ActorNames, Message>,
+        Message: 'static,
+    {
+        self.arm::<Message>(Self::slot(message, time), 0, None);
+    }
+
+    /// Deliver `message` at `first`, then every `period` cycles until cancelled
+    pub fn every<Message>(&mut self, message: Message, first: Time, period: u64)
+    where
+        O: OutboxSend<ActorNames, Message>,
+        O::Sender: Handler<ActorNames, Message>,
+        Message: Clone + 'static,
+    {
+        assert!(period != 0, "Periodic timer for {} needs a period", std::any::type_name::<Message>());
+        let rearm: RearmFn<O> = Self::rearm::<Message>;
+        self.arm::<Message>(Self::slot(message, first), period, Some(rearm));
+    }
+
+    /// Returns the time the timer was next due, or None if it wasn't armed
+    pub fn cancel<Message>(&mut self) -> Option<Time>
+    where
+        O: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        let idx = self.position::<Message>()?;
+        Some(self.timers.remove(idx).slot.time())
+    }
+
+    /// When the timer is next due
+    pub fn due<Message>(&mut self) -> Option<Time>
+    where
+        O: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        let idx = self.position::<Message>()?;
+        Some(self.timers[idx].slot.time())
+    }
+
+    fn arm<Message>(&mut self, slot: O, period: u64, rearm: Option<RearmFn<O>>)
+    where
+        O: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        debug_assert!(self.firing.is_none(), "Timers can only be armed from the actor's hooks");
+        debug_assert!(!slot.time().is_lazy(), "Timers can't have a lazy time");
+        self.cancel::<Message>();
+        let msg_type = Cow::Borrowed(std::any::type_name::<Message>());
+        self.timers.push(Timer { slot, period, rearm, msg_type });
+    }
+
+    fn position<Message>(&mut self) -> Option<usize>
+    where
+        O: OutboxSend<ActorNames, Message>,
+        Message: 'static,
+    {
+        self.timers.iter_mut()
+            .position(|timer| OutboxSend::<ActorNames, Message>::as_packet(&mut timer.slot).is_some())
+    }
+
+    fn slot<Message>(message: Message, time: Time) -> O
+    where
+        O: OutboxSend<ActorNames, Message>,
+        O::Sender: Handler<ActorNames, Message>,
+        Message: 'static,
+    {
+        let mut slot = O::default();
+        let _ = slot.send::<O::Sender>(message, time);
+        slot
+    }
+
+    fn rearm<Message>(delivered: &dyn Any, time: Time) -> O
+    where
+        O: OutboxSend<ActorNames, Message>,
+        O::Sender: Handler<ActorNames, Message>,
+        Message: Clone + 'static,
+    {
+        let message = delivered.downcast_ref::<Message>().expect("Timer delivered the wrong message type");
+        Self::slot(message.clone(), time)
+    }
+}
+
+impl<ActorNames, O> Timers<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames>,
+{
+    /// After the actor's hooks have run, move the earliest timer into the outbox if it's due first
+    #[inline(always)]
+    pub(crate) fn settle(&mut self, outbox: &mut O) {
+        if self.timers.is_empty() {
+            return;
+        }
+        debug_assert!(self.firing.is_none());
+
+        let (idx, time) = self.timers.iter()
+            .enumerate()
+            .map(|(idx, timer)| (idx, timer.slot.time()))
+            .min_by_key(|&(idx, time)| (time, idx))
+            .unwrap();
+        if time < outbox.time() {
+            outbox.stash(&mut self.stashed);
+            outbox.restore(&mut self.timers[idx].slot);
+            self.firing = Some(idx);
+        }
+    }
+
+    /// Before any of the actor's hooks run, put its own message back in the outbox
+    #[inline(always)]
+    pub(crate) fn unsettle(&mut self, outbox: &mut O) {
+        if let Some(idx) = self.firing.take() {
+            outbox.stash(&mut self.timers[idx].slot);
+            outbox.restore(&mut self.stashed);
+        }
+    }
+
+    /// Like `unsettle`, but after a message was taken from the outbox for delivery. If it was a
+    /// timer, periodic timers are re-armed and one-shot timers are removed.
+    #[inline(always)]
+    pub(crate) fn delivered(&mut self, outbox: &mut O, message: &dyn Any, time: Time) {
+        if let Some(idx) = self.firing.take() {
+            let timer = &mut self.timers[idx];
+            match timer.rearm {
+                Some(rearm) => timer.slot = rearm(message, time.add(timer.period)),
+                None => { self.timers.remove(idx); }
+            }
+            outbox.restore(&mut self.stashed);
+        }
+    }
+}
+
+/// Lets `make_outbox!` find `Timers::rearm` for the messages that can be periodic timers, which
+/// are the ones that are `Clone`. Called as `(&RearmProbe::new()).rearm_fn()`, so `RearmClone`
+/// is picked when its bounds hold, and `RearmOther` otherwise.
+#[doc(hidden)]
+pub struct RearmProbe<ActorNames, O, Message>(PhantomData<(ActorNames, O, Message)>);
+
+impl<ActorNames, O, Message> RearmProbe<ActorNames, O, Message> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        RearmProbe(PhantomData)
+    }
+}
+
+#[doc(hidden)]
+pub trait RearmClone<O> {
+    fn rearm_fn(&self) -> Option<RearmFn<O>>;
+}
+
+impl<ActorNames, O, Message> RearmClone<O> for RearmProbe<ActorNames, O, Message>
+where
+    ActorNames: MakeNamed,
+    O: OutboxSend<ActorNames, Message> + Outbox<ActorNames> + Default,
+    O::Sender: Handler<ActorNames, Message>,
+    Message: Clone + 'static,
+{
+    fn rearm_fn(&self) -> Option<RearmFn<O>> {
+        Some(Timers::<ActorNames, O>::rearm::<Message>)
+    }
+}
+
+#[doc(hidden)]
+pub trait RearmOther<O> {
+    fn rearm_fn(&self) -> Option<RearmFn<O>>;
+}
+
+impl<ActorNames, O, Message> RearmOther<O> for &RearmProbe<ActorNames, O, Message> {
+    fn rearm_fn(&self) -> Option<RearmFn<O>> {
+        None
+    }
+}
+
+impl<ActorNames, O> Default for Timers<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ActorNames, O> SaveState for Timers<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.timers.len().save_state(state);
+        for timer in &self.timers {
+            timer.slot.save_state(state);
+            timer.period.save_state(state);
+            state.write_str(&timer.msg_type);
+            timer.rearm.is_some().save_state(state);
+        }
+        self.stashed.save_state(state);
+        self.firing.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        let len = usize::restore_state(state)?;
+        self.timers = (0..len)
+            .map(|_| {
+                let mut slot = O::default();
+                slot.load_state(state)?;
+                let period = u64::restore_state(state)?;
+                let msg_type = String::restore_state(state)?;
+                let rearm = match bool::restore_state(state)? {
+                    true => match O::rearm_fn(&msg_type) {
+                        Some(rearm) => Some(rearm),
+                        None => anyhow::bail!("{} can't have a periodic {} timer", std::any::type_name::<O>(), msg_type),
+                    },
+                    false => None,
+                };
+                Ok(Timer { slot, period, rearm, msg_type: Cow::Owned(msg_type) })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        self.stashed.load_state(state)?;
+        self.firing = Option::<usize>::restore_state(state)?;
+        if self.firing.is_some_and(|idx| idx >= len) {
+            anyhow::bail!("Invalid timer index in save state");
+        }
+        Ok(())
+    }
+}
+
+impl<ActorNames, O> RestoreState for Timers<ActorNames, O>
+where
+    ActorNames: MakeNamed,
+    O: Outbox<ActorNames> + Default,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let mut timers = Self::new();
+        timers.load_state(state)?;
+        Ok(timers)
+    }
+}
+
+/// Settling applies to both extra outbox slots: deferred messages and timers
+#[inline(always)]
+pub(crate) fn settle<ActorNames, A>(actor: &mut A, outbox: &mut A::OutboxType)
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+{
+    if let Some(deferred) = actor.deferred() {
+        deferred.settle(outbox);
+    }
+    if let Some(timers) = actor.timers() {
+        timers.settle(outbox);
+    }
+}
+
+#[inline(always)]
+pub(crate) fn unsettle<ActorNames, A>(actor: &mut A, outbox: &mut A::OutboxType)
+where
+    ActorNames: MakeNamed,
+    A: Actor<ActorNames>,
+{
+    if let Some(timers) = actor.timers() {
+        timers.unsettle(outbox);
+    }
+}
@@ -0,0 +1,198 @@
+use std::{borrow::Cow, fmt::Display, path::Path};
+
+use anyhow::bail;
+
+use crate::{Actor, MakeNamed, RestoreState, SaveState, StateReader, StateWriter, Time};
+
+/// A single message delivery, or lazy time being resolved, as seen by the scheduler
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub time: Time,
+    pub sender: ActorNames,
+    pub receiver: ActorNames,
+    /// Matches the name returned by `msg_type_name()` in `make_outbox!`
+    pub msg_type: Cow<'static, str>,
+    pub limit: Time,
+    /// The sender's `Actor::resolve` was called for the lazy `time`, nothing was delivered
+    pub resolve: bool,
+}
+
+impl<ActorNames> TraceEntry<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub(crate) fn new<Sender, Message>(receiver: ActorNames, time: Time, limit: Time, resolve: bool) -> Self
+    where
+        Sender: Actor<ActorNames>,
+        Message: 'static,
+    {
+        TraceEntry {
+            time,
+            sender: Sender::name(),
+            receiver,
+            msg_type: Cow::Borrowed(std::any::type_name::<Message>()),
+            limit,
+            resolve,
+        }
+    }
+}
+
+impl<ActorNames> Display for TraceEntry<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?} -> {:?} {} (limit {})", self.time, self.sender, self.receiver, self.msg_type, self.limit)?;
+        if self.resolve {
+            write!(f, " resolved")?;
+        }
+        Ok(())
+    }
+}
+
+/// A recording of every message delivered by the scheduler, in delivery order
+///
+/// Traces can get very large, every message is recorded. So is every lazy time that was resolved,
+/// as resolving can change when messages are delivered.
+pub struct Trace<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub entries: Vec<TraceEntry<ActorNames>>,
+}
+
+const MAGIC: [u8; 8] = *b"BUSMUTRC";
+const TRACE_VERSION: u32 = 2;
+
+impl<ActorNames> Trace<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut state = StateWriter::new();
+        state.write_bytes(&MAGIC);
+        TRACE_VERSION.save_state(&mut state);
+        state.write_str(std::any::type_name::<ActorNames>());
+
+        self.entries.len().save_state(&mut state);
+        for entry in &self.entries {
+            entry.time.save_state(&mut state);
+            let sender: usize = entry.sender.into();
+            sender.save_state(&mut state);
+            let receiver: usize = entry.receiver.into();
+            receiver.save_state(&mut state);
+            state.write_str(&entry.msg_type);
+            entry.limit.save_state(&mut state);
+            entry.resolve.save_state(&mut state);
+        }
+
+        std::fs::write(path, state.into_inner())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let data = std::fs::read(path)?;
+        let mut state = StateReader::new(&data);
+
+        if state.read_bytes(MAGIC.len())? != MAGIC {
+            bail!("{} is not a trace", path.display());
+        }
+        let version = u32::restore_state(&mut state)?;
+        if version != TRACE_VERSION {
+            bail!("Unsupported trace version {} (expected {})", version, TRACE_VERSION);
+        }
+        let actor_names = String::restore_state(&mut state)?;
+        if actor_names != std::any::type_name::<ActorNames>() {
+            bail!("Trace is for {}, not {}", actor_names, std::any::type_name::<ActorNames>());
+        }
+
+        let read_actor = |state: &mut StateReader| -> Result<ActorNames, anyhow::Error> {
+            let id = usize::restore_state(state)?;
+            if id >= ActorNames::COUNT {
+                bail!("Invalid actor id {} in trace", id);
+            }
+            Ok(id.into())
+        };
+
+        let len = usize::restore_state(&mut state)?;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            entries.push(TraceEntry {
+                time: Time::restore_state(&mut state)?,
+                sender: read_actor(&mut state)?,
+                receiver: read_actor(&mut state)?,
+                msg_type: Cow::Owned(String::restore_state(&mut state)?),
+                limit: Time::restore_state(&mut state)?,
+                resolve: bool::restore_state(&mut state)?,
+            });
+        }
+
+        Ok(Trace { entries })
+    }
+}
+
+/// Returned (as the error of `Scheduler::run`) when a verified run delivers a different message
+/// from the one that was recorded
+#[derive(Debug)]
+pub struct TraceDivergence<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    /// Index into the recorded trace
+    pub index: usize,
+    pub expected: TraceEntry<ActorNames>,
+    pub actual: TraceEntry<ActorNames>,
+}
+
+impl<ActorNames> Display for TraceDivergence<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trace diverged at message {}\n  expected: {}\n  actual:   {}", self.index, self.expected, self.actual)
+    }
+}
+
+impl<ActorNames> std::error::Error for TraceDivergence<ActorNames>
+where
+    ActorNames: MakeNamed,
+{}
+
+pub(crate) enum Tracer<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    Record(Vec<TraceEntry<ActorNames>>),
+    Verify(Trace<ActorNames>, usize),
+}
+
+impl<ActorNames> Tracer<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    /// Returns false once a verified trace has been fully consumed
+    pub(crate) fn log(&mut self, entry: TraceEntry<ActorNames>) -> Result<bool, anyhow::Error> {
+        match self {
+            Tracer::Record(entries) => {
+                entries.push(entry);
+                Ok(true)
+            }
+            Tracer::Verify(trace, pos) => {
+                let index = *pos;
+                let expected = &trace.entries[index];
+                if *expected != entry {
+                    return Err(TraceDivergence {
+                        index,
+                        expected: expected.clone(),
+                        actual: entry,
+                    }.into());
+                }
+                *pos += 1;
+                Ok(*pos < trace.entries.len())
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::Display;
+
+use crate::{MakeNamed, Time};
+
+/// Why `Scheduler::run_zero_limit` gave up on a cycle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZeroLimitProblem<ActorNames> {
+    /// A message from `sender` returned `SchedulerResult::ZeroLimit`, but the receiver was allowed
+    /// to run until `limit`
+    IncorrectLimit { sender: ActorNames, limit: Time },
+    /// Every message on the cycle was delivered this many times, and there were still more
+    Unsettled { iterations: usize },
+}
+
+/// A message waiting in an outbox on the stuck cycle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZeroLimitMessage<ActorNames> {
+    pub sender: ActorNames,
+    pub receiver: ActorNames,
+    /// Matches the name returned by `msg_type_name()` in `make_outbox!`
+    pub msg_type: &'static str,
+    /// Lazy messages are resolved by the sender instead of being delivered
+    pub lazy: bool,
+}
+
+/// A delivery attempted while trying to settle the cycle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZeroLimitDelivery<ActorNames> {
+    pub iteration: usize,
+    pub message: ZeroLimitMessage<ActorNames>,
+    /// The receiver (or sender, for lazy messages) returned `SchedulerResult::ZeroLimit`
+    pub zero_limit: bool,
+}
+
+/// Returned as an error when the scheduler can't get past a cycle where actors keep reporting
+/// zero limits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZeroLimitReport<ActorNames> {
+    pub time: Time,
+    pub problem: ZeroLimitProblem<ActorNames>,
+    /// Messages still waiting on the cycle when the scheduler gave up
+    pub pending: Vec<ZeroLimitMessage<ActorNames>>,
+    /// Deliveries from the last few iterations, oldest first
+    pub deliveries: Vec<ZeroLimitDelivery<ActorNames>>,
+}
+
+impl<ActorNames> Display for ZeroLimitMessage<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} -> {:?} {}", self.sender, self.receiver, self.msg_type)?;
+        if self.lazy {
+            write!(f, " (lazy)")?;
+        }
+        Ok(())
+    }
+}
+
+impl<ActorNames> Display for ZeroLimitReport<ActorNames>
+where
+    ActorNames: MakeNamed,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.problem {
+            ZeroLimitProblem::IncorrectLimit { sender, limit } => {
+                writeln!(f, "Zero limit reported at {} for a message from {:?}, but the limit was {}",
+                    self.time, sender, limit)?;
+            }
+            ZeroLimitProblem::Unsettled { iterations } => {
+                writeln!(f, "Zero limit cycle detected at {}, still unsettled after {} iterations",
+                    self.time, iterations)?;
+            }
+        }
+        writeln!(f, "Pending:")?;
+        for message in &self.pending {
+            writeln!(f, "    {}", message)?;
+        }
+        if !self.deliveries.is_empty() {
+            writeln!(f, "Last deliveries:")?;
+        }
+        for delivery in &self.deliveries {
+            let result = if delivery.zero_limit { "ZeroLimit" } else { "Ok" };
+            writeln!(f, "    [{}] {} => {}", delivery.iteration, delivery.message, result)?;
+        }
+        Ok(())
+    }
+}
+
+impl<ActorNames> std::error::Error for ZeroLimitReport<ActorNames>
+where
+    ActorNames: MakeNamed,
+{}
@@ -0,0 +1,168 @@
+//! Every scheduler backend must deliver the same messages at the same times
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(T1), handles(Tick))]
+    T1,
+    #[named(class(T3), handles(Tick))]
+    T3,
+    #[named(class(T5), handles(Tick))]
+    T5,
+    #[named(class(T7), handles(Tick))]
+    T7,
+    #[named(class(Burst), handles(Tick))]
+    Burst,
+    #[named(class(F0), handles(Tick))]
+    F0,
+    #[named(class(F1), handles(Tick))]
+    F1,
+    #[named(class(F2), handles(Tick))]
+    F2,
+    #[named(class(Sink), handles(Hello))]
+    Sink,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+macro_rules! ticker {
+    ($name:ident, $outbox:ident, period: $period:expr, ticks: $ticks:expr) => {
+        pub struct $name {
+            ticks: Vec<u64>,
+        }
+        impl_save_state!($name { ticks });
+
+        make_outbox!($outbox<Names, $name> { tick: Tick });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                outbox.send::<$name>(Tick, time.add($period));
+                Ok($name { ticks: Vec::new() })
+            }
+        }
+
+        impl Handler<Names, Tick> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                self.ticks.push(time.into());
+                if self.ticks.len() < $ticks {
+                    outbox.send::<$name>(Tick, time.add($period));
+                }
+                SchedulerResult::Ok
+            }
+        }
+    };
+}
+
+ticker!(T1, T1Outbox, period: 1, ticks: usize::MAX);
+ticker!(T3, T3Outbox, period: 3, ticks: usize::MAX);
+ticker!(T5, T5Outbox, period: 5, ticks: usize::MAX);
+ticker!(T7, T7Outbox, period: 7, ticks: usize::MAX);
+// Goes idle part way through
+ticker!(Burst, BurstOutbox, period: 2, ticks: 4);
+
+pub struct Hello {
+    from: usize,
+}
+impl_save_state!(Hello { from });
+
+macro_rules! fifo_sender {
+    ($name:ident, $outbox:ident, sent_at: $sent_at:expr) => {
+        pub struct $name;
+        impl_save_state!($name {});
+
+        make_outbox!($outbox<Names, $name> { tick: Tick, hello: Hello });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(_: &Config, outbox: &mut $outbox, _: Time) -> Result<Self, anyhow::Error> {
+                outbox.send::<$name>(Tick, Time::from($sent_at));
+                Ok($name)
+            }
+        }
+
+        impl Handler<Names, Tick> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, _: Tick, _: Time, _: Time) -> SchedulerResult {
+                outbox.send::<Sink>(Hello { from: Self::name().into() }, Time::from(50))
+            }
+        }
+    };
+}
+
+// Sent in the opposite order to actor ids, all delivered on the same cycle
+fifo_sender!(F0, F0Outbox, sent_at: 12);
+fifo_sender!(F1, F1Outbox, sent_at: 11);
+fifo_sender!(F2, F2Outbox, sent_at: 10);
+
+#[derive(Default)]
+pub struct Sink {
+    received: Vec<usize>,
+}
+impl_save_state!(Sink { received });
+
+make_outbox!(SinkOutbox<Names, Sink> {});
+
+impl Actor<Names> for Sink {
+    type OutboxType = SinkOutbox;
+}
+
+impl Handler<Names, Hello> for Sink {
+    fn recv(&mut self, _: &mut SinkOutbox, hello: Hello, _: Time, _: Time) -> SchedulerResult {
+        self.received.push(hello.from);
+        SchedulerResult::Ok
+    }
+}
+
+fn ticks(period: u64, until: u64) -> Vec<u64> {
+    (1..).map(|n| n * period).take_while(|&t| t < until).collect()
+}
+
+#[test]
+fn same_deliveries() {
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        assert_eq!(instance.run_until(Time::from(100)).unwrap(), StopReason::ReachedTime(Time::from(100)));
+
+        assert_eq!(instance.actor::<T1>().ticks, ticks(1, 100), "{:?}", backend);
+        assert_eq!(instance.actor::<T3>().ticks, ticks(3, 100), "{:?}", backend);
+        assert_eq!(instance.actor::<T5>().ticks, ticks(5, 100), "{:?}", backend);
+        assert_eq!(instance.actor::<T7>().ticks, ticks(7, 100), "{:?}", backend);
+        assert_eq!(instance.actor::<Burst>().ticks, vec![2, 4, 6, 8], "{:?}", backend);
+    }
+}
+
+#[test]
+fn same_cycle_fifo() {
+    for backend in [SchedulerBackend::LinkedList, SchedulerBackend::Heap] {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        instance.run_until(Time::from(100)).unwrap();
+
+        let expected: Vec<usize> = vec![Names::F2.into(), Names::F1.into(), Names::F0.into()];
+        assert_eq!(instance.actor::<Sink>().received, expected, "{:?}", backend);
+    }
+}
+
+#[test]
+fn save_state_backend_mismatch() {
+    let mut instance = Instance::<Names>::with_backend(Config, SchedulerBackend::LinkedList).unwrap();
+    instance.run_until(Time::from(10)).unwrap();
+    let state = instance.save_state_bytes();
+
+    let mut cached = Instance::<Names>::with_backend(Config, SchedulerBackend::Cached).unwrap();
+    assert!(cached.load_state_bytes(&state).is_err());
+}
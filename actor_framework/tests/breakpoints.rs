@@ -0,0 +1,222 @@
+//! Stopping the scheduler in front of matching messages
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Pinger))]
+    Pinger,
+    #[named(class(Ponger))]
+    Ponger,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Ping {
+    count: u64,
+}
+impl_save_state!(Ping { count });
+
+pub struct Pong {
+    count: u64,
+}
+impl_save_state!(Pong { count });
+
+/// Ticks every cycle, and pings Ponger every 5 ticks
+pub struct Pinger {
+    ticks: u64,
+    pongs: u64,
+}
+impl_save_state!(Pinger { ticks, pongs });
+
+make_outbox!(PingerOutbox<Names, Pinger> { tick: Tick, ping: Ping });
+
+impl Actor<Names> for Pinger {
+    type OutboxType = PingerOutbox;
+
+    fn delivering<Message>(&mut self, outbox: &mut PingerOutbox, _: &Message, time: Time)
+    where
+        Message: 'static,
+    {
+        if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<Ping>() {
+            outbox.send::<Pinger>(Tick, time.add(1));
+        }
+    }
+}
+
+impl ActorInit<Names> for Pinger {
+    fn init(_: &Config, outbox: &mut PingerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Pinger>(Tick, time.add(1));
+        Ok(Pinger { ticks: 0, pongs: 0 })
+    }
+}
+
+impl Handler<Names, Tick> for Pinger {
+    fn recv(&mut self, outbox: &mut PingerOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.ticks += 1;
+        match self.ticks % 5 {
+            0 => outbox.send::<Ponger>(Ping { count: self.ticks / 5 }, time.add(1)),
+            _ => outbox.send::<Pinger>(Tick, time.add(1)),
+        }
+    }
+}
+
+impl Handler<Names, Pong> for Pinger {
+    fn recv(&mut self, _: &mut PingerOutbox, _: Pong, _: Time, _: Time) -> SchedulerResult {
+        self.pongs += 1;
+        SchedulerResult::Ok
+    }
+}
+
+pub struct Ponger;
+impl_save_state!(Ponger {});
+
+make_outbox!(PongerOutbox<Names, Ponger> { pong: Pong });
+
+impl Actor<Names> for Ponger {
+    type OutboxType = PongerOutbox;
+}
+
+impl ActorInit<Names> for Ponger {
+    fn init(_: &Config, _: &mut PongerOutbox, _: Time) -> Result<Self, anyhow::Error> {
+        Ok(Ponger)
+    }
+}
+
+impl Handler<Names, Ping> for Ponger {
+    fn recv(&mut self, outbox: &mut PongerOutbox, ping: Ping, time: Time, _: Time) -> SchedulerResult {
+        outbox.send::<Pinger>(Pong { count: ping.count }, time.add(2))
+    }
+}
+
+fn hit(reason: StopReason<Names>) -> BreakpointHit<Names> {
+    match reason {
+        StopReason::Breakpoint(hit) => hit,
+        reason => panic!("Expected a breakpoint, got {:?}", reason),
+    }
+}
+
+/// Every message that stops `breakpoint` before `end`, as (sender, receiver, time)
+fn hits(breakpoint: Breakpoint<Names>, end: u64) -> Vec<(Names, Names, u64)> {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.add_breakpoint(breakpoint);
+    let mut hits = Vec::new();
+    while let StopReason::Breakpoint(hit) = instance.run_until(Time::from(end)).unwrap() {
+        hits.push((hit.sender, hit.receiver, hit.time.into()));
+    }
+    hits
+}
+
+// Pings go out at 6, 12, 18..., and come back as pongs 2 cycles later
+
+#[test]
+fn match_receiver() {
+    assert_eq!(hits(Breakpoint::new().receiver(Names::Ponger), 20), [
+        (Names::Pinger, Names::Ponger, 6),
+        (Names::Pinger, Names::Ponger, 12),
+        (Names::Pinger, Names::Ponger, 18),
+    ]);
+}
+
+#[test]
+fn match_sender() {
+    assert_eq!(hits(Breakpoint::new().sender(Names::Ponger), 20), [
+        (Names::Ponger, Names::Pinger, 8),
+        (Names::Ponger, Names::Pinger, 14),
+    ]);
+}
+
+#[test]
+fn match_message() {
+    assert_eq!(hits(Breakpoint::new().message::<Pong>(), 20), [
+        (Names::Ponger, Names::Pinger, 8),
+        (Names::Ponger, Names::Pinger, 14),
+    ]);
+
+    // Ticks are sent to the same receiver
+    let ticks = hits(Breakpoint::new().message::<Tick>().receiver(Names::Pinger), 20);
+    assert_eq!(ticks.len(), 16);
+    assert!(ticks.iter().all(|&(sender, _, time)| sender == Names::Pinger && ![6, 12, 18].contains(&time)));
+}
+
+#[test]
+fn match_time_range() {
+    assert_eq!(hits(Breakpoint::new().between(Time::from(10), Time::from(13)), 100), [
+        (Names::Pinger, Names::Pinger, 10),
+        (Names::Pinger, Names::Pinger, 11),
+        (Names::Pinger, Names::Ponger, 12),
+    ]);
+}
+
+#[test]
+fn match_condition() {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.add_breakpoint(Breakpoint::new().when(|ping: &Ping| ping.count == 3));
+
+    let hit = hit(instance.run_until(Time::from(100)).unwrap());
+    assert_eq!((hit.sender, hit.receiver, hit.time), (Names::Pinger, Names::Ponger, Time::from(18)));
+    assert_eq!(hit.msg_type, std::any::type_name::<Ping>());
+    let (time, ping) = instance.pending_message::<Pinger, Ping>().unwrap();
+    assert_eq!((time, ping.count), (Time::from(18), 3));
+
+    // Only the one ping matches
+    assert_eq!(instance.run_until(Time::from(100)).unwrap(), StopReason::ReachedTime(Time::from(100)));
+}
+
+#[test]
+fn resume_delivers() {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    let id = instance.add_breakpoint(Breakpoint::new().message::<Pong>());
+
+    let first = hit(instance.run_until(Time::from(100)).unwrap());
+    assert_eq!((first.id, first.time), (id, Time::from(8)));
+    assert_eq!(instance.last_breakpoint(), None);
+    assert_eq!(instance.actor::<Pinger>().pongs, 0);
+
+    // Stepping over the hit delivers it
+    loop {
+        match instance.step_message().unwrap() {
+            StopReason::Stepped { sender: Names::Ponger, time } => {
+                assert_eq!(time, Time::from(8));
+                break;
+            }
+            StopReason::Stepped { .. } => {}
+            reason => panic!("Expected a step, got {:?}", reason),
+        }
+    }
+    assert_eq!(instance.actor::<Pinger>().pongs, 1);
+
+    // And so does running
+    let second = hit(instance.run_until(Time::from(100)).unwrap());
+    assert_eq!(second.time, Time::from(14));
+    let third = hit(instance.run_until(Time::from(100)).unwrap());
+    assert_eq!(third.time, Time::from(20));
+    assert_eq!(instance.actor::<Pinger>().pongs, 2);
+}
+
+#[test]
+fn remove_hit_breakpoint() {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    let pings = instance.add_breakpoint(Breakpoint::new().message::<Ping>());
+    let pongs = instance.add_breakpoint(Breakpoint::new().message::<Pong>());
+
+    assert_eq!(hit(instance.run_until(Time::from(100)).unwrap()).time, Time::from(6));
+
+    // Removing a different breakpoint doesn't stop the same ping again
+    assert!(instance.remove_breakpoint(pongs));
+    assert_eq!(hit(instance.run_until(Time::from(100)).unwrap()).time, Time::from(12));
+
+    // But once the one that was hit is gone, a new one can stop in front of it
+    assert!(instance.remove_breakpoint(pings));
+    let receiver = instance.add_breakpoint(Breakpoint::new().receiver(Names::Ponger));
+    let hit = hit(instance.run_until(Time::from(100)).unwrap());
+    assert_eq!((hit.id, hit.time), (receiver, Time::from(12)));
+}
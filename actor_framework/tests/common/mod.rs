@@ -0,0 +1,65 @@
+//! A single actor that ticks every cycle, for tests that are about the instance rather than actors
+
+use actor_framework::*;
+use ::common::impl_save_state;
+
+pub struct Config {
+    /// Time of the first tick
+    pub start: u64,
+    /// Without one, soft resets fail
+    pub reset_button: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { start: 1, reset_button: true }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Ticker), handles(Tick))]
+    Ticker,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Ticker {
+    pub ticks: u64,
+    pub resets: u64,
+    reset_button: bool,
+}
+impl_save_state!(Ticker { ticks, resets, .. });
+
+make_outbox!(TickerOutbox<Names, Ticker> { tick: Tick });
+
+impl Actor<Names> for Ticker {
+    type OutboxType = TickerOutbox;
+
+    fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        if !self.reset_button {
+            anyhow::bail!("Ticker has no reset button");
+        }
+        self.resets += 1;
+        Ok(())
+    }
+}
+
+impl ActorInit<Names> for Ticker {
+    fn init(config: &Config, outbox: &mut TickerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Ticker>(Tick, time.add(config.start));
+        Ok(Ticker { ticks: 0, resets: 0, reset_button: config.reset_button })
+    }
+}
+
+impl Handler<Names, Tick> for Ticker {
+    fn recv(&mut self, outbox: &mut TickerOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.ticks += 1;
+        outbox.send::<Ticker>(Tick, time.add(1))
+    }
+}
@@ -0,0 +1,140 @@
+//! Commands sent while running, and the replies to them
+
+use std::{path::PathBuf, sync::mpsc};
+
+use actor_framework::*;
+use ::common::{input::PadState, pacing::{Pacer, Speed}, Command, ControlMessage, Instance as _, ResetKind, UpdateMessage};
+
+mod common;
+use common::{Config, Names, Ticker};
+
+/// A directory of its own for each test, removed even if the test fails
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(test: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("bus-mu-{}-{}", test, std::process::id()));
+        // Left over from an earlier process with the same id
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn reply(message: UpdateMessage) -> Result<Command, Command> {
+    match message {
+        UpdateMessage::Ack(command) => Ok(command),
+        UpdateMessage::Error(err) => Err(err.command),
+        _ => unreachable!(),
+    }
+}
+
+/// Sends each message once the one before has been replied to, like a script would, then returns
+/// every reply once `run` returns
+fn run_with(instance: &mut Instance<Names>, messages: Vec<ControlMessage>) -> Vec<Result<Command, Command>> {
+    let (tx_control, rx_control) = mpsc::channel();
+    let (tx_update, rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
+    let mut messages = messages.into_iter();
+
+    // The first message is seen before anything runs
+    tx_control.send(messages.next().unwrap()).unwrap();
+    std::thread::scope(|scope| {
+        let running = scope.spawn(move || instance.run(&rx_control, tx_update).unwrap());
+        let mut replies = vec![reply(rx_update.recv().unwrap())];
+        for message in messages {
+            let command = message.command();
+            tx_control.send(message).unwrap();
+            if command.is_some() {
+                replies.push(reply(rx_update.recv().unwrap()));
+            }
+        }
+        // Anything sent as `run` returns
+        replies.extend(rx_update.iter().map(reply));
+        running.join().unwrap();
+        replies
+    })
+}
+
+#[test]
+fn commands_are_acknowledged() {
+    let dir = TempDir::new("commands_are_acknowledged");
+    let path = dir.join("run.state");
+    let mut instance = Instance::<Names>::new(Config::default()).unwrap();
+    instance.set_pacer(Some(Pacer::new(1000, 100)));
+    instance.set_speed(Speed::Unlimited);
+
+    // Nothing runs between them, each run for and frame advance waits once it's done
+    let replies = run_with(&mut instance, vec![
+        ControlMessage::RunFor(50),
+        ControlMessage::SaveState(path.clone()),
+        ControlMessage::RunFor(30),
+        ControlMessage::LoadState(path.clone()),
+        ControlMessage::FrameAdvance,
+        ControlMessage::Pause,
+    ]);
+
+    assert_eq!(replies, [
+        Ok(Command::RunFor),
+        Ok(Command::SaveState),
+        Ok(Command::RunFor),
+        Ok(Command::LoadState),
+        Ok(Command::FrameAdvance),
+    ]);
+    // Back to 50 by the load, then a frame
+    assert_eq!(instance.now(), Time::from(150));
+    assert_eq!(instance.actor::<Ticker>().ticks, 149);
+}
+
+#[test]
+fn failures_are_replied_to() {
+    let config = Config { reset_button: false, ..Config::default() };
+    let mut instance = Instance::<Names>::new(config).unwrap();
+
+    // Without a pacer there are no frames to advance by, a missing save state can't be loaded and
+    // the reset button isn't wired to anything
+    let dir = TempDir::new("failures_are_replied_to");
+    let missing = dir.join("missing.state");
+    let replies = run_with(&mut instance, vec![
+        ControlMessage::SetSpeed(Speed::Unlimited),
+        ControlMessage::FrameAdvance,
+        ControlMessage::LoadState(missing),
+        ControlMessage::Reset(ResetKind::Soft),
+        // No input latch, and only failed input is replied to
+        ControlMessage::Input { port: 0, pad: PadState::default() },
+        ControlMessage::Shutdown,
+    ]);
+
+    assert_eq!(replies, [
+        Ok(Command::SetSpeed),
+        Err(Command::FrameAdvance),
+        Err(Command::LoadState),
+        Err(Command::Reset),
+        Err(Command::Input),
+        Ok(Command::Shutdown),
+    ]);
+}
+
+#[test]
+fn unfinished_run_for() {
+    let mut instance = Instance::<Names>::new(Config::default()).unwrap();
+
+    // Sent together, so it's shut down long before it gets there
+    let (tx_control, rx_control) = mpsc::channel();
+    let (tx_update, rx_update) = mpsc::sync_channel::<UpdateMessage>(2);
+    tx_control.send(ControlMessage::RunFor(1 << 40)).unwrap();
+    tx_control.send(ControlMessage::Shutdown).unwrap();
+    instance.run(&rx_control, tx_update).unwrap();
+
+    let replies: Vec<_> = rx_update.try_iter().map(reply).collect();
+    assert_eq!(replies, [Err(Command::RunFor), Ok(Command::Shutdown)]);
+}
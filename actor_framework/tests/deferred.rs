@@ -0,0 +1,64 @@
+//! Deferred messages keep the order they were sent in, even when an earlier send takes the outbox
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Marker))]
+    Marker,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Mark {
+    id: u32,
+}
+impl_save_state!(Mark { id });
+
+/// Sends marks to itself, and records when each one arrives
+pub struct Marker {
+    deferred: Deferred<Names, MarkerOutbox>,
+    received: Vec<(u64, u32)>,
+}
+impl_save_state!(Marker { deferred, received });
+
+make_outbox!(MarkerOutbox<Names, Marker> { mark: Mark });
+
+impl Actor<Names> for Marker {
+    type OutboxType = MarkerOutbox;
+
+    fn deferred(&mut self) -> Option<&mut Deferred<Names, MarkerOutbox>> {
+        Some(&mut self.deferred)
+    }
+}
+
+impl ActorInit<Names> for Marker {
+    fn init(_: &Config, outbox: &mut MarkerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        let mut deferred = Deferred::new();
+        // 1 takes the outbox and 2 is queued behind it, until 3 displaces 1
+        for (id, delay) in [(1, 10), (2, 10), (3, 5), (4, 10)] {
+            deferred.send(outbox, |o| o.send::<Marker>(Mark { id }, time.add(delay)));
+        }
+        Ok(Marker { deferred, received: Vec::new() })
+    }
+}
+
+impl Handler<Names, Mark> for Marker {
+    fn recv(&mut self, _: &mut MarkerOutbox, mark: Mark, time: Time, _: Time) -> SchedulerResult {
+        self.received.push((time.into(), mark.id));
+        SchedulerResult::Ok
+    }
+}
+
+#[test]
+fn same_time_order_survives_displacement() {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.run_until(Time::from(20)).unwrap();
+
+    assert_eq!(instance.actor::<Marker>().received, [(5, 3), (10, 1), (10, 2), (10, 4)]);
+}
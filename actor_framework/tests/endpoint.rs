@@ -0,0 +1,230 @@
+//! Messages sent through an `Endpoint`, where the receiver is only known at runtime
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Router), handles(Work, Done))]
+    Router,
+    #[named(class(WorkerA), handles(Work))]
+    WorkerA,
+    #[named(class(WorkerB), handles(Work))]
+    WorkerB,
+    #[named(class(Clock), handles(Tick))]
+    Clock,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Work {
+    job: u32,
+}
+impl_save_state!(Work { job });
+
+pub struct Done {
+    job: u32,
+    worker: usize,
+}
+impl_save_state!(Done { job, worker });
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+/// Hands out jobs round-robin, including to itself, and only sends the next one when the
+/// previous one is done
+pub struct Router {
+    workers: Vec<Endpoint<Names, Work>>,
+    next_job: u32,
+    log: Vec<(u32, u64)>,
+}
+impl_save_state!(Router { workers, next_job, log });
+
+make_outbox!(RouterOutbox<Names, Router> { work: Work });
+
+impl Actor<Names> for Router {
+    type OutboxType = RouterOutbox;
+}
+
+impl ActorInit<Names> for Router {
+    fn init(_: &Config, outbox: &mut RouterOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        let mut router = Router {
+            workers: vec![Endpoint::new::<WorkerA>(), Endpoint::new::<WorkerB>(), Endpoint::new::<Router>()],
+            next_job: 0,
+            log: Vec::new(),
+        };
+        router.dispatch(outbox, time.add(1));
+        Ok(router)
+    }
+}
+
+impl Router {
+    fn dispatch(&mut self, outbox: &mut RouterOutbox, time: Time) -> SchedulerResult {
+        let job = self.next_job;
+        self.next_job += 1;
+        let endpoint = self.workers[job as usize % self.workers.len()].clone();
+        outbox.send_endpoint(endpoint, Work { job }, time)
+    }
+}
+
+impl Handler<Names, Done> for Router {
+    fn recv(&mut self, outbox: &mut RouterOutbox, done: Done, time: Time, _: Time) -> SchedulerResult {
+        let receiver: usize = self.workers[done.job as usize % self.workers.len()].receiver().into();
+        assert_eq!(receiver, done.worker);
+        self.log.push((done.job, time.into()));
+        self.dispatch(outbox, time.add(2))
+    }
+}
+
+impl Handler<Names, Work> for Router {
+    fn recv(&mut self, outbox: &mut RouterOutbox, work: Work, time: Time, _: Time) -> SchedulerResult {
+        self.log.push((work.job, time.into()));
+        self.dispatch(outbox, time.add(2))
+    }
+}
+
+macro_rules! worker {
+    ($name:ident, $outbox:ident) => {
+        pub struct $name {
+            jobs: Vec<(u32, u64)>,
+        }
+        impl_save_state!($name { jobs });
+
+        make_outbox!($outbox<Names, $name> { done: Done });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(_: &Config, _: &mut $outbox, _: Time) -> Result<Self, anyhow::Error> {
+                Ok($name { jobs: Vec::new() })
+            }
+        }
+
+        impl Handler<Names, Work> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, work: Work, time: Time, _: Time) -> SchedulerResult {
+                self.jobs.push((work.job, time.into()));
+                outbox.send::<Router>(Done { job: work.job, worker: Self::name().into() }, time.add(3))
+            }
+        }
+    };
+}
+
+worker!(WorkerA, WorkerAOutbox);
+worker!(WorkerB, WorkerBOutbox);
+
+/// Keeps the scheduler busy, so the workers are rescheduled against another active actor
+pub struct Clock;
+impl_save_state!(Clock {});
+
+make_outbox!(ClockOutbox<Names, Clock> { tick: Tick });
+
+impl Actor<Names> for Clock {
+    type OutboxType = ClockOutbox;
+}
+
+impl ActorInit<Names> for Clock {
+    fn init(_: &Config, outbox: &mut ClockOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Clock>(Tick, time.add(1));
+        Ok(Clock)
+    }
+}
+
+impl Handler<Names, Tick> for Clock {
+    fn recv(&mut self, outbox: &mut ClockOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        outbox.send::<Clock>(Tick, time.add(1))
+    }
+}
+
+#[test]
+fn round_robin() {
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        instance.run_until(Time::from(36)).unwrap();
+
+        assert_eq!(instance.actor::<WorkerA>().jobs, vec![(0, 1), (3, 13), (6, 25)], "{:?}", backend);
+        assert_eq!(instance.actor::<WorkerB>().jobs, vec![(1, 6), (4, 18), (7, 30)], "{:?}", backend);
+        // Jobs 2, 5 and 8 were sent to the router's own endpoint
+        assert_eq!(instance.actor::<Router>().log, vec![(0, 4), (1, 9), (2, 11), (3, 16), (4, 21), (5, 23), (6, 28), (7, 33), (8, 35)], "{:?}", backend);
+    }
+}
+
+#[test]
+fn endpoint_receiver() {
+    let endpoint = Endpoint::<Names, Work>::new::<WorkerB>();
+    assert_eq!(endpoint.receiver(), Names::WorkerB);
+
+    let mut outbox = RouterOutbox::default();
+    outbox.send_endpoint(endpoint, Work { job: 1 }, Time::from(5));
+    assert_eq!(outbox.time(), Time::from(5));
+    assert_eq!(OutboxSend::<Names, Work>::as_packet(&mut outbox).unwrap().receiver(), Names::WorkerB);
+}
+
+#[test]
+fn save_state_in_flight() {
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        // Job 3 is waiting in the router's outbox for WorkerA
+        instance.run_until(Time::from(12)).unwrap();
+        let state = instance.save_state_bytes();
+
+        let mut restored = Instance::<Names>::with_backend(Config, backend).unwrap();
+        restored.load_state_bytes(&state).unwrap();
+
+        restored.run_until(Time::from(36)).unwrap();
+        assert_eq!(restored.actor::<WorkerA>().jobs, vec![(0, 1), (3, 13), (6, 25)], "{:?}", backend);
+        assert_eq!(restored.actor::<Router>().log, vec![(0, 4), (1, 9), (2, 11), (3, 16), (4, 21), (5, 23), (6, 28), (7, 33), (8, 35)], "{:?}", backend);
+    }
+}
+
+#[test]
+fn failed_load_leaves_instance_unchanged() {
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        instance.run_until(Time::from(12)).unwrap();
+        let state = instance.save_state_bytes();
+
+        let mut other = Instance::<Names>::with_backend(Config, backend).unwrap();
+        other.run_until(Time::from(30)).unwrap();
+        let before = other.save_state_bytes();
+
+        // Cut off in the clock's section, after the router and workers have been loaded
+        let mut section = StateWriter::new();
+        section.write_str("Clock");
+        let section = section.into_inner();
+        let end = state.windows(section.len()).position(|window| window == section).unwrap() + section.len();
+        let err = other.load_state_bytes(&state[..end]).unwrap_err();
+        assert!(err.to_string().contains("end of save state"), "{:?}: {}", backend, err);
+        assert_eq!(other.save_state_bytes(), before, "{:?}", backend);
+
+        other.run_until(Time::from(36)).unwrap();
+        assert_eq!(other.actor::<WorkerA>().jobs, vec![(0, 1), (3, 13), (6, 25)], "{:?}", backend);
+    }
+}
+
+#[test]
+fn saved_receivers_are_checked() {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.run_until(Time::from(12)).unwrap();
+    let mut state = instance.save_state_bytes();
+
+    // Job 3 is waiting for WorkerA. Find its receiver, right after the message type and time
+    let mut packet = StateWriter::new();
+    packet.write_str(std::any::type_name::<Work>());
+    Time::from(13).save_state(&mut packet);
+    let packet = packet.into_inner();
+    let pos = state.windows(packet.len()).position(|window| window == packet).unwrap() + packet.len();
+    let receiver = &mut state[pos..pos + 8];
+    assert_eq!(u64::from_le_bytes(receiver.try_into().unwrap()), usize::from(Names::WorkerA) as u64);
+
+    // The clock can't take the job
+    receiver.copy_from_slice(&(usize::from(Names::Clock) as u64).to_le_bytes());
+    let mut restored = Instance::<Names>::new(Config).unwrap();
+    let err = restored.load_state_bytes(&state).unwrap_err();
+    assert!(err.to_string().contains("Clock doesn't handle"), "{}", err);
+}
@@ -0,0 +1,71 @@
+//! Input from the frontend only reaches actors at the latch points
+
+use std::sync::mpsc;
+
+use actor_framework::*;
+use common::{impl_save_state, input::{Button, Buttons, InputLatch, PadState, SharedInput}, ControlMessage, Instance as _, UpdateMessage};
+
+pub struct Config {
+    input: SharedInput,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Poller))]
+    Poller,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Poll;
+impl_save_state!(Poll {});
+
+/// Checks the A button on port 0 every 10 cycles
+pub struct Poller {
+    input: SharedInput,
+    /// Time of the first poll that saw it pressed
+    pressed_at: Option<u64>,
+}
+impl_save_state!(Poller { pressed_at, .. });
+
+make_outbox!(PollerOutbox<Names, Poller> { poll: Poll });
+
+impl Actor<Names> for Poller {
+    type OutboxType = PollerOutbox;
+}
+
+impl ActorInit<Names> for Poller {
+    fn init(config: &Config, outbox: &mut PollerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Poller>(Poll, time.add(10));
+        Ok(Poller { input: config.input.clone(), pressed_at: None })
+    }
+}
+
+impl Handler<Names, Poll> for Poller {
+    fn recv(&mut self, outbox: &mut PollerOutbox, _: Poll, time: Time, _: Time) -> SchedulerResult {
+        if self.pressed_at.is_none() && self.input.port(0).buttons.pressed(Button::A) {
+            self.pressed_at = Some(time.into());
+        }
+        outbox.send::<Poller>(Poll, time.add(10))
+    }
+}
+
+#[test]
+fn latched_between_periods() {
+    let input = SharedInput::default();
+    let mut instance = Instance::<Names>::new(Config { input: input.clone() }).unwrap();
+    instance.set_input_latch(Some(InputLatch::new(input, 100)));
+    instance.run_until(Time::from(155)).unwrap();
+
+    let (tx_control, rx_control) = mpsc::channel();
+    let (tx_update, _rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
+    let pad = PadState { connected: true, buttons: Buttons::default().with(Button::A), ..PadState::default() };
+    tx_control.send(ControlMessage::Input { port: 0, pad }).unwrap();
+    instance.set_stop_time(Time::from(300));
+    instance.run(&rx_control, tx_update).unwrap();
+
+    // Arrived at 155, but only seen from the next multiple of 100
+    assert_eq!(instance.actor::<Poller>().pressed_at, Some(200));
+}
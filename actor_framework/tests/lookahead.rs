@@ -0,0 +1,114 @@
+//! Workers stop with an error when an actor sends sooner than its LOOKAHEAD
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Early))]
+    Early,
+    #[named(class(Late))]
+    Late,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Poke;
+impl_save_state!(Poke {});
+
+/// Claims to send at least 10 cycles ahead, but pokes Late on the next cycle
+pub struct Early {
+    ticks: u64,
+}
+impl_save_state!(Early { ticks });
+
+make_outbox!(EarlyOutbox<Names, Early> { tick: Tick, poke: Poke });
+
+impl Actor<Names> for Early {
+    type OutboxType = EarlyOutbox;
+    const LOOKAHEAD: u64 = 10;
+}
+
+impl ActorInit<Names> for Early {
+    fn init(_: &Config, outbox: &mut EarlyOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Early>(Tick, time.add(1));
+        Ok(Early { ticks: 0 })
+    }
+}
+
+impl Handler<Names, Tick> for Early {
+    fn recv(&mut self, outbox: &mut EarlyOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.ticks += 1;
+        match self.ticks {
+            5 => outbox.send::<Late>(Poke, time.add(1)),
+            _ => outbox.send::<Early>(Tick, time.add(1)),
+        }
+    }
+}
+
+pub struct Late {
+    ticks: u64,
+    pokes: u64,
+}
+impl_save_state!(Late { ticks, pokes });
+
+make_outbox!(LateOutbox<Names, Late> { tick: Tick });
+
+impl Actor<Names> for Late {
+    type OutboxType = LateOutbox;
+    const LOOKAHEAD: u64 = 10;
+}
+
+impl ActorInit<Names> for Late {
+    fn init(_: &Config, outbox: &mut LateOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Late>(Tick, time.add(1));
+        Ok(Late { ticks: 0, pokes: 0 })
+    }
+}
+
+impl Handler<Names, Tick> for Late {
+    fn recv(&mut self, outbox: &mut LateOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.ticks += 1;
+        outbox.send::<Late>(Tick, time.add(1))
+    }
+}
+
+impl Handler<Names, Poke> for Late {
+    fn recv(&mut self, _: &mut LateOutbox, _: Poke, _: Time, _: Time) -> SchedulerResult {
+        self.pokes += 1;
+        SchedulerResult::Ok
+    }
+}
+
+#[test]
+fn serial_ignores_lookahead() {
+    let mut instance = Instance::<Names>::with_backend(Config, SchedulerBackend::Heap).unwrap();
+    instance.run_until(Time::from(100)).unwrap();
+    assert_eq!(instance.actor::<Late>().pokes, 1);
+}
+
+#[test]
+fn early_send_is_an_error() {
+    let mut instance = Instance::<Names>::with_backend(Config, SchedulerBackend::Heap).unwrap();
+    instance.set_threads(2).unwrap();
+
+    let err = instance.run_until(Time::from(100)).unwrap_err();
+    let violation = err.downcast::<LookaheadViolation<Names>>().unwrap();
+    assert_eq!(violation, LookaheadViolation {
+        sender: Names::Early,
+        receiver: Names::Late,
+        msg_type: std::any::type_name::<Poke>(),
+        time: Time::from(6),
+        window_end: Time::from(11),
+    });
+
+    // The poke is still waiting to be delivered
+    assert_eq!(instance.actor::<Late>().pokes, 0);
+}
@@ -0,0 +1,70 @@
+//! `run` keeps emulated time in step with the host's clock
+
+use std::{sync::mpsc, time::{Duration, Instant}};
+
+use actor_framework::*;
+use ::common::{pacing::{Pacer, Speed}, ControlMessage, Instance as _, StopConditions, UpdateMessage};
+
+mod common;
+use common::{Config, Names};
+
+/// Runs `instance` until it's been running for `duration`
+fn run_for(instance: &mut Instance<Names>, duration: Duration, speed: Option<Speed>) {
+    let (tx_control, rx_control) = mpsc::sync_channel(2);
+    let (tx_update, _rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            if let Some(speed) = speed {
+                tx_control.send(ControlMessage::SetSpeed(speed)).unwrap();
+            }
+            std::thread::sleep(duration);
+            tx_control.send(ControlMessage::Pause).unwrap();
+        });
+        instance.run(&rx_control, tx_update).unwrap();
+    });
+}
+
+#[test]
+fn paced_run() {
+    // A cycle every millisecond, and 20 cycles per frame
+    let mut instance = Instance::<Names>::new(Config::default()).unwrap();
+    instance.set_pacer(Some(Pacer::new(1000, 20)));
+
+    instance.set_speed(Speed::FrameAdvance);
+    run_for(&mut instance, Duration::from_millis(50), None);
+    assert_eq!(instance.now(), Time::from(20));
+
+    // Changing speed while running
+    instance.set_speed(Speed::Percent(50));
+    let start = Instant::now();
+    run_for(&mut instance, Duration::from_millis(300), Some(Speed::Percent(100)));
+    let cycles = u64::from(instance.now()) - 20;
+    let elapsed = start.elapsed().as_millis() as u64;
+    assert!(cycles <= elapsed + 2, "{} cycles in {}ms", cycles, elapsed);
+    assert!(cycles >= 100, "Only {} cycles in {}ms", cycles, elapsed);
+
+    // Without a pacer, it just runs
+    instance.set_pacer(None);
+    run_for(&mut instance, Duration::from_millis(50), None);
+    assert!(u64::from(instance.now()) > 10_000);
+}
+
+#[test]
+fn stop_conditions() {
+    let mut instance = Instance::<Names>::new(Config::default()).unwrap();
+    instance.set_pacer(Some(Pacer::new(1000, 20)));
+    instance.set_speed(Speed::Unlimited);
+
+    // Returns by itself, without being paused
+    let stop = StopConditions { cycles: Some(50), ..StopConditions::default() };
+    instance.set_stop_conditions(stop).unwrap();
+    let (_tx_control, rx_control) = mpsc::channel();
+    let (tx_update, _rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
+    instance.run(&rx_control, tx_update).unwrap();
+    assert_eq!(instance.now(), Time::from(50));
+    assert_eq!(instance.progress().cycles, 50);
+
+    // The framework knows nothing about vsyncs or the CPU
+    let stop = StopConditions { pc: Some(0x1000), ..StopConditions::default() };
+    assert!(instance.set_stop_conditions(stop).is_err());
+}
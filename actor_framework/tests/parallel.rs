@@ -0,0 +1,216 @@
+//! Running on multiple threads must give exactly the same result as a single thread
+
+use std::any::TypeId;
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Core0), handles(Tick, Ack))]
+    Core0,
+    #[named(class(Core1), handles(Tick, Ack))]
+    Core1,
+    #[named(class(Core2), handles(Tick, Ack))]
+    Core2,
+    #[named(class(Core3), handles(Tick, Ack))]
+    Core3,
+    #[named(class(HubA), handles(Mail))]
+    HubA,
+    #[named(class(HubB), handles(Mail))]
+    HubB,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Mail {
+    from: usize,
+    value: u64,
+}
+impl_save_state!(Mail { from, value });
+
+pub struct Ack {
+    value: u64,
+}
+impl_save_state!(Ack { value });
+
+/// Ticks every cycle, and every `period` ticks stops to send mail to one of the hubs
+macro_rules! core {
+    ($name:ident, $outbox:ident, index: $index:expr, period: $period:expr) => {
+        pub struct $name {
+            ticks: u64,
+            hash: u64,
+            acks: Vec<(u64, u64)>,
+        }
+        impl_save_state!($name { ticks, hash, acks });
+
+        make_outbox!($outbox<Names, $name> { tick: Tick, mail: Mail });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+            const LOOKAHEAD: u64 = 4;
+
+            fn delivering<Message>(&mut self, outbox: &mut $outbox, _: &Message, time: Time)
+            where
+                Message: 'static,
+            {
+                if TypeId::of::<Message>() == TypeId::of::<Mail>() {
+                    outbox.send::<$name>(Tick, time.add(1));
+                }
+            }
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(_: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                outbox.send::<$name>(Tick, time.add(1));
+                Ok($name { ticks: 0, hash: $index, acks: Vec::new() })
+            }
+        }
+
+        impl Handler<Names, Tick> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+                self.ticks += 1;
+                self.hash = self.hash.wrapping_mul(31).wrapping_add(time.into());
+                if self.ticks % $period != 0 {
+                    return outbox.send::<$name>(Tick, time.add(1));
+                }
+
+                let mail = Mail { from: $index, value: self.hash };
+                match (self.ticks / $period + $index) % 2 {
+                    0 => outbox.send::<HubA>(mail, time.add(Self::LOOKAHEAD)),
+                    _ => outbox.send::<HubB>(mail, time.add(Self::LOOKAHEAD)),
+                }
+            }
+        }
+
+        impl Handler<Names, Ack> for $name {
+            fn recv(&mut self, _: &mut $outbox, ack: Ack, time: Time, _: Time) -> SchedulerResult {
+                self.acks.push((time.into(), ack.value));
+                self.hash ^= ack.value;
+                SchedulerResult::Ok
+            }
+        }
+    };
+}
+
+// Core0 and Core2 always send to the same hub on the same cycle
+core!(Core0, Core0Outbox, index: 0, period: 5);
+core!(Core1, Core1Outbox, index: 1, period: 7);
+core!(Core2, Core2Outbox, index: 2, period: 5);
+core!(Core3, Core3Outbox, index: 3, period: 11);
+
+/// Logs mail in the order it arrives, and acks it if the outbox is free
+macro_rules! hub {
+    ($name:ident, $outbox:ident) => {
+        pub struct $name {
+            cores: Vec<Endpoint<Names, Ack>>,
+            /// (time, from)
+            log: Vec<(u64, usize)>,
+            values: Vec<u64>,
+            dropped: u64,
+        }
+        impl_save_state!($name { cores, log, values, dropped });
+
+        make_outbox!($outbox<Names, $name> { ack: Ack });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+            const LOOKAHEAD: u64 = 3;
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(_: &Config, _: &mut $outbox, _: Time) -> Result<Self, anyhow::Error> {
+                Ok($name {
+                    cores: vec![
+                        Endpoint::new::<Core0>(),
+                        Endpoint::new::<Core1>(),
+                        Endpoint::new::<Core2>(),
+                        Endpoint::new::<Core3>(),
+                    ],
+                    log: Vec::new(),
+                    values: Vec::new(),
+                    dropped: 0,
+                })
+            }
+        }
+
+        impl Handler<Names, Mail> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, mail: Mail, time: Time, _: Time) -> SchedulerResult {
+                self.log.push((time.into(), mail.from));
+                self.values.push(mail.value);
+                if outbox.time() != Time::MAX {
+                    self.dropped += 1;
+                    return SchedulerResult::Ok;
+                }
+                let core = self.cores[mail.from].clone();
+                outbox.send_endpoint(core, Ack { value: mail.value }, time.add(Self::LOOKAHEAD))
+            }
+        }
+    };
+}
+
+hub!(HubA, HubAOutbox);
+hub!(HubB, HubBOutbox);
+
+#[test]
+fn identical_to_serial() {
+    for backend in [SchedulerBackend::LinkedList, SchedulerBackend::Heap] {
+        let mut serial = Instance::<Names>::with_backend(Config, backend).unwrap();
+        serial.run_until(Time::from(5000)).unwrap();
+
+        assert!(serial.actor::<HubA>().dropped > 0 && serial.actor::<HubB>().dropped > 0);
+        let expected = serial.save_state_bytes();
+
+        for threads in [2, 3, 4] {
+            let mut parallel = Instance::<Names>::with_backend(Config, backend).unwrap();
+            parallel.set_threads(threads).unwrap();
+            // Stop part way through, to make sure windows are cut short by the target
+            parallel.run_until(Time::from(1234)).unwrap();
+            parallel.run_until(Time::from(5000)).unwrap();
+
+            assert_eq!(parallel.now(), serial.now(), "{:?} {} threads", backend, threads);
+            assert_eq!(parallel.scheduler_stats().runs, serial.scheduler_stats().runs, "{:?} {} threads", backend, threads);
+            assert_eq!(parallel.actor::<HubA>().log, serial.actor::<HubA>().log, "{:?} {} threads", backend, threads);
+            assert!(parallel.save_state_bytes() == expected, "{:?} {} threads: save states differ", backend, threads);
+        }
+    }
+}
+
+#[test]
+fn breakpoints_fall_back_to_serial() {
+    let mut serial = Instance::<Names>::with_backend(Config, SchedulerBackend::Heap).unwrap();
+    let mut parallel = Instance::<Names>::with_backend(Config, SchedulerBackend::Heap).unwrap();
+    parallel.set_threads(4).unwrap();
+
+    for instance in [&mut serial, &mut parallel] {
+        instance.run_until(Time::from(1000)).unwrap();
+        instance.add_breakpoint(Breakpoint::new().message::<Ack>().receiver(Names::Core3));
+        match instance.run_until(Time::from(5000)).unwrap() {
+            StopReason::Breakpoint(hit) => assert_eq!(hit.receiver, Names::Core3),
+            reason => panic!("Expected a breakpoint, got {:?}", reason),
+        }
+    }
+    assert_eq!(parallel.now(), serial.now());
+    assert!(parallel.save_state_bytes() == serial.save_state_bytes());
+
+    // And back to multiple threads once the breakpoint is gone
+    for instance in [&mut serial, &mut parallel] {
+        instance.clear_breakpoints();
+        instance.run_until(Time::from(5000)).unwrap();
+    }
+    assert!(parallel.save_state_bytes() == serial.save_state_bytes());
+}
+
+#[test]
+fn needs_fifo_backend() {
+    let mut instance = Instance::<Names>::with_backend(Config, SchedulerBackend::Cached).unwrap();
+    assert!(instance.set_threads(2).is_err());
+    assert!(instance.set_threads(1).is_ok());
+}
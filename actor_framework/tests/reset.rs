@@ -0,0 +1,51 @@
+//! Resets requested with `ControlMessage::Reset` while running
+
+use std::sync::mpsc;
+
+use actor_framework::*;
+use ::common::{ControlMessage, Instance as _, ResetKind, UpdateMessage};
+
+mod common;
+use common::{Config, Names, Tick, Ticker};
+
+/// Sends `messages` before running, so they're all handled before anything is delivered
+fn run_with(instance: &mut Instance<Names>, messages: Vec<ControlMessage>) {
+    let (tx_control, rx_control) = mpsc::channel();
+    let (tx_update, _rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
+    for message in messages {
+        tx_control.send(message).unwrap();
+    }
+    instance.run(&rx_control, tx_update).unwrap();
+}
+
+#[test]
+fn soft_reset() {
+    let mut instance = Instance::<Names>::new(Config { start: 5, ..Config::default() }).unwrap();
+    instance.run_until(Time::from(10)).unwrap();
+
+    run_with(&mut instance, vec![ControlMessage::Reset(ResetKind::Soft), ControlMessage::Pause]);
+
+    // Only the actors are told, nothing else changes
+    let ticker = instance.actor::<Ticker>();
+    assert_eq!((ticker.ticks, ticker.resets), (5, 1));
+    assert_eq!(instance.now(), Time::from(10));
+}
+
+#[test]
+fn power_cycle() {
+    let mut instance = Instance::<Names>::new(Config { start: 5, ..Config::default() }).unwrap();
+    instance.set_stop_time(Time::from(20));
+    instance.run_until(Time::from(10)).unwrap();
+
+    run_with(&mut instance, vec![ControlMessage::Reset(ResetKind::PowerCycle), ControlMessage::Pause]);
+
+    // Back to time zero, with actors recreated from the same config
+    assert_eq!(instance.now(), Time::default());
+    assert_eq!(instance.actor::<Ticker>().ticks, 0);
+    assert_eq!(instance.pending_message::<Ticker, Tick>().map(|(time, _)| time), Some(Time::from(5)));
+
+    // The stop time survives
+    run_with(&mut instance, vec![]);
+    assert_eq!(instance.now(), Time::from(20));
+    assert_eq!(instance.actor::<Ticker>().ticks, 15);
+}
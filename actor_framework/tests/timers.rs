@@ -0,0 +1,142 @@
+//! Timers are delivered through the outbox, without getting in the way of the actor's own messages
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Poller), handles(Tick, Alarm, Pong))]
+    Poller,
+    #[named(class(Echo), handles(Ping))]
+    Echo,
+    #[named(terminal)]
+    Terminal,
+}
+
+#[derive(Clone)]
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Alarm;
+impl_save_state!(Alarm {});
+
+pub struct Ping;
+impl_save_state!(Ping {});
+
+pub struct Pong;
+impl_save_state!(Pong {});
+
+/// Ticks every 10 cycles and pings Echo whenever its outbox is free. The third tick sets an
+/// alarm, which speeds the ticks up, and the eighth tick stops them
+pub struct Poller {
+    timers: Timers<Names, PollerOutbox>,
+    ticks: Vec<u64>,
+    alarms: Vec<u64>,
+    pongs: Vec<u64>,
+}
+impl_save_state!(Poller { timers, ticks, alarms, pongs });
+
+make_outbox!(PollerOutbox<Names, Poller> { tick: Tick, alarm: Alarm, ping: Ping });
+
+impl Actor<Names> for Poller {
+    type OutboxType = PollerOutbox;
+
+    fn timers(&mut self) -> Option<&mut Timers<Names, PollerOutbox>> {
+        Some(&mut self.timers)
+    }
+}
+
+impl ActorInit<Names> for Poller {
+    fn init(_: &Config, _: &mut PollerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        let mut timers = Timers::new();
+        timers.every(Tick, time.add(10), 10);
+        Ok(Poller { timers, ticks: Vec::new(), alarms: Vec::new(), pongs: Vec::new() })
+    }
+}
+
+impl Handler<Names, Tick> for Poller {
+    fn recv(&mut self, outbox: &mut PollerOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.ticks.push(time.into());
+        match self.ticks.len() {
+            3 => self.timers.once(Alarm, time.add(5)),
+            8 => assert_eq!(self.timers.cancel::<Tick>(), Some(time.add(7))),
+            _ => {}
+        }
+        if outbox.time() == Time::MAX {
+            return outbox.send::<Echo>(Ping, time.add(25));
+        }
+        SchedulerResult::Ok
+    }
+}
+
+impl Handler<Names, Alarm> for Poller {
+    fn recv(&mut self, _: &mut PollerOutbox, _: Alarm, time: Time, _: Time) -> SchedulerResult {
+        self.alarms.push(time.into());
+        assert_eq!(self.timers.due::<Tick>(), Some(time.add(5)));
+        self.timers.every(Tick, time.add(1), 7);
+        SchedulerResult::Ok
+    }
+}
+
+impl Handler<Names, Pong> for Poller {
+    fn recv(&mut self, outbox: &mut PollerOutbox, _: Pong, time: Time, _: Time) -> SchedulerResult {
+        // Timers never show up in the outbox
+        assert!(!outbox.contains::<Tick>() && !outbox.contains::<Alarm>());
+        self.pongs.push(time.into());
+        SchedulerResult::Ok
+    }
+}
+
+#[derive(Default)]
+pub struct Echo {}
+impl_save_state!(Echo {});
+
+make_outbox!(EchoOutbox<Names, Echo> { pong: Pong });
+
+impl Actor<Names> for Echo {
+    type OutboxType = EchoOutbox;
+}
+
+impl Handler<Names, Ping> for Echo {
+    fn recv(&mut self, outbox: &mut EchoOutbox, _: Ping, time: Time, _: Time) -> SchedulerResult {
+        outbox.send::<Poller>(Pong, time.add(1))
+    }
+}
+
+const TICKS: [u64; 8] = [10, 20, 30, 36, 43, 50, 57, 64];
+
+#[test]
+fn periodic_and_one_shot() {
+    for backend in SchedulerBackend::ALL {
+        let mut instance = Instance::<Names>::with_backend(Config, backend).unwrap();
+        instance.run_until(Time::from(200)).unwrap();
+
+        let poller = instance.actor::<Poller>();
+        assert_eq!(poller.ticks, TICKS, "{:?}", backend);
+        assert_eq!(poller.alarms, [35], "{:?}", backend);
+        // The ping sent on the first tick is still in flight for the next two ticks
+        assert_eq!(poller.pongs, [36, 62, 90], "{:?}", backend);
+        assert!(poller.timers.is_empty(), "{:?}", backend);
+    }
+}
+
+#[test]
+fn save_state_while_due() {
+    // At 37, the next tick is waiting in the outbox while the ping is put aside
+    let mut saved = Instance::<Names>::new(Config).unwrap();
+    saved.run_until(Time::from(37)).unwrap();
+    assert_eq!(saved.actor::<Poller>().timers.len(), 1);
+    let state = saved.save_state_bytes();
+
+    let mut loaded = Instance::<Names>::new(Config).unwrap();
+    loaded.load_state_bytes(&state).unwrap();
+    loaded.run_until(Time::from(200)).unwrap();
+
+    let poller = loaded.actor::<Poller>();
+    assert_eq!(poller.ticks, TICKS);
+    assert_eq!(poller.pongs, [36, 62, 90]);
+}
@@ -0,0 +1,149 @@
+//! Recording a run's messages, and checking a later run against the recording
+
+use std::borrow::Cow;
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Producer))]
+    Producer,
+    #[named(class(Consumer))]
+    Consumer,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Tick;
+impl_save_state!(Tick {});
+
+pub struct Data {
+    value: u64,
+}
+impl_save_state!(Data { value });
+
+/// Ticks, then sends data with a lazy time, so the trace has resolves in it
+pub struct Producer {
+    count: u64,
+}
+impl_save_state!(Producer { count });
+
+make_outbox!(ProducerOutbox<Names, Producer> { tick: Tick, data: Data });
+
+impl Actor<Names> for Producer {
+    type OutboxType = ProducerOutbox;
+
+    fn delivering<Message>(&mut self, outbox: &mut ProducerOutbox, _: &Message, time: Time)
+    where
+        Message: 'static,
+    {
+        if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<Data>() {
+            outbox.send::<Producer>(Tick, time.add(2));
+        }
+    }
+}
+
+impl ActorInit<Names> for Producer {
+    fn init(_: &Config, outbox: &mut ProducerOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Producer>(Tick, time.add(1));
+        Ok(Producer { count: 0 })
+    }
+}
+
+impl Handler<Names, Tick> for Producer {
+    fn recv(&mut self, outbox: &mut ProducerOutbox, _: Tick, time: Time, _: Time) -> SchedulerResult {
+        self.count += 1;
+        outbox.send::<Consumer>(Data { value: self.count }, time.add(3).into_lazy())
+    }
+}
+
+pub struct Consumer {
+    total: u64,
+}
+impl_save_state!(Consumer { total });
+
+make_outbox!(ConsumerOutbox<Names, Consumer> { });
+
+impl Actor<Names> for Consumer {
+    type OutboxType = ConsumerOutbox;
+}
+
+impl ActorInit<Names> for Consumer {
+    fn init(_: &Config, _: &mut ConsumerOutbox, _: Time) -> Result<Self, anyhow::Error> {
+        Ok(Consumer { total: 0 })
+    }
+}
+
+impl Handler<Names, Data> for Consumer {
+    fn recv(&mut self, _: &mut ConsumerOutbox, data: Data, _: Time, _: Time) -> SchedulerResult {
+        self.total += data.value;
+        SchedulerResult::Ok
+    }
+}
+
+fn record(until: u64) -> Trace<Names> {
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.record_trace();
+    instance.run_until(Time::from(until)).unwrap();
+    instance.stop_trace().unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let trace = record(100);
+    assert!(trace.entries.iter().any(|entry| entry.resolve), "No resolves recorded");
+    assert!(trace.entries.iter().any(|entry| !entry.resolve && entry.receiver == Names::Consumer));
+
+    let path = std::env::temp_dir().join(format!("bus-mu-trace-{}.trace", std::process::id()));
+    trace.save(&path).unwrap();
+    let loaded = Trace::<Names>::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.entries, trace.entries);
+
+    // The same run matches the whole trace
+    let mut instance = Instance::<Names>::new(Config).unwrap();
+    instance.verify_trace(loaded);
+    instance.run_until(Time::from(100)).unwrap();
+    let delivered = trace.entries.iter().filter(|entry| !entry.resolve && entry.receiver == Names::Consumer).count() as u64;
+    assert_eq!(instance.actor::<Consumer>().total, (1..=delivered).sum());
+}
+
+type Change = fn(&mut TraceEntry<Names>);
+
+#[test]
+fn first_divergence() {
+    let trace = record(100);
+    let resolve = trace.entries.iter().position(|entry| entry.resolve).unwrap();
+
+    let changes: [(usize, Change); 6] = [
+        (3, |entry| entry.time = entry.time.add(1)),
+        (6, |entry| entry.sender = Names::Consumer),
+        (9, |entry| entry.receiver = Names::Terminal),
+        (12, |entry| entry.msg_type = Cow::Borrowed("Other")),
+        (15, |entry| entry.limit = entry.limit.add(1)),
+        (resolve, |entry| entry.resolve = false),
+    ];
+    for (index, change) in changes {
+        // Diverging again later doesn't matter, only the first is reported
+        let mut changed = Trace { entries: trace.entries.clone() };
+        change(&mut changed.entries[index]);
+        change(changed.entries.last_mut().unwrap());
+
+        let mut instance = Instance::<Names>::new(Config).unwrap();
+        instance.verify_trace(changed);
+        let err = instance.run_until(Time::from(100)).unwrap_err();
+        let divergence = err.downcast::<TraceDivergence<Names>>().unwrap();
+        assert_eq!(divergence.index, index);
+        assert_eq!(divergence.actual, trace.entries[index]);
+        assert_ne!(divergence.expected, divergence.actual);
+
+        // Stopped before anything past the divergence ran
+        assert_eq!(instance.now(), trace.entries[index].time.lower_bound());
+    }
+}
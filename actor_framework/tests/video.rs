@@ -0,0 +1,95 @@
+//! Frames from actors are passed on to the UI when it syncs
+
+use std::sync::mpsc;
+
+use actor_framework::*;
+use common::{impl_save_state, video::{self, Field, Frame, FramePool, FrameSender, PixelFormat}, ControlMessage, Instance as _, UpdateMessage};
+
+pub struct Config {
+    frames: FrameSender,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(Painter))]
+    Painter,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Paint;
+impl_save_state!(Paint {});
+
+/// Draws a frame filled with the frame number every 10 cycles
+pub struct Painter {
+    count: u8,
+    pool: FramePool,
+    frames: FrameSender,
+}
+impl_save_state!(Painter { count, .. });
+
+make_outbox!(PainterOutbox<Names, Painter> { paint: Paint });
+
+impl Actor<Names> for Painter {
+    type OutboxType = PainterOutbox;
+}
+
+impl ActorInit<Names> for Painter {
+    fn init(config: &Config, outbox: &mut PainterOutbox, time: Time) -> Result<Self, anyhow::Error> {
+        outbox.send::<Painter>(Paint, time.add(10));
+        Ok(Painter { count: 0, pool: FramePool::new(), frames: config.frames.clone() })
+    }
+}
+
+impl Handler<Names, Paint> for Painter {
+    fn recv(&mut self, outbox: &mut PainterOutbox, _: Paint, time: Time, _: Time) -> SchedulerResult {
+        self.count += 1;
+        let mut data = self.pool.buffer(4 * 4 * 3);
+        data.fill(self.count);
+        self.frames.send(Frame { width: 4, height: 3, stride: 16, format: PixelFormat::Rgba8888, field: Field::Progressive, data });
+        outbox.send::<Painter>(Paint, time.add(10))
+    }
+}
+
+/// Syncs like the UI does, returning the frame if there was one
+fn ui_sync(instance: &mut Instance<Names>) -> Option<Frame> {
+    let (tx_control, rx_control) = mpsc::channel();
+    let (tx_update, rx_update) = mpsc::sync_channel::<UpdateMessage>(4);
+    tx_control.send(ControlMessage::UiSync).unwrap();
+    tx_control.send(ControlMessage::Pause).unwrap();
+    instance.run(&rx_control, tx_update).unwrap();
+
+    let mut frame = None;
+    let mut stats = false;
+    for update in rx_update.try_iter() {
+        match update {
+            UpdateMessage::Vsync(new_frame) => {
+                assert!(!stats, "Frame should arrive before the stats");
+                frame = Some(new_frame);
+            }
+            UpdateMessage::Stats(_) => stats = true,
+            UpdateMessage::UiSynced => return frame,
+            UpdateMessage::Ack(_) | UpdateMessage::Error(_) => panic!("No commands were sent"),
+        }
+    }
+    panic!("Never synced");
+}
+
+#[test]
+fn latest_frame_on_sync() {
+    let (tx, rx) = video::frame_channel();
+    let mut instance = Instance::<Names>::new(Config { frames: tx }).unwrap();
+    instance.set_frame_receiver(Some(rx));
+    // Nothing more gets drawn while syncing
+    instance.set_stop_time(Time::from(35));
+
+    instance.run_until(Time::from(35)).unwrap();
+    let frame = ui_sync(&mut instance).unwrap();
+    assert_eq!((frame.width, frame.height), (4, 3));
+    assert!(frame.data.iter().all(|&pixel| pixel == 3), "Earlier frames should have been replaced");
+
+    // Nothing new since the last sync
+    assert!(ui_sync(&mut instance).is_none());
+}
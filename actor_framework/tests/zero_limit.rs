@@ -0,0 +1,105 @@
+//! A cycle that never settles is reported as an error instead of panicking
+
+use actor_framework::*;
+use common::impl_save_state;
+
+pub struct Config {
+    /// Start both spinners, instead of just SpinnerA
+    both: bool,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Named)]
+#[named(base(actor_framework::ActorBox), config(Config))]
+pub enum Names {
+    #[named(class(SpinnerA))]
+    SpinnerA,
+    #[named(class(SpinnerB))]
+    SpinnerB,
+    #[named(terminal)]
+    Terminal,
+}
+
+pub struct Spin;
+impl_save_state!(Spin {});
+
+/// Keeps sending itself a message on cycle 10, and always claims to be zero limited
+macro_rules! spinner {
+    ($name:ident, $outbox:ident, $start:expr) => {
+        pub struct $name;
+        impl_save_state!($name {});
+
+        make_outbox!($outbox<Names, $name> { spin: Spin });
+
+        impl Actor<Names> for $name {
+            type OutboxType = $outbox;
+        }
+
+        impl ActorInit<Names> for $name {
+            fn init(config: &Config, outbox: &mut $outbox, time: Time) -> Result<Self, anyhow::Error> {
+                if $start(config) {
+                    outbox.send::<$name>(Spin, time.add(10));
+                }
+                Ok($name)
+            }
+        }
+
+        impl Handler<Names, Spin> for $name {
+            fn recv(&mut self, outbox: &mut $outbox, _: Spin, time: Time, _: Time) -> SchedulerResult {
+                outbox.send::<$name>(Spin, time);
+                SchedulerResult::ZeroLimit
+            }
+        }
+    };
+}
+
+spinner!(SpinnerA, SpinnerAOutbox, |_: &Config| true);
+spinner!(SpinnerB, SpinnerBOutbox, |config: &Config| config.both);
+
+/// Only backends that fall back to `run_zero_limit`, FIFO backends would spin forever
+const BACKENDS: [SchedulerBackend; 3] = [
+    SchedulerBackend::Branchless,
+    SchedulerBackend::Cached,
+    SchedulerBackend::UpdatingCache,
+];
+
+fn run(config: Config, backend: SchedulerBackend) -> ZeroLimitReport<Names> {
+    let mut instance = Instance::<Names>::with_backend(config, backend).unwrap();
+    let err = instance.run_until(Time::from(100)).unwrap_err();
+    err.downcast::<ZeroLimitReport<Names>>().unwrap()
+}
+
+#[test]
+fn unsettled() {
+    for backend in BACKENDS {
+        let report = run(Config { both: true }, backend);
+        assert_eq!(report.time, Time::from(10), "{:?}", backend);
+        assert_eq!(report.problem, ZeroLimitProblem::Unsettled { iterations: 9 }, "{:?}", backend);
+
+        let pending: Vec<_> = report.pending.iter().map(|msg| (msg.sender, msg.receiver)).collect();
+        assert_eq!(pending, [(Names::SpinnerA, Names::SpinnerA), (Names::SpinnerB, Names::SpinnerB)], "{:?}", backend);
+        assert!(report.pending[0].msg_type.ends_with("Spin"), "{}", report.pending[0].msg_type);
+
+        let deliveries: Vec<_> = report.deliveries.iter()
+            .map(|delivery| (delivery.iteration, delivery.message.sender, delivery.zero_limit))
+            .collect();
+        assert_eq!(deliveries, [
+            (7, Names::SpinnerA, true),
+            (7, Names::SpinnerB, true),
+            (8, Names::SpinnerA, true),
+            (8, Names::SpinnerB, true),
+        ], "{:?}", backend);
+        assert!(report.to_string().starts_with("Zero limit cycle detected at cycle 10"), "{}", report);
+    }
+}
+
+#[test]
+fn incorrect_limit() {
+    for backend in BACKENDS {
+        // Nothing else is scheduled, so SpinnerA is only limited by `run_until`
+        let report = run(Config { both: false }, backend);
+        assert_eq!(report.problem, ZeroLimitProblem::IncorrectLimit { sender: Names::SpinnerA, limit: Time::from(100) }, "{:?}", backend);
+        assert_eq!(report.pending.len(), 1, "{:?}", backend);
+        assert!(report.deliveries.is_empty(), "{:?}", backend);
+    }
+}
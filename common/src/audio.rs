@@ -0,0 +1,366 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{atomic::{AtomicU32, AtomicUsize, Ordering}, Arc},
+};
+
+/// One sample for each channel, left then right
+pub type StereoSample = [i16; 2];
+
+/// Where a core sends its audio
+///
+/// Cores push 16 bit stereo PCM in chunks, whenever they have some. The sample rate is chosen by
+/// the core and can change between chunks.
+pub trait AudioSink: Send {
+    fn push(&mut self, rate: u32, samples: &[StereoSample]);
+
+    /// Called when the core is finished with the sink. Errors that happened while pushing are
+    /// reported here
+    fn flush(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// Creates a lock-free ring buffer holding up to `capacity` samples, for passing audio from the
+/// emulation thread to the host's audio callback
+pub fn audio_channel(capacity: usize) -> (AudioProducer, AudioConsumer) {
+    let ring = Arc::new(Ring {
+        samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+        rate: AtomicU32::new(0),
+    });
+    let consumer = AudioConsumer { ring: ring.clone(), resampler: None, resampled: Vec::new(), scratch: Vec::new() };
+    (AudioProducer { ring }, consumer)
+}
+
+struct Ring {
+    /// Samples are packed into a u32, so each one can be stored atomically
+    samples: Box<[AtomicU32]>,
+    /// Only ever increase, the position in `samples` is taken modulo the capacity
+    read: AtomicUsize,
+    write: AtomicUsize,
+    /// The rate of the most recently pushed samples. Zero until the first push
+    rate: AtomicU32,
+}
+
+impl Ring {
+    fn pack(sample: StereoSample) -> u32 {
+        (sample[0] as u16 as u32) << 16 | sample[1] as u16 as u32
+    }
+
+    fn unpack(packed: u32) -> StereoSample {
+        [(packed >> 16) as i16, packed as i16]
+    }
+
+    fn len(&self) -> usize {
+        self.write.load(Ordering::Acquire).wrapping_sub(self.read.load(Ordering::Relaxed))
+    }
+
+    fn pop(&self, out: &mut [StereoSample]) -> usize {
+        let capacity = self.samples.len();
+        let read = self.read.load(Ordering::Relaxed);
+        let count = self.len().min(out.len());
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            *sample = Ring::unpack(self.samples[read.wrapping_add(i) % capacity].load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+/// The emulation side of `audio_channel`
+///
+/// When the ring is full, new samples are dropped rather than waiting for the host to catch up.
+pub struct AudioProducer {
+    ring: Arc<Ring>,
+}
+
+impl AudioSink for AudioProducer {
+    fn push(&mut self, rate: u32, samples: &[StereoSample]) {
+        let ring = &*self.ring;
+        let capacity = ring.samples.len();
+        let write = ring.write.load(Ordering::Relaxed);
+        let free = capacity - write.wrapping_sub(ring.read.load(Ordering::Acquire));
+
+        // The consumer assumes every sample in the ring has the latest rate. It's close enough,
+        // as rates don't change often and the ring is short
+        ring.rate.store(rate, Ordering::Relaxed);
+        for (i, &sample) in samples.iter().take(free).enumerate() {
+            ring.samples[write.wrapping_add(i) % capacity].store(Ring::pack(sample), Ordering::Relaxed);
+        }
+        ring.write.store(write.wrapping_add(samples.len().min(free)), Ordering::Release);
+    }
+}
+
+/// The host side of `audio_channel`
+pub struct AudioConsumer {
+    ring: Arc<Ring>,
+    resampler: Option<Resampler>,
+    /// Output from the resampler that didn't fit in the last `fill`
+    resampled: Vec<StereoSample>,
+    scratch: Vec<StereoSample>,
+}
+
+impl AudioConsumer {
+    /// The rate of the samples in the ring, or `None` if nothing has been pushed yet
+    pub fn rate(&self) -> Option<u32> {
+        match self.ring.rate.load(Ordering::Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// Samples waiting in the ring
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes samples from the ring at the core's rate, returning how many were copied to `out`
+    pub fn pop(&mut self, out: &mut [StereoSample]) -> usize {
+        self.ring.pop(out)
+    }
+
+    /// Fills all of `out` with audio resampled to `host_rate`, for the host's audio callback.
+    ///
+    /// If the core hasn't produced enough audio, the rest is filled with silence. Returns how many
+    /// samples were real audio.
+    pub fn fill(&mut self, host_rate: u32, out: &mut [StereoSample]) -> usize {
+        let resampler = self.resampler.get_or_insert_with(|| Resampler::new(host_rate));
+        resampler.set_output_rate(host_rate);
+
+        while self.resampled.len() < out.len() {
+            let rate = self.ring.rate.load(Ordering::Relaxed);
+            if rate == 0 {
+                break;
+            }
+            self.scratch.resize(256, [0, 0]);
+            let count = self.ring.pop(&mut self.scratch);
+            if count == 0 {
+                break;
+            }
+            resampler.process(rate, &self.scratch[..count], &mut self.resampled);
+        }
+
+        let count = self.resampled.len().min(out.len());
+        out[..count].copy_from_slice(&self.resampled[..count]);
+        out[count..].fill([0, 0]);
+        self.resampled.drain(..count);
+        count
+    }
+}
+
+/// Converts between sample rates with linear interpolation
+///
+/// Chunks are treated as one continuous stream, so there are no clicks at the boundaries. When
+/// the rates match, samples are passed through unchanged.
+pub struct Resampler {
+    output_rate: u32,
+    /// Position of the next output sample, in input samples relative to the start of the next
+    /// chunk. Negative positions fall between `last` and the start of the chunk
+    position: f64,
+    last: StereoSample,
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32) -> Self {
+        Resampler { output_rate, position: 0.0, last: [0, 0] }
+    }
+
+    pub fn set_output_rate(&mut self, output_rate: u32) {
+        self.output_rate = output_rate;
+    }
+
+    /// Resamples `input` from `input_rate`, appending the result to `out`
+    pub fn process(&mut self, input_rate: u32, input: &[StereoSample], out: &mut Vec<StereoSample>) {
+        let Some(&last) = input.last() else { return };
+        let step = input_rate as f64 / self.output_rate as f64;
+        let end = (input.len() - 1) as f64;
+
+        let previous = self.last;
+        let sample = |index: isize| match index {
+            -1 => previous,
+            index => input[index as usize],
+        };
+
+        while self.position <= end {
+            let index = self.position.floor();
+            let fraction = self.position - index;
+            let a = sample(index as isize);
+            out.push(match fraction == 0.0 {
+                true => a,
+                false => {
+                    let b = sample(index as isize + 1);
+                    let lerp = |a: i16, b: i16| (a as f64 + (b as f64 - a as f64) * fraction).round() as i16;
+                    [lerp(a[0], b[0]), lerp(a[1], b[1])]
+                }
+            });
+            self.position += step;
+        }
+
+        self.position -= input.len() as f64;
+        self.last = last;
+    }
+}
+
+/// Writes audio to a 16 bit stereo WAV file
+///
+/// The file uses the rate of the first chunk, later chunks at other rates are resampled to match.
+/// The header is only complete once `flush` has been called, which also happens on drop.
+pub struct WavWriter<W: Write + Seek + Send> {
+    out: W,
+    rate: Option<u32>,
+    resampler: Option<Resampler>,
+    resampled: Vec<StereoSample>,
+    samples: u32,
+    /// The first error while writing samples, reported by `flush`
+    error: Option<io::Error>,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek + Send> WavWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        // Filled in properly by `flush`, once the rate and length are known
+        out.write_all(&[0; 44])?;
+        Ok(WavWriter { out, rate: None, resampler: None, resampled: Vec::new(), samples: 0, error: None })
+    }
+
+    fn write_samples(&mut self, samples: &[StereoSample]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&sample[0].to_le_bytes())?;
+            self.out.write_all(&sample[1].to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let rate = self.rate.unwrap_or(44100);
+        let data_bytes = self.samples * 4;
+
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(b"RIFF")?;
+        self.out.write_all(&(36 + data_bytes).to_le_bytes())?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_all(&16u32.to_le_bytes())?;
+        self.out.write_all(&1u16.to_le_bytes())?; // PCM
+        self.out.write_all(&2u16.to_le_bytes())?; // Channels
+        self.out.write_all(&rate.to_le_bytes())?;
+        self.out.write_all(&(rate * 4).to_le_bytes())?; // Bytes per second
+        self.out.write_all(&4u16.to_le_bytes())?; // Bytes per sample, for all channels
+        self.out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        self.out.write_all(b"data")?;
+        self.out.write_all(&data_bytes.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write + Seek + Send> AudioSink for WavWriter<W> {
+    fn push(&mut self, rate: u32, samples: &[StereoSample]) {
+        if self.error.is_some() {
+            return;
+        }
+        let file_rate = *self.rate.get_or_insert(rate);
+        let result = if rate == file_rate && self.resampler.is_none() {
+            self.write_samples(samples)
+        } else {
+            // Once resampling starts, it continues so the stream stays continuous
+            let resampler = self.resampler.get_or_insert_with(|| Resampler::new(file_rate));
+            let mut resampled = std::mem::take(&mut self.resampled);
+            resampled.clear();
+            resampler.process(rate, samples, &mut resampled);
+            let result = self.write_samples(&resampled);
+            self.resampled = resampled;
+            result
+        };
+        self.error = result.err();
+    }
+
+    fn flush(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        Ok(self.write_header()?)
+    }
+}
+
+impl<W: Write + Seek + Send> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_header();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_drops_when_full() {
+        let (mut producer, mut consumer) = audio_channel(4);
+        assert_eq!(consumer.rate(), None);
+
+        producer.push(32000, &[[1, -1], [2, -2], [3, -3]]);
+        let mut out = [[0; 2]; 2];
+        assert_eq!(consumer.pop(&mut out), 2);
+        assert_eq!(out, [[1, -1], [2, -2]]);
+
+        // Wraps around the end, and the last sample doesn't fit
+        producer.push(32000, &[[4, -4], [5, -5], [6, -6], [7, -7]]);
+        let mut out = [[0; 2]; 8];
+        assert_eq!(consumer.pop(&mut out), 4);
+        assert_eq!(out[..4], [[3, -3], [4, -4], [5, -5], [6, -6]]);
+        assert_eq!(consumer.rate(), Some(32000));
+    }
+
+    #[test]
+    fn resampling() {
+        // Same rate passes straight through
+        let mut resampler = Resampler::new(100);
+        let mut out = Vec::new();
+        resampler.process(100, &[[0, 0], [10, -10]], &mut out);
+        resampler.process(100, &[[20, -20]], &mut out);
+        assert_eq!(out, [[0, 0], [10, -10], [20, -20]]);
+
+        // Doubling interpolates, including across chunks
+        let mut resampler = Resampler::new(200);
+        let mut out = Vec::new();
+        resampler.process(100, &[[0, 0], [10, -10]], &mut out);
+        resampler.process(100, &[[20, -20]], &mut out);
+        assert_eq!(out, [[0, 0], [5, -5], [10, -10], [15, -15], [20, -20]]);
+
+        // Host callback pads with silence when the core falls behind
+        let (mut producer, mut consumer) = audio_channel(16);
+        producer.push(100, &[[0, 0], [10, -10]]);
+        let mut out = [[1; 2]; 4];
+        assert_eq!(consumer.fill(200, &mut out), 3);
+        assert_eq!(out, [[0, 0], [5, -5], [10, -10], [0, 0]]);
+    }
+
+    #[test]
+    fn wav_file() {
+        let mut wav = WavWriter::new(io::Cursor::new(Vec::new())).unwrap();
+        wav.push(22050, &[[1, 2], [3, 4]]);
+        wav.push(44100, &[[5, 6], [5, 6]]);
+        wav.flush().unwrap();
+
+        let bytes = wav.out.get_ref().clone();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 22050);
+        // Halving the second chunk's rate gives one more sample
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 3 * 4);
+        assert_eq!(bytes.len(), 44 + 3 * 4);
+        assert_eq!(&bytes[44..48], &[1, 0, 2, 0]);
+    }
+}
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::{Args, ValueEnum, ArgAction};
 
+use crate::pacing::Speed;
+
 #[derive(Debug, Args)]
 #[clap(name = "bus-mu", version, disable_help_flag = true, disable_version_flag = true)]
 #[clap(next_help_heading = "Global Options")]
@@ -13,6 +17,37 @@ where
     #[arg(long)]
     pub nogui : bool,
 
+    /// Emulation speed: a percentage of real time, "unlimited" or "frame" (frame advance).
+    /// Defaults to 100% with the UI, and unlimited with --nogui
+    #[arg(long, value_name = "SPEED")]
+    pub speed: Option<Speed>,
+
+    /// Write a host-time profile in folded stack format when a --nogui run ends
+    /// (requires the "profiling" feature)
+    #[arg(long, value_name = "PATH")]
+    pub profile: Option<PathBuf>,
+
+    /// Stop a --nogui run after this many emulated cycles
+    #[arg(long, value_name = "CYCLES", help_heading = "Headless Options")]
+    pub stop_cycles: Option<u64>,
+
+    /// Stop a --nogui run after this many vsyncs
+    #[arg(long, value_name = "COUNT", help_heading = "Headless Options")]
+    pub stop_vsyncs: Option<u64>,
+
+    /// Stop a --nogui run after this many seconds of real time
+    #[arg(long, value_name = "SECONDS", help_heading = "Headless Options")]
+    pub stop_seconds: Option<f64>,
+
+    /// Stop a --nogui run when the CPU reaches this address. If any other limit is hit first,
+    /// the exit status is 3
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_address, help_heading = "Headless Options")]
+    pub stop_pc: Option<u64>,
+
+    /// Write the core's audio output to a WAV file during a --nogui run
+    #[arg(long, value_name = "PATH", help_heading = "Headless Options")]
+    pub wav: Option<PathBuf>,
+
     #[arg(long, short, action = ArgAction::Help)]
     help: (),
 
@@ -20,6 +55,14 @@ where
     version: (),
 }
 
+/// Accepts hex with a 0x prefix, or decimal
+fn parse_address(s: &str) -> Result<u64, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    }
+}
+
 #[macro_export]
 macro_rules! register_cores {
     { $( $core_type:ident ),* $(,)? } => {
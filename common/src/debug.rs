@@ -0,0 +1,237 @@
+//! A core-agnostic view of a paused instance, for debuggers
+//!
+//! Cores implement `Debuggable` and hand it out with `Instance::debuggable`, so frontends and
+//! tools can inspect any core without knowing its types.
+
+use anyhow::bail;
+
+/// A CPU in the emulated machine, as listed by `Debuggable::cpus`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cpu {
+    pub name: &'static str,
+    /// Every register `Debuggable::register` accepts, in the order a debugger should show them
+    pub registers: Vec<&'static str>,
+}
+
+/// Somewhere memory can be peeked and poked, as listed by `Debuggable::address_spaces`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressSpace {
+    pub name: &'static str,
+    /// Valid addresses are below this
+    pub size: u64,
+}
+
+impl AddressSpace {
+    /// Returns an error unless `len` bytes from `address` are all inside the address space
+    pub fn check(&self, address: u64, len: usize) -> Result<(), anyhow::Error> {
+        match address.checked_add(len as u64) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => bail!("{} bytes at {:#x} don't fit in {} (size {:#x})", len, address, self.name, self.size),
+        }
+    }
+}
+
+/// Debugger access to an instance while it's paused
+///
+/// CPUs and address spaces are referred to by their index in `cpus` and `address_spaces`.
+pub trait Debuggable {
+    fn cpus(&self) -> Vec<Cpu>;
+
+    fn register(&mut self, cpu: usize, name: &str) -> Result<u64, anyhow::Error>;
+
+    /// Returns an error for registers that can't be changed
+    fn set_register(&mut self, cpu: usize, name: &str, value: u64) -> Result<(), anyhow::Error>;
+
+    fn address_spaces(&self) -> Vec<AddressSpace>;
+
+    /// Reads memory without any of the side effects a real access would have, like acknowledging
+    /// an interrupt or opening a DRAM row
+    fn peek(&mut self, space: usize, address: u64, data: &mut [u8]) -> Result<(), anyhow::Error>;
+
+    /// Writes memory without any side effects, like `peek`
+    fn poke(&mut self, space: usize, address: u64, data: &[u8]) -> Result<(), anyhow::Error>;
+
+    /// Addresses `cpu` stops at. Reaching one makes `Instance::run` return, like a pause
+    fn breakpoints(&mut self, cpu: usize) -> Result<Vec<u64>, anyhow::Error>;
+
+    fn set_breakpoint(&mut self, cpu: usize, address: u64) -> Result<(), anyhow::Error>;
+
+    /// Returns an error if there was no such breakpoint
+    fn clear_breakpoint(&mut self, cpu: usize, address: u64) -> Result<(), anyhow::Error>;
+
+    /// Runs the whole machine until `cpu` moves on to its next instruction
+    fn step(&mut self, cpu: usize) -> Result<(), anyhow::Error>;
+}
+
+/// Draws a debugger for any core: registers, breakpoints and stepping for each CPU, then a hex
+/// view of each address space
+#[cfg(feature = "ui")]
+pub fn ui(debuggable: &mut dyn Debuggable, ui: &mut egui::Ui) {
+    for (index, cpu) in debuggable.cpus().into_iter().enumerate() {
+        egui::CollapsingHeader::new(cpu.name).default_open(true).show(ui, |ui| {
+            cpu_ui(debuggable, index, &cpu, ui);
+        });
+    }
+    for (index, space) in debuggable.address_spaces().into_iter().enumerate() {
+        egui::CollapsingHeader::new(space.name).show(ui, |ui| {
+            memory_ui(debuggable, index, &space, ui);
+        });
+    }
+}
+
+#[cfg(feature = "ui")]
+fn cpu_ui(debuggable: &mut dyn Debuggable, cpu: usize, info: &Cpu, ui: &mut egui::Ui) {
+    if ui.button("Step").clicked() {
+        if let Err(err) = debuggable.step(cpu) {
+            eprintln!("Step failed: {:?}", err);
+        }
+    }
+
+    egui::CollapsingHeader::new("Registers").default_open(true).show(ui, |ui| {
+        egui::Grid::new(("Registers", cpu))
+        .min_col_width(160.0)
+        .show(ui, |ui| {
+            for (i, name) in info.registers.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.monospace(*name);
+                    match debuggable.register(cpu, name) {
+                        Ok(value) => {
+                            let id = ui.id().with((cpu, i));
+                            if let Some(value) = hex_edit(ui, id, value) {
+                                if let Err(err) = debuggable.set_register(cpu, name, value) {
+                                    eprintln!("Setting {} failed: {:?}", name, err);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+                        }
+                    }
+                });
+
+                if i % 4 == 3 { // Rows of four registers
+                    ui.end_row();
+                }
+            }
+        });
+    });
+
+    egui::CollapsingHeader::new("Breakpoints").show(ui, |ui| {
+        match debuggable.breakpoints(cpu) {
+            Ok(breakpoints) => {
+                for address in breakpoints {
+                    ui.horizontal(|ui| {
+                        ui.monospace(format!("{:#018x}", address));
+                        if ui.button("Remove").clicked() {
+                            if let Err(err) = debuggable.clear_breakpoint(cpu, address) {
+                                eprintln!("Removing breakpoint failed: {:?}", err);
+                            }
+                        }
+                    });
+                }
+            }
+            Err(err) => {
+                ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+            }
+        }
+
+        ui.horizontal(|ui| {
+            let id = ui.make_persistent_id(("New breakpoint", cpu));
+            let mut text = ui.ctx().data(|data| data.get_temp::<String>(id)).unwrap_or_default();
+            ui.add(egui::TextEdit::singleline(&mut text).font(egui::TextStyle::Monospace));
+            if ui.button("Add").clicked() {
+                match u64::from_str_radix(text.trim_start_matches("0x"), 16) {
+                    Ok(address) => {
+                        if let Err(err) = debuggable.set_breakpoint(cpu, address) {
+                            eprintln!("Adding breakpoint failed: {:?}", err);
+                        }
+                        text.clear();
+                    }
+                    Err(_) => eprintln!("Invalid breakpoint address {:?}", text),
+                }
+            }
+            ui.ctx().data_mut(|data| data.insert_temp(id, text));
+        });
+    });
+}
+
+#[cfg(feature = "ui")]
+fn memory_ui(debuggable: &mut dyn Debuggable, space: usize, info: &AddressSpace, ui: &mut egui::Ui) {
+    const ROWS: u64 = 16;
+    const COLUMNS: usize = 16;
+
+    let id = ui.make_persistent_id(("Memory", space));
+    let address = ui.ctx().data(|data| data.get_temp::<u64>(id)).unwrap_or_default();
+    let address = ui.horizontal(|ui| {
+        ui.label("Address");
+        hex_edit(ui, id.with("Address"), address).unwrap_or(address)
+    }).inner;
+    // Rows start on a multiple of their length, and stay inside the address space where possible
+    let address = std::cmp::min(address, info.size.saturating_sub(ROWS * COLUMNS as u64)) & !(COLUMNS as u64 - 1);
+    ui.ctx().data_mut(|data| data.insert_temp(id, address));
+
+    for row in 0..ROWS {
+        let row_address = address + row * COLUMNS as u64;
+        let len = std::cmp::min(COLUMNS as u64, info.size.saturating_sub(row_address)) as usize;
+        if len == 0 {
+            break;
+        }
+        let mut data = [0; COLUMNS];
+        let text = match debuggable.peek(space, row_address, &mut data[..len]) {
+            Ok(()) => data[..len].iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" "),
+            Err(err) => err.to_string(),
+        };
+        ui.monospace(format!("{:08x}  {}", row_address, text));
+    }
+}
+
+/// A hex text box. Returns the new value once the user clicks away from valid input
+#[cfg(feature = "ui")]
+fn hex_edit(ui: &mut egui::Ui, id: egui::Id, value: u64) -> Option<u64> {
+    // Persist invalid input values until they are valid
+    let dirty_string: Option<String> = ui.ctx().data(
+        |data| data.get_temp::<String>(id)
+    );
+    let (mut string, dirty, text_color) = match dirty_string {
+        Some(string) => (string, true, ui.visuals().error_fg_color),
+        None => (format!("{:X}", value), false, ui.visuals().text_color()),
+    };
+
+    let edit = egui::TextEdit::singleline(&mut string)
+        .horizontal_align(egui::Align::RIGHT)
+        .cursor_at_end(true)
+        .font(egui::TextStyle::Monospace)
+        .text_color(text_color);
+
+    let response = ui.add(edit);
+
+    let mut new_value = None;
+    if dirty && response.lost_focus() {
+        if string.is_empty() {
+            // If the user clicks away while the input is empty, reset to the current value
+            ui.ctx().data_mut(|d| d.remove::<String>(id));
+        } else if let Ok(value) = u64::from_str_radix(&string, 16) {
+            // Clear the dirty string
+            ui.ctx().data_mut(|d| d.remove::<String>(id));
+            new_value = Some(value);
+        }
+    }
+    if response.changed() {
+        ui.ctx().data_mut(|d| d.insert_temp::<String>(id, string));
+    }
+    new_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_space_bounds() {
+        let space = AddressSpace { name: "RAM", size: 0x100 };
+        assert!(space.check(0, 0x100).is_ok());
+        assert!(space.check(0xff, 1).is_ok());
+        assert!(space.check(0xff, 2).is_err());
+        assert!(space.check(u64::MAX, 2).is_err());
+    }
+}
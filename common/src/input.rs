@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+/// How many controllers a frontend can connect
+pub const MAX_PORTS: usize = 4;
+
+/// Buttons found on most controllers. Cores map them onto their own controller, and ignore any it
+/// doesn't have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    L,
+    R,
+    Z,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+    CUp,
+    CDown,
+    CLeft,
+    CRight,
+}
+
+/// The set of buttons currently held
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons(u32);
+
+impl Buttons {
+    pub fn pressed(self, button: Button) -> bool {
+        self.0 & 1 << button as u32 != 0
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        match pressed {
+            true => self.0 |= 1 << button as u32,
+            false => self.0 &= !(1 << button as u32),
+        }
+    }
+
+    pub fn with(mut self, button: Button) -> Self {
+        self.set(button, true);
+        self
+    }
+}
+
+/// Analog inputs, from -32768 to 32767. Right and up are positive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl Axis {
+    pub const COUNT: usize = 4;
+}
+
+/// Everything about the controller in one port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PadState {
+    /// Cores report empty ports as having no controller
+    pub connected: bool,
+    pub buttons: Buttons,
+    pub axes: [i16; Axis::COUNT],
+}
+
+impl PadState {
+    pub fn axis(&self, axis: Axis) -> i16 {
+        self.axes[axis as usize]
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.axes[axis as usize] = value;
+    }
+}
+
+/// The input the core currently sees, shared between the part of the core that reads controllers
+/// and the `InputLatch` that updates it
+#[derive(Clone, Default)]
+pub struct SharedInput {
+    ports: Arc<Mutex<[PadState; MAX_PORTS]>>,
+}
+
+impl SharedInput {
+    pub fn port(&self, port: usize) -> PadState {
+        self.ports.lock().unwrap()[port]
+    }
+}
+
+impl std::fmt::Debug for SharedInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedInput")
+    }
+}
+
+/// Holds input from the frontend until the next latch point, so the core only sees changes at
+/// predictable times (like the start of a frame) instead of whenever the frontend's message
+/// happened to arrive
+pub struct InputLatch {
+    shared: SharedInput,
+    pending: [PadState; MAX_PORTS],
+    /// Latch points are multiples of this many cycles
+    period: u64,
+    /// The next latch point, if there are changes waiting for it
+    latch_at: Option<u64>,
+}
+
+impl InputLatch {
+    pub fn new(shared: SharedInput, period: u64) -> Self {
+        assert!(period != 0);
+        let pending = *shared.ports.lock().unwrap();
+        InputLatch { shared, pending, period, latch_at: None }
+    }
+
+    /// Queues new state for `port`, to be seen from the first latch point after `now`
+    pub fn set(&mut self, now: u64, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        if port >= MAX_PORTS {
+            anyhow::bail!("Port {} doesn't exist, there are only {}", port, MAX_PORTS);
+        }
+        self.pending[port] = pad;
+        self.latch_at.get_or_insert((now / self.period + 1) * self.period);
+        Ok(())
+    }
+
+    /// When `latch` needs to be called, if anything is waiting
+    pub fn latch_at(&self) -> Option<u64> {
+        self.latch_at
+    }
+
+    /// Makes all queued changes visible to the core
+    pub fn latch(&mut self) {
+        *self.shared.ports.lock().unwrap() = self.pending;
+        self.latch_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latched_on_period() {
+        let shared = SharedInput::default();
+        let mut latch = InputLatch::new(shared.clone(), 100);
+
+        let pad = PadState { connected: true, buttons: Buttons::default().with(Button::Start), axes: [0, -5, 0, 0] };
+        latch.set(150, 1, pad).unwrap();
+        assert!(latch.set(150, MAX_PORTS, pad).is_err());
+
+        // Not visible until the latch point, and later changes wait for the same one
+        assert_eq!(shared.port(1), PadState::default());
+        latch.set(199, 2, pad).unwrap();
+        assert_eq!(latch.latch_at(), Some(200));
+
+        latch.latch();
+        assert_eq!(latch.latch_at(), None);
+        assert!(shared.port(1).buttons.pressed(Button::Start));
+        assert!(!shared.port(1).buttons.pressed(Button::A));
+        assert_eq!(shared.port(2).axis(Axis::LeftY), -5);
+    }
+}
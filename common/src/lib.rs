@@ -1,7 +1,21 @@
-use std::{sync::mpsc::{self, Receiver, SyncSender}, any::Any};
+use std::{sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError}, any::Any, path::{Path, PathBuf}, time::Duration};
 
+pub mod audio;
 pub mod cli;
+pub mod debug;
+pub mod input;
+pub mod pacing;
+pub mod save_state;
+pub mod stats;
 pub mod util;
+pub mod video;
+
+use audio::AudioSink;
+use debug::Debuggable;
+use input::PadState;
+use pacing::Speed;
+use stats::Stats;
+use video::Frame;
 
 pub trait EmulationCore: Sync + Send {
     /// The name of the core
@@ -36,9 +50,11 @@ pub trait EmulationCore: Sync + Send {
     /// The Paused version of the UI has full access to the instance's state
     #[cfg(feature = "ui")]
     fn paused_ui(&self, instance: &mut dyn Instance, ui: &mut egui::Ui) {
-        // Default implementation: fall back to the normal UI
-        let _ = instance;
-        self.ui(ui);
+        // Default implementation: the debugger if the instance has one, otherwise the normal UI
+        match instance.debuggable() {
+            Some(debuggable) => debug::ui(debuggable, ui),
+            None => self.ui(ui),
+        }
     }
 }
 
@@ -54,10 +70,28 @@ where
 
 /// Messages sent from the core instance to the UI thread
 pub enum UpdateMessage {
-    /// Signals to the UI thread that the core has a new frame of video ready to be shown
-    Vsync,
+    /// The newest frame of video, sent while syncing with the UI thread (just before `Stats`) if
+    /// the core has produced one since the last sync. See `video::frame_channel`
+    Vsync(Frame),
     /// The instance has finished syncing with the UI thread
     UiSynced,
+    /// A snapshot of the instance's statistics, sent just before `UiSynced`
+    Stats(Stats),
+    /// A command from a `ControlMessage` has been carried out. The frontend needs to keep reading
+    /// updates while the instance is running, or the instance blocks until it does
+    Ack(Command),
+    /// A command from a `ControlMessage` failed. The instance keeps going
+    Error(CommandError),
+}
+
+impl UpdateMessage {
+    /// `Ack` or `Error`, depending on how the command went
+    pub fn reply(command: Command, result: Result<(), anyhow::Error>) -> Self {
+        match result {
+            Ok(()) => UpdateMessage::Ack(command),
+            Err(err) => UpdateMessage::Error(CommandError { command, message: format!("{:?}", err) }),
+        }
+    }
 }
 
 /// Messages sent from the UI thread when the core instance is running
@@ -69,6 +103,143 @@ pub enum ControlMessage {
     /// The core instance should update any shared data structures needed to draw the UI and respond
     /// with `UpdateMessage::UiSynced`
     UiSync,
+    /// Change how fast the core instance runs, see `Instance::set_speed`
+    SetSpeed(Speed),
+    /// Reset the core instance and keep running, see `Instance::reset`
+    Reset(ResetKind),
+    /// New state for the controller in `port`, see `Instance::set_input`
+    Input { port: usize, pad: PadState },
+    /// Run a single frame at the current speed, then wait for the next message
+    FrameAdvance,
+    /// Run this many cycles of the core's main clock at the current speed, then wait for the next
+    /// message
+    RunFor(u64),
+    /// Write a save state, see `Instance::save_state`
+    SaveState(PathBuf),
+    /// Restore a save state and keep running, see `Instance::load_state`
+    LoadState(PathBuf),
+    /// The core instance should stop for good and return from `Instance::run()`
+    Shutdown,
+}
+
+impl ControlMessage {
+    /// The command this message is replied to as, with `UpdateMessage::Ack` or
+    /// `UpdateMessage::Error`
+    ///
+    /// Returns None for messages without a reply. `Pause` is answered by `run` returning, and
+    /// `UiSync` by `UpdateMessage::UiSynced`. Input is sent too often to be worth acknowledging,
+    /// so it's only replied to when it fails, as `Command::Input`.
+    pub fn command(&self) -> Option<Command> {
+        match self {
+            ControlMessage::Pause | ControlMessage::UiSync | ControlMessage::Input { .. } => None,
+            ControlMessage::SetSpeed(_) => Some(Command::SetSpeed),
+            ControlMessage::Reset(_) => Some(Command::Reset),
+            ControlMessage::FrameAdvance => Some(Command::FrameAdvance),
+            ControlMessage::RunFor(_) => Some(Command::RunFor),
+            ControlMessage::SaveState(_) => Some(Command::SaveState),
+            ControlMessage::LoadState(_) => Some(Command::LoadState),
+            ControlMessage::Shutdown => Some(Command::Shutdown),
+        }
+    }
+
+    /// Carries out the message on an instance that isn't running
+    ///
+    /// Returns an error for messages that only make sense while running (`Pause`, `UiSync`,
+    /// `FrameAdvance` and `RunFor`). `Shutdown` does nothing, it's up to the caller to drop the
+    /// instance.
+    pub fn apply(self, instance: &mut dyn Instance) -> Result<(), anyhow::Error> {
+        match self {
+            ControlMessage::SetSpeed(speed) => instance.set_speed(speed),
+            ControlMessage::Reset(kind) => instance.reset(kind)?,
+            ControlMessage::Input { port, pad } => instance.set_input(port, pad)?,
+            ControlMessage::SaveState(path) => instance.save_state(&path)?,
+            ControlMessage::LoadState(path) => instance.load_state(&path)?,
+            ControlMessage::Shutdown => {}
+            message => anyhow::bail!("{:?} needs a running instance", message),
+        }
+        Ok(())
+    }
+
+    /// Carries out a message that `Instance::run` returned for the instance to handle itself, and
+    /// replies to it
+    ///
+    /// Returns false if the instance should stop running, because it was shut down.
+    pub fn reply(self, instance: &mut dyn Instance, update: &SyncSender<UpdateMessage>) -> Result<bool, anyhow::Error> {
+        let running = !matches!(self, ControlMessage::Shutdown);
+        let input = matches!(self, ControlMessage::Input { .. });
+        let command = self.command();
+        let result = self.apply(instance);
+        match command {
+            Some(command) => update.send(UpdateMessage::reply(command, result))?,
+            None if input => if let Err(err) = result {
+                update.send(UpdateMessage::reply(Command::Input, Err(err)))?;
+            },
+            None => result?,
+        }
+        Ok(running)
+    }
+}
+
+/// Which `ControlMessage` an `UpdateMessage::Ack` or `UpdateMessage::Error` is replying to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetSpeed,
+    Reset,
+    FrameAdvance,
+    RunFor,
+    SaveState,
+    LoadState,
+    Shutdown,
+    /// Only replied to when it fails, see `ControlMessage::command`
+    Input,
+}
+
+/// Why a command failed, see `UpdateMessage::Error`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError {
+    pub command: Command,
+    pub message: String,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} failed: {}", self.command, self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// The two ways of restarting an instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// Press the machine's reset button. Whatever survives a reset on the real hardware is kept
+    Soft,
+    /// Turn the machine off and on again, recreating it from its config
+    PowerCycle,
+}
+
+/// Conditions that make `Instance::run` return by itself, so it can run unattended
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StopConditions {
+    /// Once this many cycles of the core's main clock have been emulated since power on
+    pub cycles: Option<u64>,
+    /// Once this many vsyncs have happened since power on
+    pub vsyncs: Option<u64>,
+    /// When the main CPU reaches this address
+    pub pc: Option<u64>,
+}
+
+/// How far an instance has got since power on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Cycles of the core's main clock
+    pub cycles: u64,
+    /// Vsyncs the core's video output has produced, if it counts them
+    pub vsyncs: Option<u64>,
+    /// Instructions executed by the main CPU, if the core counts them
+    pub instructions: Option<u64>,
+    /// The main CPU's program counter
+    pub pc: Option<u64>,
 }
 
 /// Synchronous instance of an emulator core
@@ -80,6 +251,106 @@ pub trait Instance {
     ) -> Result<(), anyhow::Error>;
 
     fn as_any(&mut self) -> &mut dyn std::any::Any;
+
+    /// Change how fast `run` goes, compared to the real hardware
+    fn set_speed(&mut self, speed: Speed) {
+        // Default implementation: always unlimited
+        let _ = speed;
+    }
+
+    /// Emulated time per unit of real time, measured while running. 1.0 is full speed
+    fn speed_ratio(&self) -> Option<f64> {
+        // Default implementation: not measured
+        None
+    }
+
+    /// Replaces any previous stop conditions
+    ///
+    /// Returns an error if the core doesn't support one of them
+    fn set_stop_conditions(&mut self, stop: StopConditions) -> Result<(), anyhow::Error> {
+        // Default implementation: only running forever is supported
+        match stop == StopConditions::default() {
+            true => Ok(()),
+            false => anyhow::bail!("This core doesn't support stop conditions"),
+        }
+    }
+
+    fn progress(&mut self) -> Progress {
+        // Default implementation: nothing is tracked
+        Progress::default()
+    }
+
+    /// Reset the instance. `run` can be called again afterwards to keep going
+    ///
+    /// Returns an error if the core doesn't support this kind of reset
+    fn reset(&mut self, kind: ResetKind) -> Result<(), anyhow::Error> {
+        // Default implementation: no resets
+        anyhow::bail!("This core doesn't support {:?} resets", kind)
+    }
+
+    /// Change what the controller in `port` is doing. The core sees it from its next input latch
+    /// point, see `input::InputLatch`
+    ///
+    /// Returns an error if the core doesn't take input, or doesn't have that port
+    fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        // Default implementation: no input
+        let _ = pad;
+        anyhow::bail!("This core doesn't support input on port {}", port)
+    }
+
+    /// Replaces where the core sends its audio, returning the previous sink. `None` discards audio
+    ///
+    /// Returns an error if the core doesn't produce audio
+    fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) -> Result<Option<Box<dyn AudioSink>>, anyhow::Error> {
+        // Default implementation: no audio
+        match sink {
+            Some(_) => anyhow::bail!("This core doesn't support audio output"),
+            None => Ok(None),
+        }
+    }
+
+    /// Write a snapshot of the entire instance to `path`
+    ///
+    /// Returns an error if the core doesn't support save states
+    fn save_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        // Default implementation: no save states
+        anyhow::bail!("This core doesn't support save states, {} wasn't written", path.display())
+    }
+
+    /// Restore a snapshot written by `save_state`
+    ///
+    /// If an error is returned, the instance is left unchanged.
+    fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        // Default implementation: no save states
+        anyhow::bail!("This core doesn't support save states, {} wasn't loaded", path.display())
+    }
+
+    /// Debugger access to the instance while it's paused
+    ///
+    /// Returns None if the core doesn't support debugging
+    fn debuggable(&mut self) -> Option<&mut dyn Debuggable> {
+        // Default implementation: no debugger
+        None
+    }
+
+    /// Returns a snapshot of the instance's statistics
+    fn stats(&mut self) -> Stats {
+        // Default implementation: no stats
+        Stats::default()
+    }
+
+    /// Resets all statistics, starting a new measurement window
+    fn reset_stats(&mut self) {
+        // Default implementation: do nothing
+    }
+
+    /// Host-time profile in folded stack format (for flamegraph tools)
+    ///
+    /// Returns None if the core was built without profiling
+    fn profile_folded(&mut self) -> Option<String> {
+        // Default implementation: profiling not supported
+        None
+    }
 }
 
 /// Status of a threaded instance
@@ -89,27 +360,58 @@ pub enum Status {
     Running,
     /// The instance is paused
     Paused,
-    /// The thread has panicked
+    /// The thread has panicked, or the instance returned an error. See `ThreadedInstance::error`
     Error,
 }
 
 // Asynchronous instance of an emulator core
+//
+// Commands are carried out straight away while paused, and sent to the core while running. Either
+// way, their outcome is reported by `take_replies`. The methods themselves only fail if the
+// command couldn't be sent.
 pub trait ThreadedInstance {
     /// Starts the core (non-blocking)
     fn start(&mut self) -> Result<(), anyhow::Error>;
     /// Pauses the core (blocks until paused)
     fn pause(&mut self) -> Result<(), anyhow::Error>;
+    /// Change how fast the core runs, whether or not it's running
+    fn set_speed(&mut self, speed: Speed) -> Result<(), anyhow::Error>;
+    /// Reset the core, whether or not it's running. It stays paused or running
+    fn reset(&mut self, kind: ResetKind) -> Result<(), anyhow::Error>;
+    /// Change what the controller in `port` is doing, whether or not it's running
+    fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error>;
+    /// Run a single frame, then wait. Starts the core if it's paused
+    fn frame_advance(&mut self) -> Result<(), anyhow::Error>;
+    /// Run this many cycles of the core's main clock, then wait. Starts the core if it's paused
+    fn run_for(&mut self, cycles: u64) -> Result<(), anyhow::Error>;
+    /// Write a save state, whether or not it's running
+    fn save_state(&mut self, path: &Path) -> Result<(), anyhow::Error>;
+    /// Restore a save state, whether or not it's running
+    fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error>;
+    /// Stops the core for good (blocks until stopped)
+    fn shutdown(self: Box<Self>) -> Result<(), anyhow::Error>;
+    /// Every reply to a command since the last call, oldest first
+    fn take_replies(&mut self) -> Vec<Result<Command, CommandError>>;
     /// Draw the UI
     #[cfg(feature = "ui")]
-    fn ui(&self, core: &dyn EmulationCore, ui: &mut egui::Ui);
+    fn ui(&mut self, core: &dyn EmulationCore, ui: &mut egui::Ui);
     /// Draw the paused version of the UI
     #[cfg(feature = "ui")]
     fn paused_ui(&mut self, core: &dyn EmulationCore, ui: &mut egui::Ui);
 
-    /// Get the current status of the instance
-    fn status(&self) -> Status;
+    /// Get the current status of the instance, taking it back if `run` returned by itself
+    fn status(&mut self) -> Status;
+
+    /// Why the instance stopped, once the status is `Status::Error`
+    fn error(&mut self) -> Option<&str>;
+
+    /// The newest frame of video received from the core, kept while paused
+    fn frame(&self) -> Option<&Frame>;
 }
 
+/// A paused instance and its control channel, which move between threads together
+type Parked = (Box<dyn Instance + Send>, Receiver<ControlMessage>);
+
 /// Takes a raw synchronous Instance and wraps it in a thread
 ///
 /// The instance gets moved to the thread when running and then back to the parent thread when paused.
@@ -117,11 +419,17 @@ pub trait ThreadedInstance {
 /// boundaries.
 pub struct ThreadAdapter {
     instance: Option<Box<dyn Instance + Send>>,
+    /// Moves with the instance, so messages it didn't see before `run` returned can be handled
+    /// here once it's back
+    rx_control: Option<Receiver<ControlMessage>>,
     tx_control: SyncSender<ControlMessage>,
     rx_update: Receiver<UpdateMessage>,
-    tx_instance: SyncSender<Box<dyn Instance + Send>>,
-    rx_instance_return: Receiver<Option<Box<dyn Instance + Send>>>,
+    tx_instance: SyncSender<Parked>,
+    rx_instance_return: Receiver<Option<Parked>>,
     join: Option<std::thread::JoinHandle<Result<(), anyhow::Error>>>,
+    error: Option<String>,
+    frame: Option<Frame>,
+    replies: Vec<Result<Command, CommandError>>,
 }
 
 impl ThreadAdapter {
@@ -129,41 +437,187 @@ impl ThreadAdapter {
         // Create all our channels
         let (tx_control, rx_control) = mpsc::sync_channel::<ControlMessage>(1);
         let (tx_update, rx_update) = mpsc::sync_channel::<UpdateMessage>(1);
-        let (tx_instance, rx_instance) = mpsc::sync_channel::<Box<dyn Instance + Send>>(1);
-        let (tx_instance_return, rx_instance_return) =
-            mpsc::sync_channel::<Option<Box<dyn Instance + Send>>>(1);
+        let (tx_instance, rx_instance) = mpsc::sync_channel(1);
+        let (tx_instance_return, rx_instance_return) = mpsc::sync_channel(1);
 
         // Spawn the thread now.
         let join = std::thread::spawn(move || {
-            Self::thread_main(rx_instance, tx_instance_return, rx_control, tx_update)
+            Self::thread_main(rx_instance, tx_instance_return, tx_update)
         });
 
         Ok(Self {
             instance: Some(instance),
+            rx_control: Some(rx_control),
             tx_control,
             rx_update,
             tx_instance,
             rx_instance_return,
             join: Some(join),
+            error: None,
+            frame: None,
+            replies: Vec::new(),
         })
     }
 
+    /// Sends a message to the running instance. Updates are read while waiting, so the instance
+    /// can't be stuck replying to an earlier command
+    ///
+    /// If `run` returns by itself before there's room, the message is handled here instead.
+    fn send(&mut self, message: ControlMessage) -> Result<(), anyhow::Error> {
+        let mut message = message;
+        loop {
+            match self.tx_control.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(returned)) => {
+                    message = returned;
+                    self.receive_updates();
+                    self.check_returned();
+                    if self.instance.is_some() {
+                        self.apply(message);
+                        return Ok(());
+                    }
+                    std::thread::yield_now();
+                }
+                Err(TrySendError::Disconnected(_)) => anyhow::bail!("Channel closed"),
+            }
+        }
+    }
+
+    /// Keeps any replies and frames the instance sent outside of a UI sync
+    fn receive_updates(&mut self) {
+        while let Ok(message) = self.rx_update.try_recv() {
+            self.receive(message);
+        }
+    }
+
+    fn receive(&mut self, message: UpdateMessage) {
+        match message {
+            // The old frame's buffer goes back to the core's pool
+            UpdateMessage::Vsync(frame) => self.frame = Some(frame),
+            UpdateMessage::Ack(command) => self.replies.push(Ok(command)),
+            UpdateMessage::Error(err) => self.replies.push(Err(err)),
+            // Only expected during a UI sync
+            UpdateMessage::UiSynced | UpdateMessage::Stats(_) => {}
+        }
+    }
+
+    /// Takes the instance back if `run` has returned, which it can do by itself (at a breakpoint
+    /// or stop condition) without being paused
+    fn check_returned(&mut self) {
+        if self.instance.is_none() && self.join.is_some() {
+            if let Ok(returned) = self.rx_instance_return.try_recv() {
+                self.returned(returned);
+            }
+        }
+    }
+
+    /// Takes back what the thread sent once `run` returned. None means it returned an error
+    fn returned(&mut self, returned: Option<Parked>) {
+        // Anything sent just before returning
+        self.receive_updates();
+        match returned {
+            Some((instance, rx_control)) => {
+                self.instance = Some(instance);
+                // Messages sent after `run` stopped looking, which would otherwise be seen the
+                // next time it's started
+                for message in rx_control.try_iter() {
+                    self.apply(message);
+                }
+                self.rx_control = Some(rx_control);
+            }
+            None => self.join_thread(),
+        }
+    }
+
+    /// Carries out a message on the paused instance, replying like the running instance would
+    fn apply(&mut self, message: ControlMessage) {
+        let instance = self.instance.as_mut().expect("invalid instance state");
+        match message {
+            // It's already paused, and there's nothing running to sync with
+            ControlMessage::Pause | ControlMessage::UiSync => {}
+            ControlMessage::Input { port, pad } => {
+                if let Err(err) = instance.set_input(port, pad) {
+                    self.receive(UpdateMessage::reply(Command::Input, Err(err)));
+                }
+            }
+            message => {
+                let command = message.command().expect("not a command");
+                let reply = UpdateMessage::reply(command, message.apply(instance.as_mut()));
+                self.receive(reply);
+            }
+        }
+    }
+
+    /// Waits for the instance to come back from the thread, after `run` returns
+    fn wait_for_instance(&mut self) -> Result<(), anyhow::Error> {
+        while self.instance.is_none() && self.join.is_some() {
+            match self.rx_instance_return.recv_timeout(Duration::from_millis(1)) {
+                Ok(returned) => self.returned(returned),
+                Err(RecvTimeoutError::Timeout) => self.receive_updates(),
+                Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Channel closed"),
+            }
+        }
+        match self.instance {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!(self.error.clone().unwrap_or_else(|| "Instance paniced".to_string()))),
+        }
+    }
+
+    /// Carries out a command on the paused instance, or sends it to the running one
+    fn command(&mut self, message: ControlMessage) -> Result<(), anyhow::Error> {
+        match self.instance {
+            Some(_) => {
+                self.apply(message);
+                Ok(())
+            }
+            None => self.send(message),
+        }
+    }
+
+    /// Sends a command that needs the instance to be running, starting it if it's paused
+    fn run_command(&mut self, message: ControlMessage) -> Result<(), anyhow::Error> {
+        // Sent first, so nothing runs before the instance sees it
+        self.send(message)?;
+        match self.instance {
+            Some(_) => self.start(),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for the thread to exit, keeping whatever error or panic message it exited with
+    fn join_thread(&mut self) {
+        let join = self.join.take().expect("invalid instance state");
+        self.error = match join.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(format!("{:?}", err)),
+            Err(panic) => Some(match panic.downcast::<String>() {
+                Ok(msg) => format!("Panicked: {}", msg),
+                Err(panic) => match panic.downcast::<&str>() {
+                    Ok(msg) => format!("Panicked: {}", msg),
+                    Err(_) => "Panicked".to_string(),
+                },
+            }),
+        };
+    }
+
     fn thread_main<'b>(
-        rx_instance: Receiver<Box<dyn Instance + Send>>,
-        tx_instance: SyncSender<Option<Box<dyn Instance + Send>>>,
-        rx_control: Receiver<ControlMessage>,
+        rx_instance: Receiver<Parked>,
+        tx_instance: SyncSender<Option<Parked>>,
         tx_update: SyncSender<UpdateMessage>,
     ) -> Result<(), anyhow::Error> {
         // We don't want to pay the overhead of starting a thread every time we unpause.
         // Instead, we transfer the instance to and from the thread main loop as needed
         loop {
-            let mut instance = rx_instance.recv()?;
+            // The adapter has been shut down or dropped
+            let Ok((mut instance, rx_control)) = rx_instance.recv() else {
+                return Ok(());
+            };
             let result = instance.run(&rx_control, tx_update.clone());
 
             match result {
                 Ok(_) => {
                     tx_instance
-                        .send(Some(instance))
+                        .send(Some((instance, rx_control)))
                         .map_err(|_| anyhow::anyhow!("Channel closed"))?;
                 }
                 Err(e) => {
@@ -180,58 +634,209 @@ impl ThreadAdapter {
 
 impl ThreadedInstance for ThreadAdapter {
     fn start(&mut self) -> Result<(), anyhow::Error> {
-        match self.instance.take() {
-            Some(instance) => self
+        match (self.instance.take(), self.rx_control.take()) {
+            (Some(instance), Some(rx_control)) => self
                 .tx_instance
-                .send(instance)
+                .send((instance, rx_control))
                 .map_err(|_| anyhow::anyhow!("Channel closed")),
-            None => anyhow::bail!("invalid instance state"),
+            _ => anyhow::bail!("invalid instance state"),
         }
     }
     fn pause(&mut self) -> Result<(), anyhow::Error> {
-        self.tx_control.send(ControlMessage::Pause)?;
-        self.instance = self.rx_instance_return.recv()?;
-        return match self.instance {
-            Some(_) => Ok(()),
-            None => {
-                self.join
-                    .take()
-                    .expect("invalid instance state")
-                    .join()
-                    .unwrap()?;
-                Err(anyhow::anyhow!("Instance paniced"))
+        self.send(ControlMessage::Pause)?;
+        self.wait_for_instance()
+    }
+    fn set_speed(&mut self, speed: Speed) -> Result<(), anyhow::Error> {
+        self.command(ControlMessage::SetSpeed(speed))
+    }
+    fn reset(&mut self, kind: ResetKind) -> Result<(), anyhow::Error> {
+        self.command(ControlMessage::Reset(kind))
+    }
+    fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        match self.instance {
+            Some(ref mut instance) => instance.set_input(port, pad),
+            None => self.send(ControlMessage::Input { port, pad }),
+        }
+    }
+    fn frame_advance(&mut self) -> Result<(), anyhow::Error> {
+        self.run_command(ControlMessage::FrameAdvance)
+    }
+    fn run_for(&mut self, cycles: u64) -> Result<(), anyhow::Error> {
+        self.run_command(ControlMessage::RunFor(cycles))
+    }
+    fn save_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.command(ControlMessage::SaveState(path.to_owned()))
+    }
+    fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.command(ControlMessage::LoadState(path.to_owned()))
+    }
+    fn shutdown(mut self: Box<Self>) -> Result<(), anyhow::Error> {
+        match self.status() {
+            Status::Running => {
+                self.send(ControlMessage::Shutdown)?;
+                self.wait_for_instance()?;
             }
-        };
+            Status::Paused => {}
+            Status::Error => anyhow::bail!(self.error().unwrap_or("Instance stopped").to_string()),
+        }
+        // Closing the channel lets the thread exit
+        let ThreadAdapter { instance, tx_instance, mut join, .. } = *self;
+        drop((instance, tx_instance));
+        match join.take().expect("invalid instance state").join() {
+            Ok(result) => result,
+            Err(_) => anyhow::bail!("Instance paniced"),
+        }
+    }
+    fn take_replies(&mut self) -> Vec<Result<Command, CommandError>> {
+        std::mem::take(&mut self.replies)
     }
     #[cfg(feature = "ui")]
     fn paused_ui(&mut self, core: &dyn EmulationCore, ui: &mut egui::Ui) {
         match self.instance {
-            Some(ref mut instance) => core.paused_ui(instance.as_mut(), ui),
+            Some(ref mut instance) => {
+                core.paused_ui(instance.as_mut(), ui);
+
+                ui.separator();
+                if ui.button("Reset stats").clicked() {
+                    instance.reset_stats();
+                }
+                instance.stats().ui(ui);
+            }
             None => panic!("Instance running or paniced"),
         }
     }
 
     #[cfg(feature = "ui")]
-    fn ui(&self, core: &dyn EmulationCore, ui: &mut egui::Ui) {
+    fn ui(&mut self, core: &dyn EmulationCore, ui: &mut egui::Ui) {
         assert!(self.instance.is_none());
         // Sync with instance thread
-        if self.tx_control.send(ControlMessage::UiSync).is_ok() {
+        let mut stats = None;
+        if self.send(ControlMessage::UiSync).is_ok() {
             loop {
-                match self.rx_update.recv() {
+                match self.rx_update.recv_timeout(Duration::from_millis(1)) {
                     Ok(UpdateMessage::UiSynced) => break,
-                    Ok(_) => continue, // TODO: We probably should be processing these
-                    Err(_) => return,  // Channel closed
+                    Ok(UpdateMessage::Stats(new_stats)) => stats = Some(new_stats),
+                    Ok(message) => self.receive(message),
+                    // `run` returned without syncing, the next frame draws the paused UI
+                    Err(RecvTimeoutError::Timeout) => {
+                        if !matches!(self.status(), Status::Running) {
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
                 }
             }
         }
         core.ui(ui);
+
+        if let Some(stats) = stats {
+            ui.separator();
+            stats.ui(ui);
+        }
     }
 
-    fn status(&self) -> Status {
+    fn status(&mut self) -> Status {
+        self.check_returned();
         match (&self.instance, &self.join) {
             (Some(_), _) => Status::Paused,
             (None, Some(join)) if !join.is_finished() => Status::Running,
             _ => Status::Error,
         }
     }
+
+    fn error(&mut self) -> Option<&str> {
+        if self.join.as_ref().is_some_and(|join| join.is_finished()) {
+            self.join_thread();
+        }
+        self.error.as_deref()
+    }
+
+    fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    use std::time::Instant;
+
+    /// Stops at a breakpoint as soon as it's started, without being paused
+    struct Breakpoint {
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl Instance for Breakpoint {
+        fn run(&mut self, _: &Receiver<ControlMessage>, _: SyncSender<UpdateMessage>) -> Result<(), anyhow::Error> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn as_any(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    fn wait_until_paused(adapter: &mut ThreadAdapter) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !matches!(adapter.status(), Status::Paused) {
+            assert!(Instant::now() < deadline, "The instance never came back");
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn run_returns_by_itself() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut adapter = ThreadAdapter::new(Box::new(Breakpoint { runs: runs.clone() })).unwrap();
+
+        adapter.start().unwrap();
+        wait_until_paused(&mut adapter);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Sent to an instance that won't read it, so it's carried out once the instance is back.
+        // Pausing it after it stopped by itself doesn't wait forever
+        adapter.start().unwrap();
+        adapter.set_speed(Speed::Percent(50)).unwrap();
+        adapter.pause().unwrap();
+        assert!(matches!(adapter.status(), Status::Paused));
+        assert_eq!(adapter.take_replies(), vec![Ok(Command::SetSpeed)]);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        adapter.start().unwrap();
+        wait_until_paused(&mut adapter);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        Box::new(adapter).shutdown().unwrap();
+    }
+
+    #[cfg(feature = "ui")]
+    struct BreakpointCore;
+
+    #[cfg(feature = "ui")]
+    impl EmulationCore for BreakpointCore {
+        fn name(&self) -> &'static str {
+            "Breakpoint"
+        }
+
+        fn short_name(&self) -> &'static str {
+            "bp"
+        }
+
+        fn new(&self, _: Box<dyn Any>) -> Result<Box<dyn Instance + Send>, anyhow::Error> {
+            Ok(Box::new(Breakpoint { runs: Arc::default() }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    #[test]
+    fn ui_after_run_returns() {
+        let mut adapter = ThreadAdapter::new(BreakpointCore.new(Box::new(())).unwrap()).unwrap();
+        adapter.start().unwrap();
+        // Nothing will answer the sync, so this has to notice the instance came back instead
+        let _ = egui::Context::default().run(Default::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| adapter.ui(&BreakpointCore, ui));
+        });
+        assert!(matches!(adapter.status(), Status::Paused));
+    }
 }
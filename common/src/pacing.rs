@@ -0,0 +1,211 @@
+use std::{fmt::Display, str::FromStr, time::{Duration, Instant}};
+
+use crate::stats::Stats;
+
+/// How fast an instance runs, compared to the real machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// Percentage of real time, 100 is full speed
+    Percent(u32),
+    /// As fast as the host can go
+    Unlimited,
+    /// Run a single frame each time the instance is started, then wait until it's paused
+    FrameAdvance,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Percent(100)
+    }
+}
+
+impl FromStr for Speed {
+    type Err = anyhow::Error;
+
+    /// Accepts "unlimited", "frame", or a percentage like "200" or "200%"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unlimited" => Ok(Speed::Unlimited),
+            "frame" => Ok(Speed::FrameAdvance),
+            _ => match s.trim_end_matches('%').parse::<u32>() {
+                Ok(0) => anyhow::bail!("Speed can't be 0%"),
+                Ok(percent) => Ok(Speed::Percent(percent)),
+                Err(_) => anyhow::bail!("Invalid speed {:?}, expected a percentage, \"unlimited\" or \"frame\"", s),
+            },
+        }
+    }
+}
+
+impl Display for Speed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Speed::Percent(percent) => write!(f, "{}%", percent),
+            Speed::Unlimited => write!(f, "Unlimited"),
+            Speed::FrameAdvance => write!(f, "Frame advance"),
+        }
+    }
+}
+
+/// Keeps emulated time in step with the host's clock
+///
+/// The run loop runs in slices, each ending at `slice_end` (in emulated cycles), then calls
+/// `pace`, which sleeps until the host has caught up.
+pub struct Pacer {
+    cycles_per_second: u64,
+    cycles_per_frame: u64,
+    speed: Speed,
+    /// Emulated cycle and host time that the current speed is measured from
+    anchor: (u64, Instant),
+    slice_end: u64,
+    /// Start of the current speed measurement
+    sample: (u64, Instant),
+    ratio: Option<f64>,
+}
+
+/// Length of each slice, in host time. Also how often control messages are checked while asleep
+const SLICE: Duration = Duration::from_millis(1);
+/// Give up catching up if the host falls this far behind, rather than running flat out until
+/// it has
+const MAX_LAG: Duration = Duration::from_millis(100);
+/// How often the speed ratio is updated
+const SAMPLE: Duration = Duration::from_millis(500);
+
+impl Pacer {
+    pub fn new(cycles_per_second: u64, cycles_per_frame: u64) -> Self {
+        assert!(cycles_per_second != 0 && cycles_per_frame != 0);
+        let now = Instant::now();
+        let mut pacer = Pacer {
+            cycles_per_second,
+            cycles_per_frame,
+            speed: Speed::default(),
+            anchor: (0, now),
+            slice_end: 0,
+            sample: (0, now),
+            ratio: None,
+        };
+        pacer.resume(0);
+        pacer
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Length of a frame, in emulated cycles
+    pub fn cycles_per_frame(&self) -> u64 {
+        self.cycles_per_frame
+    }
+
+    pub fn set_speed(&mut self, speed: Speed, cycles: u64) {
+        self.speed = speed;
+        self.resume(cycles);
+    }
+
+    /// Start pacing from `cycles`, without trying to make up for any time spent paused
+    pub fn resume(&mut self, cycles: u64) {
+        let now = Instant::now();
+        self.anchor = (cycles, now);
+        self.sample = (cycles, now);
+        self.slice_end = cycles.saturating_add(self.slice_len());
+    }
+
+    /// Emulated cycle the run loop should stop at before calling `pace`
+    #[inline(always)]
+    pub fn slice_end(&self) -> u64 {
+        self.slice_end
+    }
+
+    /// Called once emulation reaches `slice_end`. Sleeps until the host catches up, then starts
+    /// the next slice.
+    ///
+    /// Returns false when frame advance has finished its frame, and the run loop should wait.
+    pub fn pace(&mut self, cycles: u64) -> bool {
+        let now = Instant::now();
+        self.measure(cycles, now);
+
+        if let Speed::Percent(percent) = self.speed {
+            let emulated = cycles.saturating_sub(self.anchor.0) as f64 / self.cycles_per_second as f64;
+            let due = self.anchor.1 + Duration::from_secs_f64(emulated * 100.0 / percent as f64);
+            if due > now {
+                std::thread::sleep(due - now);
+            } else if now - due > MAX_LAG {
+                self.anchor = (cycles, now);
+            }
+        }
+
+        if self.speed == Speed::FrameAdvance {
+            return false;
+        }
+        self.slice_end = cycles.saturating_add(self.slice_len());
+        true
+    }
+
+    /// Emulated time per unit of host time, measured while running. 1.0 is full speed
+    pub fn ratio(&self) -> Option<f64> {
+        self.ratio
+    }
+
+    pub fn add_to(&self, stats: &mut Stats) {
+        let pacing = stats.group("Pacing");
+        if let Speed::Percent(percent) = self.speed {
+            pacing.ratio("Target speed", percent as f64 / 100.0);
+        }
+        if let Some(ratio) = self.ratio {
+            pacing.ratio("Speed (emulated/real)", ratio);
+        }
+    }
+
+    fn slice_len(&self) -> u64 {
+        match self.speed {
+            Speed::Percent(percent) => {
+                let cycles = self.cycles_per_second as f64 * SLICE.as_secs_f64() * percent as f64 / 100.0;
+                std::cmp::max(cycles as u64, 1)
+            }
+            Speed::Unlimited => self.cycles_per_frame,
+            Speed::FrameAdvance => self.cycles_per_frame,
+        }
+    }
+
+    fn measure(&mut self, cycles: u64, now: Instant) {
+        let elapsed = now - self.sample.1;
+        if elapsed >= SAMPLE {
+            let emulated = cycles.saturating_sub(self.sample.0) as f64 / self.cycles_per_second as f64;
+            self.ratio = Some(emulated / elapsed.as_secs_f64());
+            self.sample = (cycles, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_speed() {
+        assert_eq!("100".parse::<Speed>().unwrap(), Speed::Percent(100));
+        assert_eq!("250%".parse::<Speed>().unwrap(), Speed::Percent(250));
+        assert_eq!("unlimited".parse::<Speed>().unwrap(), Speed::Unlimited);
+        assert_eq!("frame".parse::<Speed>().unwrap(), Speed::FrameAdvance);
+        assert!("0".parse::<Speed>().is_err());
+        assert!("fast".parse::<Speed>().is_err());
+    }
+
+    #[test]
+    fn paces_to_wall_time() {
+        // 1000 cycles per second, so each cycle is a millisecond
+        let mut pacer = Pacer::new(1000, 100);
+        pacer.set_speed(Speed::Percent(200), 0);
+        let start = Instant::now();
+        let mut cycles = 0;
+        while cycles < 100 {
+            cycles = pacer.slice_end();
+            assert!(pacer.pace(cycles));
+        }
+        // 100 cycles at double speed is 50ms
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        pacer.set_speed(Speed::FrameAdvance, cycles);
+        assert_eq!(pacer.slice_end(), cycles + 100);
+        assert!(!pacer.pace(cycles + 100));
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::BinaryHeap;
+
+use anyhow::bail;
+
+#[doc(hidden)]
+pub use anyhow;
+
+/// Serializes a value into a save state.
+///
+/// Loading happens in place, which lets a type skip anything that isn't emulated state (channels,
+/// ROM images, host resources). Those are left as they were when the instance was created.
+pub trait SaveState {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error>;
+}
+
+/// Constructs a new value from a save state.
+///
+/// Only needed for values which might not exist at load time, such as messages, or the contents
+/// of an `Option` or `Vec`.
+pub trait RestoreState: Sized {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    #[inline(always)]
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Writes a length-prefixed string, which can be read back with `String::restore_state`
+    pub fn write_str(&mut self, string: &str) {
+        string.len().save_state(self);
+        self.write_bytes(string.as_bytes());
+    }
+
+    /// Writes a named, length-prefixed section.
+    ///
+    /// Sections allow the reader to detect when an object has changed size or order, rather
+    /// than silently loading garbage into everything that follows.
+    pub fn section(&mut self, name: &str, f: impl FnOnce(&mut StateWriter)) {
+        self.write_str(name);
+        let len_pos = self.data.len();
+        0u64.save_state(self);
+        f(self);
+        let len = (self.data.len() - len_pos - 8) as u64;
+        self.data[len_pos..len_pos + 8].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    #[inline(always)]
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        if self.data.len() - self.pos < len {
+            bail!("Unexpected end of save state at offset {}", self.pos);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a section written by `StateWriter::section`
+    pub fn section(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(&mut StateReader) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let found = String::restore_state(self)?;
+        if found != name {
+            bail!("Expected save state section {}, found {}", name, found);
+        }
+        let len = u64::restore_state(self)? as usize;
+        let mut inner = StateReader::new(self.read_bytes(len)?);
+        f(&mut inner)?;
+        if !inner.is_empty() {
+            bail!("Save state section {} has {} unread bytes", name, inner.data.len() - inner.pos);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+macro_rules! impl_primitive {
+    ($($type:ty),*) => {
+        $(
+            impl SaveState for $type {
+                #[inline(always)]
+                fn save_state(&self, state: &mut StateWriter) {
+                    state.write_bytes(&self.to_le_bytes());
+                }
+                #[inline(always)]
+                fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+                    *self = Self::restore_state(state)?;
+                    Ok(())
+                }
+            }
+
+            impl RestoreState for $type {
+                #[inline(always)]
+                fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+                    let bytes = state.read_bytes(core::mem::size_of::<$type>())?;
+                    Ok(<$type>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive!(u8, u16, u32, u64, u128, i8, i16, i32, i64);
+
+// usize is always saved as 64 bits, so save states are portable between 32 and 64 bit hosts
+impl SaveState for usize {
+    fn save_state(&self, state: &mut StateWriter) {
+        (*self as u64).save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for usize {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(usize::try_from(u64::restore_state(state)?)?)
+    }
+}
+
+impl SaveState for bool {
+    fn save_state(&self, state: &mut StateWriter) {
+        (*self as u8).save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for bool {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        match u8::restore_state(state)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => bail!("Invalid bool {} in save state", other),
+        }
+    }
+}
+
+impl SaveState for () {
+    fn save_state(&self, _: &mut StateWriter) {}
+    fn load_state(&mut self, _: &mut StateReader) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+impl RestoreState for () {
+    fn restore_state(_: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(())
+    }
+}
+
+impl SaveState for String {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_str(self);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for String {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let len = usize::restore_state(state)?;
+        Ok(String::from_utf8(state.read_bytes(len)?.to_vec())?)
+    }
+}
+
+impl<T, const N: usize> SaveState for [T; N]
+where
+    T: SaveState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        for item in self {
+            item.save_state(state);
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        for item in self {
+            item.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> RestoreState for [T; N]
+where
+    T: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let items = (0..N)
+            .map(|_| T::restore_state(state))
+            .collect::<Result<Vec<T>, _>>()?;
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+impl<T> SaveState for Vec<T>
+where
+    T: SaveState + RestoreState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.len().save_state(state);
+        for item in self {
+            item.save_state(state);
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<T> RestoreState for Vec<T>
+where
+    T: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let len = usize::restore_state(state)?;
+        (0..len).map(|_| T::restore_state(state)).collect()
+    }
+}
+
+impl<T> SaveState for BinaryHeap<T>
+where
+    T: SaveState + RestoreState + Ord,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.len().save_state(state);
+        for item in self.iter() {
+            item.save_state(state);
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl<T> RestoreState for BinaryHeap<T>
+where
+    T: RestoreState + Ord,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Vec::<T>::restore_state(state)?.into())
+    }
+}
+
+impl<T> SaveState for Option<T>
+where
+    T: SaveState + RestoreState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        match self {
+            None => false.save_state(state),
+            Some(value) => {
+                true.save_state(state);
+                value.save_state(state);
+            }
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        match (bool::restore_state(state)?, self.as_mut()) {
+            (false, _) => *self = None,
+            // Load in place when possible, to avoid re-allocating large objects
+            (true, Some(value)) => value.load_state(state)?,
+            (true, None) => *self = Some(T::restore_state(state)?),
+        }
+        Ok(())
+    }
+}
+
+impl<T> RestoreState for Option<T>
+where
+    T: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        match bool::restore_state(state)? {
+            false => Ok(None),
+            true => Ok(Some(T::restore_state(state)?)),
+        }
+    }
+}
+
+impl<T> SaveState for Box<T>
+where
+    T: SaveState + ?Sized,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        (**self).save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        (**self).load_state(state)
+    }
+}
+
+impl<T> RestoreState for Box<T>
+where
+    T: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Box::new(T::restore_state(state)?))
+    }
+}
+
+impl<A, B> SaveState for (A, B)
+where
+    A: SaveState,
+    B: SaveState,
+{
+    fn save_state(&self, state: &mut StateWriter) {
+        self.0.save_state(state);
+        self.1.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.0.load_state(state)?;
+        self.1.load_state(state)
+    }
+}
+
+impl<A, B> RestoreState for (A, B)
+where
+    A: RestoreState,
+    B: RestoreState,
+{
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok((A::restore_state(state)?, B::restore_state(state)?))
+    }
+}
+
+/// Implements `SaveState` for a struct or enum by listing the fields/variants to save.
+///
+///  - `impl_save_state!(Foo { a, b })` saves every field and also implements `RestoreState`.
+///    Listing every field is enforced by the compiler.
+///  - `impl_save_state!(in_place Foo { a, b })` also saves every field, enforced by the compiler,
+///    but doesn't implement `RestoreState`. For types that can only be loaded in place, or that
+///    implement `RestoreState` themselves.
+///  - `impl_save_state!(Foo { a, .. })` only saves the listed fields. The rest are left untouched
+///    on load, so no `RestoreState` is implemented.
+///  - `impl_save_state!(enum Foo { A, B(x), C(x, y) })` saves the variant index and any fields.
+#[macro_export]
+macro_rules! impl_save_state {
+    (enum $name:ident { $( $variant:ident $( ( $( $field:ident ),* ) )? ),* $(,)? }) => {
+        impl $crate::save_state::SaveState for $name {
+            #[allow(unused_assignments)]
+            fn save_state(&self, state: &mut $crate::save_state::StateWriter) {
+                let mut index: u32 = 0;
+                $(
+                    if let $name::$variant $( ( $( $field ),* ) )? = self {
+                        $crate::save_state::SaveState::save_state(&index, state);
+                        $( $( $crate::save_state::SaveState::save_state($field, state); )* )?
+                        return;
+                    }
+                    index += 1;
+                )*
+                unreachable!()
+            }
+
+            fn load_state(&mut self, state: &mut $crate::save_state::StateReader) -> Result<(), $crate::save_state::anyhow::Error> {
+                *self = <Self as $crate::save_state::RestoreState>::restore_state(state)?;
+                Ok(())
+            }
+        }
+
+        impl $crate::save_state::RestoreState for $name {
+            #[allow(unused_assignments)]
+            fn restore_state(state: &mut $crate::save_state::StateReader) -> Result<Self, $crate::save_state::anyhow::Error> {
+                let found = <u32 as $crate::save_state::RestoreState>::restore_state(state)?;
+                let mut index: u32 = 0;
+                $(
+                    if found == index {
+                        return Ok($name::$variant $( ( $(
+                            { let $field = $crate::save_state::RestoreState::restore_state(state)?; $field }
+                        ),* ) )?);
+                    }
+                    index += 1;
+                )*
+                Err($crate::save_state::anyhow::anyhow!("Invalid {} variant {} in save state", stringify!($name), found))
+            }
+        }
+    };
+    ($name:ident { $( $field:ident , )* .. }) => {
+        impl $crate::save_state::SaveState for $name {
+            fn save_state(&self, _state: &mut $crate::save_state::StateWriter) {
+                $( $crate::save_state::SaveState::save_state(&self.$field, _state); )*
+            }
+
+            fn load_state(&mut self, _state: &mut $crate::save_state::StateReader) -> Result<(), $crate::save_state::anyhow::Error> {
+                $( $crate::save_state::SaveState::load_state(&mut self.$field, _state)?; )*
+                Ok(())
+            }
+        }
+    };
+    (in_place $name:ident { $( $field:ident ),* $(,)? }) => {
+        impl $crate::save_state::SaveState for $name {
+            fn save_state(&self, _state: &mut $crate::save_state::StateWriter) {
+                // Fails to compile if a field is missing
+                let $name { $( $field ),* } = self;
+                $( $crate::save_state::SaveState::save_state($field, _state); )*
+            }
+
+            fn load_state(&mut self, _state: &mut $crate::save_state::StateReader) -> Result<(), $crate::save_state::anyhow::Error> {
+                let $name { $( $field ),* } = self;
+                $( $crate::save_state::SaveState::load_state($field, _state)?; )*
+                Ok(())
+            }
+        }
+    };
+    ($name:ident { $( $field:ident ),* $(,)? }) => {
+        $crate::impl_save_state!($name { $( $field, )* .. });
+
+        impl $crate::save_state::RestoreState for $name {
+            fn restore_state(_state: &mut $crate::save_state::StateReader) -> Result<Self, $crate::save_state::anyhow::Error> {
+                Ok($name {
+                    $( $field: $crate::save_state::RestoreState::restore_state(_state)?, )*
+                })
+            }
+        }
+    };
+}
@@ -0,0 +1,86 @@
+use std::fmt::Display;
+
+/// A snapshot of an instance's statistics
+///
+/// Stats are just named groups of named values, so the UI and headless runner can display them
+/// without knowing anything about the core.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub groups: Vec<StatsGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsGroup {
+    pub name: String,
+    pub entries: Vec<(String, StatValue)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatValue {
+    Count(u64),
+    Ratio(f64),
+}
+
+impl Stats {
+    /// Adds a new group and returns it
+    pub fn group(&mut self, name: impl Into<String>) -> &mut StatsGroup {
+        self.groups.push(StatsGroup::new(name));
+        self.groups.last_mut().unwrap()
+    }
+
+    #[cfg(feature = "ui")]
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        for group in &self.groups {
+            egui::CollapsingHeader::new(&group.name).show(ui, |ui| {
+                egui::Grid::new(&group.name)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (name, value) in &group.entries {
+                        ui.label(name);
+                        ui.monospace(value.to_string());
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+    }
+}
+
+impl StatsGroup {
+    pub fn new(name: impl Into<String>) -> Self {
+        StatsGroup {
+            name: name.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn count(&mut self, name: impl Into<String>, value: u64) {
+        self.entries.push((name.into(), StatValue::Count(value)));
+    }
+
+    pub fn ratio(&mut self, name: impl Into<String>, value: f64) {
+        self.entries.push((name.into(), StatValue::Ratio(value)));
+    }
+}
+
+impl Display for StatValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatValue::Count(count) => write!(f, "{}", count),
+            StatValue::Ratio(ratio) => write!(f, "{:.3}", ratio),
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for group in &self.groups {
+            writeln!(f, "{}:", group.name)?;
+            let width = group.entries.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, value) in &group.entries {
+                writeln!(f, "  {:width$}  {}", name, value, width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
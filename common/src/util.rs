@@ -1,6 +1,8 @@
 use core::fmt;
 use std::ops::BitAnd;
 
+use crate::impl_save_state;
+
 #[derive(Copy, Clone)]
 pub struct ByteMask8 {
     mask: u64,
@@ -62,3 +64,5 @@ impl fmt::Debug for ByteMask8 {
         write!(f, "ByteMask8({:016x})", self.mask)
     }
 }
+
+impl_save_state!(ByteMask8 { mask });
@@ -0,0 +1,208 @@
+use std::{ops::{Deref, DerefMut}, sync::{mpsc, Arc, Mutex}};
+
+/// How the pixels of a `Frame` are laid out in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 4 bytes per pixel: red, green, blue, alpha
+    Rgba8888,
+    /// 2 bytes per pixel, big endian: 5 bits each of red, green and blue, then 1 bit of alpha
+    Rgba5551,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 => 4,
+            PixelFormat::Rgba5551 => 2,
+        }
+    }
+}
+
+/// Which lines of the picture a frame holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Every line, the frame is a whole picture
+    Progressive,
+    /// Interlaced, and this frame is only the even lines (counting from 0). The frontend can
+    /// weave it with the previous odd field, or just scale it up
+    Even,
+    /// Interlaced, and this frame is only the odd lines
+    Odd,
+}
+
+/// A picture produced by a core, ready to be shown
+///
+/// Frames are sent to the frontend with `UpdateMessage::Vsync`. The pixels live in a buffer from
+/// the core's `FramePool`, which gets it back when the frame is dropped.
+#[derive(Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from the start of one line to the next, at least `width * bytes_per_pixel`
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub field: Field,
+    pub data: FrameBuffer,
+}
+
+impl Frame {
+    /// Converts to tightly packed RGBA8888, the format most frontends want
+    pub fn to_rgba8(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.reserve(self.width as usize * self.height as usize * 4);
+        let line_bytes = self.width as usize * self.format.bytes_per_pixel();
+        for line in self.data.chunks(self.stride).take(self.height as usize) {
+            let line = &line[..line_bytes];
+            match self.format {
+                PixelFormat::Rgba8888 => out.extend_from_slice(line),
+                PixelFormat::Rgba5551 => {
+                    for pixel in line.chunks_exact(2) {
+                        let pixel = u16::from_be_bytes([pixel[0], pixel[1]]);
+                        let expand = |c: u16| ((c & 0x1f) << 3 | (c & 0x1f) >> 2) as u8;
+                        out.extend_from_slice(&[
+                            expand(pixel >> 11),
+                            expand(pixel >> 6),
+                            expand(pixel >> 1),
+                            if pixel & 1 != 0 { 0xff } else { 0 },
+                        ]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Recycles frame buffers, so a core doesn't need to allocate for every frame
+///
+/// Buffers are returned to the pool when the `FrameBuffer` is dropped, on whichever thread that
+/// happens.
+pub struct FramePool {
+    return_tx: mpsc::Sender<Vec<u8>>,
+    return_rx: mpsc::Receiver<Vec<u8>>,
+}
+
+impl FramePool {
+    pub fn new() -> Self {
+        let (return_tx, return_rx) = mpsc::channel();
+        FramePool { return_tx, return_rx }
+    }
+
+    /// A buffer of `len` bytes. The contents are left over from the last frame it was used for
+    pub fn buffer(&self, len: usize) -> FrameBuffer {
+        // Try to reuse a returned buffer when possible, otherwise allocate a new one
+        let mut data = self.return_rx.try_recv().unwrap_or_default();
+        data.resize(len, 0);
+        FrameBuffer { data, pool: self.return_tx.clone() }
+    }
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pixel data on loan from a `FramePool`
+pub struct FrameBuffer {
+    data: Vec<u8>,
+    pool: mpsc::Sender<Vec<u8>>,
+}
+
+impl Deref for FrameBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for FrameBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        // If the pool is gone, the buffer is just freed
+        let _ = self.pool.send(std::mem::take(&mut self.data));
+    }
+}
+
+impl std::fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FrameBuffer({} bytes)", self.data.len())
+    }
+}
+
+/// Creates the two ends of a frame handoff. The core sends, and the instance's run loop passes
+/// the latest frame on to the frontend whenever it syncs
+pub fn frame_channel() -> (FrameSender, FrameReceiver) {
+    let latest = Arc::new(Mutex::new(None));
+    (FrameSender { latest: latest.clone() }, FrameReceiver { latest })
+}
+
+/// Never blocks: if the frontend hasn't picked up the last frame yet, it gets replaced
+#[derive(Clone)]
+pub struct FrameSender {
+    latest: Arc<Mutex<Option<Frame>>>,
+}
+
+impl FrameSender {
+    pub fn send(&self, frame: Frame) {
+        // The replaced frame's buffer goes back to the pool
+        let _replaced = self.latest.lock().unwrap().replace(frame);
+    }
+}
+
+impl std::fmt::Debug for FrameSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FrameSender")
+    }
+}
+
+pub struct FrameReceiver {
+    latest: Arc<Mutex<Option<Frame>>>,
+}
+
+impl FrameReceiver {
+    /// The newest frame sent since the last call, if any
+    pub fn latest(&self) -> Option<Frame> {
+        self.latest.lock().unwrap().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pooled_handoff() {
+        let pool = FramePool::new();
+        let (tx, rx) = frame_channel();
+
+        let frame = |pool: &FramePool, value: u8| {
+            let mut data = pool.buffer(2 * 2 * 2 + 4);
+            data.fill(value);
+            Frame { width: 2, height: 2, stride: 6, format: PixelFormat::Rgba5551, field: Field::Odd, data }
+        };
+
+        // Only the newest frame is kept, and the replaced one is returned to the pool
+        tx.send(frame(&pool, 0x00));
+        tx.send(frame(&pool, 0xff));
+        assert!(pool.return_rx.try_recv().is_ok());
+
+        let latest = rx.latest().unwrap();
+        assert!(rx.latest().is_none());
+
+        // Padding at the end of each line is skipped
+        let mut rgba = Vec::new();
+        latest.to_rgba8(&mut rgba);
+        assert_eq!(rgba, [0xff; 16]);
+
+        drop(latest);
+        let reused = pool.buffer(4);
+        assert_eq!(reused.len(), 4);
+        assert!(pool.return_rx.try_recv().is_err(), "Buffer should have been reused");
+    }
+}
@@ -1,20 +1,43 @@
-
-
 use actor_framework::*;
-use crate::c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished};
+use common::{audio::{AudioSink, StereoSample}, impl_save_state};
+use crate::{c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished}, d_bus::DBus, RCP_CLOCK};
 
-use super::{N64Actors, cpu_actor::CpuActor};
+use super::{N64Actors, cpu_actor::CpuActor, bus_actor::{BusPair, request_bus, GrantBus, ReleaseBus, ReturnBus, BusRequest, BusActor}};
+
+/// The DAC's clock is the NTSC video clock, divided by AI_DACRATE + 1
+const VIDEO_CLOCK: u64 = 48_681_812;
 
 pub struct AiActor {
     dram_addr: u32,
     length: u32,
     dma_enable: bool,
+    dac_rate: u32,
+    bit_rate: u32,
+    /// The buffer being played
+    playing: Option<AiBuffer>,
+    /// When the playing buffer will finish
+    play_end: Time,
+    /// The AI can queue one buffer behind the one that's playing
+    queued: Option<AiBuffer>,
+    deferred: Deferred<N64Actors, AiOutbox>,
+    pub(crate) bus: Option<Box<BusPair>>,
+    /// Not saved, it belongs to the frontend. See `InstanceN64::set_audio_sink`
+    pub(crate) sink: Option<Box<dyn AudioSink>>,
+    samples: Vec<StereoSample>,
 }
 
+impl_save_state!(AiActor {
+    dram_addr, length, dma_enable, dac_rate, bit_rate, playing, play_end, queued, deferred, bus, ..
+});
+
 make_outbox!(
     AiOutbox<N64Actors, AiActor> {
         cpu: ReadFinished,
         cpu_w: WriteFinished,
+        dma: AiDma,
+        done: AiBufferDone,
+        bus: BusRequest,
+        return_bus: ReleaseBus,
     }
 );
 
@@ -24,17 +47,87 @@ impl Default for AiActor {
             dram_addr: 0,
             length: 0,
             dma_enable: false,
+            dac_rate: 0,
+            bit_rate: 0,
+            playing: None,
+            play_end: Time::default(),
+            queued: None,
+            deferred: Deferred::new(),
+            bus: None,
+            sink: None,
+            samples: Vec::new(),
         }
     }
 }
 
+impl AiActor {
+    fn sample_rate(&self) -> u32 {
+        (VIDEO_CLOCK / (self.dac_rate as u64 + 1)) as u32
+    }
+
+    /// Cycles it takes the DAC to play `bytes` of samples
+    fn play_cycles(&self, bytes: u32) -> u64 {
+        let samples = bytes as u64 / 4;
+        samples * RCP_CLOCK * (self.dac_rate as u64 + 1) / VIDEO_CLOCK
+    }
+
+    /// Bytes the DAC plays in `cycles`
+    fn played_bytes(&self, cycles: u64) -> u64 {
+        cycles * VIDEO_CLOCK / (RCP_CLOCK * (self.dac_rate as u64 + 1)) * 4
+    }
+
+    /// Starts the queued buffer if the DAC is idle
+    fn try_start(&mut self, outbox: &mut AiOutbox, time: Time) -> SchedulerResult {
+        if self.playing.is_some() || !self.dma_enable {
+            return SchedulerResult::Ok;
+        }
+        match self.queued.take() {
+            Some(buffer) => {
+                self.play_end = time.add(self.play_cycles(buffer.length));
+                self.playing = Some(buffer);
+                self.deferred.send(outbox, |o| o.send::<Self>(AiDma, time.add(1)))
+            }
+            None => SchedulerResult::Ok,
+        }
+    }
+
+    /// Reads the whole buffer from RDRAM as it starts playing, and sends it to the sink.
+    ///
+    /// The hardware fetches 8 bytes at a time as the DAC needs them, so games that overwrite a
+    /// buffer while it plays will sound different.
+    fn do_dma(&mut self, outbox: &mut AiOutbox, d_bus: &mut DBus, time: Time) -> SchedulerResult {
+        let buffer = self.playing.unwrap();
+
+        self.samples.clear();
+        for addr in (buffer.dram_addr..buffer.dram_addr + buffer.length).step_by(8) {
+            let (_cycles, data) = d_bus.read_qword(addr);
+            for sample in [(data >> 32) as u32, data as u32] {
+                self.samples.push([(sample >> 16) as i16, sample as i16]);
+            }
+        }
+        let rate = self.sample_rate();
+        if let Some(sink) = &mut self.sink {
+            sink.push(rate, &self.samples);
+        }
+
+        let done = std::cmp::max(self.play_end, time.add(1));
+        self.deferred.send(outbox, |o| o.send::<Self>(AiBufferDone, done))
+    }
+}
+
 impl Actor<N64Actors> for AiActor {
     type OutboxType = AiOutbox;
+
+    #[inline(always)]
+    fn deferred(&mut self) -> Option<&mut Deferred<N64Actors, AiOutbox>> {
+        Some(&mut self.deferred)
+    }
 }
 
 impl Handler<N64Actors, CBusWrite> for AiActor {
     fn recv(&mut self, outbox: &mut AiOutbox, message: CBusWrite, time: Time, _limit: Time) -> SchedulerResult {
         let data = message.data;
+        let mut started = SchedulerResult::Ok;
         match message.address & 0x1c {
             0x00 => { // AI_DRAM_ADDR
                 println!("AI_DRAM_ADDR = {:#010x}", data);
@@ -43,26 +136,36 @@ impl Handler<N64Actors, CBusWrite> for AiActor {
             0x04 => { // AI_LENGTH
                 println!("AI_LENGTH = {:#010x}", data);
                 self.length = data & 0x0003_fff8;
+                // Writes while the queue is full are ignored
+                if self.queued.is_none() && self.length != 0 {
+                    self.queued = Some(AiBuffer { dram_addr: self.dram_addr, length: self.length });
+                    started = self.try_start(outbox, time);
+                }
             }
             0x08 => { // AI_CONTROL
                 println!("AI_CONTROL = {:#010x}", data);
                 self.dma_enable = data & 0x1 != 0;
+                started = self.try_start(outbox, time);
             }
             0x0c => { // AI_STATUS
-                todo!("AI_STATUS = {:#010x}", data);
+                // TODO: Clear interrupt
             }
             0x10 => { // AI_DACRATE
-                todo!("AI_DACRATE = {:#010x}", data);
+                self.dac_rate = data & 0x3fff;
             }
             0x14 => { // AI_BITRATE
-                todo!("AI_BITRATE = {:#010x}", data);
+                self.bit_rate = data & 0xf;
             }
             0x1c => { // unknown
                 todo!("AI unknown = {:#010x}", data);
             }
             _ => unreachable!()
         }
-        outbox.send::<CpuActor>(WriteFinished {}, time.add(4))
+        let finished = self.deferred.send(outbox, |o| o.send::<CpuActor>(WriteFinished {}, time.add(4)));
+        match started {
+            SchedulerResult::Ok => finished,
+            started => started,
+        }
     }
 }
 
@@ -74,8 +177,14 @@ impl Handler<N64Actors, CBusRead> for AiActor {
                 self.dram_addr
             }
             0x04 => { // AI_LENGTH
-                println!("read AI_LENGTH");
-                self.length
+                // Counts down as the current buffer plays
+                match self.playing {
+                    Some(buffer) => {
+                        let cycles = u64::from(self.play_end).saturating_sub(time.into());
+                        self.played_bytes(cycles).min(buffer.length as u64) as u32 & !0x7
+                    }
+                    None => 0,
+                }
             }
             0x08 => { // AI_CONTROL
                 println!("read AI_CONTROL");
@@ -83,7 +192,10 @@ impl Handler<N64Actors, CBusRead> for AiActor {
                 self.length
             }
             0x0c => { // AI_STATUS
-                todo!("AI_STATUS");
+                let full = self.queued.is_some() as u32;
+                let busy = self.playing.is_some() as u32;
+                // Bits 20 and 24 always read as set
+                full << 31 | busy << 30 | (self.dma_enable as u32) << 25 | 0x0110_0000 | full
             }
             0x10 => { // AI_DACRATE
                 println!("read AI_DACRATE");
@@ -100,6 +212,58 @@ impl Handler<N64Actors, CBusRead> for AiActor {
             }
             _ => unreachable!()
         };
-        outbox.send::<CpuActor>(ReadFinished { data }, time.add(4))
+        self.deferred.send(outbox, |o| o.send::<CpuActor>(ReadFinished { data }, time.add(4)))
+    }
+}
+
+impl Handler<N64Actors, AiDma> for AiActor {
+    fn recv(&mut self, outbox: &mut AiOutbox, _: AiDma, time: Time, _limit: Time) -> SchedulerResult {
+        match self.bus.take() {
+            Some(mut bus) => {
+                let result = self.do_dma(outbox, &mut bus.d_bus, time);
+                self.bus = Some(bus);
+                result
+            }
+            None => self.deferred.send(outbox, |o| request_bus(o, time)),
+        }
+    }
+}
+
+impl Handler<N64Actors, AiBufferDone> for AiActor {
+    fn recv(&mut self, outbox: &mut AiOutbox, _: AiBufferDone, time: Time, _limit: Time) -> SchedulerResult {
+        // TODO: Raise the AI interrupt
+        self.playing = None;
+        self.try_start(outbox, time)
+    }
+}
+
+impl Handler<N64Actors, GrantBus> for AiActor {
+    fn recv(&mut self, outbox: &mut AiOutbox, Grant(mut bus): GrantBus, time: Time, _: Time) -> SchedulerResult {
+        let result = self.do_dma(outbox, &mut bus.d_bus, time);
+        self.bus = Some(bus);
+        result
     }
 }
+
+impl Handler<N64Actors, ReturnBus> for AiActor {
+    fn recv(&mut self, outbox: &mut AiOutbox, _: ReturnBus, time: Time, _: Time) -> SchedulerResult {
+        let bus = self.bus.take().unwrap();
+        self.deferred.send(outbox, |o| o.send::<BusActor>(Release(bus), time))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AiBuffer {
+    dram_addr: u32,
+    length: u32,
+}
+
+impl_save_state!(AiBuffer { dram_addr, length });
+
+pub(super) struct AiDma;
+
+impl_save_state!(AiDma {});
+
+pub(super) struct AiBufferDone;
+
+impl_save_state!(AiBufferDone {});
@@ -1,6 +1,5 @@
-use std::{collections::BinaryHeap, any::TypeId};
-
 use actor_framework::*;
+use common::impl_save_state;
 use crate::{c_bus::CBus, d_bus::DBus};
 
 use super::{N64Actors, cpu_actor::CpuActor};
@@ -8,50 +7,39 @@ use super::{N64Actors, cpu_actor::CpuActor};
 /// This actor represents RCP's internal bus and handles all bus arbitration
 /// For now, we do it all synchronously, so this is going to be a huge bottleneck
 pub struct BusActor {
-    queue: BinaryHeap<BusRequest>,
-    commited_time: Time,
-    /// A channel to the current Bus owner
-    bus_owner: Option<ReturnBusChannel>,
+    pub(crate) bus: Resource<N64Actors, BusActor, BusPair>,
 }
 
+impl_save_state!(BusActor { bus });
+
 impl Default for BusActor {
     fn default() -> Self {
         Self {
-            queue: BinaryHeap::new(),
-            commited_time: Time::default(),
             // To simplify things, CpuActor starts with the bus resource
-            // TODO: Allow actors to pass the bus between each other.
-            //       We might need to make resource sharing a native feature of actor_framework and
-            //       have the scheduler manage resources. Maybe resources should be full sub-actors
-            //       that can be called without going though the scheduler?
-            bus_owner: Some(Channel::new::<CpuActor>()),
+            bus: Resource::lent_to::<CpuActor>(),
         }
     }
 }
 
 make_outbox!(
     BusOutbox<N64Actors, BusActor> {
-        grant_c_bus: Box<BusPair>,
-        return_c_bus: ReturnBus,
+        grant_bus: GrantBus,
+        return_bus: ReturnBus,
     }
 );
 
-
-/// Return the borrowed CBus
-pub struct ReturnBus {}
+/// Ask the current owner to return the bus
+pub type ReturnBus = Preempt<BusPair>;
+pub type GrantBus = Grant<BusPair>;
+pub type ReleaseBus = Release<BusPair>;
+pub type BusRequest = Acquire<N64Actors, BusActor, BusPair>;
 
 pub struct BusPair {
     pub c_bus: CBus,
     pub d_bus: DBus,
 }
 
-type GrantBusChannel = Channel<N64Actors, BusActor, Box<BusPair>>;
-type ReturnBusChannel = Channel<N64Actors, BusActor, ReturnBus>;
-
-pub struct BusRequest {
-    channels: (GrantBusChannel, ReturnBusChannel),
-    piority: u16,
-}
+impl_save_state!(BusPair { c_bus, d_bus });
 
 const fn piority(actor: N64Actors) -> u16 {
     match actor {
@@ -78,72 +66,22 @@ pub fn request_bus<Requestor, Out>(outbox: &mut Out, time: Time) -> SchedulerRes
 where
 Out: Outbox<N64Actors, Sender = Requestor>,
 Out: OutboxSend<N64Actors, BusRequest>,
-Requestor: Handler<N64Actors, Box<BusPair>> + Actor<N64Actors> + Handler<N64Actors, ReturnBus>,
+Requestor: Handler<N64Actors, GrantBus> + Actor<N64Actors> + Handler<N64Actors, ReturnBus>,
 {
-    let bus_request = BusRequest {
-        channels: (Channel::new::<Requestor>(), Channel::new::<Requestor>()),
-        piority: piority(Requestor::name()),
-    };
-    outbox.send::<BusActor>(bus_request, time)
-}
-
-impl PartialEq for BusRequest {
-    fn eq(&self, other: &Self) -> bool {
-        self.piority == other.piority
-    }
-}
-
-impl Eq for BusRequest {}
-
-impl PartialOrd for BusRequest {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl Ord for BusRequest {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.piority.cmp(&other.piority)
-    }
+    outbox.send::<BusActor>(BusRequest::new::<Requestor>(piority(Requestor::name())), time)
 }
 
 impl Handler<N64Actors, BusRequest> for BusActor {
     #[inline(always)]
     fn recv(&mut self, outbox: &mut BusOutbox, message: BusRequest, time: Time, _limit: Time) -> SchedulerResult {
-        let new_piority = message.piority;
-        self.queue.push(message);
-
-        if let Some(bus_owner) = self.bus_owner.take() {
-            // Request the bus from the current owner
-            outbox.send_channel(bus_owner, ReturnBus {}, time);
-        } else if outbox.contains::<BusPair>() && self.commited_time == time {
-            // We already accepted a request this cycle, but we might need to change our mind and
-            // accept this one if it has a higher piority
-            let highest = self.queue.peek().unwrap();
-
-            // Note: All priorities should be unique per sender, and they are only allowed one
-            //       outstanding request. So we can just compare priorities
-            // TODO: PERF: Should we modify BinaryHeap to tell us if the top element changed?
-            if highest.piority == new_piority {
-                // This request takes priority, cancel the previous grant
-                let (_, bus) = outbox.cancel();
-                let (grant, _) = highest.channels.clone();
-
-                outbox.send_channel(grant, bus, time.add(1));
-            }
-        }
-        SchedulerResult::Ok
+        self.bus.acquire(outbox, message, time)
     }
 }
 
-impl Handler<N64Actors, Box<BusPair>> for BusActor {
+impl Handler<N64Actors, ReleaseBus> for BusActor {
     #[inline(always)]
-    fn recv(&mut self, outbox: &mut BusOutbox, bus: Box<BusPair>, time: Time, _limit: Time) -> SchedulerResult {
-        let highest = self.queue.peek().expect("There should be a request in the queue");
-        self.commited_time = time;
-
-        let (grant, _) = highest.channels.clone();
-        outbox.send_channel(grant, bus, time.add(1))
+    fn recv(&mut self, outbox: &mut BusOutbox, message: ReleaseBus, time: Time, _limit: Time) -> SchedulerResult {
+        self.bus.release(outbox, message, time)
     }
 }
 
@@ -154,19 +92,87 @@ impl Actor<N64Actors> for BusActor {
     fn delivering<Message>(&mut self, outbox: &mut BusOutbox, _: &Message, time: Time)
     where Message: 'static
     {
-        if TypeId::of::<Message>() == TypeId::of::<Box<BusPair>>() {
-            let request = self.queue.pop().unwrap();
-
-            // Increment committed time
-            self.commited_time = self.commited_time.add(1);
-            let (_, return_channel) = request.channels;
-
-            if self.queue.is_empty() {
-                self.bus_owner = Some(return_channel);
-            } else {
-                // There is another request waiting, immediately ask for the bus back
-                outbox.send_channel(return_channel, ReturnBus {}, time.add(1));
-            }
-        }
+        self.bus.delivering::<Message>(outbox, time);
+    }
+
+    fn stats(&self, stats: &mut StatsGroup) {
+        self.bus.stats(stats);
+    }
+
+    fn reset_stats(&mut self) {
+        self.bus.reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::{pi_actor::PiActor, si_actor::SiActor};
+
+    fn request<Requestor>() -> BusRequest
+    where
+        Requestor: Handler<N64Actors, GrantBus> + Handler<N64Actors, ReturnBus>,
+    {
+        BusRequest::new::<Requestor>(piority(Requestor::name()))
+    }
+
+    fn bus() -> ReleaseBus {
+        Release(Box::new(BusPair { c_bus: CBus::new(), d_bus: DBus::new() }))
+    }
+
+    #[test]
+    fn highest_priority_first() {
+        let mut bus_actor = ActorHarness::<N64Actors, BusActor>::new(BusActor::default());
+
+        // The CPU starts with the bus
+        bus_actor.deliver(request::<PiActor>(), Time::from(10), Time::MAX);
+        bus_actor.expect::<ReturnBus>(N64Actors::CpuActor, Time::from(10));
+        bus_actor.deliver(request::<SiActor>(), Time::from(11), Time::MAX);
+        assert!(bus_actor.is_empty(), "CPU should only be asked once");
+
+        // SI has a higher priority, and PI is still waiting so SI is immediately asked to return it
+        bus_actor.deliver(bus(), Time::from(15), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::SiActor, Time::from(16));
+        bus_actor.expect::<ReturnBus>(N64Actors::SiActor, Time::from(17));
+        assert_eq!(bus_actor.actor.bus.borrower(), Some(N64Actors::SiActor));
+
+        bus_actor.deliver(bus(), Time::from(20), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::PiActor, Time::from(21));
+        assert!(bus_actor.is_empty());
+        assert_eq!(bus_actor.actor.bus.borrower(), Some(N64Actors::PiActor));
+    }
+
+    #[test]
+    fn higher_priority_same_cycle_takes_grant() {
+        let mut bus_actor = ActorHarness::<N64Actors, BusActor>::new(BusActor::default());
+
+        bus_actor.deliver(request::<PiActor>(), Time::from(10), Time::MAX);
+        bus_actor.expect::<ReturnBus>(N64Actors::CpuActor, Time::from(10));
+        bus_actor.deliver(bus(), Time::from(12), Time::MAX);
+
+        // The grant to PI hasn't been delivered yet, so SI can still take it
+        bus_actor.deliver(request::<SiActor>(), Time::from(12), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::SiActor, Time::from(13));
+        bus_actor.expect::<ReturnBus>(N64Actors::SiActor, Time::from(14));
+
+        bus_actor.deliver(bus(), Time::from(20), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::PiActor, Time::from(21));
+    }
+
+    #[test]
+    fn returns_to_owner_when_idle() {
+        let mut bus_actor = ActorHarness::<N64Actors, BusActor>::new(BusActor::default());
+
+        bus_actor.deliver(request::<PiActor>(), Time::from(10), Time::MAX);
+        bus_actor.expect::<ReturnBus>(N64Actors::CpuActor, Time::from(10));
+        bus_actor.deliver(bus(), Time::from(12), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::PiActor, Time::from(13));
+        bus_actor.deliver(bus(), Time::from(30), Time::MAX);
+        assert!(bus_actor.is_empty());
+        assert!(bus_actor.actor.bus.is_home());
+
+        // An idle bus is granted without waiting
+        bus_actor.deliver(request::<CpuActor>(), Time::from(40), Time::MAX);
+        bus_actor.expect::<GrantBus>(N64Actors::CpuActor, Time::from(40));
     }
 }
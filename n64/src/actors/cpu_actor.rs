@@ -2,12 +2,13 @@
 
 /// CpuActor: Emulates the CPU and MI (Mips Interface)
 
-use actor_framework::{Actor, Time, Handler,  OutboxSend, SchedulerResult, ActorInit, Outbox};
-use super::{N64Actors, pi_actor, bus_actor::{BusPair, ReturnBus, request_bus}};
+use actor_framework::{Actor, ClockDomain, Time, Handler,  OutboxSend, SchedulerResult, ActorInit, Outbox, StatsGroup, Grant, Preempt, Release};
+use common::impl_save_state;
+use super::{N64Actors, pi_actor, bus_actor::{BusPair, GrantBus, ReleaseBus, ReturnBus, request_bus}};
 
 use vr4300::{self, RequestType};
 
-use crate::{actors::bus_actor::{BusActor, BusRequest}, c_bus::{self, CBus}, d_bus::DBus, N64Config};
+use crate::{actors::{bus_actor::{BusActor, BusRequest}, rsp_actor::{RspMem, RspMemRequest}}, c_bus::{self, CBus}, d_bus::DBus, N64Config};
 
 pub struct CpuActor {
     committed_time: Time,
@@ -16,7 +17,7 @@ pub struct CpuActor {
     outstanding_mem_request: Option<vr4300::BusRequest>,
     c_bus_req: Option<(u32, vr4300::RequestType)>,
     bus_free: Time,
-    bus: Option<Box<BusPair>>,
+    pub(crate) bus: Option<Box<BusPair>>,
     /// tracks how many times `CpuActor::advance` has been called recursively
     /// (It can recurse when we can complete a memory request internally)
     recursion: u32,
@@ -26,76 +27,116 @@ pub struct CpuActor {
 actor_framework::make_outbox!(
     CpuOutbox<N64Actors, CpuActor> {
         bus: BusRequest,
-        bus_return: Box<BusPair>,
+        bus_return: ReleaseBus,
         run: CpuRun,
         reg_write: c_bus::CBusWrite,
         reg: c_bus::CBusRead,
-        request_resource: c_bus::ResourceRequest,
-        return_resource: c_bus::Resource,
+        request_rsp_mem: RspMemRequest,
+        return_rsp_mem: Release<RspMem>,
         pi_read: pi_actor::PiRead,
         pi_write: pi_actor::PiWrite,
     }
 );
 
-const RECURSION_LIMIT: u32 = 100;
+impl_save_state!(in_place CpuActor {
+    committed_time, _cpu_overrun, cpu_core, outstanding_mem_request, c_bus_req, bus_free, bus,
+    recursion, interrupted_msg,
+});
 
-struct CpuRun {}
+const RECURSION_LIMIT: u32 = 100;
 
-fn to_cpu_time(bus_time: u64, odd: u64) -> u64 {
-    // CPU has a 1.5x clock multiplier
+/// Returned from the scheduler when the CPU reaches the pc set with `vr4300::Core::set_break_pc`,
+/// one of its breakpoints, or the next instruction while single stepping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcReached {
+    pub pc: u64,
+    pub time: Time,
+}
 
-    // We use the bottom bit of the absolute time (odd) so our extra cycles always
-    // happen deterministically on the odd cycle of the primary system clock
-    bus_time.saturating_add(bus_time / 2u64 + odd)
+impl std::fmt::Display for PcReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CPU reached pc {:#018x} at {}", self.pc, self.time)
+    }
 }
 
-fn to_bus_time(cpu_time: u64, odd: u64) -> u64 {
-    // CPU has a 1.5x clock multiplier
-    cpu_time - ((cpu_time + odd * 2) / 3u64)
+impl std::error::Error for PcReached {}
+
+pub(super) struct CpuRun {}
+
+impl_save_state!(CpuRun {});
+
+/// The PIF's reset lines into the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuReset {
+    /// Soft reset, after the reset button has been pressed
+    Nmi,
+    /// Held in cold reset, after the PIF has given up on booting
+    Hold,
 }
 
-impl CpuActor {
-    fn advance(&mut self, outbox: &mut CpuOutbox, _: CpuRun, limit: Time) -> SchedulerResult {
-        let limit_64: u64 = limit.into();
-        let mut commit_time_64: u64 = self.committed_time.into();
+impl_save_state!(enum CpuReset { Nmi, Hold });
 
-        //println!("CpuActor::advance({}, {})", limit_64, commit_time_64);
+/// CPU has a 1.5x clock multiplier
+///
+/// The extra cycles always happen deterministically on the odd cycles of the primary system clock
+const CPU_CLOCK: ClockDomain = ClockDomain::new("VR4300", 3, 2);
 
-        let cycles: u64 = limit_64 - commit_time_64;
+impl CpuActor {
+    /// The core is ready to keep running from `committed_time`, but doesn't know when it will next
+    /// need the rest of the system. So CpuRun is published with a lazy time and the scheduler will
+    /// call `resolve` to run the core further.
+    fn schedule_run(&mut self, outbox: &mut CpuOutbox) {
+        let time = self.committed_time.into_lazy();
+        if outbox.contains::<CpuRun>() {
+            // Running ahead, just move the lower bound
+            outbox.resolve_to(time);
+        } else {
+            outbox.send::<CpuActor>(CpuRun {}, time);
+        }
+    }
 
-        let mut odd = commit_time_64 & 1u64;
+    fn advance(&mut self, outbox: &mut CpuOutbox, limit: Time) -> SchedulerResult {
+        //println!("CpuActor::advance({}, {})", limit, self.committed_time);
 
-        let mut cpu_cycles = to_cpu_time(cycles, odd);
-        //assert!(cycles == to_bus_time(cpu_cycles, odd), "cycles {} != cpu_cycles {} when odd = {}", cycles, cpu_cycles, odd);
+        let cpu_limit = CPU_CLOCK.to_local(limit);
+        let mut cpu_time = CPU_CLOCK.to_local(self.committed_time);
         loop {
-            let result = self.cpu_core.advance(to_cpu_time(cycles, odd));
+            let result = self.cpu_core.advance(cpu_limit - cpu_time);
 
-            let used_cycles = to_bus_time(result.cycles, odd);
-            commit_time_64 += used_cycles;
-            self.committed_time = commit_time_64.into();
-            //println!("core did {} ({}) cycles and returned {} at cycle {}", used_cycles, result.cycles, result.reason, commit_time_64);
-            assert!(used_cycles <= cycles, "{} > {} | {}, {}", used_cycles, cycles, cpu_cycles, result.cycles);
+            // Anything the core does part way through a bus cycle is seen by the bus on the next one
+            cpu_time += result.cycles;
+            self.committed_time = CPU_CLOCK.to_scheduler(cpu_time);
+            //println!("core did {} cycles and returned {} at {}", result.cycles, result.reason, self.committed_time);
+            assert!(self.committed_time <= limit, "{} > {} | {}, {}", self.committed_time, limit, cpu_limit, cpu_time);
 
             match result.reason {
                 vr4300::Reason::Limited => {
-                    outbox.send::<CpuActor>(CpuRun {}, self.committed_time);
+                    self.schedule_run(outbox);
                 }
                 vr4300::Reason::SyncRequest => {
                     assert!(limit.is_resolved());
-                    self.cpu_core.set_time(commit_time_64);
+                    self.cpu_core.set_time(self.committed_time.into());
 
-                    cpu_cycles -= result.cycles;
-                    if cpu_cycles > 0 {
-                        odd = commit_time_64 & 1u64;
+                    cpu_time = CPU_CLOCK.to_local(self.committed_time);
+                    if cpu_time < cpu_limit {
                         continue;
                     }
 
-                    outbox.send::<CpuActor>(CpuRun {}, self.committed_time);
+                    self.schedule_run(outbox);
                 }
                 vr4300::Reason::BusRequest(request) => {
+                    // The core is stalled until the request finishes
+                    outbox.try_cancel::<CpuRun>();
+
                     // Request over C-BUS/D-BUS
                     return self.start_request(outbox, request, limit);
                 }
+                vr4300::Reason::Breakpoint => {
+                    // Stop the scheduler, the core picks up where it left off when resumed
+                    self.schedule_run(outbox);
+                    let pc = self.cpu_core.pc();
+                    return SchedulerResult::Err(PcReached { pc, time: self.committed_time }.into());
+                }
             };
             return SchedulerResult::Ok;
         };
@@ -246,7 +287,7 @@ impl CpuActor {
         // Advance the CPU upto the finish time
         // FIXME: The vr4300 actually stalls and doesn't need to do any work...
         //        Except in caches where this request wasn't triggered by an interlock
-        let catchup_result = self.advance(outbox, CpuRun {  }, finish_time);
+        let catchup_result = self.advance(outbox, finish_time);
 
         if write {
             self.cpu_core.finish_write(req_type, transfers);
@@ -259,7 +300,7 @@ impl CpuActor {
             }
         }
 
-        let can_recurse = self.recursion < RECURSION_LIMIT && outbox.contains::<CpuRun>() && outbox.time() < limit;
+        let can_recurse = self.recursion < RECURSION_LIMIT && outbox.contains::<CpuRun>() && outbox.time().lower_bound() < limit;
 
         match catchup_result {
             SchedulerResult::Ok if can_recurse => {  }
@@ -269,8 +310,7 @@ impl CpuActor {
         // The CPU core is ready to run (it didn't issue a new memory request)
         // Might as well run it now to save scheduler overhead
         self.recursion += 1;
-        let (_ , cpurun) = outbox.cancel();
-        return self.advance(outbox, cpurun, limit);
+        return self.advance(outbox, limit);
     }
 
     #[inline(never)]
@@ -322,15 +362,36 @@ impl Actor<N64Actors> for CpuActor {
         where
             Message: 'static,
     {
-        if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<Box<BusPair>>() {
+        if std::any::TypeId::of::<Message>() == std::any::TypeId::of::<GrantBus>() {
             outbox.restore(&mut self.interrupted_msg);
         }
     }
+
+    fn resolve(&mut self, outbox: &mut CpuOutbox, limit: Time) -> SchedulerResult {
+        // CpuRun is the only message sent with a lazy time.
+        // Instead of delivering it, run the core in place and move the lower bound forwards
+        debug_assert!(outbox.contains::<CpuRun>());
+        if self.committed_time == limit {
+            // Let the scheduler know we are zero limited
+            return SchedulerResult::ZeroLimit;
+        }
+
+        self.recursion = 0; // Reset recursion
+        self.advance(outbox, limit)
+    }
+
+    fn stats(&self, stats: &mut StatsGroup) {
+        stats.count("Instructions executed", self.cpu_core.instruction_count());
+    }
+
+    fn reset_stats(&mut self) {
+        self.cpu_core.reset_instruction_count();
+    }
 }
 
 impl ActorInit<N64Actors> for CpuActor {
     fn init(_config: &N64Config, outbox: &mut CpuOutbox, time: Time) -> Result<CpuActor, anyhow::Error> {
-        outbox.send::<CpuActor>(CpuRun {}, time);
+        outbox.send::<CpuActor>(CpuRun {}, time.into_lazy());
         Ok(CpuActor {
             committed_time: Default::default(),
             _cpu_overrun: 0,
@@ -371,21 +432,34 @@ impl Handler<N64Actors, c_bus::WriteFinished> for CpuActor {
 impl Handler<N64Actors, CpuRun> for CpuActor {
     #[inline(always)]
     fn recv(&mut self, outbox: &mut CpuOutbox, msg: CpuRun, time: Time, limit: Time) -> SchedulerResult {
+        // CpuRun is normally resolved in place by `resolve`, this only runs if it gets resolved
+        // some other way
         debug_assert!(time == self.committed_time);
         if time == limit {
-            outbox.send::<CpuActor>(msg, time);
+            outbox.send::<CpuActor>(msg, time.into_lazy());
 
             // Let the scheduler know we are zero limited
             return SchedulerResult::ZeroLimit;
         }
 
         self.recursion = 0; // Reset recursion
-        self.advance(outbox, msg, limit)
+        self.advance(outbox, limit)
+    }
+}
+
+impl Handler<N64Actors, CpuReset> for CpuActor {
+    fn recv(&mut self, _: &mut CpuOutbox, reset: CpuReset, _: Time, _: Time) -> SchedulerResult {
+        // The core takes it the next time it runs, once it's not waiting on the bus
+        match reset {
+            CpuReset::Nmi => self.cpu_core.nmi(),
+            CpuReset::Hold => self.cpu_core.hold_reset(),
+        }
+        SchedulerResult::Ok
     }
 }
 
-impl Handler<N64Actors, Box<BusPair>> for CpuActor {
-    fn recv(&mut self, outbox: &mut CpuOutbox, bus: Box<BusPair>, time: Time, limit: Time) -> SchedulerResult {
+impl Handler<N64Actors, GrantBus> for CpuActor {
+    fn recv(&mut self, outbox: &mut CpuOutbox, Grant(bus): GrantBus, time: Time, limit: Time) -> SchedulerResult {
         let request = self.outstanding_mem_request.clone().unwrap();
 
         self.recursion = 0;
@@ -398,15 +472,15 @@ impl Handler<N64Actors, Box<BusPair>> for CpuActor {
     }
 }
 
-impl Handler<N64Actors, c_bus::Resource> for CpuActor {
-    fn recv(&mut self, outbox: &mut CpuOutbox, resource: c_bus::Resource, time: Time, limit: Time) -> SchedulerResult {
+impl Handler<N64Actors, Grant<RspMem>> for CpuActor {
+    fn recv(&mut self, outbox: &mut CpuOutbox, Grant(mem): Grant<RspMem>, time: Time, limit: Time) -> SchedulerResult {
         use c_bus::RegBusResult;
 
         self.recursion = 0; // Reset recursion
         let bus = self.bus.as_mut().expect("Should own Bus");
 
         // If a resource was requested, we must own c_bus
-        match bus.c_bus.receive_resource(outbox, resource, time) {
+        match bus.c_bus.receive_rsp_mem(outbox, mem, time) {
             RegBusResult::WriteCompleted => {
                 self.finish_write32(outbox, time, limit)
             }
@@ -429,16 +503,14 @@ impl Handler<N64Actors, ReturnBus> for CpuActor {
     fn recv(&mut self, outbox: &mut Self::OutboxType, _: ReturnBus, time: Time, _limit: Time) -> SchedulerResult {
         outbox.stash(&mut self.interrupted_msg);
 
-        outbox.send::<BusActor>(self.bus.take().unwrap(), time)
+        outbox.send::<BusActor>(Release(self.bus.take().unwrap()), time)
     }
 }
 
-impl Handler<N64Actors, c_bus::ResourceReturnRequest> for CpuActor {
-    fn recv(&mut self, outbox: &mut CpuOutbox, request: c_bus::ResourceReturnRequest, time: Time, _limit: Time) -> SchedulerResult {
+impl Handler<N64Actors, Preempt<RspMem>> for CpuActor {
+    fn recv(&mut self, outbox: &mut CpuOutbox, _: Preempt<RspMem>, time: Time, _limit: Time) -> SchedulerResult {
         let bus = self.bus.as_mut().expect("Should own CBus");
-        bus.c_bus.return_resource(outbox, request, time);
-
-        SchedulerResult::Ok
+        bus.c_bus.return_rsp_mem(outbox, time)
     }
 }
 
@@ -1,5 +1,7 @@
-use actor_framework::Named;
-use crate::N64Config;
+use actor_framework::{Grant, Named, Preempt, Release};
+use crate::{c_bus::{CBusRead, CBusWrite, ReadFinished, WriteFinished}, N64Config};
+use bus_actor::{BusRequest, GrantBus, ReleaseBus, ReturnBus};
+use rsp_actor::{RspMem, RspMemRequest};
 
 pub mod cpu_actor;
 pub mod pif_actor;
@@ -16,25 +18,28 @@ pub mod ri_actor;
 #[derive(Named)]
 #[named(base(actor_framework::ActorBox), config(N64Config))]
 pub enum N64Actors {
-    #[named(class(cpu_actor::CpuActor))]
+    #[named(class(cpu_actor::CpuActor), handles(ReadFinished, WriteFinished, cpu_actor::CpuRun,
+        cpu_actor::CpuReset, GrantBus, ReturnBus, Grant<RspMem>, Preempt<RspMem>))]
     CpuActor,
-    #[named(class(pif_actor::PifActor))]
+    #[named(class(pif_actor::PifActor), handles(si_actor::SiPacket, pif_actor::PifHleMain))]
     PifActor,
-    #[named(class(si_actor::SiActor))]
+    #[named(class(si_actor::SiActor), handles(CBusRead, CBusWrite, si_actor::SiPacket, GrantBus, ReturnBus))]
     SiActor,
-    #[named(class(bus_actor::BusActor))]
+    #[named(class(bus_actor::BusActor), handles(BusRequest, ReleaseBus))]
     BusActor,
-    #[named(class(rsp_actor::RspActor))]
+    #[named(class(rsp_actor::RspActor), handles(CBusRead, CBusWrite, RspMemRequest, Release<RspMem>))]
     RspActor,
-    #[named(class(pi_actor::PiActor))]
+    #[named(class(pi_actor::PiActor), handles(CBusRead, CBusWrite, pi_actor::PiRead, pi_actor::PiWrite,
+        pi_actor::DmaTransfer, GrantBus, ReturnBus))]
     PiActor,
-    #[named(class(vi_actor::ViActor))]
+    #[named(class(vi_actor::ViActor), handles(CBusRead, CBusWrite))]
     ViActor,
-    #[named(class(ai_actor::AiActor))]
+    #[named(class(ai_actor::AiActor), handles(CBusRead, CBusWrite, ai_actor::AiDma, ai_actor::AiBufferDone,
+        GrantBus, ReturnBus))]
     AiActor,
-    #[named(class(rdp_actor::RdpActor))]
+    #[named(class(rdp_actor::RdpActor), handles(CBusRead, CBusWrite))]
     RdpActor,
-    #[named(class(ri_actor::RiActor))]
+    #[named(class(ri_actor::RiActor), handles(CBusRead, CBusWrite))]
     RiActor,
     #[named(terminal)]
     Terminal,
@@ -1,13 +1,11 @@
 
 
-use std::any::TypeId;
-
 use actor_framework::*;
 use anyhow::Context;
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 use crate::{c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished}, d_bus::DBus, N64Config};
 
-use super::{N64Actors, cpu_actor::CpuActor, bus_actor::{BusPair, request_bus, ReturnBus, BusRequest, BusActor}};
+use super::{N64Actors, cpu_actor::CpuActor, bus_actor::{BusPair, request_bus, GrantBus, ReleaseBus, ReturnBus, BusRequest, BusActor}};
 
 pub struct PiActor {
     dram_addr: u32,
@@ -15,19 +13,24 @@ pub struct PiActor {
     wr_len: u32,
     rd_len: u32,
     dma_status: DmaStatus,
-    queued_dma_event: Time,
+    deferred: Deferred<N64Actors, PiOutbox>,
     domains: [PiDomain; 2],
     rom: Vec<u16>,
-    bus: Option<Box<BusPair>>,
+    pub(crate) bus: Option<Box<BusPair>>,
 }
 
+// The rom isn't saved, it's reloaded from the config
+impl_save_state!(PiActor {
+    dram_addr, cart_addr, wr_len, rd_len, dma_status, deferred, domains, bus, ..
+});
+
 make_outbox!(
     PiOutbox<N64Actors, PiActor> {
         finish_read: ReadFinished,
         finish_write: WriteFinished,
         dma: DmaTransfer,
         bus: BusRequest,
-        return_bus: Box<BusPair>,
+        return_bus: ReleaseBus,
     }
 );
 
@@ -51,7 +54,7 @@ impl ActorInit<N64Actors> for PiActor {
             wr_len: 0,
             rd_len: 0,
             dma_status: DmaStatus::Idle,
-            queued_dma_event: Time::MAX,
+            deferred: Deferred::new(),
             domains: Default::default(),
             rom,
             bus: None,
@@ -115,18 +118,10 @@ impl PiActor {
         }
     }
 
-    fn clear_outbox(&mut self, outbox: &mut PiOutbox) {
-        if let Some((dma_time, _)) = outbox.try_cancel::<DmaTransfer>() {
-            self.queued_dma_event = dma_time;
-        }
-        if let Some((bus_time, _)) = outbox.try_cancel::<BusRequest>() {
-            // TODO: This doesn't seem quite right, might need a better solution.
-            //       This happens when a CBusRead/CBusWrite lands on the same cycle
-            //       of a DmaTransfer event. The DmaTransfer runs first,
-            self.queued_dma_event = bus_time;
-        }
-
-        assert!(outbox.is_empty(), "outbox should be empty, but has {}", outbox.msg_type_name());
+    fn cancel_dma(&mut self, outbox: &mut PiOutbox) {
+        self.deferred.try_cancel::<DmaTransfer>(outbox);
+        // TODO: A BusRequest that has already been delivered will still return the bus
+        self.deferred.try_cancel::<BusRequest>(outbox);
     }
 }
 
@@ -134,26 +129,13 @@ impl Actor<N64Actors> for PiActor {
     type OutboxType = PiOutbox;
 
     #[inline(always)]
-    fn delivering<Message>(&mut self, outbox: &mut PiOutbox, _: &Message, time: Time)
-    where
-        Message: 'static,
-    {
-        if TypeId::of::<Message>() != TypeId::of::<DmaTransfer>() && self.queued_dma_event != Time::MAX {
-            let event_time = if time > self.queued_dma_event {
-                time
-            } else {
-                self.queued_dma_event
-            };
-            outbox.send::<Self>(DmaTransfer, event_time);
-            self.queued_dma_event = Time::MAX;
-        }
+    fn deferred(&mut self) -> Option<&mut Deferred<N64Actors, PiOutbox>> {
+        Some(&mut self.deferred)
     }
 }
 
 impl Handler<N64Actors, CBusWrite> for PiActor {
     fn recv(&mut self, outbox: &mut PiOutbox, message: CBusWrite, time: Time, _limit: Time) -> SchedulerResult {
-        self.clear_outbox(outbox);
-
         let data = message.data;
         let n = (message.address >> 3) as usize & 1;
         match message.address & 0x3c {
@@ -172,15 +154,17 @@ impl Handler<N64Actors, CBusWrite> for PiActor {
                 println!("PI write PI_WR_LEN = {:#010x}", data);
                 self.wr_len = data & 0x00ff_ffff;
                 self.dma_status = DmaStatus::Writing;
-                self.queued_dma_event = self.dma_event_time(time);
-                assert!(self.queued_dma_event != Time::MAX);
-                println!("  {} queued dma event at {}", time, self.queued_dma_event)
+                let event_time = self.dma_event_time(time);
+                assert!(event_time != Time::MAX);
+                println!("  {} queued dma event at {}", time, event_time);
+                self.cancel_dma(outbox);
+                self.deferred.send(outbox, |o| o.send::<Self>(DmaTransfer, event_time));
             }
             0x10 => { // PI_STATUS
                 println!("PI write PI_STATUS = {:#010x}", data);
                 if data & 0x1 != 0 {
                     println!("  reset dma");
-                    self.queued_dma_event = Time::MAX;
+                    self.cancel_dma(outbox);
                     self.dma_status = DmaStatus::Idle;
                 }
                 if data & 0x2 != 0 {
@@ -208,16 +192,12 @@ impl Handler<N64Actors, CBusWrite> for PiActor {
             }
             _ => unreachable!(),
         }
-        outbox.send::<CpuActor>(WriteFinished {}, time.add(1));
-
-        SchedulerResult::Ok
+        self.deferred.send(outbox, |o| o.send::<CpuActor>(WriteFinished {}, time.add(1)))
     }
 }
 
 impl Handler<N64Actors, CBusRead> for PiActor {
     fn recv(&mut self, outbox: &mut PiOutbox, message: CBusRead, time: Time, _limit: Time) -> SchedulerResult {
-        self.clear_outbox(outbox);
-
         let n = (message.address >> 3) as usize & 1;
         let data = match message.address & 0x3c {
             0x00 => { // PI_DRAM_ADDR
@@ -270,9 +250,7 @@ impl Handler<N64Actors, CBusRead> for PiActor {
             }
             _ => unreachable!(),
         };
-        outbox.send::<CpuActor>(ReadFinished { data }, time.add(1));
-
-        SchedulerResult::Ok
+        self.deferred.send(outbox, |o| o.send::<CpuActor>(ReadFinished { data }, time.add(1)))
     }
 }
 
@@ -312,9 +290,7 @@ impl Handler<N64Actors, PiRead> for PiActor {
 
         let cycles = domain.calc_cycles(addr, 2);
 
-        outbox.send::<CpuActor>(ReadFinished { data }, time.add(cycles + 1));
-
-        SchedulerResult::Ok
+        self.deferred.send(outbox, |o| o.send::<CpuActor>(ReadFinished { data }, time.add(cycles + 1)))
     }
 }
 
@@ -329,6 +305,8 @@ pub struct PiRead {
     cart_addr: u32,
 }
 
+impl_save_state!(PiRead { cart_addr });
+
 impl PiRead {
     pub fn new(cart_addr: u32) -> Self {
         Self {
@@ -343,6 +321,8 @@ pub struct PiWrite {
     data: u32,
 }
 
+impl_save_state!(PiWrite { cart_addr, data });
+
 impl PiWrite {
     pub fn new(cart_addr: u32, data: u32) -> Self {
         Self {
@@ -359,7 +339,11 @@ pub enum DmaStatus {
     Writing,
 }
 
-struct DmaTransfer;
+impl_save_state!(enum DmaStatus { Idle, Reading, Writing });
+
+pub(super) struct DmaTransfer;
+
+impl_save_state!(DmaTransfer {});
 
 impl PiActor {
 
@@ -442,7 +426,7 @@ impl PiActor {
 
         if self.dma_status != DmaStatus::Idle {
             let next_time = self.dma_event_time(time.add(transfer_count));
-            outbox.send::<Self>(DmaTransfer, next_time);
+            return self.deferred.send(outbox, |o| o.send::<Self>(DmaTransfer, next_time));
         }
 
         SchedulerResult::Ok
@@ -457,13 +441,13 @@ impl Handler<N64Actors, DmaTransfer> for PiActor {
                 self.bus = Some(bus);
                 result
             }
-            None => request_bus(outbox, time),
+            None => self.deferred.send(outbox, |o| request_bus(o, time)),
         }
     }
 }
 
-impl Handler<N64Actors, Box<BusPair>> for PiActor {
-    fn recv(&mut self, outbox: &mut PiOutbox, mut bus: Box<BusPair>, time: Time, _: Time) -> SchedulerResult
+impl Handler<N64Actors, GrantBus> for PiActor {
+    fn recv(&mut self, outbox: &mut PiOutbox, Grant(mut bus): GrantBus, time: Time, _: Time) -> SchedulerResult
     {
         let result = self.do_dma(outbox, &mut bus.d_bus, time);
         self.bus = Some(bus);
@@ -474,9 +458,9 @@ impl Handler<N64Actors, Box<BusPair>> for PiActor {
 
 impl Handler<N64Actors, ReturnBus> for PiActor {
     fn recv(&mut self, outbox: &mut PiOutbox, _: ReturnBus, time: Time, _: Time) -> SchedulerResult {
-        self.clear_outbox(outbox);
-
-        outbox.send::<BusActor>(self.bus.take().unwrap(), time)
+        // Any pending DmaTransfer stays deferred, and will request the bus again
+        let bus = self.bus.take().unwrap();
+        self.deferred.send(outbox, |o| o.send::<BusActor>(Release(bus), time))
     }
 }
 
@@ -488,6 +472,8 @@ struct PiDomain {
     release: u8,
 }
 
+impl_save_state!(PiDomain { latency, pulse_width, page_size, release });
+
 impl PiDomain {
     fn calc_cycles(&self, addr: u32, hwords: u64) -> u64 {
         let offset = (addr as u64 / 2) % (self.page_size as u64 + 1);
@@ -506,3 +492,45 @@ impl PiDomain {
         (bytes + offset).min(page_size) - offset
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The timings IPL2 programs for the cartridge domain
+    fn dom1() -> PiDomain {
+        PiDomain { latency: 0x40, pulse_width: 0x12, page_size: 0x07, release: 0x03 }
+    }
+
+    #[test]
+    fn calc_cycles_within_page() {
+        // Each halfword costs pulse_width + 1 + release + 1 cycles
+        assert_eq!(dom1().calc_cycles(0x1000_0000, 1), 23);
+        assert_eq!(dom1().calc_cycles(0x1000_0000, 7), 7 * 23);
+    }
+
+    #[test]
+    fn calc_cycles_page_crossing() {
+        // Every page boundary reached adds 14 + latency + 1 cycles
+        assert_eq!(dom1().calc_cycles(0x1000_0000, 8), 79 + 8 * 23);
+        assert_eq!(dom1().calc_cycles(0x1000_0000, 24), 3 * 79 + 24 * 23);
+
+        // Starting part way through a page, reaches the boundary sooner
+        assert_eq!(dom1().calc_cycles(0x1000_000c, 1), 23);
+        assert_eq!(dom1().calc_cycles(0x1000_000c, 2), 79 + 2 * 23);
+    }
+
+    #[test]
+    fn calc_cycles_zero_timings() {
+        let domain = PiDomain::default();
+        assert_eq!(domain.calc_cycles(0x1000_0000, 1), 15 + 2);
+        assert_eq!(domain.calc_cycles(0x1000_0000, 0), 0);
+    }
+
+    #[test]
+    fn clamped_bytes() {
+        assert_eq!(dom1().clamped_bytes(0x1000_0000, 8), 8);
+        assert_eq!(dom1().clamped_bytes(0x1000_0000, 64), 16);
+        assert_eq!(dom1().clamped_bytes(0x1000_000c, 64), 4);
+    }
+}
@@ -2,9 +2,10 @@
 /// PifActor: Emulates the SI (Serial Interface) and the connected PIF
 
 
-use actor_framework::{Actor, Time, Handler, make_outbox, OutboxSend, SchedulerResult, ActorInit};
-use anyhow::Context;
-use super::{N64Actors, si_actor::{SiPacket, SiActor}};
+use actor_framework::{Actor, Time, Handler, make_outbox, OutboxSend, SchedulerResult, ActorInit, Timers, Deferred};
+use anyhow::{bail, Context};
+use common::{impl_save_state, input::{PadState, SharedInput}};
+use super::{N64Actors, cpu_actor::{CpuActor, CpuReset}, si_actor::{SiPacket, SiActor}};
 
 use crate::{pif, cic, N64Config};
 
@@ -14,31 +15,57 @@ pub struct PifActor {
     addr: u16,
     burst: bool,
     enable_rom: bool,
+    /// The boot process has finished, so the reset button does something
+    reset_enabled: bool,
+    /// Latched by `soft_reset` until the HLE main loop gets to it
+    reset_button: bool,
+    /// Waiting to be sent to the CPU after the HLE main loop
+    cpu_reset: Option<CpuReset>,
     pif_core: pif::PifHle,
-    pif_time: Time,
     cic_core: cic::CicHle,
+    timers: Timers<N64Actors, PifOutbox>,
+    deferred: Deferred<N64Actors, PifOutbox>,
+    /// The controllers, from the config so they stay plugged in across power cycles
+    input: SharedInput,
 }
 
+impl_save_state!(PifActor {
+    pif_mem, state, addr, burst, enable_rom, reset_enabled, reset_button, cpu_reset, pif_core,
+    cic_core, timers, deferred, ..
+});
+
 make_outbox!(
     PifOutbox<N64Actors, PifActor> {
         si_packet: SiPacket,
         hle: PifHleMain,
+        cpu_reset: CpuReset,
     }
 );
 
 impl Actor<N64Actors> for PifActor {
     type OutboxType = PifOutbox;
 
-    #[inline(always)]
-    fn delivering<Message>(&mut self, outbox: &mut PifOutbox, _: &Message, _: Time) {
-        let time = self.pif_time;
-        outbox.send::<Self>(PifHleMain{}, time);
+    fn timers(&mut self) -> Option<&mut Timers<N64Actors, PifOutbox>> {
+        Some(&mut self.timers)
+    }
+
+    fn deferred(&mut self) -> Option<&mut Deferred<N64Actors, PifOutbox>> {
+        Some(&mut self.deferred)
+    }
+
+    fn soft_reset(&mut self) -> Result<(), anyhow::Error> {
+        if !self.reset_enabled {
+            bail!("The PIF ignores the reset button until it has finished booting");
+        }
+        self.reset_button = true;
+        Ok(())
     }
 }
 
 impl ActorInit<N64Actors> for PifActor {
-    fn init(config: &N64Config, outbox: &mut Self::OutboxType, time: Time) -> Result<PifActor, anyhow::Error> {
-        outbox.send::<Self>(PifHleMain{}, time);
+    fn init(config: &N64Config, _: &mut Self::OutboxType, time: Time) -> Result<PifActor, anyhow::Error> {
+        let mut timers = Timers::new();
+        timers.every(PifHleMain{}, time, pif::MAIN_PERIOD);
 
         let pif_rom = std::fs::read(&config.pif_data)
             .with_context(|| format!("Failed to read pif_rom from {}", config.pif_data.display()))?;
@@ -55,14 +82,36 @@ impl ActorInit<N64Actors> for PifActor {
             addr: 0,
             burst: false,
             enable_rom: true,
+            reset_enabled: false,
+            reset_button: false,
+            cpu_reset: None,
             pif_core: pif::PifHle::new(),
-            pif_time: 0.into(),
             cic_core: cic::CicHle::new(cic::CIC::Nus6102),
+            timers,
+            deferred: Deferred::new(),
+            input: config.input.clone(),
         })
     }
 }
 
 impl PifActor {
+    fn interrupt_a(&mut self, dir: pif::Dir) -> Result<(), anyhow::Error> {
+        let size = match self.burst {
+            true => pif::Size::Size64,
+            false => pif::Size::Size4,
+        };
+        let (pif_core, mut io) = PifHleIoProxy::split(self);
+        pif_core.interrupt_a(&mut io, dir, size)
+    }
+
+    /// The PIF carries on after a failed interrupt, so the run can be resumed once it's reported
+    fn after_interrupt(interrupt: Result<(), anyhow::Error>, result: SchedulerResult) -> SchedulerResult {
+        match interrupt {
+            Ok(()) => result,
+            Err(err) => SchedulerResult::Err(err),
+        }
+    }
+
     fn read_word(&mut self, addr: usize) -> u32 {
         let offet = addr & 0x1ff;
         match offet {
@@ -77,11 +126,11 @@ impl PifActor {
             let data: [u32; 16] = core::array::from_fn(|i| {
                 self.read_word(self.addr as usize + i)
             });
-            outbox.send::<SiActor>(SiPacket::Data64(data), time)
+            self.deferred.send(outbox, |o| o.send::<SiActor>(SiPacket::Data64(data), time))
         } else {
             let data = self.read_word(self.addr as usize);
             println!("PIF: Read {:08x} from {:04x}", data, self.addr);
-            outbox.send::<SiActor>(SiPacket::Data4(data), time)
+            self.deferred.send(outbox, |o| o.send::<SiActor>(SiPacket::Data4(data), time))
 
         }
     }
@@ -93,6 +142,16 @@ impl PifActor {
         }
         self.addr += 1;
     }
+
+    /// Read a byte of PIF RAM, for debuggers
+    pub(crate) fn peek_ram(&self, address: u32) -> u8 {
+        read_ram(&self.pif_mem, address)
+    }
+
+    /// Write a byte of PIF RAM, for debuggers
+    pub(crate) fn poke_ram(&mut self, address: u32, value: u8) {
+        write_ram(&mut self.pif_mem, address, value)
+    }
 }
 
 #[derive(Debug)]
@@ -102,67 +161,55 @@ enum PifState {
     WaitData,
 }
 
+impl_save_state!(enum PifState { WaitCmd, WaitAck, WaitData });
+
 impl Handler<N64Actors, SiPacket> for PifActor {
     fn recv(&mut self, outbox: &mut PifOutbox, message: SiPacket, time: Time, _limit: Time) -> SchedulerResult {
-        if outbox.contains::<PifHleMain>() {
-            let (_, _msg) : (_, PifHleMain) = outbox.cancel();
-        }
-
-        if outbox.contains::<SiPacket>() {
-            let (old_time, old_msg) : (_, SiPacket) = outbox.cancel();
-
+        if let Some((old_time, old_msg)) = self.deferred.try_cancel::<SiPacket>(outbox) {
             panic!("PIF: {:?} stompted {:?} during {:?} @ {}", message, old_msg, self.state, old_time);
         }
 
         match self.state {
             PifState::WaitCmd => {
 
-                let (dir, size) = match message {
+                match message {
                     SiPacket::Read4(addr) => {
                         self.addr = addr;
                         self.state = PifState::WaitAck;
                         self.burst = false;
-                        (pif::Dir::Read, pif::Size::Size4)
                     }
                     SiPacket::Read64(addr) => {
                         println!("PIF: Read64 {:04x}", addr);
                         self.addr = addr;
                         self.state = PifState::WaitAck;
                         self.burst = true;
-                        (pif::Dir::Read, pif::Size::Size64)
                     }
                     SiPacket::Write4(addr) => {
                         println!("PIF: Write4 {:04x}", addr);
                         self.addr = addr;
                         self.state = PifState::WaitData;
                         self.burst = false;
-                        (pif::Dir::Write, pif::Size::Size4)
                     }
                     SiPacket::Write64(addr) => {
                         self.addr = addr;
                         self.state = PifState::WaitData;
                         self.burst = true;
-                        (pif::Dir::Write, pif::Size::Size64)
                     }
                     _ => panic!("Unexpected message"),
-                };
-
-                //let (pif_core, mut io) = PifHleIoProxy::split(self);
-                let enable_rom = &mut self.enable_rom;
-                let cic_core = &mut self.cic_core;
+                }
 
-                let mut io = PifHleIoProxy {
-                    pif_mem: &mut self.pif_mem,
-                    enable_rom: enable_rom,
-                    cic_core: cic_core,
+                // Reads are handled before the data goes out, writes once it has arrived
+                let interrupt = match self.state {
+                    PifState::WaitAck => self.interrupt_a(pif::Dir::Read),
+                    _ => Ok(()),
                 };
-                self.pif_core.interrupt_a(&mut io, dir, size);
 
                 // HWTEST: UltraPIF inserts a 4 cycle delay here
                 //         But n64-systembench indicates it's more like 1800 cycles
                 //         This is chaotic, caused by how long it takes for the sm5 core to respond
                 //         to an interrupt and halt
-                outbox.send::<SiActor>(SiPacket::Ack, time.add(450 * 4))
+                let result = self.deferred.send(outbox, |o| o.send::<SiActor>(SiPacket::Ack, time.add(450 * 4)));
+                Self::after_interrupt(interrupt, result)
             }
             PifState::WaitAck => match message {
                 SiPacket::Ack => {
@@ -185,9 +232,10 @@ impl Handler<N64Actors, SiPacket> for PifActor {
                     _ => panic!("Unexpected message {:?}", message),
                 }
 
+                let interrupt = self.interrupt_a(pif::Dir::Write);
                 self.state = PifState::WaitCmd;
-                outbox.send::<SiActor
-                >(SiPacket::Finish, time)
+                let result = self.deferred.send(outbox, |o| o.send::<SiActor>(SiPacket::Finish, time));
+                Self::after_interrupt(interrupt, result)
             }
         }
     }
@@ -200,10 +248,27 @@ fn calc_address(address: u32) -> (usize, usize) {
     (word_offset as usize, shift as usize)
 }
 
+fn read_ram(pif_mem: &[u32; 512], address: u32) -> u8 {
+    let (offset, shift) = calc_address(address);
+
+    (pif_mem[offset] >> shift) as u8
+}
+
+fn write_ram(pif_mem: &mut [u32; 512], address: u32, value: u8) {
+    let (offset, shift) = calc_address(address);
+    let mask = !(0xff << shift);
+    pif_mem[offset] &= mask;
+    pif_mem[offset] |= (value as u32) << shift;
+}
+
 struct PifHleIoProxy<'a> {
     pif_mem: &'a mut [u32; 512],
     enable_rom: &'a mut bool,
+    reset_enabled: &'a mut bool,
+    reset_button: &'a mut bool,
+    cpu_reset: &'a mut Option<CpuReset>,
     cic_core: &'a mut cic::CicHle,
+    input: &'a SharedInput,
 }
 
 impl<'a> PifHleIoProxy<'a> {
@@ -211,7 +276,11 @@ impl<'a> PifHleIoProxy<'a> {
         let io = PifHleIoProxy {
             pif_mem: &mut actor.pif_mem,
             enable_rom: &mut actor.enable_rom,
+            reset_enabled: &mut actor.reset_enabled,
+            reset_button: &mut actor.reset_button,
+            cpu_reset: &mut actor.cpu_reset,
             cic_core: &mut actor.cic_core,
+            input: &actor.input,
         };
         (&mut actor.pif_core, io)
     }
@@ -219,24 +288,41 @@ impl<'a> PifHleIoProxy<'a> {
 
 impl pif::PifIO for PifHleIoProxy<'_> {
     fn read(&self, address: u32) -> u8 {
-        let (offset, shift) = calc_address(address);
-
-        (self.pif_mem[offset] >> shift) as u8
+        read_ram(self.pif_mem, address)
     }
 
     fn write(&mut self, address: u32, value: u8) {
-        let (offset, shift) = calc_address(address);
-        let mask = !(0xff << shift);
-        self.pif_mem[offset] &= mask;
-        self.pif_mem[offset] |= (value as u32) << shift;
+        write_ram(self.pif_mem, address, value)
+    }
+
+    fn controller(&self, port: usize) -> PadState {
+        self.input.port(port)
     }
 
     fn rom_lockout(&mut self) {
         *self.enable_rom = false;
     }
 
+    fn rom_enable(&mut self) {
+        *self.enable_rom = true;
+    }
+
     fn reset_enable(&mut self) {
-        // TODO
+        *self.reset_enabled = true;
+    }
+
+    fn reset_pressed(&mut self) -> bool {
+        std::mem::take(self.reset_button)
+    }
+
+    fn nmi(&mut self) {
+        // The button is ignored again until the next boot finishes
+        *self.reset_enabled = false;
+        *self.cpu_reset = Some(CpuReset::Nmi);
+    }
+
+    fn hold_reset(&mut self) {
+        *self.cpu_reset = Some(CpuReset::Hold);
     }
 
     fn cic_read(&mut self) -> u8 {
@@ -260,15 +346,21 @@ impl pif::PifIO for PifHleIoProxy<'_> {
     }
 }
 
-struct PifHleMain {}
+#[derive(Clone)]
+pub(super) struct PifHleMain {}
+
+impl_save_state!(PifHleMain {});
 
 impl Handler<N64Actors, PifHleMain> for PifActor {
     #[inline(always)]
-    fn recv(&mut self, _outbox: &mut PifOutbox,  _: PifHleMain, time: Time, _: Time) -> SchedulerResult {
+    fn recv(&mut self, outbox: &mut PifOutbox,  _: PifHleMain, time: Time, _: Time) -> SchedulerResult {
         let (pif_core, mut io) = PifHleIoProxy::split(self);
 
-        self.pif_time = pif_core.main(&mut io, time);
+        pif_core.main(&mut io, time);
 
-        SchedulerResult::Ok
+        match self.cpu_reset.take() {
+            Some(reset) => self.deferred.send(outbox, |o| o.send::<CpuActor>(reset, time)),
+            None => SchedulerResult::Ok,
+        }
     }
 }
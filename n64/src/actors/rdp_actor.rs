@@ -1,5 +1,6 @@
 
 use actor_framework::*;
+use common::impl_save_state;
 
 use crate::{N64Actors, c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished}};
 
@@ -10,6 +11,8 @@ pub struct RdpActor {
     end: u32,
 }
 
+impl_save_state!(RdpActor { start, end });
+
 make_outbox!(
     RdpOutbox<N64Actors, RdpActor> {
         finish_read: ReadFinished,
@@ -1,4 +1,5 @@
 use actor_framework::*;
+use common::impl_save_state;
 use crate::c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished};
 
 use super::{N64Actors, cpu_actor::CpuActor};
@@ -13,6 +14,8 @@ pub struct RiActor {
 
 }
 
+impl_save_state!(RiActor {});
+
 make_outbox!(
     RiOutbox<N64Actors, RiActor> {
         finish_read: ReadFinished,
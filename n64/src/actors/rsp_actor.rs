@@ -1,20 +1,27 @@
 use actor_framework::*;
-use crate::c_bus::{CBusRead, CBusWrite, self, WriteFinished, ReadFinished};
+use common::impl_save_state;
+use crate::c_bus::{CBusRead, CBusWrite, WriteFinished, ReadFinished};
 
 use super::{N64Actors, cpu_actor::CpuActor};
 
 pub struct RspActor {
     halted: bool,
     dma_busy: bool,
-    dmem_imem: Option<Box<[u32; 2048]>>,
+    dmem_imem: Resource<N64Actors, RspActor, RspMem>,
 }
 
+impl_save_state!(RspActor { halted, dma_busy, dmem_imem });
+
+/// DMEM and IMEM. The CPU borrows them, so CBus can access them without sending messages
+pub type RspMem = [u32; 0x800];
+pub type RspMemRequest = Acquire<N64Actors, RspActor, RspMem>;
+
 make_outbox!(
     RspOutbox<N64Actors, RspActor> {
         finish_read: ReadFinished,
         finish_write: WriteFinished,
-        send_mem: c_bus::Resource,
-        request_mem: c_bus::ResourceReturnRequest,
+        send_mem: Grant<RspMem>,
+        request_mem: Preempt<RspMem>,
     }
 );
 
@@ -25,13 +32,28 @@ impl Default for RspActor {
             //         enter the halted state immediately on a soft reset.
             halted: true,
             dma_busy: false,
-            dmem_imem: Some(Box::new([0; 2048])),
+            dmem_imem: Resource::new(Box::new([0; 0x800])),
         }
     }
 }
 
 impl Actor<N64Actors> for RspActor {
     type OutboxType = RspOutbox;
+
+    fn delivering<Message>(&mut self, outbox: &mut RspOutbox, _: &Message, time: Time)
+    where
+        Message: 'static,
+    {
+        self.dmem_imem.delivering::<Message>(outbox, time);
+    }
+
+    fn stats(&self, stats: &mut StatsGroup) {
+        self.dmem_imem.stats(stats);
+    }
+
+    fn reset_stats(&mut self) {
+        self.dmem_imem.reset_stats();
+    }
 }
 
 impl Handler<N64Actors, CBusRead> for RspActor {
@@ -152,31 +174,17 @@ impl Handler<N64Actors, CBusWrite> for RspActor {
     }
 }
 
-impl Handler<N64Actors, c_bus::Resource> for RspActor {
-    fn recv(&mut self, _outbox: &mut RspOutbox, message: c_bus::Resource, _time: Time, _limit: Time) -> SchedulerResult {
-
-        match message {
-            c_bus::Resource::RspMem(mem) => {
-                self.dmem_imem = Some(mem);
-            }
-            //_ => panic!("RSP received unexpected resource"),
-        }
-
+impl Handler<N64Actors, Release<RspMem>> for RspActor {
+    fn recv(&mut self, outbox: &mut RspOutbox, message: Release<RspMem>, time: Time, _limit: Time) -> SchedulerResult {
         // TODO: If the RSP is running, we need to continue it
-        SchedulerResult::Ok
+        self.dmem_imem.release(outbox, message, time)
     }
 }
 
-impl Handler<N64Actors, c_bus::ResourceRequest> for RspActor {
-    fn recv(&mut self, outbox: &mut RspOutbox, message: c_bus::ResourceRequest, time: Time, _limit: Time) -> SchedulerResult {
+impl Handler<N64Actors, RspMemRequest> for RspActor {
+    fn recv(&mut self, outbox: &mut RspOutbox, message: RspMemRequest, time: Time, _limit: Time) -> SchedulerResult {
         // TODO: calculate timings for when RSP is busy
         // TODO: Handle cases where the RSP DMA is active (which apparently corrupts CPU accesses)
-        match message {
-            c_bus::ResourceRequest::RspMem => {
-                outbox.send::<CpuActor>(c_bus::Resource::RspMem(self.dmem_imem.take().unwrap()), time);
-            }
-            //_ => panic!("RSP received unexpected resource request"),
-        }
-        SchedulerResult::Ok
+        self.dmem_imem.acquire(outbox, message, time)
     }
 }
@@ -1,16 +1,21 @@
 use std::any::TypeId;
 
-use actor_framework::{Actor, Handler, OutboxSend, SchedulerResult, Time, TimeQueue};
+use actor_framework::{Actor, ClockDomain, Deferred, Handler, OutboxSend, Release, SchedulerResult, Time};
+
+use common::impl_save_state;
 
 use crate::c_bus::{CBusRead, CBusWrite, ReadFinished, WriteFinished};
 
 use super::{
-    bus_actor::{request_bus, BusActor, BusPair, BusRequest, ReturnBus},
+    bus_actor::{request_bus, BusActor, BusPair, BusRequest, GrantBus, ReleaseBus, ReturnBus},
     cpu_actor::CpuActor,
     pif_actor::PifActor,
     N64Actors,
 };
 
+/// The serial link to the PIF runs at a quarter of the RCP clock
+pub const SI_CLOCK: ClockDomain = ClockDomain::new("SI", 1, 4);
+
 pub struct SiActor {
     buffer: [u32; 16],
     state: SiState,
@@ -18,11 +23,15 @@ pub struct SiActor {
     dram_address: u32,
     dma_active: bool,
     error: bool,
-    queue: TimeQueue<QueuedMessage>,
+    deferred: Deferred<N64Actors, SiOutbox>,
     queued_read: Option<u16>,
-    bus: Option<Box<BusPair>>,
+    pub(crate) bus: Option<Box<BusPair>>,
 }
 
+impl_save_state!(in_place SiActor {
+    buffer, state, next_state, dram_address, dma_active, error, deferred, queued_read, bus,
+});
+
 impl Default for SiActor {
     fn default() -> Self {
         SiActor {
@@ -32,7 +41,7 @@ impl Default for SiActor {
             dram_address: 0,
             dma_active: false,
             error: false,
-            queue: TimeQueue::new(),
+            deferred: Deferred::new(),
             queued_read: None,
             bus: None,
         }
@@ -42,23 +51,18 @@ impl Default for SiActor {
 actor_framework::make_outbox!(
     SiOutbox<N64Actors, SiActor> {
         bus: BusRequest,
-        return_bus: Box<BusPair>,
+        return_bus: ReleaseBus,
         si_packet: SiPacket,
         finish_read: ReadFinished,
         finish_write: WriteFinished,
     }
 );
 
-enum QueuedMessage {
-    SiPacket(SiPacket),
-    Bus,
-}
-
 impl Actor<N64Actors> for SiActor {
     type OutboxType = SiOutbox;
 
     #[inline(always)]
-    fn delivering<Message>(&mut self, outbox: &mut SiOutbox, _: &Message, _: Time)
+    fn delivering<Message>(&mut self, _: &mut SiOutbox, _: &Message, _: Time)
     where
         Message: 'static,
     {
@@ -67,33 +71,25 @@ impl Actor<N64Actors> for SiActor {
         {
             self.finish_bus();
         }
-        if let Some((time, msg)) = self.queue.pop() {
-            match msg {
-                QueuedMessage::SiPacket(packet) => {
-                    outbox.send::<PifActor>(packet, time);
-                }
-                QueuedMessage::Bus => {
-                    self.do_bus(outbox, time);
-                }
-            }
-        }
+    }
+
+    #[inline(always)]
+    fn deferred(&mut self) -> Option<&mut Deferred<N64Actors, SiOutbox>> {
+        Some(&mut self.deferred)
     }
 }
 
 impl SiActor {
     fn pif_read(&mut self, outbox: &mut SiOutbox, pif_addr: u16, time: Time) {
-        let mut time64: u64 = time.into();
-
-        // align with 4 cycle boundary
-        time64 = (time64 + 3) & !3;
-        time64 += 4 * 12; // The command packet is 11 bits long, with an extra start bit
+        // The command packet is 11 bits long, with an extra start bit
+        let packet_time = SI_CLOCK.add(time, 12);
 
-        println!("SI: PIF Read {:08x} at {}", pif_addr, time64);
+        println!("SI: PIF Read {:08x} at {}", pif_addr, u64::from(packet_time));
 
         self.next_state = SiState::CpuRead;
         self.state = SiState::WaitAck;
 
-        outbox.send::<PifActor>(SiPacket::Read4(pif_addr), time64.into());
+        self.deferred.send(outbox, |o| o.send::<PifActor>(SiPacket::Read4(pif_addr), packet_time));
     }
 
     fn req_time(&self, time: Time) -> Time {
@@ -164,12 +160,7 @@ impl Handler<N64Actors, CBusRead> for SiActor {
                     }
                     _ => unreachable!(),
                 };
-                if outbox.contains::<SiPacket>() {
-                    let (time, packet) = outbox.cancel();
-                    self.queue.push(time, QueuedMessage::SiPacket(packet));
-                }
-
-                outbox.send::<CpuActor>(ReadFinished {data}, time.add(4));
+                self.deferred.send(outbox, |o| o.send::<CpuActor>(ReadFinished {data}, time.add(4)));
             }
             0x1fc0_0000..=0x1fc0_07ff => {
                 // PIF ROM/RAM
@@ -248,31 +239,27 @@ impl Handler<N64Actors, CBusWrite> for SiActor {
                     }
                     _ => unreachable!(),
                 };
-                outbox.send::<CpuActor>(WriteFinished {}, time.add(4));
+                self.deferred.send(outbox, |o| o.send::<CpuActor>(WriteFinished {}, time.add(4)));
             }
             0x1fc0_0000..=0x1fc0_07ff => {
                 // PIF ROM/RAM
                 // Accept the write instantly
-                outbox.send::<CpuActor>(WriteFinished {}, time.add(4));
+                self.deferred.send(outbox, |o| o.send::<CpuActor>(WriteFinished {}, time.add(4)));
 
                 let pif_address = (address >> 2) as u16 & 0x1ff;
-                let mut time64: u64 = time.into();
-
-                // align with 4 cycle boundary
-                time64 = (time64 + 3) & !3;
-                time64 += 4 * 12; // The command packet is 11 bits long, with an extra start bit
+                // The command packet is 11 bits long, with an extra start bit
+                let packet_time = SI_CLOCK.add(time, 12);
 
-                println!("SI: Write {:08x} = {:08x} at {}", address, data, time64);
+                println!("SI: Write {:08x} = {:08x} at {}", address, data, u64::from(packet_time));
 
                 self.next_state = SiState::CpuWrite;
                 self.state = SiState::WaitAck;
                 self.buffer[15] = data;
 
-                // Queue this message for after we finish telling the cpu it's write finished
-                self.queue.push(
-                    time64.into(),
-                    QueuedMessage::SiPacket(SiPacket::Write4(pif_address)),
-                );
+                // Goes out after we finish telling the cpu it's write finished
+                self.deferred.send(outbox, |o| {
+                    o.send::<PifActor>(SiPacket::Write4(pif_address), packet_time)
+                });
             }
             0x1fc0_0800..=0x1fcf_ffff => {
                 // Reserved SI range... not sure what should happen here
@@ -297,6 +284,17 @@ pub enum SiPacket {
     Finish, // Not a real packet, just used to track when the write to PIF finishes
 }
 
+impl_save_state!(enum SiPacket {
+    Read4(addr),
+    Read64(addr),
+    Write4(addr),
+    Write64(addr),
+    Ack,
+    Data4(data),
+    Data64(data),
+    Finish,
+});
+
 // Handle responses from PIF
 impl Handler<N64Actors, SiPacket> for SiActor {
     fn recv(
@@ -313,15 +311,16 @@ impl Handler<N64Actors, SiPacket> for SiActor {
                 req_time = time.add(4);
                 self.state = match self.next_state {
                     SiState::CpuRead => {
-                        outbox.send::<PifActor>(SiPacket::Ack, req_time);
+                        self.deferred.send(outbox, |o| o.send::<PifActor>(SiPacket::Ack, req_time));
                         SiState::CpuRead
                     }
                     SiState::CpuWrite => {
-                        outbox.send::<PifActor>(SiPacket::Data4(self.buffer[15]), req_time);
+                        let data = self.buffer[15];
+                        self.deferred.send(outbox, |o| o.send::<PifActor>(SiPacket::Data4(data), req_time));
                         SiState::CpuWrite
                     }
                     SiState::DmaRead(count) => {
-                        outbox.send::<PifActor>(SiPacket::Ack, req_time);
+                        self.deferred.send(outbox, |o| o.send::<PifActor>(SiPacket::Ack, req_time));
                         SiState::DmaRead(count)
                     }
                     _ => unimplemented!(),
@@ -366,35 +365,36 @@ enum SiState {
     WaitAck,
 }
 
-impl Handler<N64Actors, Box<BusPair>> for SiActor {
+impl_save_state!(enum SiState { CpuRead, CpuWrite, DmaRead(count), DmaWrite(count), Idle, WaitAck });
+
+impl Handler<N64Actors, GrantBus> for SiActor {
     fn recv(
         &mut self,
         outbox: &mut SiOutbox,
-        bus: Box<BusPair>,
+        grant: GrantBus,
         time: Time,
         _limit: Time,
     ) -> SchedulerResult {
-        self.bus = Some(bus);
+        self.bus = Some(grant.0);
         self.do_bus(outbox, time)
     }
 }
 
 impl SiActor {
-    fn do_bus(&self, outbox: &mut SiOutbox, time: Time) -> SchedulerResult {
-        let _ = match self.bus.as_ref() {
-            Some(bus) => bus,
-            None => return request_bus(outbox, time),
-        };
+    fn do_bus(&mut self, outbox: &mut SiOutbox, time: Time) -> SchedulerResult {
+        if self.bus.is_none() {
+            return self.deferred.send(outbox, |o| request_bus(o, time));
+        }
 
         match self.state {
             SiState::CpuRead => {
                 let data = self.buffer[15];
-                outbox.send::<CpuActor>(ReadFinished {data}, time)
+                self.deferred.send(outbox, |o| o.send::<CpuActor>(ReadFinished {data}, time))
             }
             SiState::DmaRead(1) => {
                 unimplemented!("Write to RDRAM");
             }
-            SiState::CpuWrite => outbox.send::<CpuActor>(WriteFinished {}, time),
+            SiState::CpuWrite => self.deferred.send(outbox, |o| o.send::<CpuActor>(WriteFinished {}, time)),
             SiState::DmaWrite(1) => {
                 unimplemented!("Read from RDRAM");
                 // let data_msg = match self.burst {
@@ -453,17 +453,113 @@ impl Handler<N64Actors, ReturnBus> for SiActor {
         time: Time,
         _limit: Time,
     ) -> SchedulerResult {
-        if outbox.contains::<ReadFinished>() {
-            let (time, _) : (Time, ReadFinished) = outbox.cancel();
-            self.queue.push(time, QueuedMessage::Bus);
-        }
-        if outbox.contains::<WriteFinished>() {
-            let (time, _) : (Time, WriteFinished) = outbox.cancel();
-            self.queue.push(time, QueuedMessage::Bus);
-        } else if outbox.contains::<SiPacket>() {
-            let (time, packet) = outbox.cancel();
-            self.queue.push(time, QueuedMessage::SiPacket(packet));
+        // A pending bus response can't be delivered without the bus, ask for it back
+        let interrupted = self.deferred.try_cancel::<ReadFinished>(outbox).map(|(time, _)| time)
+            .or_else(|| self.deferred.try_cancel::<WriteFinished>(outbox).map(|(time, _)| time));
+
+        let bus = self.bus.take().unwrap();
+        let result = self.deferred.send(outbox, |o| o.send::<BusActor>(Release(bus), time));
+
+        match interrupted {
+            Some(interrupted_time) => self.deferred.send(outbox, |o| request_bus(o, interrupted_time)),
+            None => result,
         }
-        outbox.send::<BusActor>(self.bus.take().unwrap(), time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actor_framework::{ActorHarness, Grant};
+
+    use crate::{c_bus::CBus, d_bus::DBus};
+
+    use super::*;
+
+    const PIF_RAM_LAST: u32 = 0x1fc0_07fc;
+
+    fn bus() -> GrantBus {
+        Grant(Box::new(BusPair { c_bus: CBus::new(), d_bus: DBus::new() }))
+    }
+
+    #[test]
+    fn pif_read() {
+        let mut si = ActorHarness::<N64Actors, SiActor>::new(SiActor::default());
+
+        si.deliver(CBusRead { address: PIF_RAM_LAST }, Time::from(101), Time::MAX);
+        // Aligned to 4 cycles, then 12 bits of command
+        let packet = si.expect::<SiPacket>(N64Actors::PifActor, Time::from(104 + 48));
+        assert!(matches!(packet, SiPacket::Read4(0x1ff)), "{:?}", packet);
+        assert!(si.is_empty());
+
+        si.deliver(SiPacket::Ack, Time::from(200), Time::MAX);
+        let packet = si.expect::<SiPacket>(N64Actors::PifActor, Time::from(204));
+        assert!(matches!(packet, SiPacket::Ack), "{:?}", packet);
+
+        // PIF responds instantly, SI adds the transfer time before it needs the bus
+        si.deliver(SiPacket::Data4(0x1234_5678), Time::from(300), Time::MAX);
+        si.expect::<BusRequest>(N64Actors::BusActor, Time::from(300 + 133));
+
+        si.deliver(bus(), Time::from(434), Time::MAX);
+        let finished = si.expect::<ReadFinished>(N64Actors::CpuActor, Time::from(434));
+        assert_eq!(finished.data, 0x1234_5678);
+        assert!(matches!(si.actor.state, SiState::Idle));
+    }
+
+    #[test]
+    fn pif_write() {
+        let mut si = ActorHarness::<N64Actors, SiActor>::new(SiActor::default());
+
+        si.deliver(CBusWrite { address: PIF_RAM_LAST, data: 0xdead_beef }, Time::from(100), Time::MAX);
+        // The CPU is released first, the write continues in the background
+        si.expect::<WriteFinished>(N64Actors::CpuActor, Time::from(104));
+        let packet = si.expect::<SiPacket>(N64Actors::PifActor, Time::from(148));
+        assert!(matches!(packet, SiPacket::Write4(0x1ff)), "{:?}", packet);
+
+        si.deliver(SiPacket::Ack, Time::from(200), Time::MAX);
+        let packet = si.expect::<SiPacket>(N64Actors::PifActor, Time::from(204));
+        assert!(matches!(packet, SiPacket::Data4(0xdead_beef)), "{:?}", packet);
+
+        si.deliver(SiPacket::Finish, Time::from(300), Time::MAX);
+        assert!(si.is_empty());
+        assert!(matches!(si.actor.state, SiState::Idle));
+    }
+
+    #[test]
+    fn read_while_busy_is_queued() {
+        let mut si = ActorHarness::<N64Actors, SiActor>::new(SiActor::default());
+
+        si.deliver(CBusWrite { address: PIF_RAM_LAST, data: 1 }, Time::from(100), Time::MAX);
+        si.expect::<WriteFinished>(N64Actors::CpuActor, Time::from(104));
+        si.deliver(CBusRead { address: PIF_RAM_LAST - 4 }, Time::from(110), Time::MAX);
+
+        // The read waits for the write to finish
+        si.expect::<SiPacket>(N64Actors::PifActor, Time::from(148));
+        si.deliver(SiPacket::Ack, Time::from(200), Time::MAX);
+        si.expect::<SiPacket>(N64Actors::PifActor, Time::from(204));
+        si.deliver(SiPacket::Finish, Time::from(300), Time::MAX);
+
+        let packet = si.expect::<SiPacket>(N64Actors::PifActor, Time::from(348));
+        assert!(matches!(packet, SiPacket::Read4(0x1fe)), "{:?}", packet);
+    }
+
+    #[test]
+    fn bus_returned_mid_response() {
+        let mut si = ActorHarness::<N64Actors, SiActor>::new(SiActor::default());
+
+        si.deliver(CBusRead { address: PIF_RAM_LAST }, Time::from(100), Time::MAX);
+        si.expect::<SiPacket>(N64Actors::PifActor, Time::from(148));
+        si.deliver(SiPacket::Ack, Time::from(200), Time::MAX);
+        si.expect::<SiPacket>(N64Actors::PifActor, Time::from(204));
+        si.deliver(SiPacket::Data4(7), Time::from(300), Time::MAX);
+        si.expect::<BusRequest>(N64Actors::BusActor, Time::from(433));
+        si.deliver(bus(), Time::from(434), Time::MAX);
+
+        // Someone else wants the bus before our response goes out
+        si.deliver(ReturnBus::default(), Time::from(434), Time::MAX);
+        si.expect::<ReleaseBus>(N64Actors::BusActor, Time::from(434));
+        si.expect::<BusRequest>(N64Actors::BusActor, Time::from(434));
+
+        si.deliver(bus(), Time::from(440), Time::MAX);
+        assert_eq!(si.expect::<ReadFinished>(N64Actors::CpuActor, Time::from(440)).data, 7);
     }
 }
@@ -1,4 +1,5 @@
 use actor_framework::*;
+use common::impl_save_state;
 use crate::{c_bus::{CBusWrite, CBusRead, ReadFinished, WriteFinished}, vi::{control::*, ViCore}};
 
 use super::{N64Actors, cpu_actor::CpuActor};
@@ -14,6 +15,10 @@ pub struct ViActor {
     _vi_core: ViCore,
 }
 
+impl_save_state!(in_place ViActor {
+    ctrl, fb_origin, fb_width, v_intr, output_format, dirty, _vi_core,
+});
+
 make_outbox!(
     ViOutbox<N64Actors, ViActor> {
         finish_read: ReadFinished,
@@ -1,6 +1,8 @@
-use actor_framework::{OutboxSend, Time, Handler, Outbox};
+use actor_framework::{OutboxSend, Time, SaveState, RestoreState, StateWriter, StateReader, Release, SchedulerResult, Handler};
+use anyhow::bail;
+use common::impl_save_state;
 
-use crate::{N64Actors, actors::{cpu_actor::{CpuOutbox, CpuActor}, rsp_actor::RspActor, rdp_actor::RdpActor, vi_actor::ViActor, ai_actor::AiActor, pi_actor::{PiActor, PiRead, PiWrite}, si_actor::SiActor, ri_actor::RiActor}};
+use crate::{N64Actors, actors::{cpu_actor::{CpuOutbox, CpuActor}, rsp_actor::{RspActor, RspMem, RspMemRequest}, rdp_actor::RdpActor, vi_actor::ViActor, ai_actor::AiActor, pi_actor::{PiActor, PiRead, PiWrite}, si_actor::SiActor, ri_actor::RiActor}};
 
 /// CBus covers all devices that the CPU can access, other than RDRAM
 /// This includes all MMIO mapped registers and mapped memory (RSP DMEM/IMEM, Cartridge ROM, Pif RAM)
@@ -16,10 +18,12 @@ use crate::{N64Actors, actors::{cpu_actor::{CpuOutbox, CpuActor}, rsp_actor::Rsp
 /// without having to send messages.
 ///
 pub struct CBus {
-    dmem_imem: Option<Box<[u32; 0x800]>>,
+    dmem_imem: Option<Box<RspMem>>,
     outstanding_request: Option<Outstanding>,
 }
 
+impl_save_state!(CBus { dmem_imem, outstanding_request });
+
 pub enum RegBusResult {
     /// The read completed instantly, no further action is needed
     ReadCompleted(u32),
@@ -36,6 +40,47 @@ enum Outstanding {
     Write(WriteFn, u32, u32),
 }
 
+// Handlers are saved as their index in HANDLERS
+impl SaveState for Outstanding {
+    fn save_state(&self, state: &mut StateWriter) {
+        match self {
+            Outstanding::Read(read_fn, address) => {
+                0u8.save_state(state);
+                let index = HANDLERS.iter().position(|(f, _)| std::ptr::fn_addr_eq(*f, *read_fn));
+                index.expect("read_fn isn't in HANDLERS").save_state(state);
+                address.save_state(state);
+            }
+            Outstanding::Write(write_fn, address, data) => {
+                1u8.save_state(state);
+                let index = HANDLERS.iter().position(|(_, f)| std::ptr::fn_addr_eq(*f, *write_fn));
+                index.expect("write_fn isn't in HANDLERS").save_state(state);
+                address.save_state(state);
+                data.save_state(state);
+            }
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for Outstanding {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        let kind = u8::restore_state(state)?;
+        let index = usize::restore_state(state)?;
+        let Some((read_fn, write_fn)) = HANDLERS.get(index) else {
+            bail!("Invalid CBus handler {} in save state", index);
+        };
+        match kind {
+            0 => Ok(Outstanding::Read(*read_fn, u32::restore_state(state)?)),
+            1 => Ok(Outstanding::Write(*write_fn, u32::restore_state(state)?, u32::restore_state(state)?)),
+            _ => bail!("Invalid CBus request {} in save state", kind),
+        }
+    }
+}
+
 enum HandlerResult {
     ReadCompleted(u32),
     WriteCompleted,
@@ -44,32 +89,6 @@ enum HandlerResult {
     Incomplete(Outstanding),
 }
 
-pub enum Resource {
-    RspMem(Box<[u32; 2048]>),
-}
-
-pub enum ResourceRequest {
-    RspMem,
-}
-
-pub struct ResourceReturnRequest {
-    request: ResourceRequest,
-    //channel: Channel<N64Actors, CpuActor, Resource>,
-}
-
-impl ResourceReturnRequest {
-    pub fn request<Out>(outbox: &mut Out, request: ResourceRequest, time: Time)
-    where
-        Out: Outbox<N64Actors> + OutboxSend<N64Actors, ResourceReturnRequest>,
-        <Out as Outbox<N64Actors>>::Sender: Handler<N64Actors, Resource>,
-    {
-        outbox.send::<CpuActor>(Self {
-            request,
-            //channel: Channel::<N64Actors, CpuActor, Resource>::new::<Out::Sender>(),
-        }, time);
-    }
-}
-
 pub struct CBusRead {
     pub address: u32
 }
@@ -85,6 +104,11 @@ pub struct ReadFinished {
 
 pub struct WriteFinished;
 
+impl_save_state!(CBusRead { address });
+impl_save_state!(CBusWrite { address, data });
+impl_save_state!(ReadFinished { data });
+impl_save_state!(WriteFinished {});
+
 impl CBus {
     pub fn new() -> Self {
         Self {
@@ -154,12 +178,9 @@ impl CBus {
         }
     }
 
-    pub fn receive_resource(&mut self, outbox: &mut CpuOutbox, resource: Resource, time: Time) -> RegBusResult {
-        match resource {
-            Resource::RspMem(mem) => {
-                self.dmem_imem = Some(mem);
-            }
-        }
+    pub fn receive_rsp_mem(&mut self, outbox: &mut CpuOutbox, mem: Box<RspMem>, time: Time) -> RegBusResult {
+        self.dmem_imem = Some(mem);
+
         match self.outstanding_request.take().unwrap() {
             Outstanding::Read(read_fn, address) => {
                 let result = (read_fn)(self, outbox, address, time);
@@ -172,12 +193,8 @@ impl CBus {
         }
     }
 
-    pub fn return_resource(&mut self, outbox: &mut CpuOutbox, request: ResourceReturnRequest, time: Time) {
-        match request.request {
-            ResourceRequest::RspMem => {
-                outbox.send::<RspActor>(Resource::RspMem(self.dmem_imem.take().unwrap()), time);
-            }
-        }
+    pub fn return_rsp_mem(&mut self, outbox: &mut CpuOutbox, time: Time) -> SchedulerResult {
+        outbox.send::<RspActor>(Release(self.dmem_imem.take().unwrap()), time)
     }
 }
 
@@ -204,6 +221,9 @@ static HANDLERS : [(ReadFn, WriteFn); 16] = [
     (read_unmapped, write_unmapped),
 ];
 
+/// Only the CPU borrows RSP memory for now
+const CPU_RSP_MEM_PRIORITY: u16 = 1;
+
 fn read_rsp(resources: &mut CBus, outbox: &mut CpuOutbox, address: u32, time: Time) -> HandlerResult
 {
     match address >> 18 & 0x3 {
@@ -214,7 +234,7 @@ fn read_rsp(resources: &mut CBus, outbox: &mut CpuOutbox, address: u32, time: Ti
                 HandlerResult::ReadCompleted(data)
             } else {
                 // We don't currently have ownership of imem/dmem, need to request it from RspActor
-                outbox.send::<RspActor>(ResourceRequest::RspMem, time);
+                outbox.send::<RspActor>(RspMemRequest::new::<CpuActor>(CPU_RSP_MEM_PRIORITY), time);
                 HandlerResult::Incomplete(Outstanding::Read(read_rsp, address))
             }
         }
@@ -238,7 +258,7 @@ fn write_rsp(resources: &mut CBus, outbox: &mut CpuOutbox, address: u32, data: u
                 HandlerResult::WriteCompleted
             } else {
                 // We don't currently have ownership of imem/dmem, need to request it from RspActor
-                outbox.send::<RspActor>(ResourceRequest::RspMem, time);
+                outbox.send::<RspActor>(RspMemRequest::new::<CpuActor>(CPU_RSP_MEM_PRIORITY), time);
                 HandlerResult::Incomplete(Outstanding::Write(write_rsp, address, data))
             }
         }
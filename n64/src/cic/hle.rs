@@ -1,21 +1,24 @@
 
 
-/// This is a quick and dirty HLE implementation of the CIC SM5 core
-/// I'm just wanting to get enough so I can finish booting, I'll come back to do SM5 LLE later
-///
-/// I'm mostly copying the implementation from Ares:
-///     Copyright (c) 2004-2021 ares team, Near et al
-///     Permission to use, copy, modify, and/or distribute this software for any
-///     purpose with or without fee is hereby granted, provided that the above
-///     copyright notice and this permission notice appear in all copies.
-///
-///     THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
-///     WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
-///     MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
-///     ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
-///     WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
-///     ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
-///     OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+// This is a quick and dirty HLE implementation of the CIC SM5 core
+// I'm just wanting to get enough so I can finish booting, I'll come back to do SM5 LLE later
+//
+// I'm mostly copying the implementation from Ares:
+//     Copyright (c) 2004-2021 ares team, Near et al
+//     Permission to use, copy, modify, and/or distribute this software for any
+//     purpose with or without fee is hereby granted, provided that the above
+//     copyright notice and this permission notice appear in all copies.
+//
+//     THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+//     WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+//     MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+//     ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+//     WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+//     ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+//     OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+
+use actor_framework::{RestoreState, SaveState, StateReader, StateWriter};
+use common::impl_save_state;
 
 use super::CIC;
 
@@ -45,6 +48,17 @@ impl Fifo {
     }
 }
 
+impl SaveState for Fifo {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.0.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        self.0 = Vec::restore_state(state)?;
+        Ok(())
+    }
+}
+
 pub struct CicHle {
     state: State,
     region: Region,
@@ -60,6 +74,11 @@ pub struct CicHle {
 enum State {
     BootRegion, BootSeed, BootChecksum, Run, Challenge, Dead
 }
+// Everything else is derived from the CIC model
+impl_save_state!(CicHle { state, fifo, .. });
+
+impl_save_state!(enum State { BootRegion, BootSeed, BootChecksum, Run, Challenge, Dead });
+
 enum Region { NTSC, PAL }
 enum ChallengeAlgo { DummyChallenge, RealChallenge }
 
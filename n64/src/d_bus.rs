@@ -1,4 +1,5 @@
-use common::util::ByteMask8;
+use actor_framework::{RestoreState, SaveState, StateReader};
+use common::{impl_save_state, util::ByteMask8};
 
 
 pub struct DBus {
@@ -7,11 +8,23 @@ pub struct DBus {
     mem_data: Box<[u64; 4 * 1024 * 1024 / 8]>, // 4MB
 }
 
+impl_save_state!(in_place DBus { banks, mem_data });
+
+impl RestoreState for DBus {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        // Load in place, to avoid putting 4MB on the stack
+        let mut d_bus = DBus::new();
+        d_bus.load_state(state)?;
+        Ok(d_bus)
+    }
+}
+
 impl DBus {
     pub fn new() -> Self {
         Self {
             banks: [RambusBank::new(), RambusBank::new(), RambusBank::new(), RambusBank::new()],
-            mem_data: Box::new([0; 4 * 1024 * 1024 / 8]),
+            // Allocate directly on the heap, Box::new would build it on the stack first
+            mem_data: vec![0; 4 * 1024 * 1024 / 8].into_boxed_slice().try_into().unwrap(),
         }
     }
 
@@ -59,6 +72,21 @@ impl DBus {
         let (cycles, mem) = self.access_column(addr);
         (cycles, *mem)
     }
+
+    /// Read a byte without touching the banks, for debuggers
+    pub fn peek(&self, addr: u32) -> u8 {
+        let offset = (addr as usize & 0x3fffff) >> 3;
+        let shift = (7 - (addr & 0x7)) * 8;
+        (self.mem_data[offset] >> shift) as u8
+    }
+
+    /// Write a byte without touching the banks, for debuggers
+    pub fn poke(&mut self, addr: u32, value: u8) {
+        let offset = (addr as usize & 0x3fffff) >> 3;
+        let shift = (7 - (addr & 0x7)) * 8;
+        let mem = &mut self.mem_data[offset];
+        *mem = (*mem & !(0xff << shift)) | (value as u64) << shift;
+    }
 }
 
 
@@ -67,6 +95,8 @@ pub struct RambusBank {
     dirty: bool,
 }
 
+impl_save_state!(RambusBank { sensed_row, dirty });
+
 impl RambusBank {
     pub fn new() -> Self {
         Self {
@@ -98,3 +128,19 @@ impl RambusBank {
         time
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_poke_match_qwords() {
+        let mut d_bus = DBus::new();
+        d_bus.write_qword(0x1000, 0x0011_2233_4455_6677);
+        assert_eq!(d_bus.peek(0x1000), 0x00);
+        assert_eq!(d_bus.peek(0x1007), 0x77);
+
+        d_bus.poke(0x1001, 0xab);
+        assert_eq!(d_bus.read_qword(0x1000).1, 0x00ab_2233_4455_6677);
+    }
+}
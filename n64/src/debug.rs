@@ -0,0 +1,169 @@
+use anyhow::bail;
+use actor_framework::StopReason;
+use common::debug::{AddressSpace, Cpu, Debuggable};
+use vr4300::instructions::MIPS_REG_NAMES;
+
+use crate::{actors::{
+    ai_actor::AiActor, bus_actor::{BusActor, BusPair}, cpu_actor::{CpuActor, PcReached}, pi_actor::PiActor,
+    pif_actor::PifActor, si_actor::SiActor,
+}, InstanceN64, N64Actors, CYCLES_PER_FIELD};
+
+const RDRAM: usize = 0;
+const PIF_RAM: usize = 1;
+
+const RDRAM_SIZE: u64 = 4 * 1024 * 1024;
+const PIF_RAM_SIZE: u64 = 64;
+
+impl InstanceN64 {
+    /// The bus is passed between actors, so it could be any of them that has it
+    fn bus(&mut self) -> Result<&mut BusPair, anyhow::Error> {
+        let borrower = self.actor::<BusActor>().bus.borrower();
+        let bus = match borrower {
+            None => self.actor::<BusActor>().bus.get_mut(),
+            Some(N64Actors::CpuActor) => self.actor::<CpuActor>().bus.as_deref_mut(),
+            Some(N64Actors::AiActor) => self.actor::<AiActor>().bus.as_deref_mut(),
+            Some(N64Actors::PiActor) => self.actor::<PiActor>().bus.as_deref_mut(),
+            Some(N64Actors::SiActor) => self.actor::<SiActor>().bus.as_deref_mut(),
+            Some(_) => None,
+        };
+        match bus {
+            Some(bus) => Ok(bus),
+            None => bail!("The bus is in transit to or from {:?}, step and try again", borrower),
+        }
+    }
+
+    fn check_cpu(cpu: usize) -> Result<(), anyhow::Error> {
+        match cpu {
+            0 => Ok(()),
+            _ => bail!("CPU {} doesn't exist, the N64 only has the VR4300", cpu),
+        }
+    }
+
+    fn check_access(&self, space: usize, address: u64, len: usize) -> Result<(), anyhow::Error> {
+        match self.address_spaces().get(space) {
+            Some(info) => info.check(address, len),
+            None => bail!("Address space {} doesn't exist", space),
+        }
+    }
+}
+
+fn register_index(name: &str) -> Result<usize, anyhow::Error> {
+    match MIPS_REG_NAMES.iter().position(|&reg| reg == name) {
+        Some(index) => Ok(index),
+        None => bail!("The VR4300 has no register called {}", name),
+    }
+}
+
+/// Only the VR4300 is exposed for now. The RSP would be the second CPU
+impl Debuggable for InstanceN64 {
+    fn cpus(&self) -> Vec<Cpu> {
+        let mut registers = MIPS_REG_NAMES.to_vec();
+        registers.push("pc");
+        vec![Cpu { name: "VR4300", registers }]
+    }
+
+    fn register(&mut self, cpu: usize, name: &str) -> Result<u64, anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        let cpu_core = &self.actor::<CpuActor>().cpu_core;
+        match name {
+            "pc" => Ok(cpu_core.pc()),
+            name => Ok(cpu_core.register(register_index(name)?)),
+        }
+    }
+
+    fn set_register(&mut self, cpu: usize, name: &str, value: u64) -> Result<(), anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        match name {
+            // Changing it would mean flushing the pipeline
+            "pc" => bail!("The pc can't be changed"),
+            "$zero" => bail!("$zero is always zero"),
+            name => {
+                let index = register_index(name)?;
+                self.actor::<CpuActor>().cpu_core.set_register(index, value);
+                Ok(())
+            }
+        }
+    }
+
+    fn address_spaces(&self) -> Vec<AddressSpace> {
+        vec![
+            AddressSpace { name: "RDRAM", size: RDRAM_SIZE },
+            AddressSpace { name: "PIF RAM", size: PIF_RAM_SIZE },
+        ]
+    }
+
+    fn peek(&mut self, space: usize, address: u64, data: &mut [u8]) -> Result<(), anyhow::Error> {
+        self.check_access(space, address, data.len())?;
+        match space {
+            RDRAM => {
+                let d_bus = &self.bus()?.d_bus;
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = d_bus.peek(address as u32 + i as u32);
+                }
+            }
+            PIF_RAM => {
+                let pif = self.actor::<PifActor>();
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = pif.peek_ram(address as u32 + i as u32);
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn poke(&mut self, space: usize, address: u64, data: &[u8]) -> Result<(), anyhow::Error> {
+        self.check_access(space, address, data.len())?;
+        match space {
+            RDRAM => {
+                let d_bus = &mut self.bus()?.d_bus;
+                for (i, &byte) in data.iter().enumerate() {
+                    d_bus.poke(address as u32 + i as u32, byte);
+                }
+            }
+            PIF_RAM => {
+                let pif = self.actor::<PifActor>();
+                for (i, &byte) in data.iter().enumerate() {
+                    pif.poke_ram(address as u32 + i as u32, byte);
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn breakpoints(&mut self, cpu: usize) -> Result<Vec<u64>, anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        Ok(self.actor::<CpuActor>().cpu_core.breakpoints().to_vec())
+    }
+
+    fn set_breakpoint(&mut self, cpu: usize, address: u64) -> Result<(), anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        self.actor::<CpuActor>().cpu_core.set_breakpoint(address);
+        Ok(())
+    }
+
+    fn clear_breakpoint(&mut self, cpu: usize, address: u64) -> Result<(), anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        match self.actor::<CpuActor>().cpu_core.clear_breakpoint(address) {
+            true => Ok(()),
+            false => bail!("There's no breakpoint at {:#x}", address),
+        }
+    }
+
+    /// The rest of the machine runs along with the CPU, until the pc changes
+    fn step(&mut self, cpu: usize) -> Result<(), anyhow::Error> {
+        Self::check_cpu(cpu)?;
+        self.actor::<CpuActor>().cpu_core.set_single_step(true);
+        let result = self.run_for(CYCLES_PER_FIELD);
+        self.actor::<CpuActor>().cpu_core.set_single_step(false);
+
+        match result {
+            Err(err) if err.is::<PcReached>() => Ok(()),
+            Err(err) => Err(err),
+            Ok(StopReason::ReachedTime(_)) => bail!("The CPU didn't move on within a field"),
+            // Stopped by one of the framework's breakpoints first
+            Ok(_) => Ok(()),
+        }
+    }
+}
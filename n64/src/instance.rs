@@ -0,0 +1,145 @@
+use std::{ops::{Deref, DerefMut}, path::Path, sync::mpsc};
+
+use common::{audio::AudioSink, debug::Debuggable, input::PadState, pacing::Speed, stats::Stats, ControlMessage, Progress, ResetKind, StopConditions, UpdateMessage};
+
+use crate::{actors::{ai_actor::AiActor, cpu_actor::{CpuActor, PcReached}}, N64Actors};
+
+/// The actor framework's instance, plus the parts of `common::Instance` that need to know
+/// about the N64's actors
+pub struct InstanceN64 {
+    instance: actor_framework::Instance<N64Actors>,
+    /// Reapplied after a power cycle, which recreates the CPU
+    stop: StopConditions,
+}
+
+impl InstanceN64 {
+    pub fn new(instance: actor_framework::Instance<N64Actors>) -> Self {
+        InstanceN64 { instance, stop: StopConditions::default() }
+    }
+}
+
+impl Deref for InstanceN64 {
+    type Target = actor_framework::Instance<N64Actors>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.instance
+    }
+}
+
+impl DerefMut for InstanceN64 {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.instance
+    }
+}
+
+impl common::Instance for InstanceN64 {
+    fn run(
+        &mut self,
+        control_rx: &mpsc::Receiver<ControlMessage>,
+        update: mpsc::SyncSender<UpdateMessage>,
+    ) -> Result<(), anyhow::Error> {
+        loop {
+            match self.instance.run_until_request(control_rx, update.clone()) {
+                Ok(Some(request)) => {
+                    if !request.reply(self, &update)? {
+                        return Ok(());
+                    }
+                }
+                Ok(None) => return Ok(()),
+                // Reaching the stop pc or a breakpoint is just another way of pausing
+                Err(err) if err.is::<PcReached>() => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn as_any(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn debuggable(&mut self) -> Option<&mut dyn Debuggable> {
+        Some(self)
+    }
+
+    fn set_speed(&mut self, speed: Speed) {
+        self.instance.set_speed(speed)
+    }
+
+    fn speed_ratio(&self) -> Option<f64> {
+        self.instance.speed_ratio()
+    }
+
+    /// The VI doesn't generate its own timing yet, so there are no vsyncs to stop at
+    fn set_stop_conditions(&mut self, stop: StopConditions) -> Result<(), anyhow::Error> {
+        if stop.vsyncs.is_some() {
+            anyhow::bail!("The VI doesn't produce vsyncs yet, stop after a number of cycles instead");
+        }
+        self.instance.set_stop_conditions(StopConditions { cycles: stop.cycles, ..StopConditions::default() })?;
+
+        // Addresses are sign extended, so KSEG0/KSEG1 addresses can be given as 32 bits
+        let pc = stop.pc.map(|pc| match u32::try_from(pc) {
+            Ok(pc) => pc as i32 as u64,
+            Err(_) => pc,
+        });
+        self.instance.actor::<CpuActor>().cpu_core.set_break_pc(pc);
+        self.stop = stop;
+        Ok(())
+    }
+
+    /// A soft reset presses the reset button, which the PIF turns into an NMI once the game has
+    /// finished booting. Until then, the press is ignored and returns an error.
+    fn reset(&mut self, kind: ResetKind) -> Result<(), anyhow::Error> {
+        // The sink and breakpoints move over to the new AI and CPU
+        let sink = self.instance.actor::<AiActor>().sink.take();
+        let breakpoints = self.instance.actor::<CpuActor>().cpu_core.breakpoints().to_vec();
+        self.instance.reset(kind)?;
+        self.instance.actor::<AiActor>().sink = sink;
+        if kind == ResetKind::PowerCycle {
+            self.set_stop_conditions(self.stop)?;
+            let cpu_core = &mut self.instance.actor::<CpuActor>().cpu_core;
+            for pc in breakpoints {
+                cpu_core.set_breakpoint(pc);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_input(&mut self, port: usize, pad: PadState) -> Result<(), anyhow::Error> {
+        common::Instance::set_input(&mut self.instance, port, pad)
+    }
+
+    fn save_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.instance.save_state(path)
+    }
+
+    fn load_state(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.instance.load_state(path)
+    }
+
+    fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) -> Result<Option<Box<dyn AudioSink>>, anyhow::Error> {
+        Ok(std::mem::replace(&mut self.instance.actor::<AiActor>().sink, sink))
+    }
+
+    fn progress(&mut self) -> Progress {
+        let cycles = self.instance.progress().cycles;
+        let cpu_core = &self.instance.actor::<CpuActor>().cpu_core;
+        Progress {
+            cycles,
+            vsyncs: None,
+            instructions: Some(cpu_core.instruction_count()),
+            pc: Some(cpu_core.pc()),
+        }
+    }
+
+    fn stats(&mut self) -> Stats {
+        self.instance.stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.instance.reset_stats()
+    }
+
+    fn profile_folded(&mut self) -> Option<String> {
+        self.instance.profile_folded()
+    }
+}
@@ -2,6 +2,8 @@
 pub mod actors;
 
 pub mod cic;
+mod debug;
+mod instance;
 pub mod pif;
 pub mod vi;
 mod c_bus;
@@ -10,27 +12,30 @@ mod d_bus;
 use std::{path::PathBuf, any::Any};
 
 pub use actors::N64Actors;
+pub use instance::InstanceN64;
 use clap::{Parser, FromArgMatches, Args};
+use common::{input::{InputLatch, SharedInput}, pacing::Pacer};
 
 pub struct CoreN64;
 
+/// Scheduler time is counted in RCP cycles
+pub const RCP_CLOCK: u64 = 62_500_000;
+/// NTSC fields per second
+const FIELD_RATE: u64 = 60;
+const CYCLES_PER_FIELD: u64 = RCP_CLOCK / FIELD_RATE;
+
 impl common::EmulationCore for CoreN64 {
     fn name(&self) -> &'static str { "Nintendo 64" }
     fn short_name(&self) -> &'static str { "n64" }
 
     fn new(&self, config: Box<dyn Any>) -> Result<Box<dyn common::Instance + Send>, anyhow::Error> {
         let config = config.downcast::<N64Config>().unwrap();
-        Ok(Box::new(actor_framework::Instance::<N64Actors>::new(*config)?))
-    }
-
-    #[cfg(feature = "ui")]
-    fn paused_ui(&self, instance: &mut dyn common::Instance, ui: &mut egui::Ui) {
-        use actors::cpu_actor::CpuActor;
-
-        let instance = instance.as_any().downcast_mut::<actor_framework::Instance<N64Actors>>().unwrap();
-
-        let cpu_core = &mut instance.actor::<CpuActor>().cpu_core;
-        cpu_core.ui(ui);
+        // Controllers only change between fields
+        let input = InputLatch::new(config.input.clone(), CYCLES_PER_FIELD);
+        let mut instance = actor_framework::Instance::<N64Actors>::new(*config)?;
+        instance.set_pacer(Some(Pacer::new(RCP_CLOCK, CYCLES_PER_FIELD)));
+        instance.set_input_latch(Some(input));
+        Ok(Box::new(InstanceN64::new(instance)))
     }
 }
 
@@ -52,6 +57,10 @@ pub struct N64Config {
     #[arg(long, default_value = "pifdata.bin")]
     #[clap(next_help_heading = "N64 Core Options")]
     pif_data: PathBuf,
+
+    /// Read by the PIF, and updated by the instance's input latch
+    #[arg(skip)]
+    input: SharedInput,
 }
 
 #[derive(Debug, Parser)]
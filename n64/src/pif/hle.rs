@@ -1,26 +1,27 @@
-/// I'm mostly copying this implementation from Ares:
-///     Copyright (c) 2004-2021 ares team, Near et al
-///     Permission to use, copy, modify, and/or distribute this software for any
-///     purpose with or without fee is hereby granted, provided that the above
-///     copyright notice and this permission notice appear in all copies.
-///
-///     THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
-///     WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
-///     MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
-///     ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
-///     WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
-///     ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
-///     OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
-
-
-/// This is a quick and dirty HLE implementation of the PIF SM5 core
-/// I'm just wanting to get enough so I can finish booting, I'll come back to do SM5 LLE later
-/// This doesn't implement joybus at all
+// I'm mostly copying this implementation from Ares:
+//     Copyright (c) 2004-2021 ares team, Near et al
+//     Permission to use, copy, modify, and/or distribute this software for any
+//     purpose with or without fee is hereby granted, provided that the above
+//     copyright notice and this permission notice appear in all copies.
+//
+//     THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+//     WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+//     MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+//     ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+//     WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+//     ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+//     OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+
+
+// This is a quick and dirty HLE implementation of the PIF SM5 core
+// I'm just wanting to get enough so I can finish booting, I'll come back to do SM5 LLE later
+// Joybus only supports standard controllers, see `joybus`
 
 
 use actor_framework::Time;
+use common::{impl_save_state, input::PadState};
 
-use super::{Dir, Size};
+use super::{joybus, Dir, Size};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
@@ -30,22 +31,57 @@ enum State {
     WaitCheckChecksum,
     WaitTerminateBoot,
     Run,
-    Error
+    /// The reset button was pressed, waiting to send the NMI
+    WaitNmi,
+    Error,
+    /// The CPU is held in reset until the console is power cycled
+    Halted,
 }
 
+impl_save_state!(enum State {
+    Init,
+    WaitLockout,
+    WaitGetChecksum,
+    WaitCheckChecksum,
+    WaitTerminateBoot,
+    Run,
+    WaitNmi,
+    Error,
+    Halted,
+});
+
 struct InternalRam {
     os_info: [u8; 3],
     cpu_checksum: [u8; 6],
     cic_checksum: [u8; 6],
     boot_timeout: Time,
-    _joy_address: [u8; 5],
+    /// Where each joybus channel's command is, see `joybus::scan`
+    joy_address: [u8; joybus::CHANNELS],
 }
 
+impl_save_state!(InternalRam { os_info, cpu_checksum, cic_checksum, boot_timeout, joy_address });
+
+/// How often the PIF's main loop runs, in RCP cycles
+pub const MAIN_PERIOD: u64 = 13653;
+
+/// Time between the reset button being pressed and the NMI, so games can finish what they're doing
+const NMI_DELAY: u64 = 250000000 / 4 / 2; // 0.5 seconds
+
 pub trait PifIO {
     fn read(&self, address: u32) -> u8;
     fn write(&mut self, address: u32, value: u8);
+    /// The controller plugged into `port`, as of the last input latch
+    fn controller(&self, port: usize) -> PadState;
     fn rom_lockout(&mut self);
+    fn rom_enable(&mut self);
+    /// Start listening to the reset button
     fn reset_enable(&mut self);
+    /// True once for each press of the reset button, after `reset_enable`
+    fn reset_pressed(&mut self) -> bool;
+    /// Soft reset the CPU
+    fn nmi(&mut self);
+    /// Hold the CPU in reset
+    fn hold_reset(&mut self);
 
     fn cic_poll(&mut self);
     fn cic_read(&mut self) -> u8;
@@ -57,8 +93,11 @@ pub trait PifIO {
 pub struct PifHle {
     state: State,
     internal_ram: InternalRam,
+    nmi_time: Time,
 }
 
+impl_save_state!(PifHle { state, internal_ram, nmi_time });
+
 impl dyn PifIO + '_ {
     fn swap(&mut self, addr: u32, other: &mut u8) {
         let mut data = self.read(addr);
@@ -92,8 +131,9 @@ impl PifHle {
                 cpu_checksum: [0; 6],
                 cic_checksum: [0; 6],
                 boot_timeout: Time::MAX,
-                _joy_address: [0; 5],
-            }
+                joy_address: [joybus::NO_COMMAND; joybus::CHANNELS],
+            },
+            nmi_time: Time::MAX,
         }
     }
 
@@ -106,8 +146,8 @@ impl PifHle {
         }
     }
 
-    pub fn main(&mut self, io: &mut dyn PifIO, time: Time) -> Time{
-        let next_time = time.add(13653);
+    /// Called every `MAIN_PERIOD` cycles
+    pub fn main(&mut self, io: &mut dyn PifIO, time: Time) {
         //println!("PIF: main {:?} cmd: {:02x} @ {}", self.state, io.read_command(), time);
         let initial_state = self.state;
 
@@ -119,7 +159,7 @@ impl PifHle {
                 if hello & 0x3 != 0x1 {
                     println!("PIF: invalid CIC hello: {:x}", hello);
                     self.state = State::Error;
-                    return next_time;
+                    return;
                 }
 
                 // TODO: region check
@@ -161,7 +201,8 @@ impl PifHle {
             }
             State::WaitCheckChecksum => {
                 if io.read_command() & 0x40 != 0 {
-                    if  true { // only on cold boot
+                    let warm_boot = self.internal_ram.os_info[0] & 0x01 != 0;
+                    if !warm_boot { // only on cold boot
                         io.cic_poll();
                         let mut buf = std::array::from_fn::<u8, 16, _>(|_| io.cic_read_nibble());
                         for _ in 0..4 {
@@ -181,7 +222,7 @@ impl PifHle {
                         println!("   CIC checksum: {:x?}", self.internal_ram.cic_checksum);
                         // TODO: Uncomment once IPL2 checksum is correct
                         //self.state = State::Error;
-                        //return next_time;
+                        //return;
                     } else {
                         println!("PIF: CIC checksum OK");
                     }
@@ -204,18 +245,36 @@ impl PifHle {
 
             }
             State::Run => {
-                // do nothing
+                if io.reset_pressed() {
+                    // TODO: Raise the pre-NMI interrupt once the CPU has interrupts
+                    self.nmi_time = time.add(NMI_DELAY);
+                    self.state = State::WaitNmi;
+                }
+            }
+            State::WaitNmi => {
+                if self.nmi_time <= time {
+                    // Boot again from the PIF ROM. The warm boot flag is already set, so the CIC
+                    // checksum from the cold boot gets reused
+                    io.rom_enable();
+                    io.nmi();
+                    self.swap_secrets(io);  //show osinfo+seeds in external memory
+                    io.write_command(0x00);
+                    self.nmi_time = Time::MAX;
+                    self.state = State::WaitLockout;
+                }
             }
 
             State::Error => {
-                todo!("Reset the CPU");
+                io.hold_reset();
+                self.state = State::Halted;
+            }
+            State::Halted => {
+                // do nothing
             }
         }
         if initial_state != self.state {
             println!("PIF: state {:?} -> {:?}", initial_state, self.state);
         }
-
-        return next_time;
     }
 
     fn challenge(io: &mut dyn PifIO) {
@@ -241,7 +300,8 @@ impl PifHle {
         }
     }
 
-    pub fn interrupt_a(&mut self, io: &mut dyn PifIO, dir: super::Dir, size: super::Size) {
+    /// Only fails for joybus commands that aren't supported, see `joybus::run`
+    pub fn interrupt_a(&mut self, io: &mut dyn PifIO, dir: super::Dir, size: super::Size) -> Result<(), anyhow::Error> {
         match dir {
             Dir::Read => {
                 match size {
@@ -250,7 +310,7 @@ impl PifHle {
                             Self::challenge(io);
                         }
                         else {
-                            todo!("Poll joybus")
+                            joybus::run(io, &self.internal_ram.joy_address)?;
                         }
                     }
                     Size::Size4 => { }
@@ -260,9 +320,91 @@ impl PifHle {
                 let cmd = io.read_command();
                 if cmd & 0x01 != 0 {
                     io.write_command(cmd & !0x01);
-                    todo!("configure joybus");
+                    self.internal_ram.joy_address = joybus::scan(io);
                 }
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestIo {
+        ram: Vec<u8>,
+        rom_enabled: bool,
+        reset_pressed: bool,
+        nmi: bool,
+        held: bool,
+    }
+
+    impl PifIO for TestIo {
+        fn read(&self, address: u32) -> u8 { self.ram[address as usize] }
+        fn write(&mut self, address: u32, value: u8) { self.ram[address as usize] = value; }
+        fn controller(&self, _: usize) -> PadState { PadState::default() }
+        fn rom_lockout(&mut self) { self.rom_enabled = false; }
+        fn rom_enable(&mut self) { self.rom_enabled = true; }
+        fn reset_enable(&mut self) { }
+        fn reset_pressed(&mut self) -> bool { std::mem::take(&mut self.reset_pressed) }
+        fn nmi(&mut self) { self.nmi = true; }
+        fn hold_reset(&mut self) { self.held = true; }
+
+        fn cic_poll(&mut self) { }
+        fn cic_read(&mut self) -> u8 { unreachable!() }
+        fn cic_read_nibble(&mut self) -> u8 { unreachable!() }
+        fn cic_write(&mut self, _: u8) { unreachable!() }
+        fn cic_write_nibble(&mut self, _: u8) { unreachable!() }
+    }
+
+    /// A PIF that has finished a cold boot
+    fn booted() -> PifHle {
+        let mut pif = PifHle::new();
+        pif.state = State::Run;
+        pif.internal_ram.os_info = [0x95, 0x3f, 0x3f];
+        pif.internal_ram.cic_checksum = [1, 2, 3, 4, 5, 6];
+        pif
+    }
+
+    #[test]
+    fn warm_boot() {
+        let mut pif = booted();
+        let mut io = TestIo { ram: vec![0; 64], ..TestIo::default() };
+
+        io.reset_pressed = true;
+        pif.main(&mut io, Time::from(1000));
+        assert_eq!(pif.state, State::WaitNmi);
+
+        // Nothing happens until the game has had time to react
+        pif.main(&mut io, Time::from(1000 + NMI_DELAY - 1));
+        assert!(!io.nmi);
+        pif.main(&mut io, Time::from(1000 + NMI_DELAY));
+        assert!(io.nmi && io.rom_enabled);
+        assert_eq!(pif.state, State::WaitLockout);
+        assert_eq!(io.ram[0x25] & 0x01, 0x01, "IPL should see the warm boot flag");
+
+        // IPL1 runs again, and the CIC isn't asked for the checksum this time (TestIo would panic)
+        let time = Time::from(2 * NMI_DELAY);
+        io.ram[0x3f] = 0x10;
+        pif.main(&mut io, time);
+        assert!(!io.rom_enabled);
+        io.ram[0x32..0x38].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        io.ram[0x3f] = 0x20;
+        pif.main(&mut io, time);
+        io.ram[0x3f] = 0x40;
+        pif.main(&mut io, time);
+        assert_eq!(pif.state, State::WaitTerminateBoot);
+    }
+
+    #[test]
+    fn boot_error_holds_cpu() {
+        let mut pif = PifHle::new();
+        let mut io = TestIo { ram: vec![0; 64], ..TestIo::default() };
+        pif.state = State::Error;
+        pif.main(&mut io, Time::from(0));
+        assert!(io.held);
+        assert_eq!(pif.state, State::Halted);
     }
 }
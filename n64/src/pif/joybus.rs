@@ -0,0 +1,201 @@
+//! Joybus commands, as run by the PIF on behalf of the CPU
+//!
+//! Only standard controllers are supported, with nothing in the pak slot. The cartridge channel
+//! (EEPROM) never responds.
+
+use anyhow::bail;
+use common::input::{Axis, Button, PadState, MAX_PORTS};
+
+use super::PifIO;
+
+/// One channel for each controller port, then the cartridge
+pub const CHANNELS: usize = 5;
+
+/// Marks channels with no command in `scan`'s result
+pub const NO_COMMAND: u8 = 0xff;
+
+/// Response to the info/reset commands: a standard controller, with an empty pak slot
+const CONTROLLER_INFO: [u8; 3] = [0x05, 0x00, 0x00];
+
+/// Finds where each channel's command starts in PIF RAM
+pub fn scan(io: &dyn PifIO) -> [u8; CHANNELS] {
+    let mut addresses = [NO_COMMAND; CHANNELS];
+    let mut channel = 0;
+    let mut offset = 0;
+    while offset < 0x3f && channel < CHANNELS {
+        match io.read(offset) {
+            // Skip this channel. 0xfd resets the device, which we treat the same
+            0x00 | 0xfd => {
+                channel += 1;
+                offset += 1;
+            }
+            0xfe => break, // End of commands
+            0xff => offset += 1, // Padding
+            tx => {
+                let rx = io.read(offset + 1) & 0x3f;
+                addresses[channel] = offset as u8;
+                offset += 2 + (tx & 0x3f) as u32 + rx as u32;
+                channel += 1;
+            }
+        }
+    }
+    addresses
+}
+
+/// Runs the command at each address from `scan`, writing the responses back to PIF RAM
+///
+/// Unsupported commands get no response, like a missing device. Every channel still runs before
+/// the first one is returned as an error.
+pub fn run(io: &mut dyn PifIO, addresses: &[u8; CHANNELS]) -> Result<(), anyhow::Error> {
+    let mut unsupported = None;
+    for (channel, &address) in addresses.iter().enumerate() {
+        if address == NO_COMMAND {
+            continue;
+        }
+        let address = address as u32;
+        let tx = (io.read(address) & 0x3f) as u32;
+        let rx_byte = io.read(address + 1);
+        if tx == 0 {
+            continue;
+        }
+        let command = io.read(address + 2);
+
+        let pad = match channel < MAX_PORTS {
+            true => Some(io.controller(channel)).filter(|pad| pad.connected),
+            false => None,
+        };
+        let mut response = [0; 4];
+        let len = match (pad, command) {
+            (None, _) => None,
+            (Some(_), 0x00 | 0xff) => {
+                response[..3].copy_from_slice(&CONTROLLER_INFO);
+                Some(3)
+            }
+            (Some(pad), 0x01) => {
+                response = controller_state(&pad);
+                Some(4)
+            }
+            (Some(_), _) => {
+                unsupported.get_or_insert((command, channel));
+                None
+            }
+        };
+
+        match len {
+            Some(len) => {
+                // TODO: Flag responses that don't match the expected length
+                let start = address + 2 + tx;
+                let rx = (rx_byte & 0x3f) as usize;
+                for (i, &byte) in response[..len.min(rx)].iter().enumerate() {
+                    if start + (i as u32) < 0x3f {
+                        io.write(start + i as u32, byte);
+                    }
+                }
+            }
+            None => io.write(address + 1, rx_byte | 0x80), // Nothing responded
+        }
+    }
+
+    match unsupported {
+        Some((command, channel)) => bail!("Joybus: unsupported command {:#04x} on channel {}", command, channel),
+        None => Ok(()),
+    }
+}
+
+/// The response to a controller read: two bytes of buttons, then the stick's X and Y
+pub fn controller_state(pad: &PadState) -> [u8; 4] {
+    let bits = |buttons: &[(Button, u8)]| buttons.iter()
+        .filter(|(button, _)| pad.buttons.pressed(*button))
+        .fold(0, |acc, (_, bit)| acc | bit);
+
+    let high = bits(&[
+        (Button::A, 0x80), (Button::B, 0x40), (Button::Z, 0x20), (Button::Start, 0x10),
+        (Button::Up, 0x08), (Button::Down, 0x04), (Button::Left, 0x02), (Button::Right, 0x01),
+    ]);
+    let low = bits(&[
+        (Button::L, 0x20), (Button::R, 0x10),
+        (Button::CUp, 0x08), (Button::CDown, 0x04), (Button::CLeft, 0x02), (Button::CRight, 0x01),
+    ]);
+
+    // Real sticks only reach about 80 in each direction
+    let axis = |axis| (pad.axis(axis) as i32 * 80 / i16::MAX as i32) as i8 as u8;
+
+    [high, low, axis(Axis::LeftX), axis(Axis::LeftY)]
+}
+
+#[cfg(test)]
+mod tests {
+    use common::input::Buttons;
+
+    use super::*;
+
+    struct TestIo {
+        ram: [u8; 64],
+        pad: PadState,
+    }
+
+    impl PifIO for TestIo {
+        fn read(&self, address: u32) -> u8 { self.ram[address as usize] }
+        fn write(&mut self, address: u32, value: u8) { self.ram[address as usize] = value; }
+        fn controller(&self, port: usize) -> PadState {
+            match port {
+                0 => self.pad,
+                _ => PadState::default(),
+            }
+        }
+        fn rom_lockout(&mut self) { unreachable!() }
+        fn rom_enable(&mut self) { unreachable!() }
+        fn reset_enable(&mut self) { unreachable!() }
+        fn reset_pressed(&mut self) -> bool { unreachable!() }
+        fn nmi(&mut self) { unreachable!() }
+        fn hold_reset(&mut self) { unreachable!() }
+        fn cic_poll(&mut self) { unreachable!() }
+        fn cic_read(&mut self) -> u8 { unreachable!() }
+        fn cic_read_nibble(&mut self) -> u8 { unreachable!() }
+        fn cic_write(&mut self, _: u8) { unreachable!() }
+        fn cic_write_nibble(&mut self, _: u8) { unreachable!() }
+    }
+
+    #[test]
+    fn read_controllers() {
+        let mut pad = PadState { connected: true, buttons: Buttons::default(), axes: [i16::MAX, i16::MIN, 0, 0] };
+        pad.buttons.set(Button::A, true);
+        pad.buttons.set(Button::CRight, true);
+
+        // What libultra sends to read every controller: a read on each of the 4 channels
+        let mut ram = [0; 64];
+        for channel in 0..4 {
+            ram[channel * 8..channel * 8 + 8].copy_from_slice(&[0xff, 0x01, 0x04, 0x01, 0xff, 0xff, 0xff, 0xff]);
+        }
+        ram[32] = 0xfe;
+        let mut io = TestIo { ram, pad };
+
+        let addresses = scan(&io);
+        assert_eq!(addresses, [1, 9, 17, 25, NO_COMMAND]);
+        run(&mut io, &addresses).unwrap();
+
+        assert_eq!(io.ram[1..8], [0x01, 0x04, 0x01, 0x80, 0x01, 80, (-80i8) as u8]);
+        // Nothing connected to the other ports
+        assert_eq!(io.ram[9..16], [0x01, 0x84, 0x01, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn unsupported_command() {
+        let pad = PadState { connected: true, ..PadState::default() };
+
+        // A controller pak read on port 0, then an info request on port 1
+        let mut ram = [0; 64];
+        ram[0..6].copy_from_slice(&[0x03, 0x21, 0x02, 0x80, 0x01, 0xff]);
+        ram[6..11].copy_from_slice(&[0x01, 0x03, 0x00, 0xff, 0xff]);
+        ram[11] = 0xfe;
+        let mut io = TestIo { ram, pad };
+
+        let addresses = scan(&io);
+        let err = run(&mut io, &addresses).unwrap_err();
+        assert_eq!(err.to_string(), "Joybus: unsupported command 0x02 on channel 0");
+
+        // Port 0 didn't respond, and port 1 was still run
+        assert_eq!(io.ram[1], 0xa1);
+        assert_eq!(io.ram[6..11], [0x01, 0x03, 0x00, 0xff, 0xff]);
+    }
+}
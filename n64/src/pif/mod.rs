@@ -1,6 +1,7 @@
 mod hle;
+mod joybus;
 
-pub use hle::{PifHle, PifIO};
+pub use hle::{PifHle, PifIO, MAIN_PERIOD};
 
 pub enum Dir {
     Read,
@@ -1,4 +1,6 @@
 
+use actor_framework::{RestoreState, SaveState, StateReader, StateWriter};
+use common::impl_save_state;
 use modular_bitfield::{bitfield, specifiers::*, Specifier};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -30,6 +32,12 @@ pub struct OutputFormat {
     pub y_offset: Fixed2_10,
 }
 
+impl_save_state!(OutputFormat {
+    dedither_filter, aa_mode, divot, gamma, gamma_dither, pixel_type, hsync_width, burst_width,
+    vsync_width, burst_start, vsync, hsync, leap, leap_a, leap_b, h_start, h_end, v_start, v_end,
+    v_burst_start, v_burst_end, x_scale, x_offset, y_scale, y_offset,
+});
+
 impl OutputFormat {
     pub fn transfers_per_line(&self) -> u32 {
         let dots = self.h_end as f32 - self.h_start as f32;
@@ -176,6 +184,17 @@ pub struct ViCtrl {
     #[skip] __: B15,
 }
 
+impl SaveState for ViCtrl {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.into_bytes().save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = ViCtrl::from_bytes(RestoreState::restore_state(state)?);
+        Ok(())
+    }
+}
+
 #[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
 #[bits = 2]
 pub enum PixelType {
@@ -194,6 +213,9 @@ pub enum AaMode {
     Disabled,
 }
 
+impl_save_state!(enum PixelType { Blank, Reserved, Rgb5c3, Rgb8a5c3 });
+impl_save_state!(enum AaMode { Enabled, EnabledReducedBandwdith, ResampleOnly, Disabled });
+
 #[bitfield(bits = 32)]
 #[derive(Debug, Copy, Clone)]
 pub struct ViBurst {
@@ -272,6 +294,23 @@ pub struct Fixed2_10 {
     #[skip] __: B4,
 }
 
+impl SaveState for Fixed2_10 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.into_bytes().save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), anyhow::Error> {
+        *self = Self::restore_state(state)?;
+        Ok(())
+    }
+}
+
+impl RestoreState for Fixed2_10 {
+    fn restore_state(state: &mut StateReader) -> Result<Self, anyhow::Error> {
+        Ok(Fixed2_10::from_bytes(RestoreState::restore_state(state)?))
+    }
+}
+
 impl From<Fixed2_10> for f32 {
     fn from(fixed: Fixed2_10) -> Self {
         fixed.integer() as f32 + fixed.fractional() as f32 / 1024.0
@@ -1,21 +1,17 @@
 
 use std::sync::mpsc;
 
+use actor_framework::ClockDomain;
+use common::impl_save_state;
+
 use self::control::OutputFormat;
 
 mod aa_filter;
 mod scaler;
 pub mod control;
 
-//static VI_CLOCK_RATIO : f64 = 62.5 / 48.4;
-
-fn vi_cycles(rcp_time : u64) -> u64 {
-    (rcp_time * 484) / 625
-}
-
-fn rcp_cycles(vi_time : u64) -> u64 {
-    (vi_time * 625).div_ceil(484)
-}
+/// VI runs at 48.4 MHz, the RCP at 62.5 MHz
+const VI_CLOCK: ClockDomain = ClockDomain::new("VI", 484, 625);
 
 #[derive(Debug)]
 pub struct ViCore {
@@ -42,6 +38,12 @@ pub struct ViCore {
     buffer_rx: mpsc::Receiver<Vec<u8>>,
 }
 
+// The channels to the resolver aren't part of the emulated state
+impl_save_state!(ViCore {
+    h_pos, v_pos, format, fb_stride, fb_origin, fb_line_addr, fb_addr, h_blank, v_blank,
+    vi_cycles, buffer, ..
+});
+
 pub enum FetchType {
     WithParity(u8),
     WithoutParity(u8),
@@ -68,7 +70,7 @@ impl ViCore {
         self.fb_line_addr = self.fb_origin;
         self.fb_addr = self.fb_line_addr;
 
-        self.vi_cycles = vi_cycles(rcp_cycle);
+        self.vi_cycles = VI_CLOCK.to_local(rcp_cycle.into());
 
         (NextEvent::Prefetch(self.fb_addr, FetchType::WithoutParity(4)), rcp_cycle+1)
     }
@@ -78,13 +80,13 @@ impl ViCore {
         self.fb_line_addr += self.fb_stride;
         self.fb_addr = self.fb_line_addr;
 
-        self.vi_cycles = vi_cycles(rcp_cycle);
+        self.vi_cycles = VI_CLOCK.to_local(rcp_cycle.into());
 
         (NextEvent::Prefetch(self.fb_addr, FetchType::WithoutParity(4)), rcp_cycle+1)
     }
 
     pub fn run_dma(&mut self, rcp_cycle: u64) -> (NextEvent, u64) {
-        let vi_cycles = vi_cycles(rcp_cycle);
+        let vi_cycles = VI_CLOCK.to_local(rcp_cycle.into());
         self.h_pos += vi_cycles - self.vi_cycles;
         self.vi_cycles = vi_cycles;
 
@@ -92,7 +94,7 @@ impl ViCore {
     }
 
     pub fn run_prefetch(&mut self, rcp_cycle: u64, data: &[u8]) -> (NextEvent, u64) {
-        let vi_cycles = vi_cycles(rcp_cycle);
+        let vi_cycles = VI_CLOCK.to_local(rcp_cycle.into());
         self.h_pos += vi_cycles - self.vi_cycles;
         self.vi_cycles = vi_cycles;
 
@@ -121,7 +123,7 @@ impl ViCore {
         let cycles_per_line = self.format.hsync as u64;
         let cycles_to_end_of_line = cycles_per_line - self.h_pos;
 
-        let mut vi_diff = vi_cycles(rcp_cycle) - self.vi_cycles;
+        let mut vi_diff = VI_CLOCK.to_local(rcp_cycle.into()) - self.vi_cycles;
         if vi_diff > cycles_to_end_of_line {
             self.h_pos += vi_diff;
         } else {
@@ -142,17 +144,17 @@ impl ViCore {
     }
 
     fn next_event(&self, rcp_cycle: u64) -> (NextEvent, u64) {
-        let vi_cycle = vi_cycles(rcp_cycle);
+        let vi_cycle = VI_CLOCK.to_local(rcp_cycle.into());
         if self.format.pixel_type == control::PixelType::Blank {
             return (NextEvent::Never, u64::MAX);
         }
         if self.v_blank {
-            (NextEvent::VStart, rcp_cycles(vi_cycle + self.next_vstart()))
+            (NextEvent::VStart, VI_CLOCK.to_scheduler(vi_cycle + self.next_vstart()).into())
         } else {
             if self.h_blank {
                 let cycles = (self.format.h_start as u64).checked_sub(self.h_pos)
                     .unwrap_or_else(|| self.next_line());
-                (NextEvent::HStart, rcp_cycles(vi_cycle + cycles))
+                (NextEvent::HStart, VI_CLOCK.to_scheduler(vi_cycle + cycles).into())
             } else {
                 todo!("Format changed outside blanking")
             }
@@ -257,3 +259,5 @@ pub struct TransferBuffer {
     _v_pos: u16,
     dma_bytes: Vec<u8>,
 }
+
+impl_save_state!(TransferBuffer { _format, _h_pos, _v_pos, dma_bytes });
@@ -1,4 +1,4 @@
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 
 use super::pipeline::MemoryReq;
 
@@ -6,6 +6,8 @@ use super::pipeline::MemoryReq;
 pub struct CacheTag {
     val: u32,
 }
+impl_save_state!(CacheTag { val });
+
 impl CacheTag {
     #[inline]
     pub fn empty() -> CacheTag {
@@ -140,6 +142,8 @@ pub struct ICache {
     data: [[u32; 8]; 512],
 }
 
+impl_save_state!(ICache { tag, data });
+
 impl ICache {
     pub fn new() -> ICache {
         ICache {
@@ -170,6 +174,8 @@ pub struct DCache {
     tag: [CacheTag; 512],
 }
 
+impl_save_state!(DCache { data, tag });
+
 impl DCache {
     pub fn new() -> DCache {
         DCache {
@@ -195,6 +201,8 @@ pub struct DataCacheAttempt {
     offset: u8
 }
 
+impl_save_state!(DataCacheAttempt { tag, line, offset });
+
 impl DataCacheAttempt {
     pub fn empty() -> DataCacheAttempt {
         DataCacheAttempt {
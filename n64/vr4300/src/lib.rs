@@ -8,7 +8,7 @@ use cache::{ICache, DCache};
 use microtlb::ITlb;
 use pipeline::{MemoryReq, ExitReason};
 use pipeline::Pipeline;
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 
 use self::pipeline::MemoryResponce;
 
@@ -19,8 +19,6 @@ pub mod cache;
 pub mod microtlb;
 pub mod joint_tlb;
 pub mod regfile;
-#[cfg(feature = "ui")]
-pub mod ui;
 
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -33,6 +31,15 @@ pub enum RequestType {
     UncachedWrite,
 }
 
+impl_save_state!(enum RequestType {
+    ICacheFill,
+    DCacheFill,
+    DCacheWriteback,
+    UncachedInstructionRead,
+    UncachedDataRead,
+    UncachedWrite,
+});
+
 pub struct Core {
     pipeline: Pipeline,
     icache: ICache,
@@ -41,8 +48,20 @@ pub struct Core {
     //bus: SysADBus,
     queued_flush: Option<(u32, [u64; 2])>,
     count: u64,
+    /// The NMI pin has been asserted, but the core hasn't taken the soft reset yet
+    nmi_pending: bool,
+    /// The cold reset pin is held, so the core does nothing until it's power cycled
+    held_in_reset: bool,
+    /// Debugger state, not part of the machine, so none of it is saved
+    break_pc: Option<u64>,
+    breakpoints: Vec<u64>,
+    single_step: bool,
+    /// Any of the above is set, so `advance` needs to check for them
+    check_breaks: bool,
 }
 
+impl_save_state!(Core { pipeline, icache, dcache, itlb, queued_flush, count, nmi_pending, held_in_reset, .. });
+
 impl Core {
     pub fn advance(&mut self, cycle_limit: u64) -> CoreRunResult {
         if self.pipeline.blocked() {
@@ -52,10 +71,24 @@ impl Core {
             }
         }
 
+        // Resets are only taken once the bus is idle, so no memory request is left hanging
+        if !self.pipeline.waiting_for_memory() && self.queued_flush.is_none() {
+            if self.held_in_reset {
+                return CoreRunResult {
+                    cycles: cycle_limit,
+                    reason: Reason::Limited,
+                }
+            }
+            if self.nmi_pending {
+                self.soft_reset();
+            }
+        }
+
         let mut cycles = 0;
         while cycles < cycle_limit {
             cycles += 1;
 
+            let pc = self.pipeline.pc();
             let reason = self.pipeline.cycle(
                 &mut self.icache,
                 &mut self.dcache,
@@ -63,6 +96,13 @@ impl Core {
             );
             // TODO: implement flush buffers
             let reason = Reason::BusRequest(match reason {
+                Ok(()) if self.check_breaks && self.should_break(pc) => {
+                    self.set_single_step(false);
+                    return CoreRunResult {
+                        cycles,
+                        reason: Reason::Breakpoint,
+                    }
+                }
                 Ok(()) | Err(ExitReason::Stalled) => { continue; }
                 Err(ExitReason::Blocked) => {
                     cycles = cycle_limit;
@@ -173,6 +213,97 @@ impl Core {
         )
     }
 
+    /// Number of instructions executed since the last `reset_instruction_count`
+    pub fn instruction_count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn reset_instruction_count(&mut self) {
+        self.count = 0;
+    }
+
+    /// Address of the next instruction to be fetched
+    pub fn pc(&self) -> u64 {
+        self.pipeline.pc()
+    }
+
+    /// Stop `advance` with `Reason::Breakpoint` as soon as the pipeline moves on to fetching `pc`
+    pub fn set_break_pc(&mut self, pc: Option<u64>) {
+        self.break_pc = pc;
+        self.update_check_breaks();
+    }
+
+    /// Debugger breakpoints, which work like `set_break_pc`
+    pub fn breakpoints(&self) -> &[u64] {
+        &self.breakpoints
+    }
+
+    pub fn set_breakpoint(&mut self, pc: u64) {
+        if !self.breakpoints.contains(&pc) {
+            self.breakpoints.push(pc);
+        }
+        self.update_check_breaks();
+    }
+
+    /// Returns false if there was no breakpoint at `pc`
+    pub fn clear_breakpoint(&mut self, pc: u64) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&b| b != pc);
+        self.update_check_breaks();
+        self.breakpoints.len() != len
+    }
+
+    /// Stop `advance` with `Reason::Breakpoint` as soon as the pipeline moves on to fetching the
+    /// next pc, wherever it is. Cleared once it stops
+    pub fn set_single_step(&mut self, single_step: bool) {
+        self.single_step = single_step;
+        self.update_check_breaks();
+    }
+
+    fn update_check_breaks(&mut self) {
+        self.check_breaks = self.break_pc.is_some() || !self.breakpoints.is_empty() || self.single_step;
+    }
+
+    /// The pipeline has just moved on from `pc` to somewhere we need to stop
+    fn should_break(&self, pc: u64) -> bool {
+        let next = self.pipeline.pc();
+        next != pc && (self.single_step || self.break_pc == Some(next) || self.breakpoints.contains(&next))
+    }
+
+    /// General purpose register `index`, as written back. Results still in the pipeline aren't
+    /// included
+    pub fn register(&self, index: usize) -> u64 {
+        self.pipeline.regs.regs[index]
+    }
+
+    /// Writes to $zero are ignored, like on the hardware
+    pub fn set_register(&mut self, index: usize, value: u64) {
+        if index != 0 {
+            self.pipeline.regs.regs[index] = value;
+        }
+    }
+
+    /// Assert the NMI pin. The soft reset is taken next time the core advances with the bus idle
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Hold the cold reset pin. The core stops once the bus is idle, and there is no way back
+    /// short of creating a new core
+    pub fn hold_reset(&mut self) {
+        self.held_in_reset = true;
+    }
+
+    /// The soft reset exception: Flush the pipeline and jump to the reset vector, keeping the
+    /// registers and caches.
+    ///
+    /// TODO: COP0 isn't implemented yet, so Status.SR/ERL/BEV and ErrorEPC aren't set, and the
+    ///       boot code can't tell that it was an NMI.
+    fn soft_reset(&mut self) {
+        self.nmi_pending = false;
+        self.pipeline.soft_reset();
+    }
+
 }
 
 impl Default for Core {
@@ -184,21 +315,23 @@ impl Default for Core {
             itlb: ITlb::new(),
             queued_flush: None,
             count: 0,
+            nmi_pending: false,
+            held_in_reset: false,
+            break_pc: None,
+            breakpoints: Vec::new(),
+            single_step: false,
+            check_breaks: false,
         }
     }
 }
 
-impl Drop for Core {
-    fn drop(&mut self) {
-        eprintln!("Core executed {} instructions", self.count);
-    }
-}
-
 #[derive(Copy, Clone)]
 pub enum Reason {
     Limited,
     SyncRequest,
     BusRequest(BusRequest),
+    /// Reached the pc set with `Core::set_break_pc`, a breakpoint, or the end of a single step
+    Breakpoint,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -212,6 +345,16 @@ pub enum BusRequest {
     BusWrite128(RequestType, u32, [u64; 2]),
 }
 
+impl_save_state!(enum BusRequest {
+    BusRead32(request_type, addr),
+    BusRead64(request_type, addr),
+    BusRead128(request_type, addr),
+    BusRead256(request_type, addr),
+    BusWrite32(request_type, addr, data, mask),
+    BusWrite64(request_type, addr, data, mask),
+    BusWrite128(request_type, addr, data),
+});
+
 impl BusRequest {
     #[inline(always)]
     pub fn address(&self) -> u32 {
@@ -259,6 +402,7 @@ impl std::fmt::Display for Reason {
             Reason::Limited => write!(f, "Limited"),
             Reason::SyncRequest => write!(f, "SyncRequest"),
             Reason::BusRequest(req) => write!(f, "{}", req),
+            Reason::Breakpoint => write!(f, "Breakpoint"),
         }
     }
 }
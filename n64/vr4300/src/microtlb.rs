@@ -1,3 +1,5 @@
+use common::impl_save_state;
+
 use super::cache::{CacheTag, ICacheAddress};
 
 
@@ -8,12 +10,16 @@ struct TlbEntry {
     g: bool,
 }
 
+impl_save_state!(TlbEntry { vpn, pfn, _asid, g });
+
 pub struct ITlb {
     entires: [TlbEntry; 2],
     lru: u8, // vr4300 user manual says:
              //    Micro-TLB "uses the least-recently- used (LRU) replacement algorithm"
 }
 
+impl_save_state!(ITlb { entires, lru });
+
 impl ITlb {
     pub fn new() -> ITlb {
         ITlb {
@@ -1,4 +1,4 @@
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 
 use crate::cache::{DataCacheAttempt, CacheTag, DCache};
 
@@ -17,6 +17,16 @@ pub enum MemMode
     ConditionalStoreFail,
 }
 
+impl_save_state!(enum MemMode {
+    LoadSignExtend(up, down),
+    LoadZeroExtend(up, down),
+    LoadMergeWord(align),
+    LoadMergeDouble(align),
+    Store,
+    ConditionalStore,
+    ConditionalStoreFail,
+});
+
 pub struct DataCache {
     pub cache_attempt: DataCacheAttempt,
     pub tlb_tag: CacheTag,
@@ -27,6 +37,8 @@ pub struct DataCache {
     pub mem_mask: ByteMask8,
 }
 
+impl_save_state!(DataCache { cache_attempt, tlb_tag, writeback_reg, alu_out, mem_mode, mem_size, mem_mask });
+
 impl DataCache {
     #[inline(always)]
     pub fn cycle(&mut self, ex: &Execute, dcache: &mut DCache, writeback_has_work: &mut bool) -> Result<(), ExitReason> {
@@ -1,4 +1,4 @@
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 
 use super::{register_file::RegisterFile, data_cache::MemMode, ExitReason};
 
@@ -22,6 +22,11 @@ pub struct Execute {
     pub subinstruction_cycle: u32,
 }
 
+impl_save_state!(Execute {
+    next_pc, alu_out, addr, skip_next, mem_size, mem_mode, mem_mask, trap, writeback_reg,
+    hilo, ll_bit, ll_addr, subinstruction_cycle,
+});
+
 impl Default for Execute {
     fn default() -> Self {
         Execute{
@@ -125,6 +130,55 @@ pub enum ExMode {
     ExUnimplemented,
 }
 
+impl_save_state!(enum ExMode {
+    Nop,
+    Jump,
+    Branch(cmp),
+    BranchLikely(cmp),
+    Add32,
+    AddU32,
+    Add64,
+    AddU64,
+    Sub32,
+    Sub64,
+    SubU32,
+    SubU64,
+    SetLess,
+    SetLessU,
+    And,
+    Or,
+    Xor,
+    Nor,
+    InsertUpper,
+    ShiftLeft32,
+    ShiftRight32,
+    ShiftRightArith32,
+    ShiftLeft64,
+    ShiftRight64,
+    ShiftRightArith64,
+    Mul32,
+    MulU32,
+    Div32,
+    DivU32,
+    Mul64,
+    MulU64,
+    Div64,
+    DivU64,
+    Load(size),
+    LoadUnsigned(size),
+    LoadLeft(size),
+    LoadRight(size),
+    MemLoadLinked(size),
+    Store(size),
+    StoreLeft(size),
+    StoreRight(size),
+    MemStoreConditional(size),
+    LoadInternal(reg),
+    StoreInternal(reg),
+    CacheOp,
+    ExUnimplemented,
+});
+
 #[derive(Debug, Clone, Copy)]
 pub enum InternalReg {
     HI = 0,
@@ -141,6 +195,9 @@ pub enum CmpMode {
     Gt,
 }
 
+impl_save_state!(enum InternalReg { HI, LO });
+impl_save_state!(enum CmpMode { Eq, Ne, Le, Ge, Lt, Gt });
+
 fn compare(cmp: CmpMode, a: i64, b: i64) -> bool {
     match cmp {
         CmpMode::Eq => a == b,
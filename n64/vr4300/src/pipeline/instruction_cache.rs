@@ -1,3 +1,5 @@
+use common::impl_save_state;
+
 use crate::{cache::{CacheTag, ICache}, microtlb::ITlb};
 
 use super::register_file::RegisterFile;
@@ -12,6 +14,8 @@ pub struct InstructionCache {
     pub stalled: bool,
 }
 
+impl_save_state!(InstructionCache { cache_data, cache_tag, expected_tag, stalled });
+
 impl Default for InstructionCache {
     fn default() -> Self {
         Self {
@@ -5,7 +5,7 @@ pub mod execute;
 pub mod data_cache;
 pub mod writeback;
 
-use common::util::ByteMask8;
+use common::{impl_save_state, util::ByteMask8};
 
 use crate::{
     DCache, cache::{CacheTag, DataCacheAttempt, ICache}, microtlb::ITlb, regfile::RegFile
@@ -23,6 +23,7 @@ pub struct Pipeline {
     pub(crate) regs: RegFile,
 }
 
+impl_save_state!(Pipeline { ic, rf, ex, dc, wb, regs });
 
 pub enum MemoryReq
 {
@@ -74,6 +75,18 @@ impl Pipeline {
         return self.ic.stalled && !(wb_has_work || dc_has_work || ex_has_work);
     }
 
+    /// True while a stage is waiting on `memory_responce`
+    pub fn waiting_for_memory(&self) -> bool {
+        self.ic.stalled || self.wb.stalled
+    }
+
+    /// Flush every stage and restart fetching from the reset vector. Registers are kept
+    pub fn soft_reset(&mut self) {
+        debug_assert!(!self.waiting_for_memory());
+        let regs = std::mem::replace(&mut self.regs, RegFile::new());
+        *self = Pipeline { regs, ..create() };
+    }
+
     pub fn cycle(
         &mut self,
         icache: &mut ICache,
@@ -1,3 +1,5 @@
+use common::impl_save_state;
+
 use crate::{instructions::{JType, IType, RType, InstructionInfo}, pipeline::MemoryReq, regfile::RegFile};
 
 use super::{execute::{ExMode, Execute}, ExitReason, instruction_cache::InstructionCache};
@@ -10,6 +12,7 @@ pub struct RegisterFile {
     pub ex_mode: ExMode,
 }
 
+impl_save_state!(RegisterFile { next_pc, alu_a, alu_b, temp, writeback_reg, ex_mode });
 
 #[derive(Debug, Clone, Copy)]
 pub enum RfMode {
@@ -1,3 +1,5 @@
+use common::impl_save_state;
+
 use crate::{
     cache::DCache,
     pipeline::{data_cache::MemMode, ExitReason},
@@ -10,6 +12,8 @@ pub struct WriteBack {
     pub stalled: bool,
 }
 
+impl_save_state!(WriteBack { stalled });
+
 impl WriteBack {
     #[inline(always)]
     pub fn cycle(
@@ -1,3 +1,5 @@
+use common::impl_save_state;
+
 use super::instructions::MIPS_REG_NAMES;
 
 pub struct RegFile {
@@ -7,6 +9,8 @@ pub struct RegFile {
     hazard: bool,
 }
 
+impl_save_state!(RegFile { regs, bypass_reg, bypass_val, hazard });
+
 impl RegFile {
     pub(super) fn new() -> RegFile {
         RegFile {
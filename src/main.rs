@@ -1,13 +1,13 @@
-use std::{sync::mpsc, any::Any};
+use std::{sync::mpsc, any::Any, process::ExitCode, time::{Duration, Instant}};
 
 use n64::CoreN64;
-use common::{register_cores, cli::GlobalOpts};
+use common::{register_cores, audio::WavWriter, cli::GlobalOpts, pacing::Speed, ControlMessage, Progress, StopConditions};
 
 register_cores!(
     CoreN64,
 );
 
-fn main() -> Result<(), anyhow::Error> {
+fn main() -> Result<ExitCode, anyhow::Error> {
     use clap::ValueEnum;
     use clap::Parser;
 
@@ -28,14 +28,125 @@ fn main() -> Result<(), anyhow::Error> {
         }
     } else {
         #[cfg(feature = "ui")]
-        ui::run(core, config, all_cores)
+        ui::run(core, config, all_cores, global_opts.speed).map(|_| ExitCode::SUCCESS)
     }
 }
 
-fn run_no_ui(core: &dyn common::EmulationCore, config: Box<dyn Any>, _opts: GlobalOpts::<Cores>) -> Result<(), anyhow::Error> {
+/// Why a --nogui run stopped
+#[derive(Debug, PartialEq, Eq)]
+enum Stopped {
+    Cycles,
+    Vsyncs,
+    Seconds,
+    Pc,
+    /// Paused for some other reason, like a breakpoint
+    Paused,
+}
+
+impl Stopped {
+    /// Breakpoints also pause the run, so it only stopped at --stop-pc if that's where the pc is
+    fn find(stop: &StopConditions, progress: &Progress, timed_out: bool) -> Stopped {
+        let at_pc = match (stop.pc, progress.pc) {
+            (Some(stop_pc), Some(pc)) => same_address(stop_pc, pc),
+            _ => false,
+        };
+        if at_pc {
+            Stopped::Pc
+        } else if stop.cycles.is_some_and(|cycles| progress.cycles >= cycles) {
+            Stopped::Cycles
+        } else if stop.vsyncs.is_some_and(|stop| progress.vsyncs.is_some_and(|vsyncs| vsyncs >= stop)) {
+            Stopped::Vsyncs
+        } else if timed_out {
+            Stopped::Seconds
+        } else {
+            Stopped::Paused
+        }
+    }
+
+    /// With --stop-pc, the run only succeeds if the pc was reached. Otherwise any limit will do
+    fn exit_code(&self, stop: &StopConditions) -> ExitCode {
+        match stop.pc.is_some() && *self != Stopped::Pc {
+            true => ExitCode::from(3),
+            false => ExitCode::SUCCESS,
+        }
+    }
+}
+
+/// Cores with 64 bit addresses sign extend 32 bit ones given on the command line
+fn same_address(given: u64, pc: u64) -> bool {
+    match u32::try_from(given) {
+        Ok(given) => pc == given as u64 || pc == given as i32 as u64,
+        Err(_) => pc == given,
+    }
+}
+
+fn run_no_ui(core: &dyn common::EmulationCore, config: Box<dyn Any>, opts: GlobalOpts::<Cores>) -> Result<ExitCode, anyhow::Error> {
     let mut instance = core.new_sync(config)?;
-    let (_tx_control, rx_control) = mpsc::channel::<common::ControlMessage>();
+    instance.set_speed(opts.speed.unwrap_or(Speed::Unlimited));
+    let stop = StopConditions {
+        cycles: opts.stop_cycles,
+        vsyncs: opts.stop_vsyncs,
+        pc: opts.stop_pc,
+    };
+    instance.set_stop_conditions(stop)?;
+    if let Some(path) = &opts.wav {
+        let wav = WavWriter::create(path)
+            .map_err(|err| anyhow::anyhow!("Failed to create {}: {}", path.display(), err))?;
+        instance.set_audio_sink(Some(Box::new(wav)))?;
+    }
+    let timeout = opts.stop_seconds
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|err| anyhow::anyhow!("Invalid --stop-seconds: {}", err))?;
+
+    let (tx_control, rx_control) = mpsc::channel::<ControlMessage>();
     let (tx_update, _rx_update) = mpsc::sync_channel::<common::UpdateMessage>(1);
 
-    instance.run(&rx_control, tx_update)
+    if let Some(timeout) = timeout {
+        let tx_control = tx_control.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(timeout);
+            // Fails if the run has already finished
+            let _ = tx_control.send(ControlMessage::Pause);
+        });
+    }
+
+    let start = Instant::now();
+    let result = instance.run(&rx_control, tx_update);
+    let elapsed = start.elapsed();
+
+    let progress = instance.progress();
+    let timed_out = timeout.is_some_and(|timeout| elapsed >= timeout);
+    let stopped = Stopped::find(&stop, &progress, timed_out);
+
+    eprint!("{}", instance.stats());
+    match &result {
+        Ok(()) => eprintln!("Stopped: {:?}", stopped),
+        Err(err) => eprintln!("Stopped: Error: {}", err),
+    }
+    eprintln!("Emulated cycles: {}", progress.cycles);
+    if let Some(vsyncs) = progress.vsyncs {
+        eprintln!("Vsyncs: {}", vsyncs);
+    }
+    if let Some(instructions) = progress.instructions {
+        eprintln!("Instructions: {}", instructions);
+    }
+    if let Some(pc) = progress.pc {
+        eprintln!("PC: {:#018x}", pc);
+    }
+    eprintln!("Real time: {:.3}s", elapsed.as_secs_f64());
+
+    if let Some(mut wav) = instance.set_audio_sink(None)? {
+        wav.flush()?;
+    }
+
+    if let Some(path) = opts.profile {
+        match instance.profile_folded() {
+            Some(folded) => std::fs::write(path, folded)?,
+            None => eprintln!("Profiling not supported by this build, rebuild with --features profiling"),
+        }
+    }
+
+    result?;
+    Ok(stopped.exit_code(&stop))
 }
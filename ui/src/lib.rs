@@ -1,26 +1,86 @@
 use std::any::Any;
 
-use common::{EmulationCore, Status};
+use common::{input::{Axis, Button, Buttons, PadState}, pacing::Speed, video::{Field, Frame}, EmulationCore, ResetKind, Status};
 use eframe::egui;
 
 
 struct BusMuApp {
     active_core: Option<&'static dyn common::EmulationCore>,
     instance: Option<Box<dyn common::ThreadedInstance>>,
+    speed: Speed,
+    /// The last frame from the instance, uploaded for egui
+    texture: Option<egui::TextureHandle>,
+    /// Scratch space for converting frames to RGBA
+    pixels: Vec<u8>,
+    /// The keyboard's controller, as last sent to port 0
+    pad: PadState,
 }
 
 impl BusMuApp {
-    fn new(_cc: &eframe::CreationContext<'_>, core: &'static dyn EmulationCore, config: Box<dyn Any>) -> Result<Self, anyhow::Error> {
+    fn new(_cc: &eframe::CreationContext<'_>, core: &'static dyn EmulationCore, config: Box<dyn Any>, speed: Speed) -> Result<Self, anyhow::Error> {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
-        let instance = core.new_threaded(config)?;
+        let mut instance = core.new_threaded(config)?;
+        instance.set_speed(speed)?;
         Ok(Self {
             active_core: Some(core),
             instance: Some(instance),
+            speed,
+            texture: None,
+            pixels: Vec::new(),
+            pad: PadState::default(),
         })
     }
+
+    /// Maps the keyboard onto a controller
+    fn keyboard_pad(ctx: &egui::Context) -> PadState {
+        use egui::Key;
+        const BUTTONS: [(Key, Button); 14] = [
+            (Key::X, Button::A), (Key::C, Button::B), (Key::Z, Button::Z), (Key::Enter, Button::Start),
+            (Key::A, Button::L), (Key::S, Button::R),
+            (Key::T, Button::Up), (Key::G, Button::Down), (Key::F, Button::Left), (Key::H, Button::Right),
+            (Key::I, Button::CUp), (Key::K, Button::CDown), (Key::J, Button::CLeft), (Key::L, Button::CRight),
+        ];
+
+        ctx.input(|input| {
+            let mut buttons = Buttons::default();
+            for (key, button) in BUTTONS {
+                buttons.set(button, input.key_down(key));
+            }
+            let axis = |negative, positive| match (input.key_down(negative), input.key_down(positive)) {
+                (true, false) => -i16::MAX,
+                (false, true) => i16::MAX,
+                _ => 0,
+            };
+            let mut pad = PadState { connected: true, buttons, ..PadState::default() };
+            pad.set_axis(Axis::LeftX, axis(Key::ArrowLeft, Key::ArrowRight));
+            pad.set_axis(Axis::LeftY, axis(Key::ArrowDown, Key::ArrowUp));
+            pad
+        })
+    }
+
+    fn show_frame(texture: &mut Option<egui::TextureHandle>, pixels: &mut Vec<u8>, ui: &mut egui::Ui, frame: &Frame) {
+        frame.to_rgba8(pixels);
+        let size = [frame.width as usize, frame.height as usize];
+        let image = egui::ColorImage::from_rgba_unmultiplied(size, pixels);
+        let options = egui::TextureOptions::NEAREST;
+        let texture_id = match texture.as_mut() {
+            Some(texture) => {
+                texture.set(image, options);
+                texture.id()
+            }
+            None => texture.insert(ui.ctx().load_texture("frame", image, options)).id(),
+        };
+
+        // A single field only has half the lines, so stretch it back to the full picture
+        let scale = match frame.field {
+            Field::Progressive => 1.0,
+            Field::Even | Field::Odd => 2.0,
+        };
+        ui.image((texture_id, egui::vec2(frame.width as f32, frame.height as f32 * scale)));
+    }
 }
 
 impl eframe::App for BusMuApp {
@@ -30,6 +90,45 @@ impl eframe::App for BusMuApp {
                 match &mut self.instance {
                     Some(instance) => {
                         ui.heading(format!("{} is {:?}", core.name(), instance.status()));
+                        let speed = self.speed;
+                        egui::ComboBox::from_label("Speed")
+                            .selected_text(self.speed.to_string())
+                            .show_ui(ui, |ui| {
+                                for option in [Speed::Percent(50), Speed::Percent(100), Speed::Percent(200), Speed::Unlimited, Speed::FrameAdvance] {
+                                    ui.selectable_value(&mut self.speed, option, option.to_string());
+                                }
+                            });
+                        if self.speed != speed {
+                            instance.set_speed(self.speed).unwrap();
+                        }
+                        if !matches!(instance.status(), Status::Error) {
+                            let pad = Self::keyboard_pad(ctx);
+                            if pad != self.pad {
+                                if let Err(err) = instance.set_input(0, pad) {
+                                    eprintln!("Input failed: {:?}", err);
+                                }
+                                self.pad = pad;
+                            }
+                            ui.horizontal(|ui| {
+                                for (label, kind) in [("Reset", ResetKind::Soft), ("Power cycle", ResetKind::PowerCycle)] {
+                                    if ui.button(label).clicked() {
+                                        if let Err(err) = instance.reset(kind) {
+                                            eprintln!("{} failed: {:?}", label, err);
+                                        }
+                                    }
+                                }
+                                if ui.button("Frame advance").clicked() {
+                                    if let Err(err) = instance.frame_advance() {
+                                        eprintln!("Frame advance failed: {:?}", err);
+                                    }
+                                }
+                            });
+                        }
+                        for reply in instance.take_replies() {
+                            if let Err(err) = reply {
+                                eprintln!("{}", err);
+                            }
+                        }
                         match instance.status() {
                             Status::Paused => {
                                 let responce = ui.button("Resume");
@@ -43,12 +142,23 @@ impl eframe::App for BusMuApp {
                             Status::Running => {
                                 if ui.button("Pause").clicked() {
                                     instance.pause().unwrap();
+                                } else {
+                                    ui.separator();
+                                    instance.ui(core, ui);
                                 }
                             }
                             Status::Error => {
-                                ui.heading("Instance paniced");
+                                ui.heading("Instance stopped with an error");
+                                if let Some(error) = instance.error() {
+                                    ui.separator();
+                                    ui.monospace(error);
+                                }
                             }
                         }
+                        if let Some(frame) = instance.frame() {
+                            ui.separator();
+                            Self::show_frame(&mut self.texture, &mut self.pixels, ui, frame);
+                        }
                     }
                     None => {
                         ui.heading(format!("{} is stopped", core.name()));
@@ -59,7 +169,7 @@ impl eframe::App for BusMuApp {
    }
 }
 
-pub fn run(core: Option<&'static dyn EmulationCore>, config: Box<dyn Any>, cores: Vec<&'static dyn EmulationCore>) -> Result<(), anyhow::Error> {
+pub fn run(core: Option<&'static dyn EmulationCore>, config: Box<dyn Any>, cores: Vec<&'static dyn EmulationCore>, speed: Option<Speed>) -> Result<(), anyhow::Error> {
     let core = match core {
         Some(core) => core,
         None => {
@@ -72,7 +182,7 @@ pub fn run(core: Option<&'static dyn EmulationCore>, config: Box<dyn Any>, cores
     let result = eframe::run_native(
         "Bus-mu",
         native_options,
-        Box::new(|cc| Box::new(BusMuApp::new(cc, core, config).unwrap()))
+        Box::new(move |cc| Box::new(BusMuApp::new(cc, core, config, speed.unwrap_or_default()).unwrap()))
     );
     result.map_err(|e| anyhow::anyhow!("eframe error: {:?}", e))
 }